@@ -0,0 +1,11 @@
+#![no_main]
+
+use hinata::pn532::fuzzing;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((&brty, rest)) = data.split_first() {
+        let _ = fuzzing::in_list_passive_target(rest, brty % 3);
+    }
+    let _ = fuzzing::in_auto_poll(data);
+});
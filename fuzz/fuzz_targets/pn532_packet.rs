@@ -0,0 +1,10 @@
+#![no_main]
+
+use hinata::pn532::Pn532Packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = Pn532Packet::from_bytes(data) {
+        assert_eq!(Pn532Packet::from_bytes(&packet.to_bytes()).map(|p| p.payload.into_owned()).ok(), Some(packet.payload.into_owned()));
+    }
+});
@@ -0,0 +1,23 @@
+import { EventEmitter } from 'node:events'
+import type { Card, ReaderInfo, WatchOptions } from './native'
+
+export type { Card, ReaderInfo, WatchOptions } from './native'
+
+export function findDevices(exclude?: Array<string>): Promise<Array<ReaderInfo>>
+
+export declare class Reader extends EventEmitter {
+  static open(instanceId: string): Promise<Reader>
+  get instanceId(): string
+  getFirmwareTimestamp(): Promise<number>
+  getChipId(): Promise<string>
+  setLed(r: number, g: number, b: number): Promise<void>
+  resetLed(): Promise<void>
+  scanCard(timeoutMs: number): Promise<Card | null>
+  readAccessCode(): Promise<string | null>
+  watch(options?: WatchOptions): Promise<void>
+  unwatch(): Promise<void>
+  close(): Promise<void>
+
+  on(event: 'tap', listener: (card: Card) => void): this
+  on(event: 'remove' | 'disconnect', listener: () => void): this
+}
@@ -0,0 +1,65 @@
+'use strict'
+
+const { EventEmitter } = require('node:events')
+const native = require('./native.js')
+
+/**
+ * A HINATA reader. Card events are emitted as `tap` (with the card), `remove` and `disconnect`
+ * while {@link Reader#watch} is running.
+ */
+class Reader extends EventEmitter {
+  constructor (inner) {
+    super()
+    this.inner = inner
+  }
+
+  static async open (instanceId) {
+    return new Reader(await native.openReader(instanceId))
+  }
+
+  get instanceId () {
+    return this.inner.instanceId
+  }
+
+  getFirmwareTimestamp () {
+    return this.inner.getFirmwareTimestamp()
+  }
+
+  getChipId () {
+    return this.inner.getChipId()
+  }
+
+  setLed (r, g, b) {
+    return this.inner.setLed(r, g, b)
+  }
+
+  resetLed () {
+    return this.inner.resetLed()
+  }
+
+  scanCard (timeoutMs) {
+    return this.inner.scanCard(timeoutMs)
+  }
+
+  readAccessCode () {
+    return this.inner.readAccessCode()
+  }
+
+  /** Start emitting card events, other requests reject until {@link Reader#unwatch} */
+  watch (options) {
+    return this.inner.startCardEvents((event) => this.emit(event.event, event.card), options)
+  }
+
+  unwatch () {
+    return this.inner.stopCardEvents()
+  }
+
+  close () {
+    return this.inner.close()
+  }
+}
+
+module.exports = {
+  findDevices: native.findDevices,
+  Reader,
+}
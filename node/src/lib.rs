@@ -0,0 +1,226 @@
+use std::time::Duration;
+use hinata::card::aime::ArcadeCard;
+use hinata::card::PassiveTarget;
+use hinata::detector::{CardDetector, CardObserverHandle};
+use hinata::device::{HinataDevice, ScanType};
+use hinata::utils::id_format::IdFormat;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+fn to_napi(e: hinata::error::Error) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+#[napi(object)]
+pub struct ReaderInfo {
+    pub instance_id: String,
+    pub device_name: String,
+    pub product_id: u16,
+}
+
+#[napi(object)]
+pub struct Card {
+    /// `"typeA"` or `"felica"`
+    pub card_type: String,
+    /// UID for ISO14443-A, IDm for FeliCa, as upper-case hex
+    pub id: String,
+    pub raw_id: Buffer,
+}
+
+impl From<&PassiveTarget> for Card {
+    fn from(target: &PassiveTarget) -> Self {
+        let (card_type, id) = match target {
+            PassiveTarget::Iso14443a(card) => ("typeA", card.get_uid()),
+            PassiveTarget::Felica(card) => ("felica", &card.get_idm()[..]),
+        };
+        Self {
+            card_type: card_type.to_string(),
+            id: IdFormat::new(id).hex(),
+            raw_id: id.to_vec().into(),
+        }
+    }
+}
+
+/// Payload of the callback given to [`Reader::start_card_events`]
+#[napi(object)]
+pub struct CardEventPayload {
+    /// `"tap"`, `"remove"` or `"disconnect"`
+    pub event: String,
+    pub card: Option<Card>,
+}
+
+#[napi(object)]
+pub struct WatchOptions {
+    pub interval_ms: Option<u32>,
+    pub type_a: Option<bool>,
+    pub felica: Option<bool>,
+    pub felica_system_code: Option<u16>,
+}
+
+enum ReaderState {
+    Idle(HinataDevice),
+    /// The detector owns the device until the events are stopped
+    Watching(CardObserverHandle<HinataDevice>),
+    Closed,
+}
+
+#[napi]
+pub struct Reader {
+    instance_id: String,
+    state: Mutex<ReaderState>,
+}
+
+#[napi]
+pub async fn find_devices(exclude: Option<Vec<String>>) -> Result<Vec<ReaderInfo>> {
+    let builders = hinata::find_devices(exclude.unwrap_or_default()).await.map_err(to_napi)?;
+    Ok(builders.iter().map(|builder| ReaderInfo {
+        instance_id: builder.get_instance_id(),
+        device_name: builder.get_device_name(),
+        product_id: builder.get_product_id(),
+    }).collect())
+}
+
+#[napi]
+pub async fn open_reader(instance_id: String) -> Result<Reader> {
+    let builders = hinata::find_devices(vec![]).await.map_err(to_napi)?;
+    let builder = builders.iter()
+        .find(|builder| builder.get_instance_id() == instance_id)
+        .ok_or_else(|| Error::from_reason(format!("No reader with instance id {instance_id}")))?;
+    Ok(Reader {
+        instance_id,
+        state: Mutex::new(ReaderState::Idle(builder.build(false).map_err(to_napi)?)),
+    })
+}
+
+impl Reader {
+    async fn device(&self) -> Result<MappedMutexGuard<'_, HinataDevice>> {
+        MutexGuard::try_map(self.state.lock().await, |state| match state {
+            ReaderState::Idle(device) => Some(device),
+            _ => None,
+        }).map_err(|guard| match *guard {
+            ReaderState::Watching(_) => Error::from_reason("Reader is busy watching for cards"),
+            _ => Error::from_reason("Reader is closed"),
+        })
+    }
+}
+
+#[napi]
+impl Reader {
+    #[napi(getter)]
+    pub fn instance_id(&self) -> String {
+        self.instance_id.clone()
+    }
+
+    #[napi]
+    pub async fn get_firmware_timestamp(&self) -> Result<u32> {
+        self.device().await?.get_firmware_timestamp().await.map_err(to_napi)
+    }
+
+    #[napi]
+    pub async fn get_chip_id(&self) -> Result<String> {
+        let chip_id = self.device().await?.get_chip_id().await.map_err(to_napi)?;
+        Ok(IdFormat::new(&chip_id).hex())
+    }
+
+    #[napi]
+    pub async fn set_led(&self, r: u8, g: u8, b: u8) -> Result<()> {
+        self.device().await?.set_led(r, g, b).await;
+        Ok(())
+    }
+
+    #[napi]
+    pub async fn reset_led(&self) -> Result<()> {
+        self.device().await?.reset_led().await;
+        Ok(())
+    }
+
+    /// Wait up to `timeout_ms` for an ISO14443-A or FeliCa card, resolves to `null` on timeout
+    #[napi]
+    pub async fn scan_card(&self, timeout_ms: u32) -> Result<Option<Card>> {
+        let types = [ScanType::TypeA, ScanType::Felica(0xFFFF)];
+        let found = self.device().await?
+            .scan_card(Duration::from_millis(timeout_ms as u64), &types).await
+            .map_err(to_napi)?;
+        Ok(found.map(|(_, target)| Card::from(&target)))
+    }
+
+    /// Access code of the Aime card on the reader, `null` if there is none
+    #[napi]
+    pub async fn read_access_code(&self) -> Result<Option<String>> {
+        let mut device = self.device().await?;
+        match device.pn532().identify_arcade_card().await.map_err(to_napi)? {
+            Some(ArcadeCard::Aime(card)) => Ok(Some(card.access_code.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Poll in the background and call `callback` on every tap, removal and disconnect.
+    /// Other requests fail until [`Reader::stop_card_events`].
+    #[napi(ts_args_type = "callback: (event: CardEventPayload) => void, options?: WatchOptions")]
+    pub async fn start_card_events(
+        &self,
+        callback: ThreadsafeFunction<CardEventPayload, ErrorStrategy::Fatal>,
+        options: Option<WatchOptions>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let ReaderState::Idle(_) = *state else {
+            return Err(Error::from_reason("Reader is already watching for cards or closed"));
+        };
+        let ReaderState::Idle(device) = std::mem::replace(&mut *state, ReaderState::Closed) else {
+            unreachable!()
+        };
+
+        let mut detector = CardDetector::new();
+        if let Some(options) = options {
+            if let Some(interval) = options.interval_ms {
+                detector = detector.with_poll_interval(Duration::from_millis(interval as u64));
+            }
+            if let Some(enabled) = options.type_a {
+                detector = detector.with_type_a(enabled);
+            }
+            if let Some(enabled) = options.felica {
+                detector = detector.with_felica(enabled);
+            }
+            if let Some(system_code) = options.felica_system_code {
+                detector = detector.with_felica_system_code(system_code);
+            }
+        }
+
+        let emit = move |event: &str, card: Option<Card>| {
+            callback.call(CardEventPayload { event: event.to_string(), card }, ThreadsafeFunctionCallMode::NonBlocking);
+        };
+        let (on_remove, on_disconnect) = (emit.clone(), emit.clone());
+        let handle = detector.observer()
+            .on_tap(Box::new(move |target| emit("tap", Some(Card::from(target)))))
+            .on_remove(Box::new(move || on_remove("remove", None)))
+            .on_disconnect(Box::new(move || on_disconnect("disconnect", None)))
+            .spawn(device);
+        *state = ReaderState::Watching(handle);
+        Ok(())
+    }
+
+    #[napi]
+    pub async fn stop_card_events(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+        if let ReaderState::Watching(_) = *state {
+            let ReaderState::Watching(handle) = std::mem::replace(&mut *state, ReaderState::Closed) else {
+                unreachable!()
+            };
+            let device = handle.stop().await.ok_or_else(|| Error::from_reason("Card detector panicked"))?;
+            *state = ReaderState::Idle(device);
+        }
+        Ok(())
+    }
+
+    /// Stop watching and release the device, the reader can not be used afterwards
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        let state = std::mem::replace(&mut *self.state.lock().await, ReaderState::Closed);
+        if let ReaderState::Watching(handle) = state {
+            handle.stop().await;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+pub mod bridge;
+pub mod led;
+
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::error::{Error, HinataResult};
+
+/// Start of every frame, never appears escaped inside one
+pub const SYNC: u8 = 0xE0;
+/// Escapes a following `SYNC` or `ESCAPE` byte, which is sent minus one
+pub const ESCAPE: u8 = 0xD0;
+
+/// Address of the NFC part of the reader
+pub const NFC_ADDR: u8 = 0x00;
+/// Address of the LED board
+pub const LED_ADDR: u8 = 0x08;
+
+/// Baud rate of the 837-15396 reader, older boards run at 38400
+pub const BAUD_RATE: u32 = 115200;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AimeCommand {
+    GetFwVersion = 0x30,
+    GetHwVersion = 0x32,
+    RadioOn = 0x40,
+    RadioOff = 0x41,
+    Poll = 0x42,
+    MifareSelectTag = 0x43,
+    MifareSetKeyBana = 0x50,
+    BanaAuthenticate = 0x51,
+    MifareReadBlock = 0x52,
+    MifareSetKeyAime = 0x54,
+    AimeAuthenticate = 0x55,
+    Reset = 0x62,
+    FelicaEncap = 0x71,
+    LedSetColor = 0x81,
+    LedGetInfo = 0xF0,
+    LedReset = 0xF5,
+}
+
+/// Host to reader frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AimeRequest {
+    pub addr: u8,
+    pub seq: u8,
+    pub cmd: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Reader to host frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AimeResponse {
+    pub addr: u8,
+    pub seq: u8,
+    pub cmd: u8,
+    pub status: u8,
+    pub payload: Vec<u8>,
+}
+
+impl AimeRequest {
+    /// Fails for a payload of more than 250 bytes, the length byte counts the whole body
+    pub fn to_frame(&self) -> HinataResult<Vec<u8>> {
+        encode_body(&[self.addr, self.seq, self.cmd], &self.payload)
+    }
+
+    /// `body` is an unescaped frame from [`read_frame`]
+    pub fn from_body(body: &[u8]) -> HinataResult<Self> {
+        let (header, payload) = split_body(body, 5)?;
+        Ok(Self { addr: header[1], seq: header[2], cmd: header[3], payload })
+    }
+}
+
+impl AimeResponse {
+    /// Fails for a payload of more than 249 bytes, the length byte counts the whole body
+    pub fn to_frame(&self) -> HinataResult<Vec<u8>> {
+        encode_body(&[self.addr, self.seq, self.cmd, self.status], &self.payload)
+    }
+
+    /// `body` is an unescaped frame from [`read_frame`]
+    pub fn from_body(body: &[u8]) -> HinataResult<Self> {
+        let (header, payload) = split_body(body, 6)?;
+        Ok(Self { addr: header[1], seq: header[2], cmd: header[3], status: header[4], payload })
+    }
+}
+
+/// Length byte, `header`, payload length byte and payload
+fn encode_body(header: &[u8], payload: &[u8]) -> HinataResult<Vec<u8>> {
+    let len = u8::try_from(header.len() + 2 + payload.len()).map_err(|_| Error::Protocol("Aime payload too long".into()))?;
+    let mut body = vec![len];
+    body.extend_from_slice(header);
+    body.push(payload.len() as u8);
+    body.extend_from_slice(payload);
+    Ok(encode_frame(&body))
+}
+
+fn split_body(body: &[u8], header_len: usize) -> HinataResult<(&[u8], Vec<u8>)> {
+    if body.len() < header_len || body[0] as usize != body.len() {
+        return Err(Error::Parse("Aime frame length mismatch".into()));
+    }
+    let payload = &body[header_len..];
+    if body[header_len - 1] as usize != payload.len() {
+        return Err(Error::Parse("Aime payload length mismatch".into()));
+    }
+    Ok((&body[..header_len], payload.to_vec()))
+}
+
+/// Sync byte, escaped body and escaped checksum
+pub fn encode_frame(body: &[u8]) -> Vec<u8> {
+    let checksum = body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let mut frame = vec![SYNC];
+    for &byte in body.iter().chain(std::iter::once(&checksum)) {
+        if byte == SYNC || byte == ESCAPE {
+            frame.push(ESCAPE);
+            frame.push(byte - 1);
+        } else {
+            frame.push(byte);
+        }
+    }
+    frame
+}
+
+/// Read the next frame and return its unescaped body without the checksum.
+/// Bytes before the sync byte are skipped, a sync byte inside a frame starts over.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> HinataResult<Vec<u8>> {
+    read_frame_with(reader, |body| Some(body[0] as usize)).await
+}
+
+/// [`read_frame`] for framings that keep the length elsewhere, `body_len` gives the body length
+/// (checksum excluded) once enough of the body is known
+pub(crate) async fn read_frame_with<R: AsyncRead + Unpin>(reader: &mut R, body_len: impl Fn(&[u8]) -> Option<usize>) -> HinataResult<Vec<u8>> {
+    while reader.read_u8().await? != SYNC {}
+
+    let mut body = Vec::new();
+    loop {
+        let mut byte = reader.read_u8().await?;
+        if byte == SYNC {
+            body.clear();
+            continue;
+        }
+        if byte == ESCAPE {
+            byte = reader.read_u8().await?.wrapping_add(1);
+        }
+        body.push(byte);
+        if body_len(&body).is_some_and(|len| body.len() == len + 1) {
+            break;
+        }
+    }
+
+    let checksum = body.pop().unwrap_or_default();
+    if body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != checksum {
+        return Err(Error::Parse("Aime frame checksum mismatch".into()));
+    }
+    Ok(body)
+}
+
+/// Target reported by [`AimeClient::poll`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AimeTarget {
+    Mifare { uid: [u8; 4] },
+    Felica { idm: [u8; 8], pmm: [u8; 8] },
+}
+
+impl AimeTarget {
+    /// Poll response payload: count, then `type, id length, id` per target
+    pub fn parse_all(payload: &[u8]) -> HinataResult<Vec<AimeTarget>> {
+        let (&count, mut rest) = payload.split_first().ok_or(Error::Parse("Empty Aime poll response".into()))?;
+        let mut targets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let [kind, len, tail @ ..] = rest else {
+                return Err(Error::Parse("Truncated Aime poll entry".into()));
+            };
+            let id = tail.get(..*len as usize).ok_or(Error::Parse("Truncated Aime poll id".into()))?;
+            let target = match (kind, id.len()) {
+                (0x10, 4) => AimeTarget::Mifare { uid: id.try_into().unwrap() },
+                (0x20, 16) => AimeTarget::Felica { idm: id[..8].try_into().unwrap(), pmm: id[8..].try_into().unwrap() },
+                _ => return Err(Error::Parse(format!("Unknown Aime target type {kind:02X} with {len} byte id"))),
+            };
+            targets.push(target);
+            rest = &tail[*len as usize..];
+        }
+        Ok(targets)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AimeTarget::Mifare { uid } => [&[0x10, 4][..], uid].concat(),
+            AimeTarget::Felica { idm, pmm } => [&[0x20, 16][..], idm, pmm].concat(),
+        }
+    }
+}
+
+/// Host side of the Aime reader serial protocol, usually over [`crate::device::HinataDevice::open_serial`]
+pub struct AimeClient<S: AsyncRead + AsyncWrite + Unpin> {
+    stream: S,
+    seq: u8,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AimeClient<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream, seq: 0 }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    pub async fn request(&mut self, addr: u8, cmd: AimeCommand, payload: &[u8]) -> HinataResult<Vec<u8>> {
+        self.send(addr, cmd, payload).await?;
+        let body = tokio::time::timeout(RESPONSE_TIMEOUT, read_frame(&mut self.stream))
+            .await
+            .map_err(|_| Error::Timeout("Wait Aime response timeout".into()))??;
+        let response = AimeResponse::from_body(&body)?;
+        if response.cmd != cmd as u8 {
+            return Err(Error::Protocol("Aime command mismatch".into()));
+        }
+        if response.status != 0 {
+            return Err(Error::Protocol(format!("Aime command {:02X} failed with status {:02X}", response.cmd, response.status).into()));
+        }
+        Ok(response.payload)
+    }
+
+    /// For commands the reader does not answer
+    async fn send(&mut self, addr: u8, cmd: AimeCommand, payload: &[u8]) -> HinataResult<()> {
+        let request = AimeRequest { addr, seq: self.seq, cmd: cmd as u8, payload: payload.to_vec() };
+        self.seq = self.seq.wrapping_add(1);
+        self.stream.write_all(&request.to_frame()?).await?;
+        Ok(())
+    }
+
+    pub async fn reset(&mut self) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::Reset, &[]).await.map(|_| ())
+    }
+
+    /// ASCII on older boards, a single version byte on newer ones
+    pub async fn get_fw_version(&mut self) -> HinataResult<Vec<u8>> {
+        self.request(NFC_ADDR, AimeCommand::GetFwVersion, &[]).await
+    }
+
+    pub async fn get_hw_version(&mut self) -> HinataResult<Vec<u8>> {
+        self.request(NFC_ADDR, AimeCommand::GetHwVersion, &[]).await
+    }
+
+    /// `target` selects the card families to power for, `0x03` for MIFARE and FeliCa
+    pub async fn radio_on(&mut self, target: u8) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::RadioOn, &[target]).await.map(|_| ())
+    }
+
+    pub async fn radio_off(&mut self) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::RadioOff, &[]).await.map(|_| ())
+    }
+
+    pub async fn poll(&mut self) -> HinataResult<Vec<AimeTarget>> {
+        let payload = self.request(NFC_ADDR, AimeCommand::Poll, &[]).await?;
+        AimeTarget::parse_all(&payload)
+    }
+
+    pub async fn mifare_select(&mut self, uid: &[u8; 4]) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::MifareSelectTag, uid).await.map(|_| ())
+    }
+
+    pub async fn mifare_set_key_aime(&mut self, key: &[u8; 6]) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::MifareSetKeyAime, key).await.map(|_| ())
+    }
+
+    pub async fn mifare_set_key_bana(&mut self, key: &[u8; 6]) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::MifareSetKeyBana, key).await.map(|_| ())
+    }
+
+    /// Authenticate `block` with the key from [`AimeClient::mifare_set_key_aime`]
+    pub async fn mifare_authenticate_aime(&mut self, uid: &[u8; 4], block: u8) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::AimeAuthenticate, &[&uid[..], &[block]].concat()).await.map(|_| ())
+    }
+
+    /// Authenticate `block` with the key from [`AimeClient::mifare_set_key_bana`]
+    pub async fn mifare_authenticate_bana(&mut self, uid: &[u8; 4], block: u8) -> HinataResult<()> {
+        self.request(NFC_ADDR, AimeCommand::BanaAuthenticate, &[&uid[..], &[block]].concat()).await.map(|_| ())
+    }
+
+    pub async fn mifare_read_block(&mut self, uid: &[u8; 4], block: u8) -> HinataResult<[u8; 16]> {
+        let payload = self.request(NFC_ADDR, AimeCommand::MifareReadBlock, &[&uid[..], &[block]].concat()).await?;
+        payload.try_into().map_err(|_| Error::Protocol("Aime block read is not 16 bytes".into()))
+    }
+
+    /// Pass a raw FeliCa frame (length byte first) through to the card with `idm`
+    pub async fn felica_encap(&mut self, idm: &[u8; 8], frame: &[u8]) -> HinataResult<Vec<u8>> {
+        self.request(NFC_ADDR, AimeCommand::FelicaEncap, &[&idm[..], frame].concat()).await
+    }
+
+    /// The LED board does not answer colour changes
+    pub async fn set_led(&mut self, r: u8, g: u8, b: u8) -> HinataResult<()> {
+        self.send(LED_ADDR, AimeCommand::LedSetColor, &[r, g, b]).await
+    }
+
+    pub async fn get_led_info(&mut self) -> HinataResult<Vec<u8>> {
+        self.request(LED_ADDR, AimeCommand::LedGetInfo, &[]).await
+    }
+
+    pub async fn reset_led(&mut self) -> HinataResult<()> {
+        self.request(LED_ADDR, AimeCommand::LedReset, &[]).await.map(|_| ())
+    }
+}
+
+#[tokio::test]
+async fn aime_frame_test() {
+    let request = AimeRequest { addr: NFC_ADDR, seq: 0xDF, cmd: AimeCommand::MifareReadBlock as u8, payload: vec![0xE0, 0x01, 0x02, 0x03, 0x02] };
+    let frame = request.to_frame().unwrap();
+    assert_eq!(&frame[..5], &[SYNC, 0x0A, 0x00, 0xDF, 0x52]);
+    assert_eq!(&frame[6..8], &[ESCAPE, 0xDF]);
+    assert_eq!(frame.iter().skip(1).filter(|&&b| b == SYNC).count(), 0);
+
+    let mut reader = &[&[0x00, 0x55][..], &frame].concat()[..];
+    let body = read_frame(&mut reader).await.unwrap();
+    assert_eq!(AimeRequest::from_body(&body).unwrap(), request);
+
+    let targets = AimeTarget::parse_all(&[0x01, 0x10, 0x04, 0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+    assert_eq!(targets, vec![AimeTarget::Mifare { uid: [0xDE, 0xAD, 0xBE, 0xEF] }]);
+
+    let request = AimeRequest { payload: vec![0; 250], ..request };
+    assert_eq!(request.to_frame().unwrap()[1], 0xFF);
+    assert!(matches!(AimeRequest { payload: vec![0; 251], ..request }.to_frame(), Err(Error::Protocol(_))));
+    let response = AimeResponse { addr: NFC_ADDR, seq: 0, cmd: AimeCommand::FelicaEncap as u8, status: 0, payload: vec![0; 249] };
+    assert_eq!(response.to_frame().unwrap()[1], 0xFF);
+    assert!(matches!(AimeResponse { payload: vec![0; 250], ..response }.to_frame(), Err(Error::Protocol(_))));
+}
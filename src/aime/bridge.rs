@@ -0,0 +1,167 @@
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use crate::aime::{read_frame, AimeCommand, AimeRequest, AimeResponse, AimeTarget, LED_ADDR};
+use crate::card::aime::{BANDAI_NAMCO_KEY_A, SEGA_KEY_A};
+use crate::card::PassiveTarget;
+use crate::device::HinataDevice;
+use crate::error::{Error, HinataResult};
+use crate::pn532::{FelicaPollRequest, KeyType};
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_ERROR: u8 = 0x01;
+
+/// Board info the LED board of a 837-15396 reports
+const LED_INFO: &[u8] = b"15084\xFF\x10\x00\x12";
+
+/// Tg of the card selected by the last poll
+const TG: u8 = 1;
+
+/// Plays the reader side of the Aime serial protocol on `stream` and serves it from a Hinata device,
+/// so games expecting a serial Aime unit can talk to it through a virtual COM pair or pty
+pub struct AimeBridge<S: AsyncRead + AsyncWrite + Unpin> {
+    stream: S,
+    device: HinataDevice,
+    fw_version: Vec<u8>,
+    hw_version: Vec<u8>,
+    radio_on: bool,
+    key_aime: [u8; 6],
+    key_bana: [u8; 6],
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AimeBridge<S> {
+    pub fn new(stream: S, device: HinataDevice) -> Self {
+        Self {
+            stream,
+            device,
+            fw_version: b"TN32MSEC003S F/W Ver1.2".to_vec(),
+            hw_version: b"TN32MSEC003S H/W Ver3.0".to_vec(),
+            radio_on: false,
+            key_aime: SEGA_KEY_A,
+            key_bana: BANDAI_NAMCO_KEY_A,
+        }
+    }
+
+    /// Some games check the version strings, these default to a 837-15396
+    pub fn with_versions(mut self, fw_version: &[u8], hw_version: &[u8]) -> Self {
+        self.fw_version = fw_version.to_vec();
+        self.hw_version = hw_version.to_vec();
+        self
+    }
+
+    /// Serve requests until the game side closes the stream, then hand the device back
+    pub async fn run(mut self) -> HinataResult<HinataDevice> {
+        loop {
+            let body = match read_frame(&mut self.stream).await {
+                Ok(body) => body,
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(self.device),
+                // Line noise or a half frame, wait for the next sync byte
+                Err(Error::Parse(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let Ok(request) = AimeRequest::from_body(&body) else { continue };
+            if let Some(response) = self.handle(&request).await {
+                // An answer too long for one frame fails like the command did
+                let frame = match response.to_frame() {
+                    Ok(frame) => frame,
+                    Err(_) => AimeResponse { status: STATUS_ERROR, payload: Vec::new(), ..response }.to_frame()?,
+                };
+                self.stream.write_all(&frame).await?;
+            }
+        }
+    }
+
+    /// `None` for requests the real reader leaves unanswered
+    async fn handle(&mut self, request: &AimeRequest) -> Option<AimeResponse> {
+        let payload = &request.payload;
+        let result: HinataResult<Vec<u8>> = match request.cmd {
+            cmd if cmd == AimeCommand::LedSetColor as u8 && request.addr == LED_ADDR => {
+                if let [r, g, b, ..] = payload[..] {
+                    self.device.set_led(r, g, b).await;
+                }
+                return None;
+            }
+            cmd if cmd == AimeCommand::LedReset as u8 => {
+                self.device.reset_led().await;
+                Ok(Vec::new())
+            }
+            cmd if cmd == AimeCommand::LedGetInfo as u8 => Ok(LED_INFO.to_vec()),
+            cmd if cmd == AimeCommand::GetFwVersion as u8 => Ok(self.fw_version.clone()),
+            cmd if cmd == AimeCommand::GetHwVersion as u8 => Ok(self.hw_version.clone()),
+            cmd if cmd == AimeCommand::Reset as u8 || cmd == AimeCommand::RadioOff as u8 => {
+                self.radio_on = false;
+                Ok(Vec::new())
+            }
+            cmd if cmd == AimeCommand::RadioOn as u8 => {
+                self.radio_on = true;
+                Ok(Vec::new())
+            }
+            cmd if cmd == AimeCommand::Poll as u8 => self.poll().await,
+            cmd if cmd == AimeCommand::MifareSelectTag as u8 => Ok(Vec::new()),
+            cmd if cmd == AimeCommand::MifareSetKeyAime as u8 => {
+                copy_key(&mut self.key_aime, payload);
+                Ok(Vec::new())
+            }
+            cmd if cmd == AimeCommand::MifareSetKeyBana as u8 => {
+                copy_key(&mut self.key_bana, payload);
+                Ok(Vec::new())
+            }
+            cmd if cmd == AimeCommand::AimeAuthenticate as u8 => self.authenticate(payload, self.key_aime).await,
+            cmd if cmd == AimeCommand::BanaAuthenticate as u8 => self.authenticate(payload, self.key_bana).await,
+            cmd if cmd == AimeCommand::MifareReadBlock as u8 => match payload.get(4) {
+                Some(&block) => self.device.pn532().mifare_classic_read_block(TG, block).await.map(|data| data.to_vec()),
+                None => Err(Error::Parse("Short Aime read request".into())),
+            },
+            cmd if cmd == AimeCommand::FelicaEncap as u8 => match payload.get(8..) {
+                Some([len, frame @ ..]) => self.device.pn532().in_data_exchange(TG, *len, frame).await,
+                _ => Err(Error::Parse("Short FeliCa encap request".into())),
+            },
+            _ => Err(Error::NotSupport(format!("Aime command {:02X}", request.cmd))),
+        };
+
+        let (status, payload) = match result {
+            Ok(payload) => (STATUS_OK, payload),
+            Err(_) => (STATUS_ERROR, Vec::new()),
+        };
+        Some(AimeResponse { addr: request.addr, seq: request.seq, cmd: request.cmd, status, payload })
+    }
+
+    /// Type A first, then FeliCa, answered in the reader's own target format
+    async fn poll(&mut self) -> HinataResult<Vec<u8>> {
+        if !self.radio_on {
+            return Ok(vec![0]);
+        }
+        let mut pn532 = self.device.pn532();
+        let target = match pn532.in_list_passive_target(0, 1, &[]).await?.into_iter().next() {
+            Some(PassiveTarget::Iso14443a(card)) => card.get_uid().try_into().ok().map(|uid| AimeTarget::Mifare { uid }),
+            _ => pn532.felica_poll(&FelicaPollRequest::new(0xFFFF)).await?.map(|card| AimeTarget::Felica {
+                idm: *card.get_idm(),
+                pmm: *card.get_pmm(),
+            }),
+        };
+        Ok(match target {
+            Some(target) => [&[1][..], &target.to_bytes()].concat(),
+            None => vec![0],
+        })
+    }
+
+    async fn authenticate(&mut self, payload: &[u8], key: [u8; 6]) -> HinataResult<Vec<u8>> {
+        let [uid @ .., block] = payload else {
+            return Err(Error::Parse("Empty Aime authenticate request".into()));
+        };
+        self.device.pn532().mifare_classic_auth(TG, uid, *block, KeyType::A, &key).await?;
+        Ok(Vec::new())
+    }
+}
+
+fn copy_key(key: &mut [u8; 6], payload: &[u8]) {
+    if let Some(new) = payload.get(..6) {
+        key.copy_from_slice(new);
+    }
+}
+
+/// Serve a Hinata device as an Aime reader on the serial port `port` (e.g. one end of a com0com pair)
+#[cfg(feature = "serial")]
+pub async fn serve_serial(port: &str, device: HinataDevice) -> HinataResult<HinataDevice> {
+    let stream = tokio_serial::SerialStream::open(&tokio_serial::new(port, crate::aime::BAUD_RATE))
+        .map_err(|e| Error::Io(e.into()))?;
+    AimeBridge::new(stream, device).run().await
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use crate::aime::{encode_frame, read_frame_with};
+use crate::error::{Error, HinataResult};
+
+pub const HOST_ADDR: u8 = 0x01;
+pub const BOARD_ADDR: u8 = 0x02;
+
+/// Report byte of a response that went through
+const REPORT_OK: u8 = 0x01;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Commands of the 837-15093-06 LED controller
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LedCommand {
+    Reset = 0x10,
+    SetTimeout = 0x11,
+    SetDisableResponse = 0x14,
+    SetLed = 0x82,
+    SetLedCount = 0x86,
+    GetBoardInfo = 0xF0,
+    GetBoardStatus = 0xF1,
+    GetFirmwareSum = 0xF2,
+    GetProtocolVersion = 0xF3,
+}
+
+/// Board to host frame, the body is `dst, src, len, status, cmd, report, data...`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedResponse {
+    pub dst: u8,
+    pub src: u8,
+    pub status: u8,
+    pub cmd: u8,
+    pub report: u8,
+    pub data: Vec<u8>,
+}
+
+impl LedResponse {
+    pub fn from_body(body: &[u8]) -> HinataResult<Self> {
+        let [dst, src, len, status, cmd, report, data @ ..] = body else {
+            return Err(Error::Parse("Short LED board frame".into()));
+        };
+        if *len as usize != data.len() + 3 {
+            return Err(Error::Parse("LED board frame length mismatch".into()));
+        }
+        Ok(Self { dst: *dst, src: *src, status: *status, cmd: *cmd, report: *report, data: data.to_vec() })
+    }
+}
+
+/// `len` sits after the addresses and counts everything past itself
+fn response_len(body: &[u8]) -> Option<usize> {
+    body.get(2).map(|&len| len as usize + 3)
+}
+
+/// Host to board frame, `len` counts the command byte and its data
+pub fn encode_led_request(dst: u8, src: u8, cmd: u8, data: &[u8]) -> Vec<u8> {
+    let mut body = vec![dst, src, data.len() as u8 + 1, cmd];
+    body.extend_from_slice(data);
+    encode_frame(&body)
+}
+
+/// Client for the Sega LED controller protocol on the CDC interface
+pub struct LedBoard<S: AsyncRead + AsyncWrite + Unpin> {
+    stream: S,
+    addr: u8,
+    response_enabled: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> LedBoard<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream, addr: BOARD_ADDR, response_enabled: true }
+    }
+
+    pub fn with_addr(mut self, addr: u8) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    pub async fn request(&mut self, cmd: LedCommand, data: &[u8]) -> HinataResult<Vec<u8>> {
+        self.stream.write_all(&encode_led_request(self.addr, HOST_ADDR, cmd as u8, data)).await?;
+        let body = tokio::time::timeout(RESPONSE_TIMEOUT, read_frame_with(&mut self.stream, response_len))
+            .await
+            .map_err(|_| Error::Timeout("Wait LED board response timeout".into()))??;
+        let response = LedResponse::from_body(&body)?;
+        if response.cmd != cmd as u8 {
+            return Err(Error::Protocol("LED board command mismatch".into()));
+        }
+        if response.report != REPORT_OK {
+            return Err(Error::Protocol(format!("LED board command {:02X} failed with report {:02X}", response.cmd, response.report).into()));
+        }
+        Ok(response.data)
+    }
+
+    pub async fn reset(&mut self) -> HinataResult<()> {
+        self.response_enabled = true;
+        self.request(LedCommand::Reset, &[0xD9]).await.map(|_| ())
+    }
+
+    /// Board part number, chip number and firmware version
+    pub async fn get_board_info(&mut self) -> HinataResult<Vec<u8>> {
+        self.request(LedCommand::GetBoardInfo, &[]).await
+    }
+
+    pub async fn get_protocol_version(&mut self) -> HinataResult<Vec<u8>> {
+        self.request(LedCommand::GetProtocolVersion, &[]).await
+    }
+
+    /// Turn the LEDs off when no frame arrives for `timeout`, `Duration::ZERO` keeps them lit
+    pub async fn set_timeout(&mut self, timeout: Duration) -> HinataResult<()> {
+        let ms = (timeout.as_millis().min(u16::MAX as u128) as u16).to_be_bytes();
+        self.request(LedCommand::SetTimeout, &ms).await.map(|_| ())
+    }
+
+    pub async fn set_led_count(&mut self, count: u8) -> HinataResult<()> {
+        self.request(LedCommand::SetLedCount, &[count]).await.map(|_| ())
+    }
+
+    /// Stop acknowledging colour frames, the way games drive the ring at full rate
+    pub async fn set_disable_response(&mut self, disabled: bool) -> HinataResult<()> {
+        self.request(LedCommand::SetDisableResponse, &[disabled as u8]).await?;
+        self.response_enabled = !disabled;
+        Ok(())
+    }
+
+    /// One RGB triple per LED of the ring
+    pub async fn set_colors(&mut self, colors: &[[u8; 3]]) -> HinataResult<()> {
+        let data = colors.concat();
+        if self.response_enabled {
+            self.request(LedCommand::SetLed, &data).await.map(|_| ())
+        } else {
+            self.stream.write_all(&encode_led_request(self.addr, HOST_ADDR, LedCommand::SetLed as u8, &data)).await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "serial")]
+impl crate::device::HinataDevice {
+    /// The LED controller shares the CDC port with the Aime protocol
+    pub async fn open_led_board(&self) -> HinataResult<LedBoard<tokio_serial::SerialStream>> {
+        Ok(LedBoard::new(self.open_serial(crate::aime::BAUD_RATE).await?))
+    }
+}
+
+#[tokio::test]
+async fn led_frame_test() {
+    let frame = encode_led_request(BOARD_ADDR, HOST_ADDR, LedCommand::SetLedCount as u8, &[0x0A]);
+    assert_eq!(frame, vec![0xE0, 0x02, 0x01, 0x02, 0x86, 0x0A, 0x95]);
+
+    let response = encode_frame(&[0x01, 0x02, 0x03, 0x01, 0x86, 0x01]);
+    let body = read_frame_with(&mut &response[..], response_len).await.unwrap();
+    let response = LedResponse::from_body(&body).unwrap();
+    assert_eq!((response.cmd, response.report, response.data.len()), (0x86, REPORT_OK, 0));
+}
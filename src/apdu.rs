@@ -0,0 +1,200 @@
+use crate::error::{Error, HinataResult};
+
+const SHORT_MAX: usize = 256;
+const EXTENDED_MAX: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Apdu {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: Vec<u8>,
+    /// Expected response length, `None` when no response data is expected
+    pub le: Option<usize>,
+}
+
+impl Apdu {
+    pub fn new(cla: u8, ins: u8, p1: u8, p2: u8) -> Self {
+        Self {
+            cla,
+            ins,
+            p1,
+            p2,
+            data: Vec::new(),
+            le: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: &[u8]) -> Self {
+        self.data = data.to_vec();
+        self
+    }
+
+    pub fn with_le(mut self, le: usize) -> Self {
+        self.le = Some(le);
+        self
+    }
+
+    pub fn get_response(le: u8) -> Self {
+        Self::new(0x00, 0xC0, 0x00, 0x00).with_le(if le == 0 { SHORT_MAX } else { le as usize })
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.data.len() > 255 || self.le.is_some_and(|le| le > SHORT_MAX)
+    }
+
+    pub fn to_bytes(&self) -> HinataResult<Vec<u8>> {
+        if self.data.len() >= EXTENDED_MAX {
+            return Err(Error::Protocol("APDU data too long".into()));
+        }
+        if self.le.is_some_and(|le| le == 0 || le > EXTENDED_MAX) {
+            return Err(Error::Protocol("APDU Le out of range".into()));
+        }
+
+        let mut buffer = vec![self.cla, self.ins, self.p1, self.p2];
+        let extended = self.is_extended();
+
+        if !self.data.is_empty() {
+            if extended {
+                buffer.push(0x00);
+                buffer.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+            } else {
+                buffer.push(self.data.len() as u8);
+            }
+            buffer.extend_from_slice(&self.data);
+        }
+
+        if let Some(le) = self.le {
+            if extended {
+                if self.data.is_empty() {
+                    buffer.push(0x00);
+                }
+                // 65536 is encoded as 0x0000
+                buffer.extend_from_slice(&(le as u16).to_be_bytes());
+            } else {
+                buffer.push(le as u8);
+            }
+        }
+
+        Ok(buffer)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        let header = data.get(..4).ok_or(Error::Parse("APDU shorter than header".into()))?;
+        let mut apdu = Self::new(header[0], header[1], header[2], header[3]);
+        let body = &data[4..];
+
+        let decode_le = |raw: usize, max: usize| if raw == 0 { max } else { raw };
+
+        match *body {
+            [] => {}
+            [le] => apdu.le = Some(decode_le(le as usize, SHORT_MAX)),
+            [lc, ref rest @ ..] if lc != 0 => {
+                let lc = lc as usize;
+                apdu.data = rest.get(..lc).ok_or(Error::Parse("APDU Lc exceeds body".into()))?.to_vec();
+                match &rest[lc..] {
+                    [] => {}
+                    [le] => apdu.le = Some(decode_le(*le as usize, SHORT_MAX)),
+                    _ => return Err(Error::Parse("Trailing bytes after APDU Le".into())),
+                }
+            }
+            // Only the extended forms are left, they lead with 0x00 and take two more bytes for Lc or Le
+            [_, _] => return Err(Error::Parse("Truncated extended APDU length".into())),
+            [_, hi, lo] => apdu.le = Some(decode_le(u16::from_be_bytes([hi, lo]) as usize, EXTENDED_MAX)),
+            [_, hi, lo, ref rest @ ..] => {
+                let lc = u16::from_be_bytes([hi, lo]) as usize;
+                apdu.data = rest.get(..lc).ok_or(Error::Parse("APDU Lc exceeds body".into()))?.to_vec();
+                match &rest[lc..] {
+                    [] => {}
+                    [hi, lo] => apdu.le = Some(decode_le(u16::from_be_bytes([*hi, *lo]) as usize, EXTENDED_MAX)),
+                    _ => return Err(Error::Parse("Trailing bytes after APDU Le".into())),
+                }
+            }
+        }
+
+        Ok(apdu)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApduResponse {
+    data: Vec<u8>,
+    sw1: u8,
+    sw2: u8,
+}
+
+impl ApduResponse {
+    pub fn new(data: Vec<u8>, sw1: u8, sw2: u8) -> Self {
+        Self { data, sw1, sw2 }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        let split = data
+            .len()
+            .checked_sub(2)
+            .ok_or(Error::Protocol("R-APDU shorter than status word".into()))?;
+        Ok(Self::new(data[..split].to_vec(), data[split], data[split + 1]))
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn get_sw1(&self) -> u8 {
+        self.sw1
+    }
+
+    pub fn get_sw2(&self) -> u8 {
+        self.sw2
+    }
+
+    pub fn get_sw(&self) -> u16 {
+        u16::from_be_bytes([self.sw1, self.sw2])
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.get_sw() == 0x9000
+    }
+
+    /// Number of bytes still available through GET RESPONSE (`61xx`)
+    pub fn bytes_remaining(&self) -> Option<usize> {
+        (self.sw1 == 0x61).then_some(if self.sw2 == 0 { SHORT_MAX } else { self.sw2 as usize })
+    }
+
+    /// Exact length the card asks the command to be repeated with (`6Cxx`)
+    pub fn wrong_length(&self) -> Option<usize> {
+        (self.sw1 == 0x6C).then_some(if self.sw2 == 0 { SHORT_MAX } else { self.sw2 as usize })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = self.data.clone();
+        buffer.push(self.sw1);
+        buffer.push(self.sw2);
+        buffer
+    }
+}
+
+#[test]
+fn apdu_encoding_test() {
+    let select = Apdu::new(0x00, 0xA4, 0x04, 0x00)
+        .with_data(&[0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01])
+        .with_le(256);
+    let bytes = select.to_bytes().unwrap();
+    assert_eq!(bytes, vec![0x00, 0xA4, 0x04, 0x00, 0x07, 0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01, 0x00]);
+    assert_eq!(Apdu::from_bytes(&bytes).unwrap(), select);
+
+    let read = Apdu::new(0x00, 0xB0, 0x00, 0x00).with_le(0x1000);
+    let bytes = read.to_bytes().unwrap();
+    assert_eq!(bytes, vec![0x00, 0xB0, 0x00, 0x00, 0x00, 0x10, 0x00]);
+    assert_eq!(Apdu::from_bytes(&bytes).unwrap(), read);
+    assert!(matches!(Apdu::from_bytes(&[0x00, 0xB0, 0x00, 0x00, 0x00, 0x01]), Err(Error::Parse(_))));
+
+    let response = ApduResponse::from_bytes(&[0x01, 0x02, 0x61, 0x10]).unwrap();
+    assert_eq!(response.get_data(), &[0x01, 0x02]);
+    assert_eq!(response.bytes_remaining(), Some(0x10));
+}
@@ -0,0 +1,95 @@
+pub mod capture;
+#[cfg(all(feature = "nusb", not(target_os = "windows")))]
+pub mod nusb;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+
+use std::ffi::{CStr, CString};
+use hidapi::{HidApi, HidDevice};
+use crate::error::{Error, HinataResult};
+
+/// One HID interface as reported by [`HidBackend::enumerate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HidDeviceInfo {
+    /// Platform path, handed back to [`HidBackend::open`]
+    pub path: CString,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub usage_page: u16,
+    pub product_string: Option<String>,
+}
+
+/// An opened HID interface, reports include the report id as first byte
+pub trait HidTransport: Send {
+    /// Read one input report, `Ok(0)` when nothing arrived within `timeout_ms`
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize>;
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize>;
+
+    /// Read the feature report whose id is in `buf[0]`, returns its length including the id
+    fn get_feature_report(&self, _buf: &mut [u8]) -> HinataResult<usize> {
+        Err(Error::NotSupport("Feature reports are not supported by this backend".into()))
+    }
+
+    /// Send a feature report, `data` starts with the report id
+    fn send_feature_report(&self, _data: &[u8]) -> HinataResult<()> {
+        Err(Error::NotSupport("Feature reports are not supported by this backend".into()))
+    }
+}
+
+/// Source of HID interfaces, swap it out to run on another USB stack or against a mock
+pub trait HidBackend: Send + Sync + std::fmt::Debug {
+    /// Every interface of `vendor_id`
+    fn enumerate(&self, vendor_id: u16) -> HinataResult<Vec<HidDeviceInfo>>;
+
+    fn open(&self, path: &CStr) -> HinataResult<Box<dyn HidTransport>>;
+
+    /// Whether the interfaces belong to a USB device with a CDC serial port to look up on Windows
+    fn has_com_port(&self) -> bool {
+        true
+    }
+}
+
+/// The default backend on top of the hidapi C library
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HidApiBackend;
+
+impl HidBackend for HidApiBackend {
+    fn enumerate(&self, vendor_id: u16) -> HinataResult<Vec<HidDeviceInfo>> {
+        let mut hid = HidApi::new()?;
+        hid.add_devices(vendor_id, 0)?;
+        Ok(hid
+            .device_list()
+            .filter(|device| device.vendor_id() == vendor_id)
+            .map(|device| HidDeviceInfo {
+                path: device.path().to_owned(),
+                vendor_id: device.vendor_id(),
+                product_id: device.product_id(),
+                usage_page: device.usage_page(),
+                product_string: device.product_string().map(|s| s.to_string()),
+            })
+            .collect())
+    }
+
+    fn open(&self, path: &CStr) -> HinataResult<Box<dyn HidTransport>> {
+        Ok(Box::new(HidApi::new()?.open_path(path)?))
+    }
+}
+
+impl HidTransport for HidDevice {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        Ok(HidDevice::read_timeout(self, buf, timeout_ms)?)
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        Ok(HidDevice::write(self, data)?)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HinataResult<usize> {
+        Ok(HidDevice::get_feature_report(self, buf)?)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HinataResult<()> {
+        Ok(HidDevice::send_feature_report(self, data)?)
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::backend::{HidBackend, HidDeviceInfo, HidTransport};
+use crate::error::{Error, HinataResult};
+use crate::utils::id_format::IdFormat;
+
+const HEADER: &str = "# hinata capture v1";
+const REPLAY_WRITE_WAIT: Duration = Duration::from_millis(200);
+
+/// One line of a capture file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureEntry {
+    /// An interface returned by enumeration, replayed so the same devices show up again
+    Device(HidDeviceInfo),
+    /// Report written to the device, `elapsed` counts from the start of the capture
+    Out { elapsed: Duration, data: Vec<u8> },
+    /// Report read from the device
+    In { elapsed: Duration, data: Vec<u8> },
+}
+
+impl CaptureEntry {
+    /// Tab separated: `D vid pid usage_page path product`, `<elapsed_us> > hex` or `<elapsed_us> < hex`
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::Device(info) => format!(
+                "D\t{:04X}\t{:04X}\t{:04X}\t{}\t{}",
+                info.vendor_id,
+                info.product_id,
+                info.usage_page,
+                info.path.to_string_lossy(),
+                info.product_string.as_deref().unwrap_or_default()
+            ),
+            Self::Out { elapsed, data } => format!("{}\t>\t{}", elapsed.as_micros(), IdFormat::new(data).hex()),
+            Self::In { elapsed, data } => format!("{}\t<\t{}", elapsed.as_micros(), IdFormat::new(data).hex()),
+        }
+    }
+
+    /// `None` for blank and comment lines
+    pub fn parse(line: &str) -> HinataResult<Option<Self>> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let hex16 = |s: &str| u16::from_str_radix(s, 16).map_err(|_| Error::Parse(format!("Bad capture line: {line}")));
+        let entry = match fields[..] {
+            ["D", vid, pid, usage_page, path, product] => Self::Device(HidDeviceInfo {
+                path: CString::new(path).map_err(|_| Error::Parse(format!("Bad capture line: {line}")))?,
+                vendor_id: hex16(vid)?,
+                product_id: hex16(pid)?,
+                usage_page: hex16(usage_page)?,
+                product_string: (!product.is_empty()).then(|| product.to_string()),
+            }),
+            [elapsed, direction @ (">" | "<"), hex] => {
+                let elapsed = Duration::from_micros(elapsed.parse()?);
+                let data = parse_hex(hex).ok_or(Error::Parse(format!("Bad capture line: {line}")))?;
+                if direction == ">" {
+                    Self::Out { elapsed, data }
+                } else {
+                    Self::In { elapsed, data }
+                }
+            }
+            _ => return Err(Error::Parse(format!("Bad capture line: {line}"))),
+        };
+        Ok(Some(entry))
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Read every entry of a capture file
+pub fn read_capture(path: impl AsRef<Path>) -> HinataResult<Vec<CaptureEntry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Some(entry) = CaptureEntry::parse(&line?)? {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Link type of exported reports, map it to a dissector through Wireshark's DLT_User table (USER0)
+pub const PCAPNG_LINKTYPE: u16 = 147;
+const PCAPNG_SNAPLEN: u32 = 0xFFFF;
+const EPB_FLAG_INBOUND: u32 = 0b01;
+const EPB_FLAG_OUTBOUND: u32 = 0b10;
+
+/// Streams reports into a pcapng file with one interface, inbound/outbound set from the report direction
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Write the section and interface headers
+    pub fn new(mut writer: W) -> HinataResult<Self> {
+        // Section Header Block, length -1 means unspecified
+        let mut shb = Vec::with_capacity(16);
+        shb.extend_from_slice(&0x1A2B3C4Du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, 0x0A0D0D0A, &shb)?;
+
+        // Interface Description Block, default microsecond resolution
+        let mut idb = Vec::with_capacity(8);
+        idb.extend_from_slice(&PCAPNG_LINKTYPE.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&PCAPNG_SNAPLEN.to_le_bytes());
+        write_block(&mut writer, 0x00000001, &idb)?;
+        Ok(Self { writer })
+    }
+
+    /// `timestamp` is the time since the Unix epoch
+    pub fn write_packet(&mut self, timestamp: Duration, inbound: bool, data: &[u8]) -> HinataResult<()> {
+        let micros = timestamp.as_micros() as u64;
+        let mut epb = Vec::with_capacity(data.len() + 36);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(data);
+        epb.resize(epb.len().next_multiple_of(4), 0);
+        // epb_flags then opt_endofopt
+        epb.extend_from_slice(&2u16.to_le_bytes());
+        epb.extend_from_slice(&4u16.to_le_bytes());
+        epb.extend_from_slice(&(if inbound { EPB_FLAG_INBOUND } else { EPB_FLAG_OUTBOUND }).to_le_bytes());
+        epb.extend_from_slice(&[0; 4]);
+        write_block(&mut self.writer, 0x00000006, &epb)
+    }
+
+    pub fn flush(&mut self) -> HinataResult<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> HinataResult<()> {
+    let len = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_le_bytes())?;
+    Ok(())
+}
+
+/// Convert a text capture to pcapng, capture times are relative so `start` anchors them
+pub fn export_pcapng(entries: &[CaptureEntry], start: SystemTime, writer: impl Write) -> HinataResult<()> {
+    let start = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut pcapng = PcapngWriter::new(writer)?;
+    for entry in entries {
+        match entry {
+            CaptureEntry::Device(_) => {}
+            CaptureEntry::Out { elapsed, data } => pcapng.write_packet(start + *elapsed, false, data)?,
+            CaptureEntry::In { elapsed, data } => pcapng.write_packet(start + *elapsed, true, data)?,
+        }
+    }
+    pcapng.flush()
+}
+
+enum CaptureSink {
+    Text(Box<dyn Write + Send>),
+    /// Enumerated devices have no place in pcapng and are left out
+    Pcapng(PcapngWriter<Box<dyn Write + Send>>, Duration),
+}
+
+struct CaptureLog {
+    start: Instant,
+    sink: Mutex<CaptureSink>,
+}
+
+impl CaptureLog {
+    fn log(&self, entry: CaptureEntry) {
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        // A capture is a debugging aid, losing a line must not break the device
+        let _ = match (&mut *sink, &entry) {
+            (CaptureSink::Text(writer), _) => writeln!(writer, "{}", entry.to_line()).and_then(|_| writer.flush()).map_err(Error::from),
+            (CaptureSink::Pcapng(..), CaptureEntry::Device(_)) => Ok(()),
+            (CaptureSink::Pcapng(pcapng, start), CaptureEntry::Out { elapsed, data }) => pcapng.write_packet(*start + *elapsed, false, data).and_then(|_| pcapng.flush()),
+            (CaptureSink::Pcapng(pcapng, start), CaptureEntry::In { elapsed, data }) => pcapng.write_packet(*start + *elapsed, true, data).and_then(|_| pcapng.flush()),
+        };
+    }
+}
+
+/// Wraps another backend and writes every enumerated interface and exchanged report to a capture file
+pub struct RecordingBackend {
+    inner: Arc<dyn HidBackend>,
+    log: Arc<CaptureLog>,
+}
+
+impl std::fmt::Debug for RecordingBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordingBackend").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Arc<dyn HidBackend>, path: impl AsRef<Path>) -> HinataResult<Self> {
+        Ok(Self::with_writer(inner, BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn with_writer(inner: Arc<dyn HidBackend>, mut writer: impl Write + Send + 'static) -> Self {
+        let _ = writeln!(writer, "{HEADER}");
+        Self::with_sink(inner, CaptureSink::Text(Box::new(writer)))
+    }
+
+    /// Record straight to pcapng for Wireshark, such a capture cannot be replayed
+    pub fn new_pcapng(inner: Arc<dyn HidBackend>, path: impl AsRef<Path>) -> HinataResult<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(Self::with_sink(inner, CaptureSink::Pcapng(PcapngWriter::new(writer)?, start)))
+    }
+
+    fn with_sink(inner: Arc<dyn HidBackend>, sink: CaptureSink) -> Self {
+        Self {
+            inner,
+            log: Arc::new(CaptureLog {
+                start: Instant::now(),
+                sink: Mutex::new(sink),
+            }),
+        }
+    }
+}
+
+impl HidBackend for RecordingBackend {
+    fn enumerate(&self, vendor_id: u16) -> HinataResult<Vec<HidDeviceInfo>> {
+        let devices = self.inner.enumerate(vendor_id)?;
+        devices.iter().for_each(|device| self.log.log(CaptureEntry::Device(device.clone())));
+        Ok(devices)
+    }
+
+    fn open(&self, path: &CStr) -> HinataResult<Box<dyn HidTransport>> {
+        Ok(Box::new(RecordingTransport {
+            inner: self.inner.open(path)?,
+            log: self.log.clone(),
+        }))
+    }
+}
+
+struct RecordingTransport {
+    inner: Box<dyn HidTransport>,
+    log: Arc<CaptureLog>,
+}
+
+impl HidTransport for RecordingTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        let len = self.inner.read_timeout(buf, timeout_ms)?;
+        if len > 0 {
+            self.log.log(CaptureEntry::In { elapsed: self.log.start.elapsed(), data: buf[..len].to_vec() });
+        }
+        Ok(len)
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        self.log.log(CaptureEntry::Out { elapsed: self.log.start.elapsed(), data: data.to_vec() });
+        self.inner.write(data)
+    }
+
+    /// Not part of the capture, replays answer feature reports as unsupported
+    fn get_feature_report(&self, buf: &mut [u8]) -> HinataResult<usize> {
+        self.inner.get_feature_report(buf)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HinataResult<()> {
+        self.inner.send_feature_report(data)
+    }
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    /// Recorded traffic not yet replayed, reads wait until every write before them happened
+    traffic: VecDeque<CaptureEntry>,
+}
+
+/// Plays a capture back: enumeration returns the recorded interfaces, writes must match the recording
+/// in order and reads return what the device answered at that point.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    devices: Vec<HidDeviceInfo>,
+    state: Arc<(Mutex<ReplayState>, Condvar)>,
+}
+
+impl ReplayBackend {
+    pub fn open(path: impl AsRef<Path>) -> HinataResult<Self> {
+        Ok(Self::new(read_capture(path)?))
+    }
+
+    pub fn new(entries: Vec<CaptureEntry>) -> Self {
+        let (devices, traffic): (Vec<_>, Vec<_>) = entries.into_iter().partition(|entry| matches!(entry, CaptureEntry::Device(_)));
+        let mut devices: Vec<HidDeviceInfo> = devices.into_iter()
+            .filter_map(|entry| match entry {
+                CaptureEntry::Device(info) => Some(info),
+                _ => None,
+            })
+            .collect();
+        // Every enumeration logs the devices again
+        let mut seen = Vec::new();
+        devices.retain(|info| {
+            let key = (info.path.clone(), info.usage_page);
+            let new = !seen.contains(&key);
+            seen.push(key);
+            new
+        });
+        Self {
+            devices,
+            state: Arc::new((Mutex::new(ReplayState { traffic: traffic.into() }), Condvar::new())),
+        }
+    }
+
+    /// Whether all recorded traffic has been played
+    pub fn is_done(&self) -> bool {
+        self.state.0.lock().unwrap_or_else(|e| e.into_inner()).traffic.is_empty()
+    }
+}
+
+impl HidBackend for ReplayBackend {
+    fn enumerate(&self, vendor_id: u16) -> HinataResult<Vec<HidDeviceInfo>> {
+        Ok(self.devices.iter().filter(|device| device.vendor_id == vendor_id).cloned().collect())
+    }
+
+    fn open(&self, path: &CStr) -> HinataResult<Box<dyn HidTransport>> {
+        if !self.devices.iter().any(|device| device.path.as_c_str() == path) {
+            return Err(Error::NotFound(format!("{} is not in the capture", path.to_string_lossy())));
+        }
+        Ok(Box::new(ReplayTransport { state: self.state.clone() }))
+    }
+}
+
+struct ReplayTransport {
+    state: Arc<(Mutex<ReplayState>, Condvar)>,
+}
+
+impl HidTransport for ReplayTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        let (state, written) = &*self.state;
+        let state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+        let (mut state, _) = written
+            .wait_timeout_while(state, timeout, |state| !matches!(state.traffic.front(), Some(CaptureEntry::In { .. })))
+            .unwrap_or_else(|e| e.into_inner());
+        match state.traffic.front() {
+            Some(CaptureEntry::In { data, .. }) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                state.traffic.pop_front();
+                written.notify_all();
+                Ok(len)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        let (state, written) = &*self.state;
+        let state = state.lock().unwrap_or_else(|e| e.into_inner());
+        // The reader thread may still be catching up on reports recorded before this write
+        let (mut state, _) = written
+            .wait_timeout_while(state, REPLAY_WRITE_WAIT, |state| matches!(state.traffic.front(), Some(CaptureEntry::In { .. })))
+            .unwrap_or_else(|e| e.into_inner());
+        match state.traffic.front() {
+            Some(CaptureEntry::Out { data: expected, .. }) if expected == data => {
+                state.traffic.pop_front();
+                written.notify_all();
+                Ok(data.len())
+            }
+            Some(CaptureEntry::Out { data: expected, .. }) => Err(Error::Protocol(format!(
+                "Replay diverged, recorded {:02X?} but got {data:02X?}", expected
+            ).into())),
+            _ => Err(Error::Protocol(format!("Replay expected a read, got write {data:02X?}").into())),
+        }
+    }
+}
+
+#[test]
+fn capture_test() {
+    let device = HidDeviceInfo {
+        path: CString::new("/dev/hidraw0").unwrap(),
+        vendor_id: 0xF822,
+        product_id: 0x0001,
+        usage_page: 0x06,
+        product_string: Some("HINATA Lite".into()),
+    };
+    let entries = vec![
+        CaptureEntry::Device(device.clone()),
+        CaptureEntry::Out { elapsed: Duration::from_micros(10), data: vec![1, 0xE6] },
+        CaptureEntry::In { elapsed: Duration::from_micros(900), data: vec![1, 0xE6, 0xAA] },
+    ];
+    for entry in &entries {
+        assert_eq!(CaptureEntry::parse(&entry.to_line()).unwrap().as_ref(), Some(entry));
+    }
+
+    let replay = ReplayBackend::new(entries);
+    assert_eq!(replay.enumerate(0xF822).unwrap(), vec![device.clone()]);
+    let transport = replay.open(&device.path).unwrap();
+    let mut buf = [0; 64];
+    assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 0);
+    assert!(transport.write(&[1, 0xE5]).is_err());
+    assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 0);
+    transport.write(&[1, 0xE6]).unwrap();
+    assert_eq!(transport.read_timeout(&mut buf, 0).unwrap(), 3);
+    assert_eq!(buf[2], 0xAA);
+    assert!(replay.is_done());
+}
+
+#[test]
+fn pcapng_test() {
+    let entries = [
+        CaptureEntry::Out { elapsed: Duration::from_micros(10), data: vec![1, 0xE6] },
+        CaptureEntry::In { elapsed: Duration::from_micros(900), data: vec![1, 0xE6, 0xAA, 0xBB, 0xCC] },
+    ];
+    let mut out = Vec::new();
+    export_pcapng(&entries, UNIX_EPOCH, &mut out).unwrap();
+
+    let u32_at = |i: usize| u32::from_le_bytes(out[i..i + 4].try_into().unwrap());
+    // SHB 28 + IDB 20 + EPB (32 + 4 padded data + 12 options) * 2, the second has 8 bytes of data
+    assert_eq!(out.len(), 28 + 20 + 48 + 52);
+    assert_eq!((u32_at(0), u32_at(4), u32_at(8)), (0x0A0D0D0A, 28, 0x1A2B3C4D));
+    assert_eq!((u32_at(28), u32_at(32)), (1, 20));
+    assert_eq!((u32_at(48), u32_at(52), u32_at(64)), (6, 48, 10));
+    assert_eq!(u32_at(48 + 48 - 4), 48);
+    // Flags option of the inbound packet
+    assert_eq!(u32_at(96 + 52 - 12), EPB_FLAG_INBOUND);
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use ::nusb::transfer::{Direction, EndpointType, Queue, RequestBuffer};
+use tokio::runtime::Runtime;
+use crate::backend::{HidBackend, HidDeviceInfo, HidTransport};
+use crate::error::{Error, HinataResult};
+
+const HID_CLASS: u8 = 0x03;
+const REPORT_SIZE: usize = 64;
+/// Reads kept in flight so reports arriving back to back are not dropped between calls
+const READ_QUEUE_DEPTH: usize = 4;
+/// Usage pages the builder pairs into one device, both collections live on the same interface
+const USAGE_PAGES: [u16; 2] = [0x01, 0x06];
+
+/// Pure Rust backend talking to the interrupt endpoints directly through nusb.
+///
+/// Needs the HID interface to be claimable: on Linux the kernel driver is detached while
+/// the device is open, Windows keeps HID interfaces on its own driver and is not supported.
+#[derive(Debug, Default)]
+pub struct NusbBackend {
+    /// The read and write side open the same interface, which may only be claimed once
+    claimed: Mutex<HashMap<CString, Weak<ClaimedInterface>>>,
+}
+
+struct ClaimedInterface {
+    interface: ::nusb::Interface,
+    endpoint_in: u8,
+    endpoint_out: u8,
+}
+
+impl NusbBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn path(device: &::nusb::DeviceInfo, interface: u8) -> CString {
+        let path = format!("usb-{}-{}:{}", device.bus_number(), device.device_address(), interface);
+        CString::new(path).unwrap_or_default()
+    }
+
+    fn claim(&self, path: &CStr) -> HinataResult<Arc<ClaimedInterface>> {
+        let mut claimed = self.claimed.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(interface) = claimed.get(path).and_then(Weak::upgrade) {
+            return Ok(interface);
+        }
+
+        let (device, number) = ::nusb::list_devices()?
+            .flat_map(|device| {
+                device.interfaces()
+                    .filter(|interface| interface.class() == HID_CLASS)
+                    .map(|interface| interface.interface_number())
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .map(move |number| (device.clone(), number))
+            })
+            .find(|(device, number)| Self::path(device, *number).as_c_str() == path)
+            .ok_or(Error::NotFound(format!("{} is gone", path.to_string_lossy())))?;
+
+        let device = device.open()?;
+        #[cfg(target_os = "linux")]
+        let interface = device.detach_and_claim_interface(number)?;
+        #[cfg(not(target_os = "linux"))]
+        let interface = device.claim_interface(number)?;
+
+        let endpoint = |direction: Direction| {
+            interface.descriptors()
+                .flat_map(|alt| alt.endpoints().collect::<Vec<_>>())
+                .find(|ep| ep.transfer_type() == EndpointType::Interrupt && ep.direction() == direction)
+                .map(|ep| ep.address())
+                .ok_or(Error::NotSupport(format!("Interface has no interrupt {direction:?} endpoint")))
+        };
+        let endpoint_in = endpoint(Direction::In)?;
+        let endpoint_out = endpoint(Direction::Out)?;
+
+        let interface = Arc::new(ClaimedInterface { interface, endpoint_in, endpoint_out });
+        claimed.retain(|_, interface| interface.strong_count() > 0);
+        claimed.insert(path.to_owned(), Arc::downgrade(&interface));
+        Ok(interface)
+    }
+}
+
+impl HidBackend for NusbBackend {
+    fn enumerate(&self, vendor_id: u16) -> HinataResult<Vec<HidDeviceInfo>> {
+        let mut infos = Vec::new();
+        for device in ::nusb::list_devices()?.filter(|device| device.vendor_id() == vendor_id) {
+            for interface in device.interfaces().filter(|interface| interface.class() == HID_CLASS) {
+                // Usage pages live in the report descriptor, which needs the device opened. Report
+                // every HID interface under both pages instead, the builder pairs them by path.
+                for usage_page in USAGE_PAGES {
+                    infos.push(HidDeviceInfo {
+                        path: Self::path(&device, interface.interface_number()),
+                        vendor_id,
+                        product_id: device.product_id(),
+                        usage_page,
+                        product_string: device.product_string().map(|s| s.to_string()),
+                    });
+                }
+            }
+        }
+        Ok(infos)
+    }
+
+    fn open(&self, path: &CStr) -> HinataResult<Box<dyn HidTransport>> {
+        Ok(Box::new(NusbTransport {
+            interface: self.claim(path)?,
+            reader: Mutex::new(None),
+            runtime: runtime()?,
+        }))
+    }
+}
+
+struct NusbTransport {
+    interface: Arc<ClaimedInterface>,
+    /// Created on the first read, only the read side ever owns the IN queue
+    reader: Mutex<Option<Queue<RequestBuffer>>>,
+    /// Drives the nusb transfer futures with a timeout
+    runtime: &'static Runtime,
+}
+
+/// Shared by every transport and never dropped, since dropping a runtime panics inside another one,
+/// as a transport dropped from async code would. Its worker thread drives the timers, so the io
+/// threads can block on it wherever the caller's runtime runs.
+fn runtime() -> HinataResult<&'static Runtime> {
+    static RUNTIME: OnceLock<std::io::Result<Runtime>> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("hinata-nusb")
+                .enable_time()
+                .build()
+        })
+        .as_ref()
+        .map_err(|e| Error::Other(format!("Failed to start the nusb runtime: {e}")))
+}
+
+impl HidTransport for NusbTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        let mut reader = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = reader.get_or_insert_with(|| self.interface.interface.interrupt_in_queue(self.interface.endpoint_in));
+        while queue.pending() < READ_QUEUE_DEPTH {
+            queue.submit(RequestBuffer::new(REPORT_SIZE));
+        }
+
+        let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+        let Ok(completion) = self.runtime.block_on(async { tokio::time::timeout(timeout, queue.next_complete()).await }) else {
+            return Ok(0);
+        };
+        completion.status.map_err(|e| Error::Disconnected(e.to_string()))?;
+        let len = completion.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&completion.data[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        let transfer = self.interface.interface.interrupt_out(self.interface.endpoint_out, data.to_vec());
+        let completion = self.runtime.block_on(transfer);
+        completion.status.map_err(|e| Error::Disconnected(e.to_string()))?;
+        Ok(completion.data.actual_length())
+    }
+}
+
+#[tokio::test]
+async fn runtime_test() {
+    // Transfers block on the shared runtime from io threads started inside the caller's runtime
+    let io_thread = std::thread::spawn(|| {
+        runtime().unwrap().block_on(async { tokio::time::timeout(Duration::from_millis(10), std::future::pending::<()>()).await })
+    });
+    assert!(io_thread.join().unwrap().is_err());
+    assert!(std::ptr::eq(runtime().unwrap(), runtime().unwrap()));
+}
@@ -0,0 +1,703 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use crate::backend::{HidBackend, HidDeviceInfo, HidTransport};
+use crate::builder::HINATA_VID;
+use crate::card::felica::system_code_matches;
+use crate::card::virtual_::VirtualCard;
+use crate::card::PassiveTarget;
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532Command, Pn532Direction, Pn532Packet};
+use crate::ChipId;
+
+const REPORT_ID: u8 = 1;
+const PN532_ACK: [u8; 6] = [0, 0, 0xFF, 0, 0xFF, 0];
+/// What the PN532 answers a frame it does not understand with
+const PN532_SYNTAX_ERROR: [u8; 8] = [0, 0, 0xFF, 0x01, 0xFF, 0x7F, 0x81, 0];
+const USAGE_PAGES: [u16; 2] = [0x01, 0x06];
+const STATUS_OK: u8 = 0x00;
+const STATUS_TIMEOUT: u8 = 0x01;
+const STATUS_CONTEXT: u8 = 0x27;
+/// Status flag of an InDataExchange answer with more card data to come
+const STATUS_MI: u8 = 0x40;
+/// Card data per InDataExchange answer, longer answers are chained so a frame fits one HID report
+const EXCHANGE_CHUNK: usize = 48;
+
+/// A reader that exists only in memory: firmware commands, the LED and a PN532 with the cards placed on it.
+///
+/// Cheap to clone, every clone drives the same device, so keep one around to move cards while the
+/// [`SimulatorBackend`] it was added to is owned by a builder.
+#[derive(Clone)]
+pub struct VirtualHinata {
+    inner: Arc<Simulated>,
+}
+
+struct Simulated {
+    state: Mutex<SimState>,
+    reports: Condvar,
+}
+
+struct SimState {
+    product_id: u16,
+    product_string: String,
+    chip_id: ChipId,
+    commit_hash: [u8; 4],
+    firmware_timestamp: u32,
+    reports: VecDeque<Vec<u8>>,
+    led: Option<(u8, u8, u8)>,
+    field: Vec<VirtualCard>,
+    /// The target listed as Tg 1, until released or polled again
+    selected: Option<PassiveTarget>,
+    /// Rest of a chained InDataExchange answer, handed out while the host keeps asking
+    chained: Vec<u8>,
+    rf_on: bool,
+    disconnected: bool,
+    /// USB vendor and product id of the bootloader, which only enumerates once the firmware rebooted into it
+    bootloader: Option<(u16, u16)>,
+    in_bootloader: bool,
+    /// Last feature report sent per report id, read back as is
+    feature_reports: HashMap<u8, Vec<u8>>,
+}
+
+impl Default for VirtualHinata {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualHinata {
+    pub fn new() -> Self {
+        let state = SimState {
+            product_id: 0x0001,
+            product_string: "HINATA Simulator".into(),
+            chip_id: [0; 4],
+            commit_hash: [0; 4],
+            firmware_timestamp: 2025051301,
+            reports: VecDeque::new(),
+            led: None,
+            field: Vec::new(),
+            selected: None,
+            chained: Vec::new(),
+            rf_on: false,
+            disconnected: false,
+            bootloader: None,
+            in_bootloader: false,
+            feature_reports: HashMap::new(),
+        };
+        Self {
+            inner: Arc::new(Simulated {
+                state: Mutex::new(state),
+                reports: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn with_product(self, product_id: u16, product_string: &str) -> Self {
+        let mut state = self.state();
+        state.product_id = product_id;
+        state.product_string = product_string.to_string();
+        drop(state);
+        self
+    }
+
+    pub fn with_chip_id(self, chip_id: ChipId) -> Self {
+        self.state().chip_id = chip_id;
+        self
+    }
+
+    pub fn with_commit_hash(self, commit_hash: [u8; 4]) -> Self {
+        self.state().commit_hash = commit_hash;
+        self
+    }
+
+    /// `YYYYMMDDNN` like the real firmware, older than `2025051301` hides the chip id
+    pub fn with_firmware_timestamp(self, firmware_timestamp: u32) -> Self {
+        self.state().firmware_timestamp = firmware_timestamp;
+        self
+    }
+
+    /// Enumerate a bootloader interface with these USB ids after the enter bootloader command
+    pub fn with_bootloader(self, vendor_id: u16, product_id: u16) -> Self {
+        self.state().bootloader = Some((vendor_id, product_id));
+        self
+    }
+
+    /// Put a card on the reader, it answers the next poll
+    pub fn place_card(&self, card: impl Into<VirtualCard>) {
+        self.state().field.push(card.into());
+    }
+
+    /// The cards on the reader as the host left them
+    pub fn get_cards(&self) -> Vec<VirtualCard> {
+        self.state().field.clone()
+    }
+
+    /// Take every card off the reader
+    pub fn remove_cards(&self) {
+        self.state().field.clear();
+    }
+
+    /// Color set by the host, `None` while the firmware drives the LED
+    pub fn get_led(&self) -> Option<(u8, u8, u8)> {
+        self.state().led
+    }
+
+    /// Whether the host switched the RF field on
+    pub fn is_rf_on(&self) -> bool {
+        self.state().rf_on
+    }
+
+    /// Unplug the device, every open transport fails from now on
+    pub fn disconnect(&self) {
+        self.state().disconnected = true;
+        self.inner.reports.notify_all();
+    }
+
+    /// Plug the device back in, e.g. once a simulated firmware update finished.
+    /// Reports the host never read are gone.
+    pub fn reconnect(&self) {
+        let mut state = self.state();
+        state.disconnected = false;
+        state.in_bootloader = false;
+        state.reports.clear();
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.state().disconnected
+    }
+
+    pub fn is_in_bootloader(&self) -> bool {
+        self.state().in_bootloader
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn handle_report(&self, data: &[u8]) {
+        let [_, cmd, payload @ ..] = data else {
+            return;
+        };
+        let mut state = self.state();
+        match cmd {
+            0x01 => {
+                let timestamp = state.firmware_timestamp.to_string();
+                state.push_report(0, timestamp.as_bytes())
+            }
+            0xE5 => {
+                let commit_hash = state.commit_hash;
+                state.push_report(0xE5, &commit_hash)
+            }
+            0xE6 => {
+                let chip_id = state.chip_id;
+                state.push_report(0xE6, &chip_id)
+            }
+            0x07 => {
+                if let [r, g, b, ..] = *payload {
+                    state.led = Some((r, g, b));
+                }
+            }
+            0xEA => state.led = None,
+            // Rebooting into the bootloader drops the HID interfaces
+            0xF0 => {
+                state.disconnected = true;
+                state.in_bootloader = true;
+            }
+            0xE2 => {
+                let Ok(packet) = Pn532Packet::from_bytes(payload) else {
+                    return;
+                };
+                if packet.direction != Pn532Direction::HostToPn532 {
+                    return;
+                }
+                state.push_report(0xE2, &PN532_ACK);
+                match state.pn532(packet.command, &packet.payload) {
+                    Some(response) => {
+                        let mut frame = Vec::with_capacity(response.len() + 9);
+                        Pn532Packet::new(Pn532Direction::Pn532ToHost, packet.command, response).write_to(&mut frame);
+                        state.push_report(0xE2, &frame);
+                    }
+                    None => state.push_report(0xE2, &PN532_SYNTAX_ERROR),
+                }
+            }
+            _ => {}
+        }
+        self.inner.reports.notify_all();
+    }
+}
+
+impl SimState {
+    /// Reports lead with the report id and the command they answer, except the timestamp which is raw text
+    fn push_report(&mut self, cmd: u8, data: &[u8]) {
+        let mut report = vec![REPORT_ID];
+        if cmd != 0 {
+            report.push(cmd);
+        }
+        report.extend_from_slice(data);
+        self.reports.push_back(report);
+    }
+
+    /// `None` for commands the simulated PN532 does not implement
+    fn pn532(&mut self, command: Pn532Command, payload: &[u8]) -> Option<Vec<u8>> {
+        let response = match command {
+            Pn532Command::GetFirmwareVersion => vec![0x32, 0x01, 0x06, 0x07],
+            Pn532Command::Diagnose => match payload.first() {
+                // Attention request, only ISO14443-4 targets can answer it
+                Some(0x06) => match &self.selected {
+                    Some(PassiveTarget::Iso14443a(card)) if card.get_sak() & 0x20 == 0 => vec![STATUS_CONTEXT],
+                    _ => vec![self.selected_status()],
+                },
+                // Communication line test echoes its input
+                Some(0x00) => payload.to_vec(),
+                _ => vec![STATUS_OK],
+            },
+            Pn532Command::SamConfiguration
+            | Pn532Command::SetParameters
+            | Pn532Command::WriteRegister
+            | Pn532Command::WriteGpio => vec![],
+            Pn532Command::RfConfiguration => {
+                if let [0x01, on, ..] = *payload {
+                    self.rf_on = on & 0x01 != 0;
+                }
+                vec![]
+            }
+            Pn532Command::ReadRegister => vec![0; payload.len() / 2],
+            Pn532Command::PowerDown => vec![STATUS_OK],
+            Pn532Command::InListPassiveTarget => {
+                let [_, brty, initial_data @ ..] = payload else {
+                    return None;
+                };
+                self.rf_on = true;
+                if let Some(card) = self.selected_card() {
+                    card.reset();
+                }
+                self.selected = self.find_target(*brty, initial_data);
+                match &self.selected {
+                    Some(target) => {
+                        let mut response = vec![1, 1];
+                        response.extend(target_data(target, initial_data));
+                        response
+                    }
+                    None => vec![0],
+                }
+            }
+            Pn532Command::InAutoPoll => {
+                let [_, _, types @ ..] = payload else {
+                    return None;
+                };
+                self.rf_on = true;
+                self.selected = None;
+                let mut response = vec![0];
+                for &target_type in types {
+                    let brty = match target_type {
+                        0x10 | 0x20 => 0,
+                        0x11 => 1,
+                        0x12 => 2,
+                        _ => continue,
+                    };
+                    let polling = [0x00, 0xFF, 0xFF, 0x00, 0x00];
+                    if let Some(target) = self.find_target(brty, &polling) {
+                        let data = target_data(&target, &polling);
+                        response[0] = 1;
+                        response.extend_from_slice(&[target_type, data.len() as u8 + 1, 1]);
+                        response.extend(data);
+                        self.selected = Some(target);
+                        break;
+                    }
+                }
+                response
+            }
+            Pn532Command::InSelect => vec![self.selected_status()],
+            Pn532Command::InRelease | Pn532Command::InDeselect => {
+                if let Some(card) = self.selected_card() {
+                    card.reset();
+                }
+                self.selected = None;
+                vec![STATUS_OK]
+            }
+            // A bare Tg after an answer flagged MI asks for the next part
+            Pn532Command::InDataExchange if payload.len() == 1 && !self.chained.is_empty() => self.next_chained(),
+            Pn532Command::InDataExchange | Pn532Command::InCommunicateThru => {
+                let data = match command {
+                    Pn532Command::InDataExchange => payload.get(1..)?,
+                    _ => payload,
+                };
+                match self.selected_card().map(|card| card.exchange(data)) {
+                    Some(Ok(response)) if command == Pn532Command::InDataExchange => {
+                        self.chained = response;
+                        self.next_chained()
+                    }
+                    Some(Ok(mut response)) => {
+                        response.insert(0, STATUS_OK);
+                        response
+                    }
+                    Some(Err(error)) => vec![error as u8],
+                    None => vec![STATUS_TIMEOUT],
+                }
+            }
+            _ => return None,
+        };
+        Some(response)
+    }
+
+    /// The next part of a chained answer, flagged MI while more is left
+    fn next_chained(&mut self) -> Vec<u8> {
+        let rest = self.chained.split_off(self.chained.len().min(EXCHANGE_CHUNK));
+        let part = std::mem::replace(&mut self.chained, rest);
+        let status = if self.chained.is_empty() { STATUS_OK } else { STATUS_OK | STATUS_MI };
+        [vec![status], part].concat()
+    }
+
+    fn selected_status(&self) -> u8 {
+        match &self.selected {
+            Some(target) if self.field.iter().any(|card| card.target() == *target) => STATUS_OK,
+            _ => STATUS_TIMEOUT,
+        }
+    }
+
+    /// The listed card, as long as it is still on the reader
+    fn selected_card(&mut self) -> Option<&mut VirtualCard> {
+        let selected = self.selected.as_ref()?;
+        self.field.iter_mut().find(|card| card.target() == *selected)
+    }
+
+    /// First card in the field answering `brty`, FeliCa also filters on the polled system code
+    fn find_target(&self, brty: u8, initial_data: &[u8]) -> Option<PassiveTarget> {
+        self.field.iter().map(VirtualCard::target).find(|target| match (target, brty) {
+            (PassiveTarget::Iso14443a(_), 0) => true,
+            (PassiveTarget::Felica(card), 1 | 2) => {
+                let polled = match initial_data {
+                    [_, hi, lo, ..] => u16::from_be_bytes([*hi, *lo]),
+                    _ => 0xFFFF,
+                };
+                polled == 0xFFFF || card.get_system_codes().iter().any(|&code| system_code_matches(polled, code))
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Target data as InListPassiveTarget reports it after the Tg byte
+fn target_data(target: &PassiveTarget, initial_data: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    match target {
+        PassiveTarget::Iso14443a(card) => {
+            data.extend_from_slice(&card.get_aqta().to_be_bytes());
+            data.push(card.get_sak());
+            data.push(card.get_uid().len() as u8);
+            data.extend_from_slice(card.get_uid());
+        }
+        PassiveTarget::Felica(card) => {
+            let system_code = match (initial_data, card.get_system_codes()) {
+                ([_, _, _, 0x01, ..], [code, ..]) => Some(*code),
+                _ => None,
+            };
+            data.push(18 + if system_code.is_some() { 2 } else { 0 });
+            data.push(0x01);
+            data.extend_from_slice(card.get_idm());
+            data.extend_from_slice(card.get_pmm());
+            if let Some(system_code) = system_code {
+                data.extend_from_slice(&system_code.to_be_bytes());
+            }
+        }
+    }
+    data
+}
+
+/// [`HidBackend`] serving [`VirtualHinata`] devices, for CI and examples without a reader plugged in
+#[derive(Default)]
+pub struct SimulatorBackend {
+    devices: Vec<VirtualHinata>,
+}
+
+impl std::fmt::Debug for SimulatorBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulatorBackend").field("devices", &self.devices.len()).finish()
+    }
+}
+
+impl SimulatorBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_device(mut self, device: VirtualHinata) -> Self {
+        self.devices.push(device);
+        self
+    }
+
+    /// Both usage pages share one path, shaped so `parse_hid_path` pairs them on every platform
+    fn path(index: usize, product_id: u16) -> CString {
+        #[cfg(target_os = "windows")]
+        let path = format!("\\\\?\\HID#VID_{HINATA_VID:04X}&PID_{product_id:04X}&MI_00#sim&{index}&0&0000#{{4d1e55b2-f16f-11cf-88cb-001111000030}}");
+        #[cfg(not(target_os = "windows"))]
+        let path = format!("sim-{index}-{product_id:04x}");
+        CString::new(path).unwrap_or_default()
+    }
+}
+
+impl HidBackend for SimulatorBackend {
+    fn enumerate(&self, vendor_id: u16) -> HinataResult<Vec<HidDeviceInfo>> {
+        let mut infos = Vec::new();
+        for (index, device) in self.devices.iter().enumerate() {
+            let state = device.state();
+            if vendor_id == HINATA_VID && !state.disconnected {
+                infos.extend(USAGE_PAGES.map(|usage_page| HidDeviceInfo {
+                    path: Self::path(index, state.product_id),
+                    vendor_id,
+                    product_id: state.product_id,
+                    usage_page,
+                    product_string: Some(state.product_string.clone()),
+                }));
+            }
+            if let Some((bootloader_vid, product_id)) = state.bootloader && state.in_bootloader && bootloader_vid == vendor_id {
+                infos.push(HidDeviceInfo {
+                    path: CString::new(format!("sim-{index}-bootloader")).unwrap_or_default(),
+                    vendor_id,
+                    product_id,
+                    usage_page: 0xFF00,
+                    product_string: None,
+                });
+            }
+        }
+        Ok(infos)
+    }
+
+    fn open(&self, path: &CStr) -> HinataResult<Box<dyn HidTransport>> {
+        let device = self.devices.iter().enumerate()
+            .find(|(index, device)| Self::path(*index, device.state().product_id).as_c_str() == path)
+            .map(|(_, device)| device.clone())
+            .ok_or(Error::NotFound(format!("{} is not simulated", path.to_string_lossy())))?;
+        if device.state().disconnected {
+            return Err(Error::Disconnected("Simulated device is unplugged".into()));
+        }
+        Ok(Box::new(SimulatorTransport(device)))
+    }
+
+    fn has_com_port(&self) -> bool {
+        false
+    }
+}
+
+struct SimulatorTransport(VirtualHinata);
+
+impl HidTransport for SimulatorTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+        let state = self.0.state();
+        let (mut state, _) = self.0.inner.reports
+            .wait_timeout_while(state, timeout, |state| state.reports.is_empty() && !state.disconnected)
+            .unwrap_or_else(|e| e.into_inner());
+        if state.disconnected {
+            return Err(Error::Disconnected("Simulated device is unplugged".into()));
+        }
+        let Some(report) = state.reports.pop_front() else {
+            return Ok(0);
+        };
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        if self.0.state().disconnected {
+            return Err(Error::Disconnected("Simulated device is unplugged".into()));
+        }
+        self.0.handle_report(data);
+        Ok(data.len())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HinataResult<usize> {
+        let state = self.0.state();
+        let report_id = buf.first().copied().unwrap_or_default();
+        let report = state.feature_reports.get(&report_id)
+            .ok_or(Error::NotSupport(format!("No feature report {report_id:02X}")))?;
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HinataResult<()> {
+        let Some(&report_id) = data.first() else {
+            return Err(Error::Parse("Feature report without report id".into()));
+        };
+        self.0.state().feature_reports.insert(report_id, data.to_vec());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn simulator_feature_report_test() {
+    let backend = Arc::new(SimulatorBackend::new().with_device(VirtualHinata::new()));
+    let mut device = crate::find_devices_with_backend(backend, vec![]).await.unwrap()[0].build(false).unwrap();
+
+    assert!(matches!(device.get_feature_report(3, 4).await, Err(Error::NotSupport(_))));
+    device.send_feature_report(&[3, 0x10, 0x20]).await.unwrap();
+    assert_eq!(device.get_feature_report(3, 4).await.unwrap(), [3, 0x10, 0x20]);
+}
+
+#[tokio::test]
+async fn simulator_test() {
+    use crate::card::{CardId, Felica, Iso14443a};
+    use crate::card::mifare_classic::MifareClassicLayout;
+    use crate::hooks::{Hooks, RequestKind};
+    use crate::card::virtual_::VirtualMifareClassic;
+    use crate::pn532::{FelicaPollRequest, KeyType, Pn532Error, Pn532Port};
+
+    let reader = VirtualHinata::new().with_chip_id([1, 2, 3, 4]);
+    let backend = Arc::new(SimulatorBackend::new().with_device(reader.clone()));
+    let builders = crate::find_devices_with_backend(backend, vec![]).await.unwrap();
+    assert_eq!(builders.len(), 1);
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    let hooks = Hooks::new().on_request_end(move |kind, _, error| seen.lock().unwrap().push((kind, error.is_none())));
+    let mut device = builders.into_iter().next().unwrap().with_hooks(hooks).build(false).unwrap();
+
+    assert_eq!(device.get_firmware_timestamp().await.unwrap(), 2025051301);
+    assert_eq!(device.get_chip_id().await.unwrap(), [1, 2, 3, 4]);
+    device.set_led(1, 2, 3).await;
+    // Writes go out in order, so the answer to the next request means the LED command arrived
+    device.get_firmware_commit_hash().await.unwrap();
+    assert_eq!(reader.get_led(), Some((1, 2, 3)));
+
+    let mut pn532 = device.pn532();
+    assert!(pn532.in_list_passive_target(0, 1, &[]).await.unwrap().is_empty());
+    reader.place_card(PassiveTarget::Iso14443a(Iso14443a::new(vec![0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004)));
+    reader.place_card(PassiveTarget::Felica(Felica::new([1; 8], [2; 8], vec![0x88B4])));
+    let targets = pn532.in_list_passive_target(0, 1, &[]).await.unwrap();
+    assert!(matches!(&targets[..], [PassiveTarget::Iso14443a(card)] if card.display_id() == "DEADBEEF"));
+    assert!(pn532.is_card_still_present(1).await.unwrap());
+    let targets = pn532.in_auto_poll(1, 1, &[crate::pn532::AutoPollType::Felica212], Duration::from_secs(1)).await.unwrap();
+    assert!(matches!(&targets[..], [PassiveTarget::Felica(card)] if card.get_idm() == &[1; 8]));
+    reader.remove_cards();
+    assert!(!pn532.is_card_still_present(1).await.unwrap());
+
+    reader.place_card(PassiveTarget::Felica(Felica::new([1; 8], [2; 8], vec![0x88B4])));
+    assert!(pn532.poll_felica(0x0003).await.unwrap().is_none());
+    let request = FelicaPollRequest::new(0x88B4).with_request_code(crate::pn532::RequestCode::SystemCode);
+    let targets = pn532.in_list_passive_target(1, 1, &request.to_bytes().unwrap()).await.unwrap();
+    assert!(matches!(&targets[..], [PassiveTarget::Felica(card)] if card.get_system_codes() == [0x88B4]));
+    assert!(matches!(pn532.in_communicate_thru(&[0x00]).await, Err(Error::Pn532(_))));
+    reader.remove_cards();
+
+    let mut card = VirtualMifareClassic::new(&[1, 2, 3, 4], MifareClassicLayout::Classic1K).unwrap();
+    card.set_block(1, [0x5A; 16]).unwrap();
+    reader.place_card(card);
+    pn532.in_list_passive_target(0, 1, &[]).await.unwrap();
+    pn532.mifare_classic_auth(1, &[1, 2, 3, 4], 1, KeyType::A, &[0xFF; 6]).await.unwrap();
+    assert_eq!(pn532.mifare_classic_read_block(1, 1).await.unwrap(), [0x5A; 16]);
+    pn532.mifare_classic_write_block(1, 2, &[0xA5; 16]).await.unwrap();
+    assert!(matches!(&reader.get_cards()[..], [VirtualCard::MifareClassic(card)] if card.get_block(2) == Some(&[0xA5; 16])));
+    assert!(matches!(device.request(Pn532Command::TgGetTargetStatus, &[]).await, Err(Error::Pn532(Pn532Error::SyntaxError))));
+    assert!(device.request(Pn532Command::GetFirmwareVersion, &[]).await.is_ok());
+
+    reader.disconnect();
+    assert!(device.get_chip_id().await.is_ok());
+    assert!(device.request(Pn532Command::GetFirmwareVersion, &[]).await.is_err());
+    assert_eq!(requests.lock().unwrap().first(), Some(&(RequestKind::Firmware(1), true)));
+    assert_eq!(requests.lock().unwrap().last(), Some(&(RequestKind::Pn532(Pn532Command::GetFirmwareVersion), false)));
+}
+
+#[tokio::test]
+async fn simulator_syntax_error_retry_test() {
+    use crate::builder::DeviceOptions;
+    use crate::hooks::Hooks;
+    use crate::pn532::{Pn532Error, Pn532Port};
+
+    let backend = Arc::new(SimulatorBackend::new().with_device(VirtualHinata::new()));
+    let sent = Arc::new(Mutex::new(0));
+    let count = sent.clone();
+    // The command byte of the PN532 frame, past the report id, 0xE2 and the frame header
+    let hooks = Hooks::new().on_report_sent(move |report| {
+        if report.get(8) == Some(&(Pn532Command::TgGetTargetStatus as u8)) {
+            *count.lock().unwrap() += 1;
+        }
+    });
+    let mut device = crate::find_devices_with_backend(backend, vec![]).await.unwrap().into_iter().next().unwrap()
+        .with_options(DeviceOptions { retry_syntax_error: true, ..Default::default() })
+        .with_hooks(hooks)
+        .build(false)
+        .unwrap();
+
+    assert!(matches!(device.request(Pn532Command::TgGetTargetStatus, &[]).await, Err(Error::Pn532(Pn532Error::SyntaxError))));
+    // The hook runs after the write returns, which can be after the answer arrived; the next request orders it
+    assert!(device.request(Pn532Command::GetFirmwareVersion, &[]).await.is_ok());
+    assert_eq!(*sent.lock().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn simulator_chained_exchange_test() {
+    use crate::card::ultralight::UltralightType;
+    use crate::card::virtual_::VirtualNtag;
+    use crate::hooks::{Hooks, RequestKind};
+
+    let reader = VirtualHinata::new();
+    let backend = Arc::new(SimulatorBackend::new().with_device(reader.clone()));
+    let exchanges = Arc::new(Mutex::new(0));
+    let count = exchanges.clone();
+    let hooks = Hooks::new().on_request_end(move |kind, _, _| {
+        if kind == RequestKind::Pn532(Pn532Command::InDataExchange) {
+            *count.lock().unwrap() += 1;
+        }
+    });
+    let mut device = crate::find_devices_with_backend(backend, vec![]).await.unwrap().into_iter().next().unwrap()
+        .with_hooks(hooks)
+        .build(false)
+        .unwrap();
+
+    reader.place_card(VirtualNtag::new([0x04, 1, 2, 3, 4, 5, 6], UltralightType::Ntag213).unwrap());
+    let mut pn532 = device.pn532();
+    pn532.in_list_passive_target(0, 1, &[]).await.unwrap();
+    let mut expected = Vec::new();
+    for page in (0..0x1C).step_by(4) {
+        expected.extend(pn532.in_communicate_thru(&[0x3A, page, page + 3]).await.unwrap());
+    }
+    // FAST_READ of 28 pages answers 112 bytes, three frames of which the first two carry MI
+    assert_eq!(pn532.in_data_exchange(1, 0x3A, &[0x00, 0x1B]).await.unwrap(), expected);
+    assert_eq!(*exchanges.lock().unwrap(), 3);
+
+    // A short answer after the chain is not mixed up with it
+    assert_eq!(pn532.in_data_exchange(1, 0x3A, &[0x00, 0x00]).await.unwrap(), expected[..4]);
+    assert_eq!(*exchanges.lock().unwrap(), 4);
+}
+
+#[tokio::test]
+async fn simulator_self_test() {
+    use crate::device::SelfTestCheck;
+
+    let reader = VirtualHinata::new().with_firmware_timestamp(2024120101);
+    let backend = Arc::new(SimulatorBackend::new().with_device(reader.clone()));
+    let mut device = crate::find_devices_with_backend(backend, vec![]).await.unwrap()[0].build(false).unwrap();
+
+    let report = device.self_test().await;
+    assert_eq!(report.steps.len(), 5);
+    // Firmware this old has no chip id
+    assert_eq!(report.failures().map(|step| step.check).collect::<Vec<_>>(), vec![SelfTestCheck::ChipId]);
+    assert_eq!(report.steps[2].detail, "PN532 v1.6, support 07");
+    assert_eq!(reader.get_led(), None);
+}
+
+#[tokio::test]
+async fn simulator_stalled_report_stream_test() {
+    use crate::message::UnSubscribePolicy;
+    use crate::request::Request;
+    use tokio_stream::StreamExt;
+
+    let backend = Arc::new(SimulatorBackend::new().with_device(VirtualHinata::new()));
+    let mut device = crate::find_devices_with_backend(backend, vec![]).await.unwrap()[0].build(false).unwrap();
+
+    let mut stream = device.subscribe(0xE5, UnSubscribePolicy::Never).await.unwrap();
+    for _ in 0..40 {
+        device.send_request_detached(Request::new(0xE5)).await;
+    }
+    // Answered only if the reader thread is not stuck on the full stream
+    device.get_firmware_version().await.unwrap();
+
+    let mut received = 0;
+    while let Some(report) = stream.next().await {
+        report.unwrap();
+        received += 1;
+    }
+    assert_eq!(received, 32);
+}
@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use hinata::builder::HinataDeviceBuilder;
+use hinata::card::mifare_classic::{MifareClassicLayout, MifareKey, ARCADE_KEYS, DEFAULT_KEYS};
+use hinata::card::PassiveTarget;
+use hinata::detector::{CardDetector, CardEvent};
+use hinata::device::{HinataDevice, ScanType};
+use hinata::error::{Error, HinataResult};
+use hinata::utils::id_format::IdFormat;
+use tokio_stream::StreamExt;
+
+/// Diagnostics and field support for HINATA readers
+#[derive(Parser)]
+#[command(name = "hinata-cli", version)]
+struct Cli {
+    /// Instance id or index from `list`, the first reader if omitted
+    #[arg(short, long, global = true)]
+    device: Option<String>,
+
+    /// Print every HID report
+    #[arg(long, global = true)]
+    debug: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List connected readers
+    List,
+    /// Show firmware, chip id and paths of a reader
+    Info,
+    /// Print cards as they are tapped and removed
+    Scan {
+        /// Stop after this many seconds, run until Ctrl-C if omitted
+        #[arg(short, long)]
+        timeout: Option<u64>,
+    },
+    /// Dump a MIFARE Classic card to an .mfd file, trying the built-in key dictionary
+    Dump {
+        output: PathBuf,
+        /// Extra key to try first, 12 hex digits, may be repeated
+        #[arg(short, long)]
+        key: Vec<String>,
+        /// Seconds to wait for a card
+        #[arg(short, long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Cycle the LED through red, green, blue and white
+    Led,
+    /// Show the serial port of the reader's CDC interface
+    Com,
+    /// Run the self-test sequence
+    SelfTest,
+    /// Reboot into the bootloader to flash new firmware
+    Bootloader {
+        /// Skip the confirmation, the reader disconnects right away
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> HinataResult<()> {
+    if let Command::List = cli.command {
+        return list().await;
+    }
+
+    let builder = select(cli.device.as_deref()).await?;
+    let mut device = builder.build(cli.debug)?;
+    match cli.command {
+        Command::List => unreachable!(),
+        Command::Info => info(&mut device).await,
+        Command::Scan { timeout } => scan(device, timeout.map(Duration::from_secs)).await,
+        Command::Dump { output, key, timeout } => dump(&mut device, &output, &key, Duration::from_secs(timeout)).await,
+        Command::Led => led(&mut device).await,
+        Command::Com => com(&device).await,
+        Command::SelfTest => self_test(&mut device).await,
+        Command::Bootloader { yes } => bootloader(&mut device, yes).await,
+    }
+}
+
+async fn list() -> HinataResult<()> {
+    let builders = hinata::find_devices(vec![]).await?;
+    for (i, builder) in builders.iter().enumerate() {
+        println!("{i}: {} (PID {:04X}) {}", builder.get_device_name(), builder.get_product_id(), builder.get_instance_id());
+    }
+    Ok(())
+}
+
+async fn select(device: Option<&str>) -> HinataResult<HinataDeviceBuilder> {
+    let mut builders = hinata::find_devices(vec![]).await?;
+    let index = match device {
+        None => 0,
+        Some(device) => match device.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => builders.iter()
+                .position(|builder| builder.get_instance_id() == device)
+                .ok_or(Error::NotFound(format!("No reader with instance id {device}")))?,
+        },
+    };
+    if index >= builders.len() {
+        return Err(Error::NotFound(format!("No reader at index {index}")));
+    }
+    Ok(builders.swap_remove(index))
+}
+
+async fn info(device: &mut HinataDevice) -> HinataResult<()> {
+    // Fill the cached firmware fields, older firmware lacks some of them
+    device.get_firmware_timestamp().await?;
+    let _ = device.get_firmware_commit_hash().await;
+    let _ = device.get_chip_id().await;
+
+    let info = device.get_info();
+    println!("Name:             {}", info.device_name);
+    println!("Product id:       {:04X}", info.pid);
+    println!("Instance id:      {}", info.instance_id);
+    println!("Read path:        {}", info.path_read);
+    println!("Write path:       {}", info.path_write);
+    if let Some(timestamp) = info.firmware_timestamp {
+        println!("Firmware:         {timestamp}");
+    }
+    if let Some(hash) = info.firmware_commit_hash {
+        println!("Firmware commit:  {}", IdFormat::new(&hash).hex());
+    }
+    if let Some(chip_id) = info.chip_id {
+        println!("Chip id:          {}", IdFormat::new(&chip_id).hex());
+    }
+    Ok(())
+}
+
+async fn scan(device: HinataDevice, timeout: Option<Duration>) -> HinataResult<()> {
+    let (mut events, _) = CardDetector::new().spawn(device);
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    println!("Waiting for cards, Ctrl-C to stop");
+    loop {
+        let event = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, events.next()).await {
+                Ok(event) => event,
+                Err(_) => return Ok(()),
+            },
+            None => events.next().await,
+        };
+        match event {
+            Some(CardEvent::Tapped(target)) => println!("{target}"),
+            Some(CardEvent::Removed) => println!("Removed"),
+            Some(CardEvent::Disconnected) | None => return Err(Error::Disconnected("Reader disconnected".into())),
+        }
+    }
+}
+
+fn parse_key(key: &str) -> HinataResult<[u8; 6]> {
+    if key.len() != 12 || !key.is_ascii() {
+        return Err(Error::Parse(format!("Key {key} is not 12 hex digits")));
+    }
+    let mut bytes = [0u8; 6];
+    for (byte, pair) in bytes.iter_mut().zip(key.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).unwrap_or_default(), 16)?;
+    }
+    Ok(bytes)
+}
+
+async fn dump(device: &mut HinataDevice, output: &PathBuf, extra_keys: &[String], timeout: Duration) -> HinataResult<()> {
+    let mut keys = extra_keys.iter().map(|key| parse_key(key)).collect::<HinataResult<Vec<_>>>()?;
+    keys.extend_from_slice(DEFAULT_KEYS);
+    keys.extend_from_slice(ARCADE_KEYS);
+    let keys: Vec<MifareKey> = keys.iter().map(|k| MifareKey::A(*k)).chain(keys.iter().map(|k| MifareKey::B(*k))).collect();
+
+    println!("Place a MIFARE Classic card on the reader");
+    let Some((tg, PassiveTarget::Iso14443a(card))) = device.scan_card(timeout, &[ScanType::TypeA]).await? else {
+        return Err(Error::Timeout("No card found".into()));
+    };
+    let layout = MifareClassicLayout::from_sak(card.get_sak())
+        .ok_or(Error::NotSupport(format!("SAK {:02X} is not a MIFARE Classic card", card.get_sak())))?;
+
+    let dump = device.pn532().dump_card(tg, card.get_uid(), layout, &keys).await?;
+    let unread: Vec<u8> = (0..layout.sector_count()).filter(|&sector| !dump.is_sector_read(sector)).collect();
+    std::fs::write(output, dump.to_mfd())?;
+    println!("Wrote {:?} of {} to {}", layout, card.uid_format().hex(), output.display());
+    if !unread.is_empty() {
+        println!("No key opened sectors {unread:?}, they are zero-filled");
+    }
+    Ok(())
+}
+
+async fn led(device: &mut HinataDevice) -> HinataResult<()> {
+    for (name, (r, g, b)) in [("red", (0xFF, 0, 0)), ("green", (0, 0xFF, 0)), ("blue", (0, 0, 0xFF)), ("white", (0xFF, 0xFF, 0xFF))] {
+        println!("{name}");
+        device.set_led(r, g, b).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    device.reset_led().await;
+    Ok(())
+}
+
+#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+async fn com(device: &HinataDevice) -> HinataResult<()> {
+    println!("{}", device.get_com_port_async().await?);
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+async fn com(_device: &HinataDevice) -> HinataResult<()> {
+    Err(Error::NotSupport("COM port lookup is not supported on this platform".into()))
+}
+
+async fn self_test(device: &mut HinataDevice) -> HinataResult<()> {
+    let report = device.self_test().await;
+    for step in &report.steps {
+        let result = if step.passed { "PASS" } else { "FAIL" };
+        println!("{result} {:<20} {:>6.1} ms  {}", format!("{:?}", step.check), step.elapsed.as_secs_f64() * 1000.0, step.detail);
+    }
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(Error::Other(format!("{} of {} checks failed", report.failures().count(), report.steps.len())))
+    }
+}
+
+async fn bootloader(device: &mut HinataDevice, yes: bool) -> HinataResult<()> {
+    if !yes {
+        println!("The reader reboots into its bootloader and disconnects, flash the new firmware with the vendor updater.");
+        println!("Continue? [y/N]");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+    }
+    device.enter_bootloader().await;
+    println!("Reader is in bootloader mode");
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+use crate::builder::{find_devices_inner, HinataDeviceBuilder};
+use crate::card::aime::ArcadeCard;
+use crate::card::felica::Block as FelicaBlock;
+use crate::card::{Felica, PassiveTarget};
+use crate::device::{HinataDevice, HinataInfo, ScanType};
+use crate::error::HinataResult;
+use crate::pn532::{KeyType, Pn532};
+
+/// Blocking [`crate::find_devices`]
+pub fn find_devices(exclude: Vec<String>) -> HinataResult<Vec<HinataDeviceBuilder>> {
+    find_devices_inner(exclude)
+}
+
+/// [`HinataDevice`] for callers without an async runtime, every call blocks the calling thread.
+/// Must not be used from inside a tokio runtime.
+pub struct HinataDeviceSync {
+    runtime: Runtime,
+    device: HinataDevice,
+}
+
+impl HinataDeviceSync {
+    pub fn new(device: HinataDevice) -> HinataResult<Self> {
+        let runtime = Builder::new_current_thread().enable_time().build()?;
+        Ok(Self { runtime, device })
+    }
+
+    pub fn open(builder: &HinataDeviceBuilder, debug: bool) -> HinataResult<Self> {
+        Self::new(builder.build(debug)?)
+    }
+
+    /// Run any async device operation the wrapper does not cover
+    pub fn run<T>(&mut self, f: impl AsyncFnOnce(&mut HinataDevice) -> T) -> T {
+        self.runtime.block_on(f(&mut self.device))
+    }
+
+    pub fn into_inner(self) -> HinataDevice {
+        self.device
+    }
+
+    pub fn pn532(&mut self) -> Pn532Sync<'_> {
+        Pn532Sync {
+            runtime: &self.runtime,
+            pn532: self.device.pn532(),
+        }
+    }
+
+    pub fn get_instance_id(&self) -> String {
+        self.device.get_instance_id()
+    }
+
+    pub fn get_device_name(&self) -> String {
+        self.device.get_device_name()
+    }
+
+    pub fn get_info(&self) -> HinataInfo {
+        self.device.get_info()
+    }
+
+    pub fn get_firmware_timestamp(&mut self) -> HinataResult<u32> {
+        self.run(async |device| device.get_firmware_timestamp().await)
+    }
+
+    pub fn get_chip_id(&mut self) -> HinataResult<[u8; 4]> {
+        self.run(async |device| device.get_chip_id().await)
+    }
+
+    pub fn set_led(&mut self, r: u8, g: u8, b: u8) {
+        self.run(async |device| device.set_led(r, g, b).await)
+    }
+
+    pub fn reset_led(&mut self) {
+        self.run(async |device| device.reset_led().await)
+    }
+
+    pub fn scan_card(&mut self, timeout: Duration, types: &[ScanType]) -> HinataResult<Option<(u8, PassiveTarget)>> {
+        self.run(async |device| device.scan_card(timeout, types).await)
+    }
+
+    pub fn wait_for_card_with_uid(&mut self, uid: &[u8], timeout: Duration) -> HinataResult<Option<PassiveTarget>> {
+        self.run(async |device| device.wait_for_card_with_uid(uid, timeout).await)
+    }
+
+    pub fn identify_arcade_card(&mut self) -> HinataResult<Option<ArcadeCard>> {
+        self.pn532().run(async |pn532| pn532.identify_arcade_card().await)
+    }
+}
+
+/// Blocking [`Pn532`] borrowed from a [`HinataDeviceSync`]
+pub struct Pn532Sync<'d> {
+    runtime: &'d Runtime,
+    pn532: Pn532<'d, HinataDevice>,
+}
+
+impl<'d> Pn532Sync<'d> {
+    /// Run any async PN532 operation the wrapper does not cover, e.g. the card specific extensions
+    pub fn run<T>(&mut self, f: impl AsyncFnOnce(&mut Pn532<'d, HinataDevice>) -> T) -> T {
+        self.runtime.block_on(f(&mut self.pn532))
+    }
+
+    pub fn in_list_passive_target(&mut self, brty: u8, max_tg: u8, initial_data: &[u8]) -> HinataResult<Vec<PassiveTarget>> {
+        self.run(async |pn532| pn532.in_list_passive_target(brty, max_tg, initial_data).await)
+    }
+
+    pub fn poll_felica(&mut self, system_code: u16) -> HinataResult<Option<Felica>> {
+        self.run(async |pn532| pn532.poll_felica(system_code).await)
+    }
+
+    pub fn set_rf_field(&mut self, on: bool) -> HinataResult<()> {
+        self.run(async |pn532| pn532.set_rf_field(on).await)
+    }
+
+    pub fn set_max_retries(&mut self, atr: u8, psl: u8, passive_activation: u8) -> HinataResult<()> {
+        self.run(async |pn532| pn532.set_max_retries(atr, psl, passive_activation).await)
+    }
+
+    pub fn in_data_exchange(&mut self, tg: u8, cmd: u8, data: &[u8]) -> HinataResult<Vec<u8>> {
+        self.run(async |pn532| pn532.in_data_exchange(tg, cmd, data).await)
+    }
+
+    pub fn in_communicate_thru(&mut self, data: &[u8]) -> HinataResult<Vec<u8>> {
+        self.run(async |pn532| pn532.in_communicate_thru(data).await)
+    }
+
+    pub fn in_release(&mut self, tg: u8) -> HinataResult<()> {
+        self.run(async |pn532| pn532.in_release(tg).await)
+    }
+
+    pub fn mifare_classic_auth(&mut self, tg: u8, uid: &[u8], block_num: u8, key_type: KeyType, key: &[u8; 6]) -> HinataResult<()> {
+        self.run(async |pn532| pn532.mifare_classic_auth(tg, uid, block_num, key_type, key).await)
+    }
+
+    pub fn mifare_classic_read_block(&mut self, tg: u8, block_num: u8) -> HinataResult<[u8; 16]> {
+        self.run(async |pn532| pn532.mifare_classic_read_block(tg, block_num).await)
+    }
+
+    pub fn mifare_classic_write_block(&mut self, tg: u8, block_num: u8, data: &[u8]) -> HinataResult<()> {
+        self.run(async |pn532| pn532.mifare_classic_write_block(tg, block_num, data).await)
+    }
+
+    pub fn felica_read_without_encryption(&mut self, tg: u8, idm: &[u8; 8], services: &[u16], blocks: &[u16]) -> HinataResult<Vec<FelicaBlock>> {
+        self.run(async |pn532| pn532.felica_read_without_encryption(tg, idm, services, blocks).await)
+    }
+
+    pub fn felica_write_without_encryption(&mut self, tg: u8, idm: &[u8; 8], services: &[u16], blocks: &[u16], data: &[FelicaBlock]) -> HinataResult<()> {
+        self.run(async |pn532| pn532.felica_write_without_encryption(tg, idm, services, blocks, data).await)
+    }
+}
@@ -1,20 +1,69 @@
-use crate::device::{Config, HinataDevice, Info};
-use crate::error::HinataResult;
+use crate::backend::{HidApiBackend, HidBackend, HidTransport};
+use crate::hooks::Hooks;
+use crate::device::{Config, HinataDevice, Info, IoStatus};
+use crate::error::{Error, HidErrorKind, HinataResult};
 use crate::message::{InMessage, OutMessage, Subscription};
 use crate::types::HidDevicePath;
 use crate::utils::device_parse::parse_hid_path;
-use hidapi::{HidApi, HidDevice, HidError};
+use crate::utils::hexdump::HexDump;
+use bytes::BytesMut;
+use std::any::Any;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::ffi::CString;
-use std::sync::OnceLock;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio::sync::mpsc::{Receiver, Sender};
 
-const HINATA_VID: u16 = 0xF822;
+pub(crate) const HINATA_VID: u16 = 0xF822;
 const USAGE_PAGE_READ: u16 = 1;
 const USAGE_PAGE_WRITE: u16 = 0x06;
+/// Only bounds how long the reader takes to notice the device was dropped
+const READ_TIMEOUT_MS: i32 = 200;
+const SHARED_READ_TIMEOUT_MS: i32 = 16;
+const LOW_LATENCY_READ_TIMEOUT_MS: i32 = 1;
+const REPORT_SIZE: usize = 64;
+/// Reports allocated at once by the reader
+const REPORT_POOL: usize = 16;
+
+/// Timing of the io threads behind a [`HinataDevice`]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceOptions {
+    /// Longest a single HID read blocks, `None` picks 200ms or 16ms when reads and writes share one handle (macOS)
+    pub read_timeout_ms: Option<i32>,
+    /// Read with a 1ms timeout while a request is outstanding and double it back up to the read timeout once idle.
+    /// Mostly helps a shared handle, where a write waits for the read in progress.
+    pub low_latency: bool,
+    /// Send a PN532 command once more when the PN532 answers it with a syntax error frame
+    pub retry_syntax_error: bool,
+}
+
+/// Read timeout for the next HID read, adapting to whether a response is expected
+struct ReadCadence {
+    max: i32,
+    low_latency: bool,
+    current: i32,
+}
+
+impl ReadCadence {
+    fn new(max: i32, low_latency: bool) -> Self {
+        Self { max, low_latency, current: max }
+    }
+
+    fn next(&mut self, pending: bool) -> i32 {
+        self.current = match (self.low_latency, pending) {
+            (false, _) => self.max,
+            (true, true) => LOW_LATENCY_READ_TIMEOUT_MS,
+            (true, false) => (self.current * 2).min(self.max),
+        };
+        self.current
+    }
+}
 
 #[derive(Debug)]
 enum HidConnectionBuilder {
@@ -32,53 +81,149 @@ enum HidConnectionBuilder {
 
 impl HidConnectionBuilder {
     #[cfg(target_os = "macos")]
-    fn build(&self) -> Result<HidConnection, HidError> {
-        let api = HidApi::new()?;
+    fn build(&self, backend: &dyn HidBackend) -> HinataResult<HidConnection> {
         match self {
-            Self::Single { inner, .. } => Ok(HidConnection::Single(api.open_path(inner)?)),
-            _ => Err(HidError::HidApiError {
-                message: "Invalid connection builder for macOS".into(),
-            }),
+            Self::Single { inner, .. } => Ok(HidConnection::Single(backend.open(inner)?)),
+            _ => Err(Error::NotSupport("Invalid connection builder for macOS".into())),
         }
     }
 
     #[cfg(not(target_os = "macos"))]
-    fn build(&self) -> Result<HidConnection, HidError> {
-        let api = HidApi::new()?;
+    fn build(&self, backend: &dyn HidBackend) -> HinataResult<HidConnection> {
         match self {
             Self::Dual { read, write, .. } => Ok(HidConnection::Dual {
-                read: api.open_path(read)?,
-                write: api.open_path(write)?,
-            }),
-            _ => Err(HidError::HidApiError {
-                message: "Invalid connection builder for this OS".into(),
+                read: backend.open(read)?,
+                write: backend.open(write)?,
             }),
+            _ => Err(Error::NotSupport("Invalid connection builder for this OS".into())),
         }
     }
 }
 
-#[derive(Debug)]
 enum HidConnection {
-    Single(HidDevice),
-    Dual { read: HidDevice, write: HidDevice },
+    Single(Box<dyn HidTransport>),
+    Dual { read: Box<dyn HidTransport>, write: Box<dyn HidTransport> },
 }
 
 impl HidConnection {
-    fn write(&self, data: &[u8]) -> Result<usize, HidError> {
+    /// Separate the read and write side so each can block on its own thread.
+    /// A transport need not be `Sync`, a single handle is shared by both halves through a mutex.
+    fn split(self) -> (HidHalf, HidHalf) {
         match self {
-            Self::Single(device) => device.write(data),
-            Self::Dual { write: device, .. } => device.write(data),
+            Self::Single(device) => {
+                let device = Arc::new(Mutex::new(device));
+                (HidHalf::Shared(device.clone()), HidHalf::Shared(device))
+            }
+            Self::Dual { read, write } => (HidHalf::Owned(read), HidHalf::Owned(write)),
         }
     }
+}
 
-    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, HidError> {
+enum HidHalf {
+    Owned(Box<dyn HidTransport>),
+    Shared(Arc<Mutex<Box<dyn HidTransport>>>),
+}
+
+impl HidHalf {
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
         match self {
-            Self::Single(device) => device.read_timeout(buf, timeout_ms),
-            Self::Dual { read: device, .. } => device.read_timeout(buf, timeout_ms),
+            Self::Owned(device) => device.write(data),
+            Self::Shared(device) => device.lock().unwrap_or_else(|e| e.into_inner()).write(data),
+        }
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        match self {
+            Self::Owned(device) => device.read_timeout(buf, timeout_ms),
+            Self::Shared(device) => device.lock().unwrap_or_else(|e| e.into_inner()).read_timeout(buf, timeout_ms),
+        }
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HinataResult<usize> {
+        match self {
+            Self::Owned(device) => device.get_feature_report(buf),
+            Self::Shared(device) => device.lock().unwrap_or_else(|e| e.into_inner()).get_feature_report(buf),
+        }
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HinataResult<()> {
+        match self {
+            Self::Owned(device) => device.send_feature_report(data),
+            Self::Shared(device) => device.lock().unwrap_or_else(|e| e.into_inner()).send_feature_report(data),
+        }
+    }
+
+    fn default_read_timeout_ms(&self) -> i32 {
+        match self {
+            Self::Owned(_) => READ_TIMEOUT_MS,
+            // Keep the lock short so writes are not held up behind an idle read
+            Self::Shared(_) => SHARED_READ_TIMEOUT_MS,
         }
     }
 }
 
+/// State shared between the reader and writer thread of one device
+struct IoState {
+    subscribes: Mutex<HashMap<u8, Subscription>>,
+    /// Set by the writer once the device is dropped, the reader exits on its next timeout
+    stop: AtomicBool,
+    /// Set by the reader once the device stopped answering
+    disconnected: AtomicBool,
+    status: watch::Sender<IoStatus>,
+    hooks: Hooks,
+}
+
+impl IoState {
+    fn new(status: watch::Sender<IoStatus>, hooks: Hooks) -> Self {
+        Self {
+            subscribes: Mutex::new(HashMap::new()),
+            stop: AtomicBool::new(false),
+            disconnected: AtomicBool::new(false),
+            status,
+            hooks,
+        }
+    }
+
+    fn subscribes(&self) -> MutexGuard<'_, HashMap<u8, Subscription>> {
+        self.subscribes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// What a subscriber gets once the io threads are gone
+    fn failure(&self) -> OutMessage {
+        match &*self.status.borrow() {
+            IoStatus::Panicked(reason) => OutMessage::IoThreadPanicked(reason.clone()),
+            IoStatus::Failed(message) => OutMessage::IoFailed(message.clone()),
+            _ => OutMessage::DeviceDisconnect,
+        }
+    }
+
+    /// Stop serving requests, every pending subscriber is told why
+    fn fail(&self, status: IoStatus) {
+        let mut subscribes = self.subscribes();
+        self.disconnected.store(true, Ordering::Release);
+        // A panic outranks the disconnect it may cause
+        let was_running = *self.status.borrow() == IoStatus::Running;
+        let changed = self.status.send_if_modified(|current| {
+            let replace = !matches!(current, IoStatus::Panicked(_));
+            if replace {
+                *current = status;
+            }
+            replace
+        });
+        if changed && was_running {
+            self.hooks.disconnect(&self.status.borrow());
+        }
+        let failure = self.failure();
+        subscribes.drain().for_each(|(_, channel)| channel.send_no_check(failure.clone()));
+    }
+}
+
+fn panic_reason(panic: &(dyn Any + Send)) -> String {
+    panic.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".into())
+}
+
 #[derive(Debug)]
 pub struct HinataDeviceBuilder {
     connection: HidConnectionBuilder,
@@ -86,13 +231,31 @@ pub struct HinataDeviceBuilder {
     device_name: String,
     pid: u16,
     com_instance_id: OnceLock<String>,
+    options: DeviceOptions,
+    backend: Arc<dyn HidBackend>,
+    hooks: Hooks,
+}
+
+/// Builders are the same device when their instance ids match
+impl PartialEq for HinataDeviceBuilder {
+    fn eq(&self, other: &Self) -> bool {
+        self.instance_id == other.instance_id
+    }
+}
+
+impl Eq for HinataDeviceBuilder {}
+
+impl Hash for HinataDeviceBuilder {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.instance_id.hash(state)
+    }
 }
 
 impl HinataDeviceBuilder {
     pub fn build(&self, debug: bool) -> HinataResult<HinataDevice> {
         let (main_to_sub_tx, main_to_sub_rx): (Sender<InMessage>, Receiver<InMessage>) =
             mpsc::channel(255);
-        let conn = self.connection.build()?;
+        let conn = self.connection.build(self.backend.as_ref())?;
 
         let (read, write) = match &self.connection {
             HidConnectionBuilder::Dual {
@@ -103,20 +266,31 @@ impl HinataDeviceBuilder {
             HidConnectionBuilder::Single { path, .. } => (path.clone(), path.clone()),
         };
 
-        #[cfg(target_os = "windows")]
+        #[cfg(all(target_os = "windows", feature = "com-port"))]
         let path = HidDevicePath {
             read,
             write,
-            com: Some(self.get_com_instance_id()?),
+            com: if self.backend.has_com_port() { Some(self.get_com_instance_id()?) } else { None },
         };
-        #[cfg(not(target_os = "windows"))]
+        #[cfg(not(all(target_os = "windows", feature = "com-port")))]
         let path = HidDevicePath {
             read,
             write,
             com: None,
         };
 
-        let handler = thread::spawn(move || Self::io_loop(conn, main_to_sub_rx, debug));
+        let options = self.options;
+        let (status_tx, status_rx) = watch::channel(IoStatus::Running);
+        let hooks = self.hooks.clone();
+        #[cfg(feature = "metrics")]
+        let hooks = {
+            crate::metrics::record_connect(&self.instance_id);
+            hooks.with_metrics(&self.instance_id)
+        };
+        let io_hooks = hooks.clone();
+        let handler = thread::Builder::new()
+            .name(format!("hinata-io-{}", self.instance_id))
+            .spawn(move || Self::io_loop(conn, main_to_sub_rx, options, IoState::new(status_tx, io_hooks), debug))?;
 
         let info = Info {
             firmware_timestamp: 0,
@@ -133,12 +307,30 @@ impl HinataDeviceBuilder {
             Config {
                 sega_brightness: 0,
                 sega_rapid_scan: false,
+                retry_syntax_error: options.retry_syntax_error,
             },
             Some(handler),
             main_to_sub_tx,
+            status_rx,
+            hooks,
         ))
     }
 
+    pub fn with_options(mut self, options: DeviceOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn get_options(&self) -> &DeviceOptions {
+        &self.options
+    }
+
+    /// Instrumentation callbacks for the devices built from now on
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
     pub fn get_instance_id(&self) -> String {
         self.instance_id.to_string()
     }
@@ -151,86 +343,171 @@ impl HinataDeviceBuilder {
         self.pid
     }
 
-    fn handle_hid_error(subscribes: &mut HashMap<u8, Subscription>, _: HidError) {
-        subscribes.drain().for_each(|(_, channel)| {
-            let _ = channel.send_no_check(OutMessage::DeviceDisconnect);
+    /// Writes run on this thread as soon as a message arrives, reads block on a second thread
+    /// until a report comes in, so an idle device costs no wakeups beyond the read timeout.
+    /// A panic on either thread fails every pending request with [`Error::IoThreadPanicked`].
+    fn io_loop(connection: HidConnection, mut message_in: Receiver<InMessage>, options: DeviceOptions, state: IoState, debug: bool) {
+        let (reader, writer) = connection.split();
+        let state = Arc::new(state);
+
+        let read_state = state.clone();
+        let read_name = format!("{}-read", thread::current().name().unwrap_or("hinata-io"));
+        let read_handler = thread::Builder::new().name(read_name).spawn(move || {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| Self::read_loop(reader, &read_state, options, debug))) {
+                read_state.fail(IoStatus::Panicked(panic_reason(panic.as_ref())));
+            }
         });
+        if let Err(e) = &read_handler {
+            state.fail(IoStatus::Panicked(format!("Reader thread failed to start: {e}")));
+        }
+
+        let written = panic::catch_unwind(AssertUnwindSafe(|| Self::write_loop(&writer, &mut message_in, &state, debug)));
+        if let Err(panic) = written {
+            state.fail(IoStatus::Panicked(panic_reason(panic.as_ref())));
+        }
+
+        // 主线程断开，通知读线程退出
+        state.stop.store(true, Ordering::Release);
+        if let Ok(read_handler) = read_handler {
+            let _ = read_handler.join();
+        }
     }
-    fn io_loop(mut connection: HidConnection, mut message_in: Receiver<InMessage>, debug: bool) {
-        let mut buf = [0; 64];
-        let mut subscribes: HashMap<u8, Subscription> = HashMap::new();
-
-        loop {
-            loop {
-                match message_in.try_recv() {
-                    Ok(mes) => {
-                        let mut data_to_write = None;
-
-                        match mes {
-                            InMessage::SendPacket(data) => {
-                                data_to_write = Some(data);
-                            }
-                            InMessage::SendPacketAndSubscribe(data, subscription) => {
-                                let key = if data[1] == 1 { 50 } else { data[1] };
-                                subscribes.insert(key, subscription);
-                                data_to_write = Some(data);
-                            }
-                            InMessage::Subscribe(cmd, subscription) => {
-                                subscribes.insert(cmd, subscription);
-                            }
-                            InMessage::UnSubscribe(cmd) => {
-                                subscribes.remove(&cmd);
-                            }
-                        }
-
-                        if let Some(data) = data_to_write {
-                            match connection.write(&data) {
-                                Ok(_) => {
-                                    if debug {
-                                        println!("DEBUG: -> {:02X?}", data)
-                                    }
-                                }
-                                Err(e) => Self::handle_hid_error(&mut subscribes, e),
-                            }
-                        }
+
+    fn write_loop(writer: &HidHalf, message_in: &mut Receiver<InMessage>, state: &IoState, debug: bool) {
+        while let Some(mes) = message_in.blocking_recv() {
+            // The subscription waiting for this write, if any
+            let (data, pending) = match mes {
+                InMessage::SendPacket(data) => (data, None),
+                InMessage::SendPacketAndSubscribe(data, subscription) => {
+                    let mut subscribes = state.subscribes();
+                    if state.disconnected.load(Ordering::Acquire) {
+                        subscription.send_no_check(state.failure());
+                        continue;
+                    }
+                    // Subscribe before writing, the response may beat the write call back
+                    let key = if data[1] == 1 { 50 } else { data[1] };
+                    subscribes.insert(key, subscription);
+                    (data, Some(key))
+                }
+                InMessage::Subscribe(cmd, subscription) => {
+                    state.subscribes().insert(cmd, subscription);
+                    continue;
+                }
+                InMessage::UnSubscribe(cmd) => {
+                    state.subscribes().remove(&cmd);
+                    continue;
+                }
+                // Feature reports go to the vendor interface the output reports are written to
+                InMessage::GetFeatureReport(mut buf, reply) => {
+                    let res = writer.get_feature_report(&mut buf).map(|len| {
+                        buf.truncate(len);
+                        buf
+                    });
+                    let _ = reply.send(res);
+                    continue;
+                }
+                InMessage::SendFeatureReport(data, reply) => {
+                    let _ = reply.send(writer.send_feature_report(&data));
+                    continue;
+                }
+            };
+
+            match writer.write(&data) {
+                Ok(_) => {
+                    state.hooks.report_sent(&data);
+                    if debug {
+                        println!("DEBUG: -> {}", HexDump::new(&data))
+                    }
+                }
+                Err(e) => {
+                    // The reader notices a device that is gone, only the request that was not written fails here
+                    let failure = match &*state.status.borrow() {
+                        IoStatus::Running => OutMessage::IoFailed(e.to_string()),
+                        _ => state.failure(),
+                    };
+                    if let Some(channel) = pending.and_then(|key| state.subscribes().remove(&key)) {
+                        channel.send_no_check(failure);
                     }
-                    Err(e) => match e {
-                        mpsc::error::TryRecvError::Empty => break, // 没消息了，去读 HID
-                        mpsc::error::TryRecvError::Disconnected => return, // 主线程断开，退出
-                    },
                 }
             }
+        }
+    }
 
-            match connection.read_timeout(&mut buf, 16) {
+    fn read_loop(reader: HidHalf, state: &IoState, options: DeviceOptions, debug: bool) {
+        // Reports are split off one pooled buffer, it reallocates once every handed out report is dropped
+        let mut pool = BytesMut::with_capacity(REPORT_SIZE * REPORT_POOL);
+        let max = options.read_timeout_ms.unwrap_or(reader.default_read_timeout_ms());
+        let mut cadence = ReadCadence::new(max.max(LOW_LATENCY_READ_TIMEOUT_MS), options.low_latency);
+
+        while !state.stop.load(Ordering::Acquire) {
+            let timeout_ms = cadence.next(!state.subscribes().is_empty());
+            if pool.capacity() < REPORT_SIZE {
+                pool.reserve(REPORT_SIZE * REPORT_POOL);
+            }
+            pool.resize(REPORT_SIZE, 0);
+            match reader.read_timeout(&mut pool, timeout_ms) {
+                // A report id alone carries no command to dispatch on
+                Ok(0 | 1) => {}
                 Ok(len) => {
-                    if len > 0 {
-                        if let Entry::Occupied(mut entry) = subscribes.entry(buf[1]) {
-                            if entry
-                                .get_mut()
-                                .send(OutMessage::Response(buf[1..].to_vec()))
-                            {
-                                entry.remove();
-                            }
-                        }
-                        if debug {
-                            println!("DEBUG: <- {:02X?}", &buf[..len])
-                        }
+                    // Only what was read, the rest of the buffer may hold an earlier report
+                    pool.truncate(len);
+                    let report = pool.split().freeze();
+                    state.hooks.report_received(&report);
+                    if debug {
+                        println!("DEBUG: <- {}", HexDump::new(&report))
                     }
+                    if let Entry::Occupied(mut entry) = state.subscribes().entry(report[1])
+                        && entry.get_mut().send(OutMessage::Response(report.slice(1..)))
+                    {
+                        entry.remove();
+                    }
+                }
+                Err(e) => {
+                    // Reads keep failing once the device is gone, stop instead of spinning on them
+                    let status = match e.hid_error_kind() {
+                        Some(HidErrorKind::NoDevice) | None => IoStatus::Disconnected,
+                        Some(_) => IoStatus::Failed(e.to_string()),
+                    };
+                    state.fail(status);
+                    return;
                 }
-                Err(e) => Self::handle_hid_error(&mut subscribes, e),
             }
         }
     }
 
     // == Windows specific COM ==
 
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
+    #[deprecated(note = "walks the device tree on the calling thread, use `get_com_port_async`")]
     pub fn get_com_port(&mut self) -> HinataResult<String> {
         let instance_id = self.get_com_instance_id()?;
         crate::utils::com::get_com_port_by_com_instance_id(&instance_id)
     }
 
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
+    pub async fn get_com_port_async(&mut self) -> HinataResult<String> {
+        let instance_id = self.get_com_instance_id_async().await?;
+        crate::utils::com::get_com_port_by_com_instance_id_async(&instance_id).await
+    }
+
+    /// Same as [`HinataDeviceBuilder::get_com_instance_id`] without blocking the runtime
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
+    pub async fn get_com_instance_id_async(&self) -> HinataResult<String> {
+        if let Some(id) = self.com_instance_id.get() {
+            return Ok(id.clone());
+        }
+
+        let path_read = match &self.connection {
+            HidConnectionBuilder::Dual { read_path, .. } => read_path,
+            HidConnectionBuilder::Single { path, .. } => path,
+        };
+
+        let instance_id = crate::utils::com::get_com_instance_id_by_hid_instance_id_async(path_read).await?;
+        let _ = self.com_instance_id.set(instance_id.clone());
+        Ok(instance_id)
+    }
+
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
     pub fn get_com_instance_id(&self) -> HinataResult<String> {
         if let Some(id) = self.com_instance_id.get() {
             return Ok(id.clone());
@@ -247,10 +524,15 @@ impl HinataDeviceBuilder {
     }
 }
 
+pub(crate) fn find_devices_inner(exclude: Vec<String>) -> HinataResult<Vec<HinataDeviceBuilder>> {
+    find_devices_with(Arc::new(HidApiBackend), exclude)
+}
+
 #[cfg(not(target_os = "macos"))]
-pub(crate) fn find_devices_inner(
+pub(crate) fn find_devices_with(
+    backend: Arc<dyn HidBackend>,
     exclude: Vec<String>,
-) -> Result<Vec<HinataDeviceBuilder>, HidError> {
+) -> HinataResult<Vec<HinataDeviceBuilder>> {
     struct PreDeviceBuilder {
         read: Option<(CString, String)>,
         write: Option<(CString, String)>,
@@ -258,31 +540,24 @@ pub(crate) fn find_devices_inner(
         pid: Option<u16>,
     }
 
-    let mut hid = HidApi::new()?;
-    hid.add_devices(HINATA_VID, 0)?;
-
     let mut devices: HashMap<String, PreDeviceBuilder> = HashMap::new();
 
-    for device in hid.device_list() {
-        if device.vendor_id() != HINATA_VID {
-            continue;
-        }
-
-        if let Some((path, instance)) = parse_hid_path(&device.path().to_string_lossy()) {
+    for device in backend.enumerate(HINATA_VID)? {
+        if let Some((path, instance)) = parse_hid_path(&device.path.to_string_lossy()) {
             if exclude.contains(&instance) {
                 continue;
             };
             let entry = devices.entry(instance).or_insert(PreDeviceBuilder {
                 read: None,
                 write: None,
-                device_name: device.product_string().map(|s| s.to_string()),
-                pid: Some(device.product_id()),
+                device_name: device.product_string.clone(),
+                pid: Some(device.product_id),
             });
 
-            if device.usage_page() == USAGE_PAGE_READ {
-                entry.read = Some((device.path().to_owned(), path));
-            } else if device.usage_page() == USAGE_PAGE_WRITE {
-                entry.write = Some((device.path().to_owned(), path));
+            if device.usage_page == USAGE_PAGE_READ {
+                entry.read = Some((device.path, path));
+            } else if device.usage_page == USAGE_PAGE_WRITE {
+                entry.write = Some((device.path, path));
             }
         }
     }
@@ -308,6 +583,9 @@ pub(crate) fn find_devices_inner(
                     device_name: n,
                     pid: p,
                     com_instance_id: OnceLock::new(),
+                    options: DeviceOptions::default(),
+                    backend: backend.clone(),
+                    hooks: Hooks::default(),
                 })
             } else {
                 None
@@ -317,32 +595,33 @@ pub(crate) fn find_devices_inner(
 }
 
 #[cfg(target_os = "macos")]
-pub(crate) fn find_devices_inner(
+pub(crate) fn find_devices_with(
+    backend: Arc<dyn HidBackend>,
     exclude: Vec<String>,
-) -> Result<Vec<HinataDeviceBuilder>, HidError> {
-    let mut hid = HidApi::new()?;
-    hid.add_devices(HINATA_VID, 0)?;
-
+) -> HinataResult<Vec<HinataDeviceBuilder>> {
     let mut devices = Vec::new();
 
-    for device in hid.device_list() {
-        if device.vendor_id() == HINATA_VID && device.usage_page() == USAGE_PAGE_WRITE {
+    for device in backend.enumerate(HINATA_VID)? {
+        if device.usage_page == USAGE_PAGE_WRITE {
             if let (Some((instance, _)), Some(name)) = (
-                parse_hid_path(&device.path().to_string_lossy()),
-                device.product_string(),
+                parse_hid_path(&device.path.to_string_lossy()),
+                &device.product_string,
             ) {
                 if exclude.contains(&instance) {
                     continue;
                 };
                 devices.push(HinataDeviceBuilder {
                     connection: HidConnectionBuilder::Single {
-                        inner: device.path().to_owned(),
+                        inner: device.path.clone(),
                         path: instance.clone(),
                     }, // 使用统一封装
                     instance_id: instance.clone(),
                     device_name: name.to_string(),
-                    pid: device.product_id(),
+                    pid: device.product_id,
                     com_instance_id: OnceLock::new(),
+                    options: DeviceOptions::default(),
+                    backend: backend.clone(),
+                    hooks: Hooks::default(),
                 });
             };
         }
@@ -350,10 +629,152 @@ pub(crate) fn find_devices_inner(
     Ok(devices)
 }
 
+#[test]
+fn read_cadence_test() {
+    let mut cadence = ReadCadence::new(16, true);
+    assert_eq!(cadence.next(true), 1);
+    assert_eq!(cadence.next(false), 2);
+    assert_eq!(cadence.next(false), 4);
+    assert_eq!((0..4).map(|_| cadence.next(false)).last(), Some(16));
+    assert_eq!(cadence.next(true), 1);
+    assert_eq!(ReadCadence::new(16, false).next(true), 16);
+}
+
+/// Answers every write with the same report, like the firmware echoing a command id
+#[cfg(test)]
+struct LoopbackTransport(Mutex<std::collections::VecDeque<Vec<u8>>>);
+
+#[cfg(test)]
+impl HidTransport for LoopbackTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        match self.0.lock().unwrap().pop_front() {
+            Some(report) => {
+                buf[..report.len()].copy_from_slice(&report);
+                Ok(report.len())
+            }
+            None => {
+                thread::sleep(std::time::Duration::from_millis(timeout_ms as u64));
+                Ok(0)
+            }
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        self.0.lock().unwrap().push_back(data.to_vec());
+        Ok(data.len())
+    }
+}
+
+#[test]
+fn io_loop_test() {
+    use crate::message::UnSubscribePolicy;
+
+    let transport = LoopbackTransport(Mutex::new(Default::default()));
+    let (tx, rx) = mpsc::channel(8);
+    let options = DeviceOptions { read_timeout_ms: Some(5), low_latency: true, ..Default::default() };
+    let (status, _) = watch::channel(IoStatus::Running);
+    let reports = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counted = reports.clone();
+    let hooks = Hooks::new().on_report_received(move |report| {
+        assert_eq!(report[..2], [1, 0xE6]);
+        counted.fetch_add(1, Ordering::Relaxed);
+    });
+    let handler = thread::spawn(move || HinataDeviceBuilder::io_loop(HidConnection::Single(Box::new(transport)), rx, options, IoState::new(status, hooks), false));
+
+    let (subscription, mut responses) = Subscription::new(UnSubscribePolicy::Count(1));
+    // Echoed back as a bare report id, which is dropped
+    tx.blocking_send(InMessage::SendPacket(vec![1])).unwrap();
+    tx.blocking_send(InMessage::SendPacketAndSubscribe(vec![1, 0xE6, 0xAA], subscription)).unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    assert!(matches!(runtime.block_on(responses.recv()), Some(OutMessage::Response(data)) if data[..] == [0xE6, 0xAA]));
+
+    drop(tx);
+    handler.join().unwrap();
+    assert_eq!(reports.load(Ordering::Relaxed), 1);
+}
+
+/// A [`LoopbackTransport`] whose writes of command 0xE7 fail
+#[cfg(test)]
+struct RejectingTransport(LoopbackTransport);
+
+#[cfg(test)]
+impl HidTransport for RejectingTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout_ms: i32) -> HinataResult<usize> {
+        self.0.read_timeout(buf, timeout_ms)
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        match data[1] {
+            0xE7 => Err(Error::Other("write rejected".into())),
+            _ => self.0.write(data),
+        }
+    }
+}
+
+#[test]
+fn io_write_error_test() {
+    use crate::message::UnSubscribePolicy;
+
+    let transport = RejectingTransport(LoopbackTransport(Mutex::new(Default::default())));
+    let (tx, rx) = mpsc::channel(8);
+    let options = DeviceOptions { read_timeout_ms: Some(5), low_latency: true, ..Default::default() };
+    let (status, _) = watch::channel(IoStatus::Running);
+    let handler = thread::spawn(move || HinataDeviceBuilder::io_loop(HidConnection::Single(Box::new(transport)), rx, options, IoState::new(status, Hooks::new()), false));
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+    let (listener, mut reports) = Subscription::new(UnSubscribePolicy::Count(1));
+    tx.blocking_send(InMessage::Subscribe(0xE6, listener)).unwrap();
+    let (subscription, mut responses) = Subscription::once();
+    tx.blocking_send(InMessage::SendPacketAndSubscribe(vec![1, 0xE7], subscription)).unwrap();
+    assert!(matches!(runtime.block_on(responses.recv()), Some(OutMessage::IoFailed(reason)) if reason.contains("write rejected")));
+
+    // Only the request that was not written failed
+    tx.blocking_send(InMessage::SendPacket(vec![1, 0xE6, 0xAA])).unwrap();
+    assert!(matches!(runtime.block_on(reports.recv()), Some(OutMessage::Response(data)) if data[..] == [0xE6, 0xAA]));
+
+    drop(tx);
+    handler.join().unwrap();
+}
+
+#[cfg(test)]
+struct PanickingTransport;
+
+#[cfg(test)]
+impl HidTransport for PanickingTransport {
+    fn read_timeout(&self, _: &mut [u8], _: i32) -> HinataResult<usize> {
+        panic!("read exploded")
+    }
+
+    fn write(&self, data: &[u8]) -> HinataResult<usize> {
+        Ok(data.len())
+    }
+}
+
+#[test]
+fn io_thread_panic_test() {
+    let (tx, rx) = mpsc::channel(8);
+    let (status, mut status_rx) = watch::channel(IoStatus::Running);
+    let connection = HidConnection::Dual { read: Box::new(PanickingTransport), write: Box::new(PanickingTransport) };
+    let disconnects = Arc::new(Mutex::new(Vec::new()));
+    let seen = disconnects.clone();
+    let hooks = Hooks::new().on_disconnect(move |status| seen.lock().unwrap().push(status.clone()));
+    let handler = thread::spawn(move || HinataDeviceBuilder::io_loop(connection, rx, DeviceOptions::default(), IoState::new(status, hooks), false));
+
+    let (subscription, mut responses) = Subscription::once();
+    tx.blocking_send(InMessage::SendPacketAndSubscribe(vec![1, 0xE6], subscription)).unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    assert!(matches!(runtime.block_on(responses.recv()), Some(OutMessage::IoThreadPanicked(reason)) if reason == "read exploded"));
+    assert_eq!(*status_rx.borrow_and_update(), IoStatus::Panicked("read exploded".into()));
+
+    drop(tx);
+    handler.join().unwrap();
+    assert_eq!(*disconnects.lock().unwrap(), vec![IoStatus::Panicked("read exploded".into())]);
+}
+
 #[test]
 fn test_hid_init() {
     let start = std::time::Instant::now();
-    let mut hid = HidApi::new().unwrap();
+    let mut hid = hidapi::HidApi::new().unwrap();
     hid.add_devices(HINATA_VID, 0).unwrap();
     let duration = start.elapsed();
     println!("Time elapsed: {:?}", duration);
@@ -362,7 +783,7 @@ fn test_hid_init() {
 #[test]
 fn test_hid_all_init() {
     let start = std::time::Instant::now();
-    let mut hid = HidApi::new().unwrap();
+    let mut hid = hidapi::HidApi::new().unwrap();
     hid.add_devices(0, 0).unwrap();
     let duration = start.elapsed();
     println!("Time elapsed: {:?}", duration);
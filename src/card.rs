@@ -1,10 +1,139 @@
-#[derive(Debug, PartialEq)]
+pub mod aime;
+pub mod amiibo;
+#[cfg(feature = "crypto")]
+pub mod desfire;
+pub mod emv;
+pub mod felica;
+#[cfg(feature = "crypto")]
+pub mod felica_lite;
+pub mod mifare_classic;
+pub mod ntag;
+#[cfg(feature = "transit")]
+pub mod transit;
+pub mod ultralight;
+#[cfg(feature = "crypto")]
+pub mod ultralight_c;
+pub mod virtual_;
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use crate::utils::id_format::IdFormat;
+
+/// Equality and hashing go by card type and [`CardId::id_bytes`], a card read again is the same key
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum PassiveTarget {
     Iso14443a(Iso14443a),
     Felica(Felica)
 }
 
-#[derive(Debug, PartialEq)]
+/// e.g. `ISO14443-A UID 04A1B2C3, SAK 08, ATQA 0004 (MIFARE Classic 1K)`
+impl fmt::Display for PassiveTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassiveTarget::Iso14443a(card) => write!(f, "ISO14443-A {card}"),
+            PassiveTarget::Felica(card) => write!(f, "FeliCa {card}"),
+        }
+    }
+}
+
+/// Identifier access shared by every kind of target, the UID for ISO14443-A and the IDm for FeliCa
+pub trait CardId {
+    fn id_bytes(&self) -> &[u8];
+
+    /// Upper-case hex of [`CardId::id_bytes`]
+    fn display_id(&self) -> String {
+        IdFormat::new(self.id_bytes()).hex()
+    }
+}
+
+impl CardId for PassiveTarget {
+    fn id_bytes(&self) -> &[u8] {
+        match self {
+            PassiveTarget::Iso14443a(card) => card.id_bytes(),
+            PassiveTarget::Felica(card) => card.id_bytes(),
+        }
+    }
+}
+
+impl CardId for Iso14443a {
+    fn id_bytes(&self) -> &[u8] {
+        &self.uid
+    }
+}
+
+impl CardId for Felica {
+    fn id_bytes(&self) -> &[u8] {
+        &self.idm
+    }
+}
+
+impl PartialEq for Iso14443a {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl Eq for Iso14443a {}
+
+impl Hash for Iso14443a {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uid.hash(state)
+    }
+}
+
+impl PartialEq for Felica {
+    fn eq(&self, other: &Self) -> bool {
+        self.idm == other.idm
+    }
+}
+
+impl Eq for Felica {}
+
+impl Hash for Felica {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.idm.hash(state)
+    }
+}
+
+/// Card family of an ISO14443-A target, following the NXP SAK/ATQA decision tree (AN10833)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CardClass {
+    MifareMini,
+    MifareClassic1K,
+    MifareClassic4K,
+    /// MIFARE Plus in security level 2 or 3
+    MifarePlus,
+    /// MIFARE Ultralight, Ultralight C/EV1 and NTAG
+    Ultralight,
+    Desfire,
+    /// SmartMX with MIFARE Classic emulation
+    SmartMx,
+    /// Any other ISO14443-4 compliant card
+    Iso14443_4,
+    Unknown,
+}
+
+impl fmt::Display for CardClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CardClass::MifareMini => "MIFARE Mini",
+            CardClass::MifareClassic1K => "MIFARE Classic 1K",
+            CardClass::MifareClassic4K => "MIFARE Classic 4K",
+            CardClass::MifarePlus => "MIFARE Plus",
+            CardClass::Ultralight => "MIFARE Ultralight / NTAG",
+            CardClass::Desfire => "MIFARE DESFire",
+            CardClass::SmartMx => "SmartMX",
+            CardClass::Iso14443_4 => "ISO14443-4",
+            CardClass::Unknown => "unknown card",
+        })
+    }
+}
+
+/// Equality and hashing go by the UID only
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
 pub struct Iso14443a {
     uid: Vec<u8>,
     sak: u8,
@@ -24,6 +153,10 @@ impl Iso14443a {
         &self.uid
     }
 
+    pub fn uid_format(&self) -> IdFormat<'_> {
+        IdFormat::new(&self.uid)
+    }
+
     pub fn get_sak(&self) -> u8 {
         self.sak
     }
@@ -36,9 +169,36 @@ impl Iso14443a {
     pub fn is_mifare_classic(&self) -> bool {
         (self.sak == 8 || self.sak == 0x18 || self.sak == 0x88) && self.uid.len() == 4
     }
+
+    pub fn classify(&self) -> CardClass {
+        match self.sak {
+            0x00 if self.aqta == 0x0044 => CardClass::Ultralight,
+            0x09 => CardClass::MifareMini,
+            0x08 | 0x88 => CardClass::MifareClassic1K,
+            0x18 => CardClass::MifareClassic4K,
+            0x10 | 0x11 => CardClass::MifarePlus,
+            0x28 | 0x38 => CardClass::SmartMx,
+            0x20 => match self.aqta {
+                0x0344 | 0x0304 => CardClass::Desfire,
+                0x0002 | 0x0004 | 0x0042 | 0x0044 => CardClass::MifarePlus,
+                _ => CardClass::Iso14443_4,
+            },
+            sak if sak & 0x20 != 0 => CardClass::Iso14443_4,
+            _ => CardClass::Unknown,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+/// UID, SAK and ATQA in hex followed by the [`CardClass`] they decode to
+impl fmt::Display for Iso14443a {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UID {}, SAK {:02X}, ATQA {:04X} ({})", self.uid_format().hex(), self.sak, self.aqta, self.classify())
+    }
+}
+
+/// Equality and hashing go by the IDm only
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
 pub struct Felica {
     idm: [u8; 8],
     pmm: [u8; 8],
@@ -57,6 +217,10 @@ impl Felica {
     pub fn get_idm(&self) -> &[u8; 8] {
         &self.idm
     }
+
+    pub fn idm_format(&self) -> IdFormat<'_> {
+        IdFormat::new(&self.idm)
+    }
     
     pub fn get_pmm(&self) -> &[u8; 8] {
         &self.pmm
@@ -65,4 +229,76 @@ impl Felica {
     pub fn get_system_codes(&self) -> &[u16] {
         &self.system_codes
     }
-}
\ No newline at end of file
+
+    /// `None` when the system codes are unknown, they are only filled in for cards polled with
+    /// [`RequestCode::SystemCode`](crate::pn532::RequestCode::SystemCode)
+    pub fn is_amusement_ic(&self) -> Option<bool> {
+        (!self.system_codes.is_empty()).then(|| self.system_codes.contains(&felica::SYSTEM_CODE_AMUSEMENT_IC))
+    }
+
+    /// Each system code paired with its name, `None` for codes that are not well known
+    pub fn system_code_names(&self) -> Vec<(u16, Option<&'static str>)> {
+        self.system_codes.iter().map(|&code| (code, felica::system_code_name(code))).collect()
+    }
+}
+
+/// IDm and PMm in hex, then the system codes with their names where known
+impl fmt::Display for Felica {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IDm {}, PMm {}", self.idm_format().hex(), IdFormat::new(&self.pmm).hex())?;
+        for (i, (code, name)) in self.system_code_names().into_iter().enumerate() {
+            f.write_str(if i == 0 { ", system codes " } else { ", " })?;
+            write!(f, "{code:04X}")?;
+            if let Some(name) = name {
+                write!(f, " ({name})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn card_id_test() {
+    let target = PassiveTarget::Felica(Felica::new([0x01, 0x2E, 0, 0, 0, 0, 0, 0xFF], [0; 8], vec![]));
+    assert_eq!(target.display_id(), "012E0000000000FF");
+    assert_eq!(target.id_bytes().len(), 8);
+}
+
+#[test]
+fn felica_system_code_test() {
+    let felica = Felica::new([0; 8], [0; 8], vec![felica::SYSTEM_CODE_AMUSEMENT_IC, 0x1234]);
+    assert_eq!(felica.is_amusement_ic(), Some(true));
+    assert_eq!(felica.system_code_names(), vec![(0x88B4, Some("Amusement IC")), (0x1234, None)]);
+    assert_eq!(Felica::new([0; 8], [0; 8], vec![felica::SYSTEM_CODE_TRANSIT]).is_amusement_ic(), Some(false));
+    assert_eq!(Felica::new([0; 8], [0; 8], vec![]).is_amusement_ic(), None);
+}
+
+#[test]
+fn classify_test() {
+    assert_eq!(Iso14443a::new(vec![0; 4], 0x08, 0x0004).classify(), CardClass::MifareClassic1K);
+    assert_eq!(Iso14443a::new(vec![0; 7], 0x00, 0x0044).classify(), CardClass::Ultralight);
+    assert_eq!(Iso14443a::new(vec![0; 7], 0x20, 0x0344).classify(), CardClass::Desfire);
+    assert_eq!(Iso14443a::new(vec![0; 4], 0x20, 0x0008).classify(), CardClass::Iso14443_4);
+    assert_eq!(Iso14443a::new(vec![0; 4], 0x01, 0x0004).classify(), CardClass::Unknown);
+}
+
+#[test]
+fn card_display_test() {
+    let card = PassiveTarget::Iso14443a(Iso14443a::new(vec![0x04, 0xA1, 0xB2, 0xC3], 0x08, 0x0004));
+    assert_eq!(card.to_string(), "ISO14443-A UID 04A1B2C3, SAK 08, ATQA 0004 (MIFARE Classic 1K)");
+    let card = Felica::new([0x01, 0x2E, 0, 0, 0, 0, 0, 0xFF], [0x10; 8], vec![felica::SYSTEM_CODE_AMUSEMENT_IC, 0x1234]);
+    assert_eq!(card.to_string(), "IDm 012E0000000000FF, PMm 1010101010101010, system codes 88B4 (Amusement IC), 1234");
+}
+
+#[test]
+fn card_identity_test() {
+    use std::collections::HashSet;
+
+    let cards: HashSet<PassiveTarget> = [
+        PassiveTarget::Iso14443a(Iso14443a::new(vec![1, 2, 3, 4], 0x08, 0x0004)),
+        PassiveTarget::Iso14443a(Iso14443a::new(vec![1, 2, 3, 4], 0x88, 0x0004)),
+        PassiveTarget::Felica(Felica::new([1; 8], [0; 8], vec![])),
+        PassiveTarget::Felica(Felica::new([1; 8], [2; 8], vec![felica::SYSTEM_CODE_AMUSEMENT_IC])),
+    ].into_iter().collect();
+    assert_eq!(cards.len(), 2);
+}
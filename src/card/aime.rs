@@ -0,0 +1,331 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::card::{felica, Felica, Iso14443a, PassiveTarget};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{KeyType, Pn532, Pn532Port};
+use crate::utils::id_format::IdFormat;
+use crate::utils::spad0::spad0_decrypt;
+#[cfg(feature = "crypto")]
+use crate::utils::crypto::CipherKey;
+
+/// Key A of sector 0 on SEGA Aime cards, "WCCFv2"
+pub const SEGA_KEY_A: [u8; 6] = [0x57, 0x43, 0x43, 0x46, 0x76, 0x32];
+/// Key A of sector 0 on Bandai Namco Passport cards
+pub const BANDAI_NAMCO_KEY_A: [u8; 6] = [0x60, 0x90, 0xD0, 0x06, 0x32, 0xF5];
+
+/// Amusement IC cards are FeliCa Lite-S, polled through this system code
+pub const SYSTEM_CODE: u16 = felica::SYSTEM_CODE_AMUSEMENT_IC;
+const SERVICE_READ: u16 = 0x000B;
+
+/// MIFARE block holding the access code in its last 10 bytes
+const ACCESS_CODE_BLOCK: u8 = 2;
+/// FeliCa S_PAD0 block, encrypted on Amusement IC cards
+const SPAD0_BLOCK: u16 = 0x8000;
+
+/// 20 digit access code, stored as 10 BCD bytes
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AccessCode([u8; 10]);
+
+impl AccessCode {
+    pub fn from_bcd(data: &[u8]) -> HinataResult<Self> {
+        let bcd: [u8; 10] = data.try_into()
+            .map_err(|_| Error::Parse("Access code must be 10 bytes".into()))?;
+        if bcd.iter().any(|b| b >> 4 > 9 || b & 0x0F > 9) {
+            return Err(Error::Parse("Access code is not BCD".into()));
+        }
+        Ok(Self(bcd))
+    }
+
+    pub fn get_bcd(&self) -> &[u8; 10] {
+        &self.0
+    }
+
+    /// All-zero codes mark blank or unregistered cards
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+}
+
+impl fmt::Display for AccessCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02X}"))
+    }
+}
+
+impl FromStr for AccessCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 20 || !s.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(Error::Parse("Access code must be 20 digits".into()));
+        }
+        let bcd: Vec<u8> = s.as_bytes()
+            .chunks(2)
+            .map(|pair| ((pair[0] - b'0') << 4) | (pair[1] - b'0'))
+            .collect();
+        Self::from_bcd(&bcd)
+    }
+}
+
+/// Where the access code was read from
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AimeSource {
+    /// FeliCa based Amusement IC card, identified by its IDm
+    AmusementIc { idm: [u8; 8] },
+    /// Legacy MIFARE Classic card, `key` is the key A that opened sector 0
+    Mifare { uid: Vec<u8>, key: [u8; 6] },
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AimeCard {
+    pub access_code: AccessCode,
+    pub id_source: AimeSource,
+}
+
+/// Any card an arcade frontend may be handed, with the ID the matching backend expects
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArcadeCard {
+    Aime(AimeCard),
+    /// Konami e-Amusement pass on FeliCa, keyed by IDm
+    EAmusement { idm: [u8; 8] },
+    /// Any other card, keyed by its IDm or UID. NESiCA cards land here too, no signature to recognise them by is known.
+    Unknown { id: Vec<u8> },
+}
+
+impl ArcadeCard {
+    /// Canonical ID, the access code for Aime cards and the upper-case hex IDm or UID otherwise
+    pub fn get_id(&self) -> String {
+        match self {
+            Self::Aime(card) => card.access_code.to_string(),
+            Self::EAmusement { idm } => IdFormat::new(idm).hex(),
+            Self::Unknown { id } => IdFormat::new(id).hex(),
+        }
+    }
+
+    /// KONAMI ID printed on e-Amusement cards
+    #[cfg(feature = "crypto")]
+    pub fn get_konami_id(&self) -> Option<String> {
+        match self {
+            Self::EAmusement { idm } => Some(konami_id_encode(idm)),
+            _ => None,
+        }
+    }
+}
+
+/// FeliCa e-Amusement passes have IDs starting with `012E`, the prefix bemaniutils accepts next to the `E004` of ISO15693 passes
+fn is_e_amusement_idm(idm: &[u8; 8]) -> bool {
+    idm[..2] == [0x01, 0x2E]
+}
+
+#[cfg(feature = "crypto")]
+const KONAMI_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKLMNPRSTUWXYZ";
+
+/// 3DES key used by e-Amusement, each byte of the seed shifted left once
+#[cfg(feature = "crypto")]
+fn konami_cipher() -> CipherKey {
+    let mut key = *b"?I'llB2c.YouXXXeMeHaYpy!";
+    key.iter_mut().for_each(|b| *b <<= 1);
+    CipherKey::tdes3(&key)
+}
+
+#[cfg(feature = "crypto")]
+fn konami_checksum(groups: &[u8]) -> u8 {
+    let mut checksum: u32 = groups[..15].iter()
+        .enumerate()
+        .map(|(i, &g)| (i as u32 % 3 + 1) * g as u32)
+        .sum();
+    while checksum >= 0x20 {
+        checksum = (checksum & 0x1F) + (checksum >> 5);
+    }
+    checksum as u8
+}
+
+/// Convert a FeliCa IDm or an `E004` ISO15693 UID into its 16 character KONAMI ID
+#[cfg(feature = "crypto")]
+pub fn konami_id_encode(id: &[u8; 8]) -> String {
+    let card_type = if id[..2] == [0xE0, 0x04] { 1 } else { 2 };
+    let mut block = *id;
+    block.reverse();
+    konami_cipher().encrypt_block(&mut block);
+
+    let bit = |i: usize| if i < 64 { (block[i >> 3] >> (7 - (i & 7))) & 1 } else { 0 };
+    let mut groups = [0u8; 16];
+    for (i, group) in groups.iter_mut().take(13).enumerate() {
+        *group = (0..5).fold(0, |acc, j| (acc << 1) | bit(i * 5 + j));
+    }
+    groups[13] = 1;
+    groups[14] = card_type;
+    groups[0] ^= card_type;
+    for i in 1..14 {
+        groups[i] ^= groups[i - 1];
+    }
+    groups[15] = konami_checksum(&groups);
+
+    groups.iter().map(|&g| KONAMI_ALPHABET[g as usize] as char).collect()
+}
+
+/// Recover the IDm or UID behind a KONAMI ID, `I` and `O` are read as `1` and `0`
+#[cfg(feature = "crypto")]
+pub fn konami_id_decode(konami_id: &str) -> HinataResult<[u8; 8]> {
+    let mut groups = [0u8; 16];
+    if konami_id.len() != groups.len() {
+        return Err(Error::Parse("KONAMI ID must be 16 characters".into()));
+    }
+    for (group, c) in groups.iter_mut().zip(konami_id.to_ascii_uppercase().bytes()) {
+        let c = match c {
+            b'I' => b'1',
+            b'O' => b'0',
+            c => c,
+        };
+        *group = KONAMI_ALPHABET.iter()
+            .position(|&a| a == c)
+            .ok_or(Error::Parse(format!("Invalid KONAMI ID character {}", c as char)))? as u8;
+    }
+    if groups[14] != 1 && groups[14] != 2 {
+        return Err(Error::Parse("Unknown KONAMI ID card type".into()));
+    }
+    if groups[15] != konami_checksum(&groups) {
+        return Err(Error::Parse("KONAMI ID checksum mismatch".into()));
+    }
+    for i in (1..14).rev() {
+        groups[i] ^= groups[i - 1];
+    }
+    groups[0] ^= groups[14];
+
+    let mut block = [0u8; 8];
+    for i in 0..64 {
+        let bit = (groups[i / 5] >> (4 - i % 5)) & 1;
+        block[i >> 3] |= bit << (7 - (i & 7));
+    }
+    konami_cipher().decrypt_block(&mut block);
+    block.reverse();
+    Ok(block)
+}
+
+/// Access code from a decrypted S_PAD0 block
+pub fn access_code_from_spad0(block: &[u8]) -> HinataResult<AccessCode> {
+    let plain = spad0_decrypt(block).map_err(Error::Parse)?;
+    AccessCode::from_bcd(plain.get(6..16).ok_or(Error::Parse("S_PAD0 too short".into()))?)
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    /// Poll for an Amusement IC card first, then for a MIFARE Aime card
+    pub async fn read_aime(&mut self) -> HinataResult<Option<AimeCard>> {
+        if let Some(felica) = self.poll_felica(SYSTEM_CODE).await? {
+            return self.read_aime_felica(1, &felica).await.map(Some);
+        }
+        let targets = self.in_list_passive_target(0, 1, &[]).await?;
+        match targets.first() {
+            Some(PassiveTarget::Iso14443a(card)) if card.is_mifare_classic() => {
+                self.read_aime_mifare(1, card).await.map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Poll every card family and sort the card into the arcade ecosystem it belongs to, [`ArcadeCard::Unknown`] when none matches
+    pub async fn identify_arcade_card(&mut self) -> HinataResult<Option<ArcadeCard>> {
+        if let Some(felica) = self.poll_felica(0xFFFF).await? {
+            if felica.get_system_codes().contains(&SYSTEM_CODE) {
+                return self.read_aime_felica(1, &felica).await.map(|card| Some(ArcadeCard::Aime(card)));
+            }
+            let idm = *felica.get_idm();
+            return Ok(Some(if is_e_amusement_idm(&idm) {
+                ArcadeCard::EAmusement { idm }
+            } else {
+                ArcadeCard::Unknown { id: idm.to_vec() }
+            }));
+        }
+        let targets = self.in_list_passive_target(0, 1, &[]).await?;
+        let Some(PassiveTarget::Iso14443a(card)) = targets.first() else {
+            return Ok(None);
+        };
+        if card.is_mifare_classic() {
+            match self.read_aime_mifare(1, card).await {
+                Ok(aime) => return Ok(Some(ArcadeCard::Aime(aime))),
+                Err(Error::NotSupport(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(ArcadeCard::Unknown { id: card.get_uid().to_vec() }))
+    }
+
+    pub async fn read_aime_felica(&mut self, tg: u8, felica: &Felica) -> HinataResult<AimeCard> {
+        if !felica.get_system_codes().is_empty() && !felica.get_system_codes().contains(&SYSTEM_CODE) {
+            return Err(Error::NotSupport("FeliCa card is not an Amusement IC".into()));
+        }
+        let idm = *felica.get_idm();
+        let blocks = self.felica_read_without_encryption(tg, &idm, &[SERVICE_READ], &[SPAD0_BLOCK]).await?;
+        let block = blocks.first().ok_or(Error::Protocol("Empty S_PAD0 read".into()))?;
+        Ok(AimeCard {
+            access_code: access_code_from_spad0(block)?,
+            id_source: AimeSource::AmusementIc { idm },
+        })
+    }
+
+    /// Try the SEGA and Bandai Namco keys on sector 0 and read the access code from block 2
+    pub async fn read_aime_mifare(&mut self, mut tg: u8, card: &Iso14443a) -> HinataResult<AimeCard> {
+        let uid = card.get_uid();
+        for key in [SEGA_KEY_A, BANDAI_NAMCO_KEY_A] {
+            if let Err(e) = self.mifare_classic_auth(tg, uid, ACCESS_CODE_BLOCK, KeyType::A, &key).await {
+                if !matches!(e, Error::Pn532(_)) {
+                    return Err(e);
+                }
+                tg = self.mifare_classic_reselect(tg, uid).await?;
+                continue;
+            }
+            let block = self.mifare_classic_read_block(tg, ACCESS_CODE_BLOCK).await?;
+            return Ok(AimeCard {
+                access_code: AccessCode::from_bcd(&block[6..16])?,
+                id_source: AimeSource::Mifare { uid: uid.to_vec(), key },
+            });
+        }
+        Err(Error::NotSupport("MIFARE card is not an Aime card".into()))
+    }
+}
+
+#[test]
+fn access_code_test() {
+    let code: AccessCode = "01234567890123456789".parse().unwrap();
+    assert_eq!(code.get_bcd()[0], 0x01);
+    assert_eq!(code.to_string(), "01234567890123456789");
+    assert!("0123456789012345678A".parse::<AccessCode>().is_err());
+    assert!(AccessCode::from_bcd(&[0xFF; 10]).is_err());
+}
+
+#[test]
+fn arcade_card_test() {
+    assert!(is_e_amusement_idm(&[0x01, 0x2E, 0x3F, 0x4A, 0x5B, 0x6C, 0x7D, 0x8E]));
+    // A transit card is no e-Amusement pass
+    assert!(!is_e_amusement_idm(&[0x01, 0x01, 0x12, 0x01, 0x2E, 0x15, 0x6B, 0x0F]));
+    assert_eq!(ArcadeCard::Unknown { id: vec![0x04, 0xA2, 0x3B] }.get_id(), "04A23B");
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn konami_id_test() {
+    let idm = [0x01, 0x2E, 0x3F, 0x4A, 0x5B, 0x6C, 0x7D, 0x8E];
+    let konami_id = konami_id_encode(&idm);
+    assert_eq!(konami_id.len(), 16);
+    assert_eq!(&konami_id[14..15], "2");
+    assert_eq!(konami_id_decode(&konami_id).unwrap(), idm);
+
+    let uid = [0xE0, 0x04, 0x01, 0x00, 0x12, 0x34, 0x56, 0x78];
+    let konami_id = konami_id_encode(&uid);
+    assert_eq!(&konami_id[14..15], "1");
+    assert_eq!(konami_id_decode(&konami_id.to_lowercase()).unwrap(), uid);
+
+    // Known cards, as in the bemaniutils card cipher tests
+    let known = [
+        ("S6E523E30ZK7ML1P", [0xE0, 0x04, 0x01, 0x00, 0x27, 0xA5, 0xFC, 0x68]),
+        ("78B592HZSM9E6712", [0xE0, 0x04, 0x01, 0x00, 0x27, 0xA6, 0x10, 0x2C]),
+    ];
+    for (konami_id, uid) in known {
+        assert_eq!(konami_id_encode(&uid), konami_id);
+        assert_eq!(konami_id_decode(konami_id).unwrap(), uid);
+    }
+}
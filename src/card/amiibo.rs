@@ -0,0 +1,139 @@
+use crate::card::ultralight::{UltralightType, PAGE_SIZE};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+
+/// Full NTAG215 memory, 135 pages
+pub const DUMP_SIZE: usize = 540;
+
+/// Capability container written on every amiibo
+pub const AMIIBO_CC: [u8; 4] = [0xF1, 0x10, 0xFF, 0xEE];
+/// First byte of page 4, marks the start of the amiibo data header
+const DATA_HEADER_MAGIC: u8 = 0xA5;
+
+const CC_OFFSET: usize = 3 * PAGE_SIZE;
+const HEADER_OFFSET: usize = 4 * PAGE_SIZE;
+/// Pages 21 and 22 hold the 8 byte amiibo ID
+const ID_OFFSET: usize = 21 * PAGE_SIZE;
+
+/// Identity stored in the unencrypted pages 21 and 22
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AmiiboId([u8; 8]);
+
+impl AmiiboId {
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        data.get(..8)
+            .map(|id| Self(id.try_into().expect("8 bytes")))
+            .ok_or(Error::Parse("amiibo ID must be 8 bytes".into()))
+    }
+
+    pub fn get_bytes(&self) -> &[u8; 8] {
+        &self.0
+    }
+
+    /// Game series, the top 12 bits of the character field
+    pub fn get_game_series(&self) -> u16 {
+        u16::from_be_bytes([self.0[0], self.0[1]]) >> 4
+    }
+
+    pub fn get_character(&self) -> u16 {
+        u16::from_be_bytes([self.0[0], self.0[1]])
+    }
+
+    pub fn get_variant(&self) -> u8 {
+        self.0[2]
+    }
+
+    /// 0 figure, 1 card, 2 yarn, 3 band
+    pub fn get_figure_type(&self) -> u8 {
+        self.0[3]
+    }
+
+    pub fn get_model_number(&self) -> u16 {
+        u16::from_be_bytes([self.0[4], self.0[5]])
+    }
+
+    pub fn get_series(&self) -> u8 {
+        self.0[6]
+    }
+
+    /// The ID as the 16 character hex string used by amiibo databases
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// Raw NTAG215 image of an amiibo
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AmiiboDump {
+    data: Vec<u8>,
+}
+
+impl AmiiboDump {
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        if data.len() < DUMP_SIZE {
+            return Err(Error::Parse(format!("amiibo dump must be {DUMP_SIZE} bytes")));
+        }
+        Ok(Self {
+            data: data[..DUMP_SIZE].to_vec(),
+        })
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn get_uid(&self) -> [u8; 7] {
+        let d = &self.data;
+        [d[0], d[1], d[2], d[4], d[5], d[6], d[7]]
+    }
+
+    pub fn get_id(&self) -> AmiiboId {
+        AmiiboId::from_bytes(&self.data[ID_OFFSET..]).expect("dump length checked")
+    }
+
+    /// Checks the capability container and data header every amiibo carries
+    pub fn is_amiibo(&self) -> bool {
+        is_amiibo_header(&self.data[CC_OFFSET..HEADER_OFFSET + 1])
+    }
+}
+
+/// `data` starts at page 3, the capability container
+fn is_amiibo_header(data: &[u8]) -> bool {
+    data.len() > PAGE_SIZE && data[..PAGE_SIZE] == AMIIBO_CC && data[PAGE_SIZE] == DATA_HEADER_MAGIC
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    /// Check the tag is an NTAG215 carrying amiibo data and return its ID
+    pub async fn amiibo_detect(&mut self) -> HinataResult<Option<AmiiboId>> {
+        if self.ntag_detect().await?.get_tag_type() != UltralightType::Ntag215 {
+            return Ok(None);
+        }
+        let header = self.ntag_fast_read(3, 4).await?;
+        if !is_amiibo_header(&header) {
+            return Ok(None);
+        }
+        let id = self.ntag_fast_read(21, 22).await?;
+        AmiiboId::from_bytes(&id).map(Some)
+    }
+
+    /// FAST_READ every page of an NTAG215
+    pub async fn amiibo_dump(&mut self) -> HinataResult<AmiiboDump> {
+        let data = self.ntag_fast_read(0, UltralightType::Ntag215.page_count() - 1).await?;
+        AmiiboDump::from_bytes(&data)
+    }
+}
+
+#[test]
+fn amiibo_dump_test() {
+    let mut data = vec![0u8; DUMP_SIZE];
+    data[CC_OFFSET..CC_OFFSET + 4].copy_from_slice(&AMIIBO_CC);
+    data[HEADER_OFFSET] = DATA_HEADER_MAGIC;
+    data[ID_OFFSET..ID_OFFSET + 8].copy_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x34, 0x00, 0x02]);
+    let dump = AmiiboDump::from_bytes(&data).unwrap();
+    assert!(dump.is_amiibo());
+    let id = dump.get_id();
+    assert_eq!(id.get_game_series(), 0x010);
+    assert_eq!(id.get_model_number(), 0x0034);
+    assert_eq!(id.to_hex(), "0100000000340002");
+}
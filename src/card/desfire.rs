@@ -0,0 +1,324 @@
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
+use crate::apdu::Apdu;
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+use crate::utils::crypto::{random_bytes, rotate_left, CipherKey};
+
+const DESFIRE_CLA: u8 = 0x90;
+const CMAC_LEN: usize = 8;
+
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum DesfireCommand {
+    AuthenticateIso = 0x1A,
+    AuthenticateAes = 0xAA,
+    GetVersion = 0x60,
+    GetApplicationIds = 0x6A,
+    SelectApplication = 0x5A,
+    GetFileIds = 0x6F,
+    ReadData = 0xBD,
+    WriteData = 0x3D,
+    GetValue = 0x6C,
+    Credit = 0x0C,
+    Debit = 0xDC,
+    CommitTransaction = 0xC7,
+    AbortTransaction = 0xA7,
+    AdditionalFrame = 0xAF,
+}
+
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum DesfireStatus {
+    Ok = 0x00,
+    NoChanges = 0x0C,
+    OutOfMemory = 0x0E,
+    IllegalCommand = 0x1C,
+    IntegrityError = 0x1E,
+    NoSuchKey = 0x40,
+    LengthError = 0x7E,
+    PermissionDenied = 0x9D,
+    ParameterError = 0x9E,
+    ApplicationNotFound = 0xA0,
+    ApplicationIntegrityError = 0xA1,
+    AuthenticationError = 0xAE,
+    AdditionalFrame = 0xAF,
+    BoundaryError = 0xBE,
+    PiccIntegrityError = 0xC1,
+    CommandAborted = 0xCA,
+    PiccDisabled = 0xCD,
+    CountError = 0xCE,
+    DuplicateError = 0xDE,
+    EepromError = 0xEE,
+    FileNotFound = 0xF0,
+    FileIntegrityError = 0xF1,
+}
+
+/// How data is protected on the wire, as configured in the file settings
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommMode {
+    Plain,
+    Mac,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesfireVersion {
+    pub hardware: [u8; 7],
+    pub software: [u8; 7],
+    pub uid: [u8; 7],
+    pub batch: [u8; 5],
+    pub production_week: u8,
+    pub production_year: u8,
+}
+
+impl DesfireVersion {
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        if data.len() < 28 {
+            return Err(Error::Protocol("DESFire version must be 28 bytes".into()));
+        }
+        Ok(Self {
+            hardware: data[0..7].try_into().expect("length checked"),
+            software: data[7..14].try_into().expect("length checked"),
+            uid: data[14..21].try_into().expect("length checked"),
+            batch: data[21..26].try_into().expect("length checked"),
+            production_week: data[26],
+            production_year: data[27],
+        })
+    }
+
+    /// Storage size in bytes, derived from the hardware storage size byte.
+    /// `None` for a size byte too large to be real, as a broken card or emulator may report.
+    pub fn storage_size(&self) -> Option<usize> {
+        1usize.checked_shl((self.hardware[5] >> 1) as u32)
+    }
+}
+
+struct Session {
+    key: CipherKey,
+    iv: Vec<u8>,
+}
+
+/// A DESFire EV1 card selected as target `tg`, native commands are wrapped in ISO 7816 APDUs
+pub struct Desfire<'p, 'a, P: Pn532Port> {
+    pn532: &'p mut Pn532<'a, P>,
+    tg: u8,
+    session: Option<Session>,
+}
+
+impl<'p, 'a, P: Pn532Port> Desfire<'p, 'a, P> {
+    pub fn new(pn532: &'p mut Pn532<'a, P>, tg: u8) -> Self {
+        Self {
+            pn532,
+            tg,
+            session: None,
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Send one native frame and return the card status with the response data
+    async fn transceive(&mut self, cmd: u8, data: &[u8]) -> HinataResult<(u8, Vec<u8>)> {
+        let mut apdu = Apdu::new(DESFIRE_CLA, cmd, 0x00, 0x00).with_le(256);
+        if !data.is_empty() {
+            apdu = apdu.with_data(data);
+        }
+        let response = self.pn532.transceive_apdu(self.tg, &apdu).await?;
+        if response.get_sw1() != 0x91 {
+            return Err(Error::Protocol(format!("Unexpected DESFire status word {:04X}", response.get_sw()).into()));
+        }
+        Ok((response.get_sw2(), response.into_data()))
+    }
+
+    fn check_status(status: u8) -> HinataResult<()> {
+        match DesfireStatus::from_u8(status) {
+            Some(DesfireStatus::Ok | DesfireStatus::NoChanges) => Ok(()),
+            Some(status) => Err(Error::Protocol(format!("DESFire error: {status:?}").into())),
+            None => Err(Error::Protocol(format!("Unknown DESFire status {status:02X}").into())),
+        }
+    }
+
+    /// Run a command including additional frames, keeping the CMAC chain of an authenticated session
+    async fn command(&mut self, cmd: DesfireCommand, header: &[u8], data: &[u8], mode: CommMode) -> HinataResult<Vec<u8>> {
+        let mut payload = header.to_vec();
+        payload.extend_from_slice(data);
+
+        if let Some(session) = self.session.as_mut() {
+            let mut input = vec![cmd as u8];
+            input.extend_from_slice(&payload);
+            session.iv = session.key.cmac(&session.iv, &input);
+            if mode == CommMode::Mac && !data.is_empty() {
+                payload.extend_from_slice(&session.iv[..CMAC_LEN]);
+            }
+        }
+
+        let (mut status, mut response) = self.transceive(cmd as u8, &payload).await?;
+        while status == DesfireStatus::AdditionalFrame as u8 {
+            let (next_status, next) = self.transceive(DesfireCommand::AdditionalFrame as u8, &[]).await?;
+            response.extend_from_slice(&next);
+            status = next_status;
+        }
+        if let Err(e) = Self::check_status(status) {
+            self.session = None;
+            return Err(e);
+        }
+
+        if let Some(session) = self.session.as_mut() {
+            let split = response.len().checked_sub(CMAC_LEN)
+                .ok_or(Error::Protocol("DESFire response is missing its CMAC".into()))?;
+            let mac = response.split_off(split);
+            let mut input = response.clone();
+            input.push(status);
+            session.iv = session.key.cmac(&session.iv, &input);
+            if session.iv[..CMAC_LEN] != mac[..] {
+                self.session = None;
+                return Err(Error::Protocol("DESFire response CMAC mismatch".into()));
+            }
+        }
+
+        Ok(response)
+    }
+
+    pub async fn get_version(&mut self) -> HinataResult<DesfireVersion> {
+        let data = self.command(DesfireCommand::GetVersion, &[], &[], CommMode::Plain).await?;
+        DesfireVersion::from_bytes(&data)
+    }
+
+    pub async fn get_application_ids(&mut self) -> HinataResult<Vec<[u8; 3]>> {
+        let data = self.command(DesfireCommand::GetApplicationIds, &[], &[], CommMode::Plain).await?;
+        Ok(data.chunks_exact(3).map(|aid| [aid[0], aid[1], aid[2]]).collect())
+    }
+
+    /// Select an application, `[0, 0, 0]` is the PICC level. Any session is dropped.
+    pub async fn select_application(&mut self, aid: &[u8; 3]) -> HinataResult<()> {
+        self.session = None;
+        self.command(DesfireCommand::SelectApplication, aid, &[], CommMode::Plain).await?;
+        Ok(())
+    }
+
+    pub async fn get_file_ids(&mut self) -> HinataResult<Vec<u8>> {
+        self.command(DesfireCommand::GetFileIds, &[], &[], CommMode::Plain).await
+    }
+
+    pub async fn authenticate_aes(&mut self, key_no: u8, key: &[u8; 16]) -> HinataResult<()> {
+        let cipher = CipherKey::aes128(key);
+        let (rnd_a, rnd_b) = self.authenticate(DesfireCommand::AuthenticateAes, key_no, &cipher).await?;
+        let mut session_key = [0u8; 16];
+        session_key[0..4].copy_from_slice(&rnd_a[0..4]);
+        session_key[4..8].copy_from_slice(&rnd_b[0..4]);
+        session_key[8..12].copy_from_slice(&rnd_a[12..16]);
+        session_key[12..16].copy_from_slice(&rnd_b[12..16]);
+        self.session = Some(Session {
+            key: CipherKey::aes128(&session_key),
+            iv: vec![0; 16],
+        });
+        Ok(())
+    }
+
+    pub async fn authenticate_3k3des(&mut self, key_no: u8, key: &[u8; 24]) -> HinataResult<()> {
+        let cipher = CipherKey::tdes3(key);
+        let (rnd_a, rnd_b) = self.authenticate(DesfireCommand::AuthenticateIso, key_no, &cipher).await?;
+        let mut session_key = [0u8; 24];
+        for (i, start) in [0, 6, 12].into_iter().enumerate() {
+            session_key[i * 8..i * 8 + 4].copy_from_slice(&rnd_a[start..start + 4]);
+            session_key[i * 8 + 4..i * 8 + 8].copy_from_slice(&rnd_b[start..start + 4]);
+        }
+        self.session = Some(Session {
+            key: CipherKey::tdes3(&session_key),
+            iv: vec![0; 8],
+        });
+        Ok(())
+    }
+
+    /// Three-pass mutual authentication, returns RndA and RndB
+    async fn authenticate(&mut self, cmd: DesfireCommand, key_no: u8, cipher: &CipherKey) -> HinataResult<(Vec<u8>, Vec<u8>)> {
+        self.session = None;
+        let mut iv = vec![0u8; cipher.block_size()];
+
+        let (status, mut rnd_b) = self.transceive(cmd as u8, &[key_no]).await?;
+        if status != DesfireStatus::AdditionalFrame as u8 {
+            Self::check_status(status)?;
+            return Err(Error::Protocol("DESFire authentication did not continue".into()));
+        }
+        cipher.cbc_decrypt(&mut iv, &mut rnd_b)?;
+
+        let rnd_a = random_bytes::<16>()?;
+        let mut token = rnd_a.to_vec();
+        token.extend_from_slice(&rotate_left(&rnd_b));
+        cipher.cbc_encrypt(&mut iv, &mut token)?;
+
+        let (status, mut rnd_a_rotated) = self.transceive(DesfireCommand::AdditionalFrame as u8, &token).await?;
+        Self::check_status(status)?;
+        cipher.cbc_decrypt(&mut iv, &mut rnd_a_rotated)?;
+        if rnd_a_rotated != rotate_left(&rnd_a) {
+            return Err(Error::Protocol("DESFire authentication failed, card proof mismatch".into()));
+        }
+
+        Ok((rnd_a.to_vec(), rnd_b))
+    }
+
+    /// Read from a standard or backup data file, `len == 0` reads the whole file
+    pub async fn read_data(&mut self, file_no: u8, offset: u32, len: u32, mode: CommMode) -> HinataResult<Vec<u8>> {
+        let mut header = vec![file_no];
+        header.extend_from_slice(&offset.to_le_bytes()[..3]);
+        header.extend_from_slice(&len.to_le_bytes()[..3]);
+        self.command(DesfireCommand::ReadData, &header, &[], mode).await
+    }
+
+    pub async fn write_data(&mut self, file_no: u8, offset: u32, data: &[u8], mode: CommMode) -> HinataResult<()> {
+        let mut header = vec![file_no];
+        header.extend_from_slice(&offset.to_le_bytes()[..3]);
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+        self.command(DesfireCommand::WriteData, &header, data, mode).await?;
+        Ok(())
+    }
+
+    pub async fn get_value(&mut self, file_no: u8, mode: CommMode) -> HinataResult<i32> {
+        let data = self.command(DesfireCommand::GetValue, &[file_no], &[], mode).await?;
+        let value = data.get(..4).ok_or(Error::Protocol("DESFire value must be 4 bytes".into()))?;
+        Ok(i32::from_le_bytes(value.try_into().expect("4 bytes")))
+    }
+
+    /// Credit a value file, takes effect after [`Self::commit_transaction`]
+    pub async fn credit(&mut self, file_no: u8, amount: i32, mode: CommMode) -> HinataResult<()> {
+        self.command(DesfireCommand::Credit, &[file_no], &amount.to_le_bytes(), mode).await?;
+        Ok(())
+    }
+
+    /// Debit a value file, takes effect after [`Self::commit_transaction`]
+    pub async fn debit(&mut self, file_no: u8, amount: i32, mode: CommMode) -> HinataResult<()> {
+        self.command(DesfireCommand::Debit, &[file_no], &amount.to_le_bytes(), mode).await?;
+        Ok(())
+    }
+
+    pub async fn commit_transaction(&mut self) -> HinataResult<()> {
+        self.command(DesfireCommand::CommitTransaction, &[], &[], CommMode::Plain).await?;
+        Ok(())
+    }
+
+    pub async fn abort_transaction(&mut self) -> HinataResult<()> {
+        self.command(DesfireCommand::AbortTransaction, &[], &[], CommMode::Plain).await?;
+        Ok(())
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    pub fn desfire(&mut self, tg: u8) -> Desfire<'_, 'a, P> {
+        Desfire::new(self, tg)
+    }
+}
+
+#[test]
+fn desfire_version_test() {
+    let version = DesfireVersion::from_bytes(&[
+        0x04, 0x01, 0x01, 0x01, 0x00, 0x18, 0x05, 0x04, 0x01, 0x01, 0x01, 0x04, 0x18, 0x05, 0x04, 0x52,
+        0x5A, 0x92, 0x3B, 0x2B, 0x80, 0xBA, 0x54, 0x48, 0x71, 0x50, 0x32, 0x13,
+    ]).unwrap();
+    assert_eq!(version.storage_size(), Some(4096));
+    assert_eq!(version.production_year, 0x13);
+
+    let bogus = DesfireVersion { hardware: [0x04, 0x01, 0x01, 0x01, 0x00, 0xFE, 0x05], ..version };
+    assert_eq!(bogus.storage_size(), None);
+}
@@ -0,0 +1,254 @@
+use crate::apdu::{Apdu, ApduResponse};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+
+/// Proximity Payment System Environment, "2PAY.SYS.DDF01"
+pub const PPSE: &[u8] = b"2PAY.SYS.DDF01";
+
+const TAG_AID: u32 = 0x4F;
+const TAG_LABEL: u32 = 0x50;
+const TAG_PAN: u32 = 0x5A;
+const TAG_TRACK2: u32 = 0x57;
+const TAG_EXPIRY: u32 = 0x5F24;
+const TAG_PRIORITY: u32 = 0x87;
+const TAG_PDOL: u32 = 0x9F38;
+const TAG_AFL: u32 = 0x94;
+const TAG_GPO_FORMAT1: u32 = 0x80;
+
+/// Terminal Transaction Qualifiers, contactless EMV mode with online capability
+const TTQ: [u8; 4] = [0xB6, 0x20, 0xC0, 0x00];
+
+/// A single BER-TLV data object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tlv {
+    pub tag: u32,
+    pub value: Vec<u8>,
+}
+
+impl Tlv {
+    pub fn is_constructed(&self) -> bool {
+        let first = self.tag.to_be_bytes().into_iter().find(|&b| b != 0).unwrap_or(0);
+        first & 0x20 != 0
+    }
+
+    /// Parse consecutive objects, `0x00` and `0xFF` padding between them is skipped
+    pub fn parse_all(mut data: &[u8]) -> HinataResult<Vec<Tlv>> {
+        let mut objects = Vec::new();
+        while let Some((&first, rest)) = data.split_first() {
+            if first == 0x00 || first == 0xFF {
+                data = rest;
+                continue;
+            }
+            let mut tag = first as u32;
+            let mut cursor = rest;
+            if first & 0x1F == 0x1F {
+                loop {
+                    let (&b, next) = cursor.split_first().ok_or(Error::Parse("Truncated TLV tag".into()))?;
+                    tag = (tag << 8) | b as u32;
+                    cursor = next;
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                }
+            }
+            let (&len, next) = cursor.split_first().ok_or(Error::Parse("Missing TLV length".into()))?;
+            cursor = next;
+            let len = match len {
+                0x00..=0x7F => len as usize,
+                0x81..=0x83 => {
+                    let n = (len & 0x7F) as usize;
+                    let bytes = cursor.get(..n).ok_or(Error::Parse("Truncated TLV length".into()))?;
+                    cursor = &cursor[n..];
+                    bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize)
+                }
+                _ => return Err(Error::Parse(format!("Unsupported TLV length byte {len:02X}"))),
+            };
+            let value = cursor.get(..len).ok_or(Error::Parse("TLV value exceeds data".into()))?;
+            objects.push(Tlv { tag, value: value.to_vec() });
+            data = &cursor[len..];
+        }
+        Ok(objects)
+    }
+
+    /// Depth-first search for the first object with `tag`
+    pub fn find(data: &[u8], tag: u32) -> Option<Vec<u8>> {
+        Self::find_all(data, tag).into_iter().next()
+    }
+
+    /// Every object with `tag`, searching inside constructed objects
+    pub fn find_all(data: &[u8], tag: u32) -> Vec<Vec<u8>> {
+        let mut found = Vec::new();
+        for object in Self::parse_all(data).unwrap_or_default() {
+            if object.tag == tag {
+                found.push(object.value.clone());
+            }
+            if object.is_constructed() {
+                found.extend(Self::find_all(&object.value, tag));
+            }
+        }
+        found
+    }
+}
+
+/// Payment application listed in the PPSE directory
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmvApplication {
+    pub aid: Vec<u8>,
+    pub label: Option<String>,
+    pub priority: Option<u8>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmvCard {
+    pub application: EmvApplication,
+    /// PAN with all but the first 6 and last 4 digits replaced by `*`, the full PAN is never kept
+    pub masked_pan: String,
+    /// Expiry as `YYMM`
+    pub expiry: Option<String>,
+}
+
+pub fn mask_pan(pan: &str) -> String {
+    if pan.len() <= 10 {
+        return "*".repeat(pan.len());
+    }
+    pan.chars()
+        .enumerate()
+        .map(|(i, c)| if i < 6 || i >= pan.len() - 4 { c } else { '*' })
+        .collect()
+}
+
+fn hex_digits(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// PAN and `YYMM` expiry from Track 2 equivalent data
+pub fn parse_track2(data: &[u8]) -> Option<(String, String)> {
+    let digits = hex_digits(data);
+    let (pan, rest) = digits.split_once('D')?;
+    Some((pan.to_string(), rest.get(..4)?.to_string()))
+}
+
+/// Fill a PDOL with terminal data, anything we have no value for is zeroed
+pub fn build_pdol_data(pdol: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut cursor = pdol;
+    while let Some((&first, rest)) = cursor.split_first() {
+        let mut tag = first as u32;
+        cursor = rest;
+        if first & 0x1F == 0x1F {
+            while let Some((&b, rest)) = cursor.split_first() {
+                tag = (tag << 8) | b as u32;
+                cursor = rest;
+                if b & 0x80 == 0 {
+                    break;
+                }
+            }
+        }
+        let Some((&len, rest)) = cursor.split_first() else { break };
+        cursor = rest;
+        let mut value = vec![0u8; len as usize];
+        match tag {
+            0x9F66 => value.iter_mut().zip(TTQ).for_each(|(v, t)| *v = t),
+            // Unpredictable number, any non-zero value is accepted for reading
+            0x9F37 => value.iter_mut().enumerate().for_each(|(i, v)| *v = 0x5A ^ i as u8),
+            _ => {}
+        }
+        data.extend_from_slice(&value);
+    }
+    data
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    async fn emv_transceive(&mut self, tg: u8, apdu: &Apdu) -> HinataResult<ApduResponse> {
+        let response = self.transceive_apdu(tg, apdu).await?;
+        if !response.is_success() {
+            return Err(Error::Protocol(format!("EMV command {:02X} failed with {:04X}", apdu.ins, response.get_sw()).into()));
+        }
+        Ok(response)
+    }
+
+    /// Applications listed by the PPSE, highest priority first
+    pub async fn emv_list_applications(&mut self, tg: u8) -> HinataResult<Vec<EmvApplication>> {
+        let select = Apdu::new(0x00, 0xA4, 0x04, 0x00).with_data(PPSE).with_le(256);
+        let fci = self.emv_transceive(tg, &select).await?.into_data();
+        let mut applications: Vec<EmvApplication> = Tlv::find_all(&fci, 0x61)
+            .iter()
+            .filter_map(|entry| {
+                Some(EmvApplication {
+                    aid: Tlv::find(entry, TAG_AID)?,
+                    label: Tlv::find(entry, TAG_LABEL).map(|l| String::from_utf8_lossy(&l).into_owned()),
+                    priority: Tlv::find(entry, TAG_PRIORITY).and_then(|p| p.first().map(|p| p & 0x0F)),
+                })
+            })
+            .collect();
+        applications.sort_by_key(|app| app.priority.unwrap_or(u8::MAX));
+        Ok(applications)
+    }
+
+    /// Select `application`, run GET PROCESSING OPTIONS and read records until a PAN turns up
+    pub async fn emv_read_application(&mut self, tg: u8, application: &EmvApplication) -> HinataResult<EmvCard> {
+        let select = Apdu::new(0x00, 0xA4, 0x04, 0x00).with_data(&application.aid).with_le(256);
+        let fci = self.emv_transceive(tg, &select).await?.into_data();
+
+        let pdol_data = Tlv::find(&fci, TAG_PDOL).map(|pdol| build_pdol_data(&pdol)).unwrap_or_default();
+        let mut command = vec![0x83, pdol_data.len() as u8];
+        command.extend_from_slice(&pdol_data);
+        let gpo = Apdu::new(0x80, 0xA8, 0x00, 0x00).with_data(&command).with_le(256);
+        let gpo = self.emv_transceive(tg, &gpo).await?.into_data();
+
+        let mut records = vec![gpo.clone()];
+        let afl = match Tlv::find(&gpo, TAG_GPO_FORMAT1) {
+            Some(format1) => format1.get(2..).unwrap_or_default().to_vec(),
+            None => Tlv::find(&gpo, TAG_AFL).unwrap_or_default(),
+        };
+        for entry in afl.chunks_exact(4) {
+            let sfi = entry[0] >> 3;
+            for record in entry[1]..=entry[2] {
+                let read = Apdu::new(0x00, 0xB2, record, (sfi << 3) | 0x04).with_le(256);
+                records.push(self.emv_transceive(tg, &read).await?.into_data());
+            }
+        }
+
+        for record in &records {
+            if let Some(pan) = Tlv::find(record, TAG_PAN) {
+                let pan = hex_digits(&pan).trim_end_matches('F').to_string();
+                let expiry = records.iter()
+                    .find_map(|r| Tlv::find(r, TAG_EXPIRY))
+                    .map(|e| hex_digits(&e).chars().take(4).collect());
+                return Ok(EmvCard { application: application.clone(), masked_pan: mask_pan(&pan), expiry });
+            }
+            if let Some((pan, expiry)) = Tlv::find(record, TAG_TRACK2).and_then(|t| parse_track2(&t)) {
+                return Ok(EmvCard { application: application.clone(), masked_pan: mask_pan(&pan), expiry: Some(expiry) });
+            }
+        }
+        Err(Error::NotFound("No PAN in EMV records".into()))
+    }
+
+    /// Read the masked PAN of the preferred payment application
+    pub async fn emv_read_card(&mut self, tg: u8) -> HinataResult<EmvCard> {
+        let applications = self.emv_list_applications(tg).await?;
+        let application = applications.first().ok_or(Error::NotFound("PPSE lists no applications".into()))?;
+        self.emv_read_application(tg, application).await
+    }
+}
+
+#[test]
+fn emv_tlv_test() {
+    let fci = [
+        0x6F, 0x23, 0x84, 0x0E, 0x32, 0x50, 0x41, 0x59, 0x2E, 0x53, 0x59, 0x53, 0x2E, 0x44, 0x44, 0x46,
+        0x30, 0x31, 0xA5, 0x11, 0xBF, 0x0C, 0x0E, 0x61, 0x0C, 0x4F, 0x07, 0xA0, 0x00, 0x00, 0x00, 0x04,
+        0x10, 0x10, 0x87, 0x01, 0x01,
+    ];
+    let entries = Tlv::find_all(&fci, 0x61);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(Tlv::find(&entries[0], TAG_AID).unwrap(), vec![0xA0, 0x00, 0x00, 0x00, 0x04, 0x10, 0x10]);
+
+    let (pan, expiry) = parse_track2(&[0x54, 0x13, 0x33, 0x00, 0x89, 0x02, 0x00, 0x01, 0xD2, 0x51, 0x22, 0x01]).unwrap();
+    assert_eq!(pan, "5413330089020001");
+    assert_eq!(expiry, "2512");
+    assert_eq!(mask_pan(&pan), "541333******0001");
+
+    assert_eq!(build_pdol_data(&[0x9F, 0x66, 0x04, 0x9F, 0x02, 0x06]), vec![0xB6, 0x20, 0xC0, 0x00, 0, 0, 0, 0, 0, 0]);
+}
@@ -0,0 +1,378 @@
+use thiserror::Error;
+use crate::error::{Error, HinataResult};
+use crate::pn532::{FelicaCommand, Pn532, Pn532Port};
+
+/// Amusement IC (FeliCa Lite-S) cards used by SEGA, Bandai Namco and KONAMI
+pub const SYSTEM_CODE_AMUSEMENT_IC: u16 = 0x88B4;
+/// CJRC transit IC cards (Suica, PASMO, ICOCA...)
+pub const SYSTEM_CODE_TRANSIT: u16 = 0x0003;
+/// Common area shared by e-money services such as Edy, nanaco and WAON
+pub const SYSTEM_CODE_COMMON_AREA: u16 = 0xFE00;
+pub const SYSTEM_CODE_OCTOPUS: u16 = 0x8008;
+
+/// Human readable name of a well known system code
+pub fn system_code_name(system_code: u16) -> Option<&'static str> {
+    match system_code {
+        SYSTEM_CODE_AMUSEMENT_IC => Some("Amusement IC"),
+        SYSTEM_CODE_TRANSIT => Some("Transit IC"),
+        SYSTEM_CODE_COMMON_AREA => Some("Common Area"),
+        SYSTEM_CODE_OCTOPUS => Some("Octopus"),
+        _ => None,
+    }
+}
+
+/// Either byte of a polled system code may be the `0xFF` wildcard, `0xFFFF` matches every system
+pub fn system_code_matches(polled: u16, system_code: u16) -> bool {
+    let [polled_hi, polled_lo] = polled.to_be_bytes();
+    let [hi, lo] = system_code.to_be_bytes();
+    (polled_hi == 0xFF || polled_hi == hi) && (polled_lo == 0xFF || polled_lo == lo)
+}
+
+/// Returned by Request Service for nodes that do not exist on the card
+pub const NODE_NOT_FOUND: u16 = 0xFFFF;
+
+pub const BLOCK_SIZE: usize = 16;
+
+pub type Block = [u8; BLOCK_SIZE];
+
+const MAX_NODES: usize = 32;
+
+/// Status flag 2 reported by commands that touch block data
+#[derive(Copy, Clone, Debug, Error, PartialEq, Eq)]
+pub enum FelicaStatus {
+    #[error("Purse data underflow")]
+    PurseUnderflow,
+    #[error("Cashback data exceeds purse data")]
+    CashbackExceeded,
+    #[error("Fatal memory error")]
+    MemoryError,
+    #[error("Number of memory rewrites exceeded")]
+    RewriteLimit,
+    #[error("Illegal number of services")]
+    IllegalServiceCount,
+    #[error("Illegal command packet (number of blocks)")]
+    IllegalBlockCount,
+    #[error("Illegal block list (service order)")]
+    IllegalBlockList,
+    #[error("Illegal service type")]
+    IllegalServiceType,
+    #[error("Access not allowed")]
+    AccessDenied,
+    #[error("Illegal service code list")]
+    IllegalServiceCode,
+    #[error("Illegal block list (access mode)")]
+    IllegalAccessMode,
+    #[error("Illegal block number")]
+    IllegalBlockNumber,
+    #[error("Data write failure")]
+    WriteFailure,
+    #[error("Unknown status {0:02X}")]
+    Other(u8),
+}
+
+impl From<u8> for FelicaStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => Self::PurseUnderflow,
+            0x02 => Self::CashbackExceeded,
+            0x70 => Self::MemoryError,
+            0x71 => Self::RewriteLimit,
+            0xA1 => Self::IllegalServiceCount,
+            0xA2 => Self::IllegalBlockCount,
+            0xA3 => Self::IllegalBlockList,
+            0xA4 => Self::IllegalServiceType,
+            0xA5 => Self::AccessDenied,
+            0xA6 => Self::IllegalServiceCode,
+            0xA7 => Self::IllegalAccessMode,
+            0xA8 => Self::IllegalBlockNumber,
+            0xA9 => Self::WriteFailure,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<FelicaStatus> for u8 {
+    fn from(status: FelicaStatus) -> Self {
+        match status {
+            FelicaStatus::PurseUnderflow => 0x01,
+            FelicaStatus::CashbackExceeded => 0x02,
+            FelicaStatus::MemoryError => 0x70,
+            FelicaStatus::RewriteLimit => 0x71,
+            FelicaStatus::IllegalServiceCount => 0xA1,
+            FelicaStatus::IllegalBlockCount => 0xA2,
+            FelicaStatus::IllegalBlockList => 0xA3,
+            FelicaStatus::IllegalServiceType => 0xA4,
+            FelicaStatus::AccessDenied => 0xA5,
+            FelicaStatus::IllegalServiceCode => 0xA6,
+            FelicaStatus::IllegalAccessMode => 0xA7,
+            FelicaStatus::IllegalBlockNumber => 0xA8,
+            FelicaStatus::WriteFailure => 0xA9,
+            FelicaStatus::Other(other) => other,
+        }
+    }
+}
+
+/// Error reported by the card through its status flags
+#[derive(Copy, Clone, Debug, Error, PartialEq, Eq)]
+#[error("status flag 1 {status1:02X}, {status2}")]
+pub struct FelicaError {
+    /// `0xFF` for errors not tied to a list entry, otherwise the failing entry as a bit mask
+    pub status1: u8,
+    pub status2: FelicaStatus,
+}
+
+/// Strip the status flags from a response, turning a non-zero status into an error
+pub(crate) fn check_status(res: &[u8]) -> HinataResult<&[u8]> {
+    match res {
+        [0x00, 0x00, rest @ ..] => Ok(rest),
+        [status1, status2, ..] => Err(Error::Felica(FelicaError {
+            status1: *status1,
+            status2: FelicaStatus::from(*status2),
+        })),
+        _ => Err(Error::Protocol("FeliCa response is missing status flags".into())),
+    }
+}
+
+/// Current mode reported by Request Response
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FelicaMode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+    Unknown(u8),
+}
+
+impl From<u8> for FelicaMode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Mode0,
+            1 => Self::Mode1,
+            2 => Self::Mode2,
+            3 => Self::Mode3,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// Key version of an area or service node, `None` when the node does not exist
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NodeKeyVersion {
+    pub node: u16,
+    pub version: Option<u16>,
+}
+
+/// Entry returned by Search Service Code
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FelicaNode {
+    Area { code: u16, end: u16 },
+    Service(u16),
+}
+
+impl FelicaNode {
+    pub fn get_code(&self) -> u16 {
+        match self {
+            Self::Area { code, .. } => *code,
+            Self::Service(code) => *code,
+        }
+    }
+
+    /// Service number without the attribute bits
+    pub fn get_number(&self) -> u16 {
+        self.get_code() >> 6
+    }
+
+    /// Service attribute, the low 6 bits of the code
+    pub fn get_attribute(&self) -> u8 {
+        (self.get_code() & 0x3F) as u8
+    }
+
+    /// Services that can be read without a key
+    pub fn is_read_without_key(&self) -> bool {
+        matches!(self, Self::Service(code) if code & 0x01 == 0x01)
+    }
+}
+
+/// An area and everything that lives below it
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FelicaArea {
+    pub code: u16,
+    pub end: u16,
+    pub services: Vec<u16>,
+    pub areas: Vec<FelicaArea>,
+}
+
+impl FelicaArea {
+    fn contains(&self, code: u16) -> bool {
+        (self.code >> 6..=self.end >> 6).contains(&(code >> 6))
+    }
+
+    fn insert(&mut self, node: FelicaNode) {
+        if let Some(area) = self.areas.iter_mut().rev().find(|area| area.contains(node.get_code())) {
+            area.insert(node);
+            return;
+        }
+        match node {
+            FelicaNode::Area { code, end } => self.areas.push(FelicaArea {
+                code,
+                end,
+                services: Vec::new(),
+                areas: Vec::new(),
+            }),
+            FelicaNode::Service(code) => self.services.push(code),
+        }
+    }
+
+    /// Arrange a flat Search Service Code listing into a tree rooted at area 0
+    pub fn from_nodes(nodes: &[FelicaNode]) -> Self {
+        let mut root = FelicaArea {
+            code: 0x0000,
+            end: 0xFFFE,
+            services: Vec::new(),
+            areas: Vec::new(),
+        };
+        for node in nodes {
+            if let FelicaNode::Area { code: 0x0000, end } = node {
+                root.end = *end;
+                continue;
+            }
+            root.insert(*node);
+        }
+        root
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    /// Send a FeliCa command addressed to `idm` and return the response after the IDm
+    pub(crate) async fn felica_command(&mut self, tg: u8, cmd: FelicaCommand, idm: &[u8; 8], data: &[u8]) -> HinataResult<Vec<u8>> {
+        let code = cmd as u8;
+        let mut input = vec![code];
+        input.extend_from_slice(idm);
+        input.extend_from_slice(data);
+
+        let length = (input.len() + 1) as u8;
+        let res = self.in_data_exchange(tg, length, &input).await?;
+        match res.as_slice() {
+            [len, response, res_idm @ ..] if res_idm.len() >= 8 => {
+                if *len as usize != res.len() || *response != code + 1 {
+                    return Err(Error::Protocol(format!("Invalid FeliCa response to command {code:02X}").into()));
+                }
+                if &res_idm[..8] != idm {
+                    return Err(Error::Protocol("FeliCa response from another card".into()));
+                }
+                Ok(res_idm[8..].to_vec())
+            }
+            _ => Err(Error::Protocol("FeliCa response too short".into())),
+        }
+    }
+
+    /// Key versions of up to 32 area or service codes
+    pub async fn felica_request_service(&mut self, tg: u8, idm: &[u8; 8], nodes: &[u16]) -> HinataResult<Vec<NodeKeyVersion>> {
+        if nodes.is_empty() || nodes.len() > MAX_NODES {
+            return Err(Error::Protocol("Request Service takes 1 to 32 nodes".into()));
+        }
+        let mut input = vec![nodes.len() as u8];
+        for node in nodes {
+            input.extend_from_slice(&node.to_le_bytes());
+        }
+
+        let res = self.felica_command(tg, FelicaCommand::RequestService, idm, &input).await?;
+        let versions = res.get(1..1 + nodes.len() * 2)
+            .ok_or(Error::Protocol("Invalid Request Service response length".into()))?;
+        Ok(nodes.iter()
+            .zip(versions.chunks_exact(2))
+            .map(|(&node, v)| {
+                let version = u16::from_le_bytes([v[0], v[1]]);
+                NodeKeyVersion {
+                    node,
+                    version: (version != NODE_NOT_FOUND).then_some(version),
+                }
+            })
+            .collect())
+    }
+
+    pub async fn felica_request_response(&mut self, tg: u8, idm: &[u8; 8]) -> HinataResult<FelicaMode> {
+        let res = self.felica_command(tg, FelicaCommand::RequestResponse, idm, &[]).await?;
+        res.first()
+            .map(|&mode| FelicaMode::from(mode))
+            .ok_or(Error::Protocol("Empty Request Response response".into()))
+    }
+
+    pub async fn felica_request_system_code(&mut self, tg: u8, idm: &[u8; 8]) -> HinataResult<Vec<u16>> {
+        let res = self.felica_command(tg, FelicaCommand::RequestSystemCode, idm, &[]).await?;
+        let count = *res.first().ok_or(Error::Protocol("Empty Request System Code response".into()))? as usize;
+        let codes = res.get(1..1 + count * 2)
+            .ok_or(Error::Protocol("Invalid Request System Code response length".into()))?;
+        Ok(codes.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+    }
+
+    /// Node at position `index` in the current system, `None` once the listing is exhausted
+    pub async fn felica_search_service_code(&mut self, tg: u8, idm: &[u8; 8], index: u16) -> HinataResult<Option<FelicaNode>> {
+        let res = self.felica_command(tg, FelicaCommand::SearchServiceCode, idm, &index.to_le_bytes()).await?;
+        let code = match res.get(..2) {
+            Some(c) => u16::from_le_bytes([c[0], c[1]]),
+            None => return Err(Error::Protocol("Invalid Search Service Code response".into())),
+        };
+        if code == NODE_NOT_FOUND {
+            return Ok(None);
+        }
+        match res.get(2..4) {
+            Some(end) => Ok(Some(FelicaNode::Area {
+                code,
+                end: u16::from_le_bytes([end[0], end[1]]),
+            })),
+            None => Ok(Some(FelicaNode::Service(code))),
+        }
+    }
+
+    /// Walk Search Service Code until the end of the listing
+    pub async fn felica_list_nodes(&mut self, tg: u8, idm: &[u8; 8]) -> HinataResult<Vec<FelicaNode>> {
+        let mut nodes = Vec::new();
+        for index in 0..NODE_NOT_FOUND {
+            match self.felica_search_service_code(tg, idm, index).await? {
+                Some(node) => nodes.push(node),
+                None => break,
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Enumerate the current system as an area/service tree
+    pub async fn felica_service_tree(&mut self, tg: u8, idm: &[u8; 8]) -> HinataResult<FelicaArea> {
+        let nodes = self.felica_list_nodes(tg, idm).await?;
+        Ok(FelicaArea::from_nodes(&nodes))
+    }
+}
+
+#[test]
+fn felica_status_test() {
+    let blocks = check_status(&[0x00, 0x00, 0x01, 0xAA]).unwrap();
+    assert_eq!(blocks, &[0x01, 0xAA]);
+    match check_status(&[0xFF, 0xA5]) {
+        Err(Error::Felica(e)) => assert_eq!(e.status2, FelicaStatus::AccessDenied),
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+#[test]
+fn felica_area_tree_test() {
+    let nodes = [
+        FelicaNode::Area { code: 0x0000, end: 0xFFFE },
+        FelicaNode::Area { code: 0x1000, end: 0x17FF },
+        FelicaNode::Service(0x1008),
+        FelicaNode::Service(0x100B),
+        FelicaNode::Area { code: 0x1400, end: 0x15FF },
+        FelicaNode::Service(0x1408),
+        FelicaNode::Service(0x2009),
+    ];
+    let tree = FelicaArea::from_nodes(&nodes);
+    assert_eq!(tree.services, vec![0x2009]);
+    assert_eq!(tree.areas.len(), 1);
+    assert_eq!(tree.areas[0].services, vec![0x1008, 0x100B]);
+    assert_eq!(tree.areas[0].areas[0].services, vec![0x1408]);
+    assert!(FelicaNode::Service(0x100B).is_read_without_key());
+}
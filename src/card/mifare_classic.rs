@@ -0,0 +1,456 @@
+use crate::error::{Error, HinataResult};
+use crate::pn532::{KeyType, Pn532, Pn532ApplicationError, Pn532Port};
+
+pub const BLOCK_SIZE: usize = 16;
+
+pub type Block = [u8; BLOCK_SIZE];
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MifareKey {
+    A([u8; 6]),
+    B([u8; 6]),
+}
+
+impl MifareKey {
+    pub fn get_key(&self) -> &[u8; 6] {
+        match self {
+            MifareKey::A(key) | MifareKey::B(key) => key,
+        }
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            MifareKey::A(_) => KeyType::A,
+            MifareKey::B(_) => KeyType::B,
+        }
+    }
+}
+
+/// Well-known factory and vendor default keys
+#[cfg(feature = "key-dictionary")]
+pub const DEFAULT_KEYS: &[[u8; 6]] = &[
+    [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+    [0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5],
+    [0xD3, 0xF7, 0xD3, 0xF7, 0xD3, 0xF7],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5],
+    [0x4D, 0x3A, 0x99, 0xC3, 0x51, 0xDD],
+    [0x1A, 0x98, 0x2C, 0x7E, 0x45, 0x9A],
+    [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF],
+    [0x71, 0x4C, 0x5C, 0x88, 0x6E, 0x97],
+    [0x58, 0x7E, 0xE5, 0xF9, 0x35, 0x0F],
+    [0xA0, 0x47, 0x8C, 0xC3, 0x90, 0x91],
+    [0x53, 0x3C, 0xB6, 0xC7, 0x23, 0xF6],
+    [0x8F, 0xD0, 0xA4, 0xF2, 0x56, 0xE9],
+];
+
+/// Keys used by arcade card ecosystems (SEGA Aime, Bandai Namco Passport)
+#[cfg(feature = "key-dictionary")]
+pub const ARCADE_KEYS: &[[u8; 6]] = &[
+    [0x57, 0x43, 0x43, 0x46, 0x76, 0x32],
+    [0x60, 0x90, 0xD0, 0x06, 0x32, 0xF5],
+];
+
+/// Result of a successful dictionary attack, `tg` may differ from the one passed in after re-selection
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DictionaryAuth {
+    pub tg: u8,
+    pub key: MifareKey,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MifareClassicLayout {
+    Mini,
+    Classic1K,
+    Classic4K,
+}
+
+impl MifareClassicLayout {
+    pub fn from_sak(sak: u8) -> Option<Self> {
+        match sak {
+            0x09 => Some(Self::Mini),
+            0x08 | 0x88 => Some(Self::Classic1K),
+            0x18 => Some(Self::Classic4K),
+            _ => None,
+        }
+    }
+
+    pub fn from_size(size: usize) -> Option<Self> {
+        match size {
+            320 => Some(Self::Mini),
+            1024 => Some(Self::Classic1K),
+            4096 => Some(Self::Classic4K),
+            _ => None,
+        }
+    }
+
+    pub fn sector_count(&self) -> u8 {
+        match self {
+            Self::Mini => 5,
+            Self::Classic1K => 16,
+            Self::Classic4K => 40,
+        }
+    }
+
+    pub fn block_count(&self) -> usize {
+        match self {
+            Self::Mini => 20,
+            Self::Classic1K => 64,
+            Self::Classic4K => 256,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.block_count() * BLOCK_SIZE
+    }
+}
+
+/// Sectors 0-31 have 4 blocks, the 4K-only sectors 32-39 have 16
+pub fn blocks_in_sector(sector: u8) -> u8 {
+    if sector < 32 { 4 } else { 16 }
+}
+
+pub fn sector_first_block(sector: u8) -> u8 {
+    if sector < 32 {
+        sector * 4
+    } else {
+        128 + (sector - 32) * 16
+    }
+}
+
+pub fn sector_trailer_block(sector: u8) -> u8 {
+    sector_first_block(sector) + (blocks_in_sector(sector) - 1)
+}
+
+pub fn block_to_sector(block: u8) -> u8 {
+    if block < 128 {
+        block / 4
+    } else {
+        32 + (block - 128) / 16
+    }
+}
+
+/// Which key is allowed to perform an operation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Never,
+    KeyA,
+    KeyB,
+    KeyAOrB,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DataBlockPermissions {
+    pub read: Access,
+    pub write: Access,
+    pub increment: Access,
+    /// Decrement, transfer and restore
+    pub decrement: Access,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TrailerPermissions {
+    pub write_key_a: Access,
+    pub read_access_bits: Access,
+    pub write_access_bits: Access,
+    pub read_key_b: Access,
+    pub write_key_b: Access,
+}
+
+/// Access conditions stored in bytes 6..9 of a sector trailer
+///
+/// Each group holds the `C1 C2 C3` bits as `0b C1 C2 C3`. Groups 0-2 are the data blocks (or blocks
+/// 0-4, 5-9, 10-14 in 16-block sectors) and group 3 is the sector trailer itself.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MifareAccessBits {
+    conditions: [u8; 4],
+}
+
+impl MifareAccessBits {
+    pub fn new(conditions: [u8; 4]) -> HinataResult<Self> {
+        if conditions.iter().any(|&c| c > 0b111) {
+            return Err(Error::Protocol("Access condition must be 3 bits".into()));
+        }
+        Ok(Self { conditions })
+    }
+
+    /// Factory default, all data blocks open with either key and key A managing the trailer (`FF 07 80`)
+    pub fn transport() -> Self {
+        Self { conditions: [0b000, 0b000, 0b000, 0b001] }
+    }
+
+    /// Decode the three access bytes, rejecting them when the inverted copies don't match
+    pub fn decode(bytes: &[u8]) -> HinataResult<Self> {
+        let bytes = bytes.get(..3).ok_or(Error::Parse("Access bits must be 3 bytes".into()))?;
+        let c1 = bytes[1] >> 4;
+        let c2 = bytes[2] & 0x0F;
+        let c3 = bytes[2] >> 4;
+        if bytes[0] & 0x0F != !c1 & 0x0F || bytes[0] >> 4 != !c2 & 0x0F || bytes[1] & 0x0F != !c3 & 0x0F {
+            return Err(Error::Parse("Access bits integrity check failed".into()));
+        }
+
+        let mut conditions = [0u8; 4];
+        for (group, condition) in conditions.iter_mut().enumerate() {
+            *condition = ((c1 >> group) & 1) << 2 | ((c2 >> group) & 1) << 1 | ((c3 >> group) & 1);
+        }
+        Ok(Self { conditions })
+    }
+
+    pub fn encode(&self) -> [u8; 3] {
+        let (mut c1, mut c2, mut c3) = (0u8, 0u8, 0u8);
+        for (group, condition) in self.conditions.iter().enumerate() {
+            c1 |= ((condition >> 2) & 1) << group;
+            c2 |= ((condition >> 1) & 1) << group;
+            c3 |= (condition & 1) << group;
+        }
+        [(!c2 & 0x0F) << 4 | (!c1 & 0x0F), c1 << 4 | (!c3 & 0x0F), c3 << 4 | c2]
+    }
+
+    pub fn get_conditions(&self) -> [u8; 4] {
+        self.conditions
+    }
+
+    pub fn data_block_permissions(&self, group: usize) -> Option<DataBlockPermissions> {
+        use Access::*;
+        let (read, write, increment, decrement) = match self.conditions.get(..3)?.get(group)? {
+            0b000 => (KeyAOrB, KeyAOrB, KeyAOrB, KeyAOrB),
+            0b010 => (KeyAOrB, Never, Never, Never),
+            0b100 => (KeyAOrB, KeyB, Never, Never),
+            0b110 => (KeyAOrB, KeyB, KeyB, KeyAOrB),
+            0b001 => (KeyAOrB, Never, Never, KeyAOrB),
+            0b011 => (KeyB, KeyB, Never, Never),
+            0b101 => (KeyB, Never, Never, Never),
+            _ => (Never, Never, Never, Never),
+        };
+        Some(DataBlockPermissions { read, write, increment, decrement })
+    }
+
+    pub fn trailer_permissions(&self) -> TrailerPermissions {
+        use Access::*;
+        let (write_key_a, read_access_bits, write_access_bits, read_key_b, write_key_b) = match self.conditions[3] {
+            0b000 => (KeyA, KeyA, Never, KeyA, KeyA),
+            0b010 => (Never, KeyA, Never, KeyA, Never),
+            0b100 => (KeyB, KeyAOrB, Never, Never, KeyB),
+            0b001 => (KeyA, KeyA, KeyA, KeyA, KeyA),
+            0b011 => (KeyB, KeyAOrB, KeyB, Never, KeyB),
+            0b101 => (Never, KeyAOrB, KeyB, Never, Never),
+            _ => (Never, KeyAOrB, Never, Never, Never),
+        };
+        TrailerPermissions { write_key_a, read_access_bits, write_access_bits, read_key_b, write_key_b }
+    }
+
+    /// Whether the access bits can still be changed after being written
+    pub fn is_reversible(&self) -> bool {
+        self.trailer_permissions().write_access_bits != Access::Never
+    }
+
+    pub fn build_trailer(&self, key_a: &[u8; 6], key_b: &[u8; 6], user_byte: u8) -> Block {
+        let mut trailer = [0u8; BLOCK_SIZE];
+        trailer[..6].copy_from_slice(key_a);
+        trailer[6..9].copy_from_slice(&self.encode());
+        trailer[9] = user_byte;
+        trailer[10..].copy_from_slice(key_b);
+        trailer
+    }
+}
+
+impl Default for MifareAccessBits {
+    fn default() -> Self {
+        Self::transport()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MifareClassicDump {
+    layout: MifareClassicLayout,
+    blocks: Vec<Block>,
+    sectors_read: Vec<bool>,
+}
+
+impl MifareClassicDump {
+    pub fn new(layout: MifareClassicLayout) -> Self {
+        Self {
+            layout,
+            blocks: vec![[0u8; BLOCK_SIZE]; layout.block_count()],
+            sectors_read: vec![false; layout.sector_count() as usize],
+        }
+    }
+
+    pub fn get_layout(&self) -> MifareClassicLayout {
+        self.layout
+    }
+
+    pub fn get_blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn get_block(&self, block: u8) -> Option<&Block> {
+        self.blocks.get(block as usize)
+    }
+
+    pub fn get_sector(&self, sector: u8) -> Option<&[Block]> {
+        let first = sector_first_block(sector) as usize;
+        self.blocks.get(first..first + blocks_in_sector(sector) as usize)
+    }
+
+    /// Whether the sector was actually read from the card, unread sectors are zero-filled
+    pub fn is_sector_read(&self, sector: u8) -> bool {
+        self.sectors_read.get(sector as usize).copied().unwrap_or(false)
+    }
+
+    pub(crate) fn set_sector(&mut self, sector: u8, blocks: &[Block]) {
+        let first = sector_first_block(sector) as usize;
+        self.blocks[first..first + blocks.len()].copy_from_slice(blocks);
+        self.sectors_read[sector as usize] = true;
+    }
+
+    /// Export as a raw `.mfd` image (all blocks back to back)
+    pub fn to_mfd(&self) -> Vec<u8> {
+        self.blocks.concat()
+    }
+
+    /// Import a raw `.mfd` image, every sector is considered read
+    pub fn from_mfd(data: &[u8]) -> HinataResult<Self> {
+        let layout = MifareClassicLayout::from_size(data.len())
+            .ok_or(Error::Parse(format!("Unexpected MIFARE Classic dump size: {}", data.len())))?;
+        let mut dump = Self::new(layout);
+        for (block, chunk) in dump.blocks.iter_mut().zip(data.chunks_exact(BLOCK_SIZE)) {
+            block.copy_from_slice(chunk);
+        }
+        dump.sectors_read.fill(true);
+        Ok(dump)
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    pub async fn read_sector(&mut self, tg: u8, uid: &[u8], sector: u8, key: &MifareKey) -> HinataResult<Vec<Block>> {
+        let first = sector_first_block(sector);
+        self.mifare_classic_auth(tg, uid, first, key.key_type(), key.get_key()).await?;
+
+        let mut blocks = Vec::with_capacity(blocks_in_sector(sector) as usize);
+        for block in first..=sector_trailer_block(sector) {
+            blocks.push(self.mifare_classic_read_block(tg, block).await?);
+        }
+        Ok(blocks)
+    }
+
+    /// Write the data blocks of a sector, the sector trailer and the manufacturer block are never touched
+    pub async fn write_sector(&mut self, tg: u8, uid: &[u8], sector: u8, key: &MifareKey, data: &[Block]) -> HinataResult<()> {
+        let first = sector_first_block(sector);
+        let data_blocks = blocks_in_sector(sector) - 1;
+        if data.len() != data_blocks as usize {
+            return Err(Error::Protocol(format!("Sector {sector} has {data_blocks} data blocks, got {}", data.len()).into()));
+        }
+
+        self.mifare_classic_auth(tg, uid, first, key.key_type(), key.get_key()).await?;
+        for (block, content) in (first..first + data_blocks).zip(data) {
+            if block == 0 {
+                continue;
+            }
+            self.mifare_classic_write_block(tg, block, content).await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite a sector trailer, refusing corrupt access bits and, unless `allow_lock` is set, ones that lock the trailer
+    pub async fn write_sector_trailer(&mut self, tg: u8, uid: &[u8], sector: u8, key: &MifareKey, trailer: &Block, allow_lock: bool) -> HinataResult<()> {
+        let access = MifareAccessBits::decode(&trailer[6..9])?;
+        if !allow_lock && !access.is_reversible() {
+            return Err(Error::Protocol("Access bits would permanently lock the sector trailer".into()));
+        }
+
+        let block = sector_trailer_block(sector);
+        self.mifare_classic_auth(tg, uid, block, key.key_type(), key.get_key()).await?;
+        self.mifare_classic_write_block(tg, block, trailer).await
+    }
+
+    /// Read every sector with the first key that authenticates, the key used is patched into the trailer
+    pub async fn dump_card(&mut self, tg: u8, uid: &[u8], layout: MifareClassicLayout, keys: &[MifareKey]) -> HinataResult<MifareClassicDump> {
+        let mut dump = MifareClassicDump::new(layout);
+        let mut tg = tg;
+
+        for sector in 0..layout.sector_count() {
+            for key in keys {
+                match self.read_sector(tg, uid, sector, key).await {
+                    Ok(mut blocks) => {
+                        let trailer = blocks.last_mut().expect("sector has at least one block");
+                        match key {
+                            MifareKey::A(k) => trailer[..6].copy_from_slice(k),
+                            MifareKey::B(k) => trailer[10..].copy_from_slice(k),
+                        }
+                        dump.set_sector(sector, &blocks);
+                        break;
+                    }
+                    Err(e) if matches!(e.application_error(), Some(Pn532ApplicationError::Reselect | Pn532ApplicationError::Retry)) => {
+                        // A failed authentication halts the card, it has to be activated again
+                        tg = self.mifare_classic_reselect(tg, uid).await?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(dump)
+    }
+
+    /// Try every key as Key A and then as Key B, re-activating the card after each failure
+    ///
+    /// The card is left authenticated to the sector of `block` on success.
+    pub async fn try_auth_with_dictionary(&mut self, tg: u8, uid: &[u8], block: u8, keys: &[[u8; 6]]) -> HinataResult<Option<DictionaryAuth>> {
+        let candidates = keys.iter().map(|k| MifareKey::A(*k)).chain(keys.iter().map(|k| MifareKey::B(*k)));
+        let mut tg = tg;
+
+        for key in candidates {
+            match self.mifare_classic_auth(tg, uid, block, key.key_type(), key.get_key()).await {
+                Ok(()) => return Ok(Some(DictionaryAuth { tg, key })),
+                Err(e) if matches!(e.application_error(), Some(Pn532ApplicationError::Reselect | Pn532ApplicationError::Retry)) => {
+                    tg = self.mifare_classic_reselect(tg, uid).await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) async fn mifare_classic_reselect(&mut self, tg: u8, uid: &[u8]) -> HinataResult<u8> {
+        let _ = self.in_release(tg).await;
+        let targets = self.in_list_passive_target(0, 1, uid).await?;
+        if targets.is_empty() {
+            return Err(Error::NotFound("Card left the field during re-selection".into()));
+        }
+        Ok(1)
+    }
+}
+
+#[test]
+fn access_bits_test() {
+    let transport = MifareAccessBits::decode(&[0xFF, 0x07, 0x80]).unwrap();
+    assert_eq!(transport, MifareAccessBits::transport());
+    assert_eq!(transport.encode(), [0xFF, 0x07, 0x80]);
+    assert!(transport.is_reversible());
+
+    let bits = MifareAccessBits::new([0b100, 0b110, 0b000, 0b011]).unwrap();
+    assert_eq!(MifareAccessBits::decode(&bits.encode()).unwrap(), bits);
+    assert_eq!(bits.data_block_permissions(1).unwrap().increment, Access::KeyB);
+    assert!(MifareAccessBits::decode(&[0xFF, 0x07, 0x81]).is_err());
+    assert!(!MifareAccessBits::new([0, 0, 0, 0b111]).unwrap().is_reversible());
+}
+
+#[test]
+fn mifare_classic_layout_test() {
+    assert_eq!(sector_first_block(31), 124);
+    assert_eq!(sector_first_block(32), 128);
+    assert_eq!(sector_trailer_block(39), 255);
+    assert_eq!(block_to_sector(143), 32);
+
+    let mut dump = MifareClassicDump::new(MifareClassicLayout::Classic1K);
+    dump.set_sector(1, &[[0xAA; 16]; 4]);
+    let mfd = dump.to_mfd();
+    assert_eq!(mfd.len(), 1024);
+    let imported = MifareClassicDump::from_mfd(&mfd).unwrap();
+    assert_eq!(imported.get_sector(1).unwrap(), &[[0xAA; 16]; 4]);
+    assert!(imported.is_sector_read(0));
+}
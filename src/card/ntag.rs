@@ -0,0 +1,174 @@
+use num_derive::{FromPrimitive, ToPrimitive};
+use crate::card::ultralight::{UltralightType, PAGE_SIZE};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+
+/// Largest FAST_READ that still fits in one response frame
+const FAST_READ_CHUNK_PAGES: u8 = 12;
+
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum NtagCommand {
+    GetVersion = 0x60,
+    FastRead = 0x3A,
+    PwdAuth = 0x1B,
+    ReadCnt = 0x39,
+    ReadSig = 0x3C,
+}
+
+/// GET_VERSION response
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NtagVersion {
+    pub vendor_id: u8,
+    pub product_type: u8,
+    pub product_subtype: u8,
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub storage_size: u8,
+    pub protocol_type: u8,
+}
+
+impl NtagVersion {
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        let data = data.get(..8).ok_or(Error::Protocol("GET_VERSION response must be 8 bytes".into()))?;
+        Ok(Self {
+            vendor_id: data[1],
+            product_type: data[2],
+            product_subtype: data[3],
+            major_version: data[4],
+            minor_version: data[5],
+            storage_size: data[6],
+            protocol_type: data[7],
+        })
+    }
+
+    pub fn tag_type(&self) -> Option<UltralightType> {
+        match (self.product_type, self.storage_size) {
+            (0x04, 0x0F) => Some(UltralightType::Ntag213),
+            (0x04, 0x11) => Some(UltralightType::Ntag215),
+            (0x04, 0x13) => Some(UltralightType::Ntag216),
+            (0x03, 0x0B) => Some(UltralightType::UltralightEv1Mf0ul11),
+            (0x03, 0x0E) => Some(UltralightType::UltralightEv1Mf0ul21),
+            _ => None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ntag {
+    version: NtagVersion,
+    tag_type: UltralightType,
+}
+
+impl Ntag {
+    pub fn from_version(version: NtagVersion) -> HinataResult<Self> {
+        let tag_type = version.tag_type().ok_or(Error::NotSupport(format!(
+            "Unknown NTAG product {:02X}/{:02X}",
+            version.product_type, version.storage_size
+        )))?;
+        Ok(Self { version, tag_type })
+    }
+
+    pub fn get_version(&self) -> &NtagVersion {
+        &self.version
+    }
+
+    pub fn get_tag_type(&self) -> UltralightType {
+        self.tag_type
+    }
+
+    /// First configuration page (MIRROR / AUTH0), followed by ACCESS, PWD and PACK
+    pub fn config_page(&self) -> u8 {
+        self.tag_type.config_page().expect("every NTAG and Ultralight EV1 type has configuration pages")
+    }
+
+    pub fn pwd_page(&self) -> u8 {
+        self.config_page() + 2
+    }
+
+    pub fn pack_page(&self) -> u8 {
+        self.config_page() + 3
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    pub async fn ntag_get_version(&mut self) -> HinataResult<NtagVersion> {
+        let res = self.in_communicate_thru(&[NtagCommand::GetVersion as u8]).await?;
+        NtagVersion::from_bytes(&res)
+    }
+
+    pub async fn ntag_detect(&mut self) -> HinataResult<Ntag> {
+        Ntag::from_version(self.ntag_get_version().await?)
+    }
+
+    /// Read pages `start..=end`, split into several FAST_READs when needed
+    pub async fn ntag_fast_read(&mut self, start: u8, end: u8) -> HinataResult<Vec<u8>> {
+        if end < start {
+            return Err(Error::Protocol("FAST_READ end page before start page".into()));
+        }
+        let mut data = Vec::with_capacity((end - start + 1) as usize * PAGE_SIZE);
+        let mut page = start;
+        loop {
+            let chunk_end = end.min(page.saturating_add(FAST_READ_CHUNK_PAGES - 1));
+            let res = self.in_communicate_thru(&[NtagCommand::FastRead as u8, page, chunk_end]).await?;
+            let expected = (chunk_end - page + 1) as usize * PAGE_SIZE;
+            data.extend_from_slice(res.get(..expected).ok_or(Error::Protocol("FAST_READ response too short".into()))?);
+            if chunk_end == end {
+                break;
+            }
+            page = chunk_end + 1;
+        }
+        Ok(data)
+    }
+
+    /// Authenticate with a 4-byte password, returning the PACK, which is checked against `expected_pack` if given
+    pub async fn ntag_pwd_auth(&mut self, pwd: &[u8; 4], expected_pack: Option<&[u8; 2]>) -> HinataResult<[u8; 2]> {
+        let mut input = vec![NtagCommand::PwdAuth as u8];
+        input.extend_from_slice(pwd);
+        let res = self.in_communicate_thru(&input).await?;
+        let pack: [u8; 2] = res
+            .get(..2)
+            .and_then(|slice| slice.try_into().ok())
+            .ok_or(Error::Protocol("PWD_AUTH response must be 2 bytes".into()))?;
+        if expected_pack.is_some_and(|expected| *expected != pack) {
+            return Err(Error::Protocol("PACK mismatch, tag may be counterfeit".into()));
+        }
+        Ok(pack)
+    }
+
+    /// NFC counter, incremented on the first read after each activation
+    pub async fn ntag_read_counter(&mut self) -> HinataResult<u32> {
+        let res = self.in_communicate_thru(&[NtagCommand::ReadCnt as u8, 0x02]).await?;
+        let counter = res.get(..3).ok_or(Error::Protocol("READ_CNT response must be 3 bytes".into()))?;
+        Ok(u32::from_le_bytes([counter[0], counter[1], counter[2], 0]))
+    }
+
+    /// 32-byte ECC originality signature over the UID
+    pub async fn ntag_read_signature(&mut self) -> HinataResult<[u8; 32]> {
+        let res = self.in_communicate_thru(&[NtagCommand::ReadSig as u8, 0x00]).await?;
+        res.get(..32)
+            .and_then(|slice| slice.try_into().ok())
+            .ok_or(Error::Protocol("READ_SIG response must be 32 bytes".into()))
+    }
+}
+
+#[test]
+fn ntag_version_test() {
+    let version = NtagVersion::from_bytes(&[0x00, 0x04, 0x04, 0x02, 0x01, 0x00, 0x11, 0x03]).unwrap();
+    let ntag = Ntag::from_version(version).unwrap();
+    assert_eq!(ntag.get_tag_type(), UltralightType::Ntag215);
+    assert_eq!(ntag.config_page(), 0x83);
+    assert_eq!(ntag.pwd_page(), 0x85);
+
+    let config_page = |product_type: u8, storage_size: u8| {
+        let version = NtagVersion::from_bytes(&[0x00, 0x04, product_type, 0x01, 0x01, 0x00, storage_size, 0x03]).unwrap();
+        Ntag::from_version(version).unwrap().config_page()
+    };
+    assert_eq!(config_page(0x03, 0x0B), 0x10);
+    assert_eq!(config_page(0x03, 0x0E), 0x25);
+    assert_eq!(config_page(0x04, 0x0F), 0x29);
+    assert_eq!(config_page(0x04, 0x11), 0x83);
+    assert_eq!(config_page(0x04, 0x13), 0xE3);
+}
@@ -0,0 +1,135 @@
+use crate::card::felica::{self, Block};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+
+/// Common area system shared by the CJRC cards (Suica, PASMO, ICOCA...)
+pub const SYSTEM_CODE: u16 = felica::SYSTEM_CODE_TRANSIT;
+pub const SERVICE_ATTRIBUTE: u16 = 0x008B;
+pub const SERVICE_HISTORY: u16 = 0x090F;
+
+pub const HISTORY_LEN: u8 = 20;
+
+/// Blocks per read, keeps each response inside a single report
+const READ_CHUNK: u8 = 2;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TransitDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl TransitDate {
+    /// 7 bit year since 2000, 4 bit month and 5 bit day, big-endian
+    pub fn from_bytes(data: [u8; 2]) -> Self {
+        let raw = u16::from_be_bytes(data);
+        Self {
+            year: 2000 + (raw >> 9),
+            month: ((raw >> 5) & 0x0F) as u8,
+            day: (raw & 0x1F) as u8,
+        }
+    }
+}
+
+/// Kind of transaction recorded in a history entry
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransitProcess {
+    Fare,
+    Charge,
+    TicketPurchase,
+    Adjustment,
+    Bus,
+    Purchase,
+    Other(u8),
+}
+
+impl From<u8> for TransitProcess {
+    fn from(value: u8) -> Self {
+        // The top bit flags payments topped up with cash
+        match value & 0x7F {
+            0x01 => Self::Fare,
+            0x02 => Self::Charge,
+            0x03 => Self::TicketPurchase,
+            0x04 | 0x05 => Self::Adjustment,
+            0x0D | 0x0F => Self::Bus,
+            0x46 | 0x4B => Self::Purchase,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TransitHistory {
+    pub terminal: u8,
+    pub process: TransitProcess,
+    pub date: TransitDate,
+    /// Line and station codes, only meaningful for rail entries
+    pub entry: (u8, u8),
+    pub exit: (u8, u8),
+    /// Balance after this transaction, in yen
+    pub balance: u16,
+    pub serial: u32,
+    pub region: u8,
+}
+
+impl TransitHistory {
+    /// `None` for blank entries on cards with a short history
+    pub fn from_block(block: &Block) -> Option<Self> {
+        if block.iter().all(|&b| b == 0) {
+            return None;
+        }
+        Some(Self {
+            terminal: block[0],
+            process: TransitProcess::from(block[1]),
+            date: TransitDate::from_bytes([block[4], block[5]]),
+            entry: (block[6], block[7]),
+            exit: (block[8], block[9]),
+            balance: u16::from_le_bytes([block[10], block[11]]),
+            serial: u32::from_be_bytes([0, block[12], block[13], block[14]]),
+            region: block[15],
+        })
+    }
+}
+
+/// Balance in yen from the attribute block
+pub fn balance_from_attribute(block: &Block) -> u16 {
+    u16::from_le_bytes([block[11], block[12]])
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    pub async fn transit_read_balance(&mut self, tg: u8, idm: &[u8; 8]) -> HinataResult<u16> {
+        let blocks = self.felica_read_without_encryption(tg, idm, &[SERVICE_ATTRIBUTE], &[0x8000]).await?;
+        blocks.first()
+            .map(balance_from_attribute)
+            .ok_or(Error::Protocol("Empty transit attribute read".into()))
+    }
+
+    /// Usage history, newest entry first
+    pub async fn transit_read_history(&mut self, tg: u8, idm: &[u8; 8]) -> HinataResult<Vec<TransitHistory>> {
+        let mut history = Vec::with_capacity(HISTORY_LEN as usize);
+        for start in (0..HISTORY_LEN).step_by(READ_CHUNK as usize) {
+            let elements: Vec<u16> = (start..(start + READ_CHUNK).min(HISTORY_LEN))
+                .map(|block| 0x8000 | block as u16)
+                .collect();
+            let blocks = self.felica_read_without_encryption(tg, idm, &[SERVICE_HISTORY], &elements).await?;
+            history.extend(blocks.iter().filter_map(TransitHistory::from_block));
+        }
+        Ok(history)
+    }
+}
+
+#[test]
+fn transit_history_test() {
+    let block = [
+        0x16, 0x01, 0x00, 0x02, 0x2F, 0x3C, 0xE3, 0x29, 0xE3, 0x3D, 0xB8, 0x0B, 0x00, 0x01, 0x2C, 0x00,
+    ];
+    let entry = TransitHistory::from_block(&block).unwrap();
+    assert_eq!(entry.process, TransitProcess::Fare);
+    assert_eq!(entry.date, TransitDate { year: 2023, month: 9, day: 28 });
+    assert_eq!(entry.balance, 3000);
+    assert_eq!(entry.serial, 0x012C);
+    assert!(TransitHistory::from_block(&[0; 16]).is_none());
+}
@@ -0,0 +1,147 @@
+use crate::error::{Error, HinataResult};
+use crate::pn532::{MifareCommand, Pn532, Pn532Port};
+
+pub const PAGE_SIZE: usize = 4;
+
+pub type Page = [u8; PAGE_SIZE];
+
+/// Type 2 tag variants and their total page count
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UltralightType {
+    Ultralight,
+    UltralightC,
+    UltralightEv1Mf0ul11,
+    UltralightEv1Mf0ul21,
+    Ntag213,
+    Ntag215,
+    Ntag216,
+}
+
+impl UltralightType {
+    pub fn page_count(&self) -> u8 {
+        match self {
+            Self::Ultralight => 16,
+            Self::UltralightC => 48,
+            Self::UltralightEv1Mf0ul11 => 20,
+            Self::UltralightEv1Mf0ul21 => 41,
+            Self::Ntag213 => 45,
+            Self::Ntag215 => 135,
+            Self::Ntag216 => 231,
+        }
+    }
+
+    /// First page after the UID, lock and capability container pages
+    pub fn first_user_page(&self) -> u8 {
+        4
+    }
+
+    /// Last page of user memory, the configuration pages that follow are excluded
+    pub fn last_user_page(&self) -> u8 {
+        match self {
+            Self::Ultralight => 15,
+            Self::UltralightC => 39,
+            Self::UltralightEv1Mf0ul11 => 15,
+            Self::UltralightEv1Mf0ul21 => 35,
+            Self::Ntag213 => 39,
+            Self::Ntag215 => 129,
+            Self::Ntag216 => 225,
+        }
+    }
+
+    /// CFG0 (MIRROR / AUTH0), `None` for the Ultralight and Ultralight C which have no such page.
+    /// The MF0UL11 has no dynamic lock page, so it follows user memory directly.
+    pub fn config_page(&self) -> Option<u8> {
+        match self {
+            Self::Ultralight | Self::UltralightC => None,
+            Self::UltralightEv1Mf0ul11 => Some(0x10),
+            Self::UltralightEv1Mf0ul21 => Some(0x25),
+            Self::Ntag213 => Some(0x29),
+            Self::Ntag215 => Some(0x83),
+            Self::Ntag216 => Some(0xE3),
+        }
+    }
+
+    pub fn user_memory_size(&self) -> usize {
+        (self.last_user_page() - self.first_user_page() + 1) as usize * PAGE_SIZE
+    }
+}
+
+/// Walks the pages of a tag, fetching four at a time with a single READ
+pub struct UltralightPages<'p, 'a, P: Pn532Port> {
+    pn532: &'p mut Pn532<'a, P>,
+    tg: u8,
+    next: u16,
+    end: u16,
+    buffer: Vec<Page>,
+}
+
+impl<'p, 'a, P: Pn532Port> UltralightPages<'p, 'a, P> {
+    pub async fn next(&mut self) -> Option<HinataResult<(u8, Page)>> {
+        if self.next > self.end {
+            return None;
+        }
+        if self.buffer.is_empty() {
+            match self.pn532.ultralight_read_pages(self.tg, self.next as u8).await {
+                // READ wraps around at the end of memory, never hand out pages past `end`
+                Ok(data) => {
+                    let remaining = (self.end - self.next + 1) as usize;
+                    self.buffer = data
+                        .chunks_exact(PAGE_SIZE)
+                        .take(remaining)
+                        .rev()
+                        .map(|c| c.try_into().expect("chunk is a page"))
+                        .collect();
+                }
+                Err(e) => {
+                    self.next = self.end + 1;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let page = self.buffer.pop()?;
+        let index = self.next as u8;
+        self.next += 1;
+        Some(Ok((index, page)))
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    /// READ returns four consecutive pages starting at `page`
+    pub async fn ultralight_read_pages(&mut self, tg: u8, page: u8) -> HinataResult<[u8; 16]> {
+        let res = self.in_data_exchange(tg, MifareCommand::Read as u8, &[page]).await?;
+        res.get(..16)
+            .and_then(|slice| slice.try_into().ok())
+            .ok_or(Error::Protocol("Invalid data length in Ultralight read response".into()))
+    }
+
+    pub async fn ultralight_write_page(&mut self, tg: u8, page: u8, data: &Page) -> HinataResult<()> {
+        let mut input = vec![page];
+        input.extend_from_slice(data);
+        self.in_data_exchange(tg, MifareCommand::UltralightWrite as u8, &input).await?;
+        Ok(())
+    }
+
+    pub fn ultralight_pages(&mut self, tg: u8, tag: UltralightType) -> UltralightPages<'_, 'a, P> {
+        self.ultralight_page_range(tg, 0, tag.page_count() - 1)
+    }
+
+    pub fn ultralight_page_range(&mut self, tg: u8, start: u8, end: u8) -> UltralightPages<'_, 'a, P> {
+        UltralightPages {
+            pn532: self,
+            tg,
+            next: start as u16,
+            end: end as u16,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub async fn ultralight_read_all(&mut self, tg: u8, tag: UltralightType) -> HinataResult<Vec<Page>> {
+        let mut pages = Vec::with_capacity(tag.page_count() as usize);
+        let mut iter = self.ultralight_pages(tg, tag);
+        while let Some(page) = iter.next().await {
+            pages.push(page?.1);
+        }
+        Ok(pages)
+    }
+}
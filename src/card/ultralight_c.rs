@@ -0,0 +1,77 @@
+use crate::card::ultralight::Page;
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+use crate::utils::crypto::{random_bytes, rotate_left, CipherKey};
+
+const AUTHENTICATE: u8 = 0x1A;
+const AUTH_CONTINUE: u8 = 0xAF;
+const AUTH_OK: u8 = 0x00;
+
+pub const AUTH0_PAGE: u8 = 0x2A;
+pub const AUTH1_PAGE: u8 = 0x2B;
+pub const KEY_PAGE: u8 = 0x2C;
+
+/// Factory key, "BREAKMEIFYOUCAN!"
+pub const DEFAULT_KEY: [u8; 16] = *b"BREAKMEIFYOUCAN!";
+
+/// Pages 0x2C..=0x2F store each half of the key byte-reversed
+pub fn key_to_pages(key: &[u8; 16]) -> [Page; 4] {
+    let page = |start: usize| -> Page {
+        [key[start + 3], key[start + 2], key[start + 1], key[start]]
+    };
+    [page(4), page(0), page(12), page(8)]
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    /// Three-pass mutual authentication with a 2K3DES key
+    pub async fn ultralight_c_authenticate(&mut self, tg: u8, key: &[u8; 16]) -> HinataResult<()> {
+        let cipher = CipherKey::tdes2(key);
+        let mut iv = [0u8; 8];
+
+        let res = self.in_data_exchange(tg, AUTHENTICATE, &[0x00]).await?;
+        let mut rnd_b = match res.as_slice() {
+            [AUTH_CONTINUE, rest @ ..] if rest.len() == 8 => rest.to_vec(),
+            _ => return Err(Error::Protocol("Invalid Ultralight C authentication challenge".into())),
+        };
+        cipher.cbc_decrypt(&mut iv, &mut rnd_b)?;
+
+        let rnd_a = random_bytes::<8>()?;
+        let mut token = rnd_a.to_vec();
+        token.extend_from_slice(&rotate_left(&rnd_b));
+        cipher.cbc_encrypt(&mut iv, &mut token)?;
+
+        let res = self.in_data_exchange(tg, AUTH_CONTINUE, &token).await?;
+        let mut rnd_a_rotated = match res.as_slice() {
+            [AUTH_OK, rest @ ..] if rest.len() == 8 => rest.to_vec(),
+            _ => return Err(Error::Protocol("Ultralight C authentication rejected".into())),
+        };
+        cipher.cbc_decrypt(&mut iv, &mut rnd_a_rotated)?;
+        if rnd_a_rotated != rotate_left(&rnd_a) {
+            return Err(Error::Protocol("Ultralight C authentication failed, card proof mismatch".into()));
+        }
+        Ok(())
+    }
+
+    /// Write a new key, the tag must already be authenticated if the key pages are protected
+    pub async fn ultralight_c_write_key(&mut self, tg: u8, key: &[u8; 16]) -> HinataResult<()> {
+        for (i, page) in key_to_pages(key).iter().enumerate() {
+            self.ultralight_write_page(tg, KEY_PAGE + i as u8, page).await?;
+        }
+        Ok(())
+    }
+
+    /// Protect pages from `auth0` onwards, reads stay open when `write_only` is set
+    pub async fn ultralight_c_set_protection(&mut self, tg: u8, auth0: u8, write_only: bool) -> HinataResult<()> {
+        self.ultralight_write_page(tg, AUTH1_PAGE, &[write_only as u8, 0, 0, 0]).await?;
+        self.ultralight_write_page(tg, AUTH0_PAGE, &[auth0, 0, 0, 0]).await
+    }
+}
+
+#[test]
+fn key_to_pages_test() {
+    let pages = key_to_pages(&DEFAULT_KEY);
+    assert_eq!(&pages[0], b"IEMK");
+    assert_eq!(&pages[1], b"AERB");
+    assert_eq!(&pages[2], b"!NAC");
+    assert_eq!(&pages[3], b"UOYF");
+}
@@ -0,0 +1,726 @@
+use std::collections::BTreeMap;
+use crate::card::felica;
+use crate::card::mifare_classic::{self, Access, MifareAccessBits, MifareClassicLayout};
+use crate::card::ntag::NtagCommand;
+use crate::card::ultralight::{Page, UltralightType, PAGE_SIZE};
+use crate::card::{Felica, Iso14443a, PassiveTarget};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{FelicaCommand, KeyType, MifareCommand, Pn532Error};
+
+/// What the PN532 reports when the card answers with a NAK
+const NAK: Pn532Error = Pn532Error::MifareAuth;
+
+/// FeliCa status flag 1 and 2 of a failed command
+type StatusFlags = (u8, u8);
+
+/// A card image that answers reader commands from memory.
+///
+/// [`VirtualCard::exchange`] takes a command as the host hands it to InDataExchange or InCommunicateThru
+/// and returns the card response, or the PN532 status the reader would report instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VirtualCard {
+    MifareClassic(VirtualMifareClassic),
+    Ntag(VirtualNtag),
+    Felica(VirtualFelica),
+    /// Answers anticollision and nothing else
+    Passive(PassiveTarget),
+}
+
+impl VirtualCard {
+    /// What InListPassiveTarget reports for this card
+    pub fn target(&self) -> PassiveTarget {
+        match self {
+            Self::MifareClassic(card) => card.target(),
+            Self::Ntag(card) => card.target(),
+            Self::Felica(card) => card.target(),
+            Self::Passive(target) => target.clone(),
+        }
+    }
+
+    pub fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, Pn532Error> {
+        match self {
+            Self::MifareClassic(card) => card.exchange(data),
+            Self::Ntag(card) => card.exchange(data),
+            Self::Felica(card) => card.exchange(data),
+            Self::Passive(_) => Err(Pn532Error::Timeout),
+        }
+    }
+
+    /// The card left the field or was released, authentication is lost
+    pub fn reset(&mut self) {
+        match self {
+            Self::MifareClassic(card) => card.authenticated = None,
+            Self::Ntag(card) => card.authenticated = false,
+            Self::Felica(_) | Self::Passive(_) => {}
+        }
+    }
+}
+
+impl From<PassiveTarget> for VirtualCard {
+    fn from(target: PassiveTarget) -> Self {
+        Self::Passive(target)
+    }
+}
+
+impl From<VirtualMifareClassic> for VirtualCard {
+    fn from(card: VirtualMifareClassic) -> Self {
+        Self::MifareClassic(card)
+    }
+}
+
+impl From<VirtualNtag> for VirtualCard {
+    fn from(card: VirtualNtag) -> Self {
+        Self::Ntag(card)
+    }
+}
+
+impl From<VirtualFelica> for VirtualCard {
+    fn from(card: VirtualFelica) -> Self {
+        Self::Felica(card)
+    }
+}
+
+/// MIFARE Classic memory with sector trailers enforcing keys and access bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualMifareClassic {
+    uid: Vec<u8>,
+    layout: MifareClassicLayout,
+    blocks: Vec<mifare_classic::Block>,
+    /// Sector and key of the last successful authentication
+    authenticated: Option<(u8, KeyType)>,
+}
+
+impl VirtualMifareClassic {
+    /// Blank card with transport trailers, key A and B both `FF FF FF FF FF FF`
+    pub fn new(uid: &[u8], layout: MifareClassicLayout) -> HinataResult<Self> {
+        if !matches!(uid.len(), 4 | 7) {
+            return Err(Error::Protocol("MIFARE Classic UID must be 4 or 7 bytes".into()));
+        }
+        let mut card = Self {
+            uid: uid.to_vec(),
+            layout,
+            blocks: vec![[0; mifare_classic::BLOCK_SIZE]; layout.block_count()],
+            authenticated: None,
+        };
+
+        let (sak, atqa) = (card.sak(), card.atqa());
+        let block0 = &mut card.blocks[0];
+        block0[..uid.len()].copy_from_slice(uid);
+        let mut end = uid.len();
+        if uid.len() == 4 {
+            block0[4] = uid.iter().fold(0, |bcc, b| bcc ^ b);
+            end = 5;
+        }
+        block0[end] = sak;
+        block0[end + 1..end + 3].copy_from_slice(&atqa.to_le_bytes());
+
+        let trailer = MifareAccessBits::transport().build_trailer(&[0xFF; 6], &[0xFF; 6], 0x69);
+        for sector in 0..layout.sector_count() {
+            card.blocks[mifare_classic::sector_trailer_block(sector) as usize] = trailer;
+        }
+        Ok(card)
+    }
+
+    /// Load a full dump, the UID is taken from block 0
+    pub fn from_dump(data: &[u8], uid_len: usize) -> HinataResult<Self> {
+        let layout = MifareClassicLayout::from_size(data.len())
+            .ok_or(Error::Parse(format!("{} bytes is not a MIFARE Classic dump", data.len())))?;
+        let mut card = Self::new(data.get(..uid_len).ok_or(Error::Parse("Dump too short".into()))?, layout)?;
+        for (block, chunk) in card.blocks.iter_mut().zip(data.chunks_exact(mifare_classic::BLOCK_SIZE)) {
+            block.copy_from_slice(chunk);
+        }
+        Ok(card)
+    }
+
+    pub fn get_layout(&self) -> MifareClassicLayout {
+        self.layout
+    }
+
+    pub fn get_block(&self, block: u8) -> Option<&mifare_classic::Block> {
+        self.blocks.get(block as usize)
+    }
+
+    /// Overwrite a block directly, bypassing keys and access bits
+    pub fn set_block(&mut self, block: u8, data: mifare_classic::Block) -> HinataResult<()> {
+        let slot = self.blocks.get_mut(block as usize).ok_or(Error::Protocol(format!("Block {block} is out of range").into()))?;
+        *slot = data;
+        Ok(())
+    }
+
+    /// Rewrite a sector trailer with new keys and access bits
+    pub fn set_sector_keys(&mut self, sector: u8, key_a: &[u8; 6], key_b: &[u8; 6], access: MifareAccessBits) -> HinataResult<()> {
+        let trailer = mifare_classic::sector_trailer_block(sector);
+        let user_byte = self.get_block(trailer).map(|block| block[9]).unwrap_or(0x69);
+        self.set_block(trailer, access.build_trailer(key_a, key_b, user_byte))
+    }
+
+    /// Every block in order, as a dump file stores them
+    pub fn dump(&self) -> Vec<u8> {
+        self.blocks.concat()
+    }
+
+    fn sak(&self) -> u8 {
+        match self.layout {
+            MifareClassicLayout::Mini => 0x09,
+            MifareClassicLayout::Classic1K => 0x08,
+            MifareClassicLayout::Classic4K => 0x18,
+        }
+    }
+
+    fn atqa(&self) -> u16 {
+        let base = if self.layout == MifareClassicLayout::Classic4K { 0x0002 } else { 0x0004 };
+        if self.uid.len() == 7 { base | 0x0040 } else { base }
+    }
+
+    fn target(&self) -> PassiveTarget {
+        PassiveTarget::Iso14443a(Iso14443a::new(self.uid.clone(), self.sak(), self.atqa()))
+    }
+
+    fn access_bits(&self, sector: u8) -> Option<MifareAccessBits> {
+        let trailer = self.get_block(mifare_classic::sector_trailer_block(sector))?;
+        MifareAccessBits::decode(&trailer[6..9]).ok()
+    }
+
+    /// Key the block's sector was authenticated with, `None` when it was not
+    fn key_for(&self, block: u8) -> Option<KeyType> {
+        match self.authenticated {
+            Some((sector, key)) if sector == mifare_classic::block_to_sector(block) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Data group of a block in the access bits, `None` for the trailer
+    fn group(block: u8) -> Option<usize> {
+        let sector = mifare_classic::block_to_sector(block);
+        let offset = block - mifare_classic::sector_first_block(sector);
+        if block == mifare_classic::sector_trailer_block(sector) {
+            None
+        } else if mifare_classic::blocks_in_sector(sector) == 4 {
+            Some(offset as usize)
+        } else {
+            Some(offset as usize / 5)
+        }
+    }
+
+    fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, Pn532Error> {
+        match *data {
+            [cmd @ (0x60 | 0x61), block, ref rest @ ..] if rest.len() >= 10 => {
+                let key_type = if cmd == MifareCommand::AuthA as u8 { KeyType::A } else { KeyType::B };
+                self.authenticate(block, key_type, &rest[..6], &rest[6..10])?;
+                Ok(vec![])
+            }
+            [0x30, block] => self.read(block),
+            [0xA0, block, ref rest @ ..] if rest.len() >= mifare_classic::BLOCK_SIZE => {
+                self.write(block, &rest[..mifare_classic::BLOCK_SIZE])?;
+                Ok(vec![])
+            }
+            _ => Err(Pn532Error::Timeout),
+        }
+    }
+
+    fn authenticate(&mut self, block: u8, key_type: KeyType, key: &[u8], uid: &[u8]) -> Result<(), Pn532Error> {
+        self.authenticated = None;
+        if block as usize >= self.blocks.len() {
+            return Err(Pn532Error::Timeout);
+        }
+        let trailer = self.blocks[mifare_classic::sector_trailer_block(mifare_classic::block_to_sector(block)) as usize];
+        let expected = match key_type {
+            KeyType::A => &trailer[..6],
+            KeyType::B => &trailer[10..],
+        };
+        if uid != &self.uid[self.uid.len() - 4..] || key != expected {
+            return Err(Pn532Error::MifareAuth);
+        }
+        // Key B is no key while the access bits let it be read
+        if key_type == KeyType::B && self.access_bits(mifare_classic::block_to_sector(block))
+            .is_some_and(|access| access.trailer_permissions().read_key_b != Access::Never) {
+            return Err(Pn532Error::MifareAuth);
+        }
+        self.authenticated = Some((mifare_classic::block_to_sector(block), key_type));
+        Ok(())
+    }
+
+    fn read(&self, block: u8) -> Result<Vec<u8>, Pn532Error> {
+        let key = self.key_for(block).ok_or(NAK)?;
+        let access = self.access_bits(mifare_classic::block_to_sector(block)).ok_or(NAK)?;
+        let data = *self.get_block(block).ok_or(NAK)?;
+        match Self::group(block) {
+            Some(group) => {
+                let permissions = access.data_block_permissions(group).ok_or(NAK)?;
+                if !allows(permissions.read, key) {
+                    return Err(NAK);
+                }
+                Ok(data.to_vec())
+            }
+            None => {
+                let permissions = access.trailer_permissions();
+                let mut trailer = [0; mifare_classic::BLOCK_SIZE];
+                if allows(permissions.read_access_bits, key) {
+                    trailer[6..10].copy_from_slice(&data[6..10]);
+                }
+                if allows(permissions.read_key_b, key) {
+                    trailer[10..].copy_from_slice(&data[10..]);
+                }
+                Ok(trailer.to_vec())
+            }
+        }
+    }
+
+    fn write(&mut self, block: u8, data: &[u8]) -> Result<(), Pn532Error> {
+        let key = self.key_for(block).ok_or(NAK)?;
+        let access = self.access_bits(mifare_classic::block_to_sector(block)).ok_or(NAK)?;
+        // Block 0 holds the manufacturer data and is read only
+        if block == 0 {
+            return Err(NAK);
+        }
+        let current = self.blocks.get_mut(block as usize).ok_or(NAK)?;
+        match Self::group(block) {
+            Some(group) => {
+                let permissions = access.data_block_permissions(group).ok_or(NAK)?;
+                if !allows(permissions.write, key) {
+                    return Err(NAK);
+                }
+                current.copy_from_slice(data);
+            }
+            None => {
+                // Each part of the trailer only changes when its own condition allows it
+                let permissions = access.trailer_permissions();
+                if allows(permissions.write_key_a, key) {
+                    current[..6].copy_from_slice(&data[..6]);
+                }
+                if allows(permissions.write_access_bits, key) {
+                    if MifareAccessBits::decode(&data[6..9]).is_err() {
+                        return Err(NAK);
+                    }
+                    current[6..10].copy_from_slice(&data[6..10]);
+                }
+                if allows(permissions.write_key_b, key) {
+                    current[10..].copy_from_slice(&data[10..]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn allows(access: Access, key: KeyType) -> bool {
+    matches!((access, key), (Access::KeyAOrB, _) | (Access::KeyA, KeyType::A) | (Access::KeyB, KeyType::B))
+}
+
+/// NTAG21x or Ultralight EV1 pages, with the password protection of the configuration pages
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualNtag {
+    uid: [u8; 7],
+    tag_type: UltralightType,
+    pages: Vec<Page>,
+    counter: u32,
+    signature: [u8; 32],
+    authenticated: bool,
+}
+
+impl VirtualNtag {
+    /// Blank tag with an NDEF capability container, no password set
+    pub fn new(uid: [u8; 7], tag_type: UltralightType) -> HinataResult<Self> {
+        if Self::version(tag_type).is_none() {
+            return Err(Error::NotSupport(format!("{tag_type:?} has no GET_VERSION")));
+        }
+        let mut pages = vec![[0; PAGE_SIZE]; tag_type.page_count() as usize];
+        pages[0] = [uid[0], uid[1], uid[2], 0x88 ^ uid[0] ^ uid[1] ^ uid[2]];
+        pages[1] = [uid[3], uid[4], uid[5], uid[6]];
+        pages[2] = [uid[3] ^ uid[4] ^ uid[5] ^ uid[6], 0x48, 0x00, 0x00];
+        pages[3] = [0xE1, 0x10, (tag_type.user_memory_size() / 8) as u8, 0x00];
+
+        let mut tag = Self { uid, tag_type, pages, counter: 0, signature: [0; 32], authenticated: false };
+        let config = tag.config_page() as usize;
+        tag.pages[config] = [0x04, 0x00, 0x00, 0xFF];
+        tag.pages[config + 2] = [0xFF; 4];
+        Ok(tag)
+    }
+
+    /// Protect pages from `auth0` on with `pwd`, reads are refused too when `protect_reads` is set
+    pub fn with_password(mut self, pwd: [u8; 4], pack: [u8; 2], auth0: u8, protect_reads: bool) -> Self {
+        let config = self.config_page() as usize;
+        self.pages[config][3] = auth0;
+        self.pages[config + 1][0] = if protect_reads { 0x80 } else { 0x00 };
+        self.pages[config + 2] = pwd;
+        self.pages[config + 3] = [pack[0], pack[1], 0, 0];
+        self
+    }
+
+    pub fn with_counter(mut self, counter: u32) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    pub fn with_signature(mut self, signature: [u8; 32]) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    pub fn get_tag_type(&self) -> UltralightType {
+        self.tag_type
+    }
+
+    pub fn get_page(&self, page: u8) -> Option<&Page> {
+        self.pages.get(page as usize)
+    }
+
+    /// Overwrite a page directly, bypassing lock bits and the password
+    pub fn set_page(&mut self, page: u8, data: Page) -> HinataResult<()> {
+        let slot = self.pages.get_mut(page as usize).ok_or(Error::Protocol(format!("Page {page} is out of range").into()))?;
+        *slot = data;
+        Ok(())
+    }
+
+    /// Fill user memory from page 4 on, e.g. with an NDEF TLV
+    pub fn write_user_memory(&mut self, data: &[u8]) -> HinataResult<()> {
+        if data.len() > self.tag_type.user_memory_size() {
+            return Err(Error::Protocol("Data does not fit in user memory".into()));
+        }
+        let first = self.tag_type.first_user_page() as usize;
+        for (page, chunk) in self.pages[first..].iter_mut().zip(data.chunks(PAGE_SIZE)) {
+            page[..chunk.len()].copy_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    fn version(tag_type: UltralightType) -> Option<[u8; 8]> {
+        let (product_type, subtype, storage_size) = match tag_type {
+            UltralightType::Ntag213 => (0x04, 0x02, 0x0F),
+            UltralightType::Ntag215 => (0x04, 0x02, 0x11),
+            UltralightType::Ntag216 => (0x04, 0x02, 0x13),
+            UltralightType::UltralightEv1Mf0ul11 => (0x03, 0x01, 0x0B),
+            UltralightType::UltralightEv1Mf0ul21 => (0x03, 0x01, 0x0E),
+            UltralightType::Ultralight | UltralightType::UltralightC => return None,
+        };
+        Some([0x00, 0x04, product_type, subtype, 0x01, 0x00, storage_size, 0x03])
+    }
+
+    fn config_page(&self) -> u8 {
+        self.tag_type.config_page().expect("tag types with GET_VERSION have configuration pages")
+    }
+
+    fn target(&self) -> PassiveTarget {
+        PassiveTarget::Iso14443a(Iso14443a::new(self.uid.to_vec(), 0x00, 0x0044))
+    }
+
+    fn auth0(&self) -> u8 {
+        self.pages[self.config_page() as usize][3]
+    }
+
+    fn reads_protected(&self) -> bool {
+        self.pages[self.config_page() as usize + 1][0] & 0x80 != 0
+    }
+
+    /// PWD and PACK always read back as zeros
+    fn read_page(&self, page: u8) -> Result<Page, Pn532Error> {
+        if !self.authenticated && self.reads_protected() && page >= self.auth0() {
+            return Err(NAK);
+        }
+        let config = self.config_page();
+        if page == config + 2 || page == config + 3 {
+            return Ok([0; PAGE_SIZE]);
+        }
+        self.get_page(page).copied().ok_or(NAK)
+    }
+
+    fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, Pn532Error> {
+        const GET_VERSION: u8 = NtagCommand::GetVersion as u8;
+        const FAST_READ: u8 = NtagCommand::FastRead as u8;
+        const PWD_AUTH: u8 = NtagCommand::PwdAuth as u8;
+        const READ_CNT: u8 = NtagCommand::ReadCnt as u8;
+        const READ_SIG: u8 = NtagCommand::ReadSig as u8;
+        const READ: u8 = MifareCommand::Read as u8;
+        const WRITE: u8 = MifareCommand::UltralightWrite as u8;
+
+        match *data {
+            [GET_VERSION] => Self::version(self.tag_type).map(Vec::from).ok_or(Pn532Error::Timeout),
+            // READ wraps around to page 0 past the last page
+            [READ, page] if page < self.tag_type.page_count() => {
+                let count = self.tag_type.page_count();
+                (0..4).map(|i| self.read_page(((page as u16 + i) % count as u16) as u8))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|pages| pages.concat())
+            }
+            [FAST_READ, start, end] if start <= end && end < self.tag_type.page_count() => {
+                (start..=end).map(|page| self.read_page(page))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|pages| pages.concat())
+            }
+            [WRITE, page, a, b, c, d] => {
+                // UID and manufacturer pages are fixed
+                if page < 2 || page >= self.tag_type.page_count() || (!self.authenticated && page >= self.auth0()) {
+                    return Err(NAK);
+                }
+                self.pages[page as usize] = [a, b, c, d];
+                Ok(vec![])
+            }
+            [PWD_AUTH, a, b, c, d] => {
+                let config = self.config_page() as usize;
+                if self.pages[config + 2] != [a, b, c, d] {
+                    self.authenticated = false;
+                    return Err(NAK);
+                }
+                self.authenticated = true;
+                Ok(self.pages[config + 3][..2].to_vec())
+            }
+            [READ_CNT, 0x02] => Ok(self.counter.to_le_bytes()[..3].to_vec()),
+            [READ_SIG, 0x00] => Ok(self.signature.to_vec()),
+            _ => Err(NAK),
+        }
+    }
+}
+
+/// One FeliCa system with services holding plain blocks, keys and mutual authentication are not modeled.
+///
+/// Service attributes follow the card: bit 0 set allows access without encryption, bit 1 set makes the service read only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualFelica {
+    idm: [u8; 8],
+    pmm: [u8; 8],
+    system_codes: Vec<u16>,
+    services: BTreeMap<u16, Vec<felica::Block>>,
+}
+
+impl VirtualFelica {
+    pub fn new(idm: [u8; 8], pmm: [u8; 8], system_code: u16) -> Self {
+        Self { idm, pmm, system_codes: vec![system_code], services: BTreeMap::new() }
+    }
+
+    /// Another system code the card answers polls for
+    pub fn with_system_code(mut self, system_code: u16) -> Self {
+        self.system_codes.push(system_code);
+        self
+    }
+
+    pub fn with_service(mut self, service_code: u16, blocks: Vec<felica::Block>) -> Self {
+        self.services.insert(service_code, blocks);
+        self
+    }
+
+    pub fn get_system_codes(&self) -> &[u16] {
+        &self.system_codes
+    }
+
+    pub fn get_block(&self, service_code: u16, block: u16) -> Option<&felica::Block> {
+        self.services.get(&service_code)?.get(block as usize)
+    }
+
+    fn target(&self) -> PassiveTarget {
+        PassiveTarget::Felica(Felica::new(self.idm, self.pmm, self.system_codes.clone()))
+    }
+
+    /// Commands arrive as `len, code, IDm, parameters`, anything addressed to another IDm goes unanswered
+    fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, Pn532Error> {
+        let (code, params) = match data {
+            [_, 0x00, rest @ ..] => {
+                let [hi, lo, ..] = *rest else {
+                    return Err(Pn532Error::Timeout);
+                };
+                let polled = u16::from_be_bytes([hi, lo]);
+                if !self.system_codes.iter().any(|&code| felica::system_code_matches(polled, code)) {
+                    return Err(Pn532Error::Timeout);
+                }
+                (FelicaCommand::Polling as u8, rest)
+            }
+            [_, code, idm @ ..] if idm.len() >= 8 && idm[..8] == self.idm => (*code, &idm[8..]),
+            _ => return Err(Pn532Error::Timeout),
+        };
+        let mut response = vec![0, code + 1];
+        response.extend_from_slice(&self.idm);
+        match code {
+            0x00 => {
+                response.extend_from_slice(&self.pmm);
+                if let [_, _, 0x01, ..] = params {
+                    response.extend_from_slice(&self.system_codes[0].to_be_bytes());
+                }
+            }
+            0x02 => {
+                let [count, ref nodes @ ..] = *params else {
+                    return Err(Pn532Error::Timeout);
+                };
+                response.push(count);
+                for node in nodes.chunks_exact(2).take(count as usize) {
+                    let node = u16::from_le_bytes([node[0], node[1]]);
+                    let exists = node == 0x0000 || self.services.contains_key(&node);
+                    response.extend_from_slice(&if exists { 0x0000u16 } else { felica::NODE_NOT_FOUND }.to_le_bytes());
+                }
+            }
+            0x04 => response.push(0x00),
+            0x06 => match self.read_blocks(params) {
+                Ok(blocks) => {
+                    response.extend_from_slice(&[0x00, 0x00, blocks.len() as u8]);
+                    response.extend(blocks.concat());
+                }
+                Err((status1, status2)) => response.extend_from_slice(&[status1, status2]),
+            },
+            0x08 => match self.write_blocks(params) {
+                Ok(()) => response.extend_from_slice(&[0x00, 0x00]),
+                Err((status1, status2)) => response.extend_from_slice(&[status1, status2]),
+            },
+            // Root area first, then every service
+            0x0A => {
+                let [lo, hi, ..] = *params else {
+                    return Err(Pn532Error::Timeout);
+                };
+                match u16::from_le_bytes([lo, hi]) {
+                    0 => response.extend_from_slice(&[0x00, 0x00, 0xFE, 0xFF]),
+                    index => match self.services.keys().nth(index as usize - 1) {
+                        Some(code) => response.extend_from_slice(&code.to_le_bytes()),
+                        None => response.extend_from_slice(&felica::NODE_NOT_FOUND.to_le_bytes()),
+                    },
+                }
+            }
+            0x0C => {
+                response.push(self.system_codes.len() as u8);
+                for code in &self.system_codes {
+                    response.extend_from_slice(&code.to_be_bytes());
+                }
+            }
+            _ => return Err(Pn532Error::Timeout),
+        }
+        response[0] = response.len() as u8;
+        Ok(response)
+    }
+
+    /// Service list and block list of Read / Write Without Encryption, resolved to `(service, block)` pairs
+    fn block_list(&self, params: &[u8]) -> Result<(Vec<(u16, u16)>, usize), StatusFlags> {
+        let (&service_count, rest) = params.split_first().ok_or((0xFF, 0xA1))?;
+        let services: Vec<u16> = rest.get(..service_count as usize * 2)
+            .ok_or((0xFF, 0xA1))?
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        let rest = &rest[services.len() * 2..];
+        let (&block_count, mut elements) = rest.split_first().ok_or((0xFF, 0xA2))?;
+
+        let mut blocks = Vec::with_capacity(block_count as usize);
+        for entry in 0..block_count {
+            let flag = 1u8 << entry.min(7);
+            let (block, len) = match *elements {
+                [head, block, ..] if head & 0x80 != 0 => (block as u16, 2),
+                [_, lo, hi, ..] => (u16::from_le_bytes([lo, hi]), 3),
+                _ => return Err((0xFF, 0xA2)),
+            };
+            let service = *services.get((elements[0] & 0x0F) as usize).ok_or((flag, 0xA3))?;
+            blocks.push((service, block));
+            elements = &elements[len..];
+        }
+        Ok((blocks, params.len() - elements.len()))
+    }
+
+    fn read_blocks(&self, params: &[u8]) -> Result<Vec<felica::Block>, StatusFlags> {
+        let (blocks, _) = self.block_list(params)?;
+        blocks.iter().enumerate().map(|(entry, &(service, block))| {
+            let flag = 1u8 << entry.min(7);
+            if service & 0x01 == 0 {
+                return Err((flag, 0xA5));
+            }
+            self.get_block(service, block).copied().ok_or((flag, 0xA8))
+        }).collect()
+    }
+
+    fn write_blocks(&mut self, params: &[u8]) -> Result<(), StatusFlags> {
+        let (blocks, used) = self.block_list(params)?;
+        let data = &params[used..];
+        if data.len() < blocks.len() * felica::BLOCK_SIZE {
+            return Err((0xFF, 0xA2));
+        }
+        for (entry, &(service, block)) in blocks.iter().enumerate() {
+            let flag = 1u8 << entry.min(7);
+            if service & 0x03 != 0x01 {
+                return Err((flag, 0xA5));
+            }
+            self.get_block(service, block).ok_or((flag, 0xA8))?;
+        }
+        for ((service, block), chunk) in blocks.into_iter().zip(data.chunks_exact(felica::BLOCK_SIZE)) {
+            if let Some(slot) = self.services.get_mut(&service).and_then(|blocks| blocks.get_mut(block as usize)) {
+                slot.copy_from_slice(chunk);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn virtual_mifare_classic_test() {
+    let mut card = VirtualMifareClassic::new(&[0xDE, 0xAD, 0xBE, 0xEF], MifareClassicLayout::Classic1K).unwrap();
+    let secret = MifareAccessBits::new([0b011, 0b000, 0b000, 0b011]).unwrap();
+    card.set_sector_keys(1, &[0xA0; 6], &[0xB0; 6], secret).unwrap();
+
+    assert_eq!(card.exchange(&[0x30, 4]), Err(NAK));
+    let mut auth = vec![0x60, 4];
+    auth.extend_from_slice(&[0xA0; 6]);
+    auth.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+    card.exchange(&auth).unwrap();
+    // Block 4 needs key B, key A still reads the open blocks and the trailer with its keys hidden
+    assert_eq!(card.exchange(&[0x30, 4]), Err(NAK));
+    assert_eq!(card.exchange(&[0x30, 5]).unwrap(), vec![0; 16]);
+    let trailer = card.exchange(&[0x30, 7]).unwrap();
+    assert_eq!(&trailer[..6], &[0; 6]);
+    assert_eq!(&trailer[6..9], &secret.encode());
+
+    auth[0] = 0x61;
+    auth[2..8].copy_from_slice(&[0xB0; 6]);
+    card.exchange(&auth).unwrap();
+    let mut write = vec![0xA0, 4];
+    write.extend_from_slice(&[0x42; 16]);
+    card.exchange(&write).unwrap();
+    assert_eq!(card.get_block(4), Some(&[0x42; 16]));
+
+    auth[2..8].copy_from_slice(&[0xFF; 6]);
+    assert_eq!(card.exchange(&auth), Err(Pn532Error::MifareAuth));
+    let reloaded = VirtualMifareClassic::from_dump(&card.dump(), 4).unwrap();
+    assert_eq!(reloaded.get_block(4), Some(&[0x42; 16]));
+}
+
+#[test]
+fn virtual_ntag_test() {
+    let mut tag = VirtualNtag::new([0x04, 1, 2, 3, 4, 5, 6], UltralightType::Ntag213).unwrap()
+        .with_password([1, 2, 3, 4], [0xAB, 0xCD], 0x10, false);
+    assert_eq!(tag.exchange(&[0x60]).unwrap(), vec![0x00, 0x04, 0x04, 0x02, 0x01, 0x00, 0x0F, 0x03]);
+    assert_eq!(&tag.exchange(&[0x30, 3]).unwrap()[..4], &[0xE1, 0x10, 0x12, 0x00]);
+
+    tag.exchange(&[0xA2, 4, 0x03, 0x00, 0xFE, 0x00]).unwrap();
+    assert_eq!(tag.exchange(&[0xA2, 0x10, 1, 1, 1, 1]), Err(NAK));
+    assert_eq!(tag.exchange(&[0x1B, 1, 2, 3, 4]).unwrap(), vec![0xAB, 0xCD]);
+    tag.exchange(&[0xA2, 0x10, 1, 1, 1, 1]).unwrap();
+    assert_eq!(tag.exchange(&[0x3A, 4, 4]).unwrap(), vec![0x03, 0x00, 0xFE, 0x00]);
+    assert_eq!(tag.exchange(&[0x3A, 0x2B, 0x2B]).unwrap(), vec![0; 4]);
+
+    // No dynamic lock page on the MF0UL11, PWD sits right after CFG0 and CFG1
+    let tag = VirtualNtag::new([0x04, 1, 2, 3, 4, 5, 6], UltralightType::UltralightEv1Mf0ul11).unwrap()
+        .with_password([1, 2, 3, 4], [0xAB, 0xCD], 0x04, false);
+    assert_eq!(tag.get_page(0x10), Some(&[0x04, 0x00, 0x00, 0x04]));
+    assert_eq!(tag.get_page(0x12), Some(&[1, 2, 3, 4]));
+    assert_eq!(tag.get_page(0x13), Some(&[0xAB, 0xCD, 0, 0]));
+}
+
+#[test]
+fn virtual_felica_test() {
+    let idm = [0x01, 0x2E, 0, 0, 0, 0, 0, 1];
+    let mut card = VirtualFelica::new(idm, [0; 8], felica::SYSTEM_CODE_AMUSEMENT_IC)
+        .with_service(0x000B, vec![[0x11; 16], [0x22; 16]])
+        .with_service(0x0009, vec![[0; 16]]);
+
+    let mut read = vec![0x00, 0x06];
+    read.extend_from_slice(&idm);
+    read.extend_from_slice(&[1, 0x0B, 0x00, 2, 0x80, 0x00, 0x80, 0x01]);
+    read[0] = read.len() as u8;
+    let response = card.exchange(&read).unwrap();
+    assert_eq!(&response[10..13], &[0x00, 0x00, 2]);
+    assert_eq!(&response[13..], [[0x11; 16], [0x22; 16]].concat());
+
+    let mut write = vec![0x00, 0x08];
+    write.extend_from_slice(&idm);
+    write.extend_from_slice(&[1, 0x0B, 0x00, 1, 0x80, 0x00]);
+    write.extend_from_slice(&[0x33; 16]);
+    assert_eq!(&card.exchange(&write).unwrap()[10..], &[0x01, 0xA5]);
+    write[11] = 0x09;
+    assert_eq!(&card.exchange(&write).unwrap()[10..], &[0x00, 0x00]);
+    assert_eq!(card.get_block(0x0009, 0), Some(&[0x33; 16]));
+
+    let mut other = read.clone();
+    other[2] ^= 0xFF;
+    assert_eq!(card.exchange(&other), Err(Pn532Error::Timeout));
+}
@@ -0,0 +1,478 @@
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use crate::card::{CardId, PassiveTarget};
+use crate::device::{poll_types, HinataDevice, ScanType};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CardEvent {
+    Tapped(PassiveTarget),
+    Removed,
+    /// Polling stopped because the device went away, no more events follow
+    Disconnected,
+}
+
+/// Card families the detector polls for
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CardType {
+    TypeA,
+    Felica,
+}
+
+/// How often and in which order the detector polls, trading latency against RF emissions and power draw
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PollingConfig {
+    pub interval: Duration,
+    /// Polled front to back within one round, the first type that answers wins
+    pub order: Vec<CardType>,
+    pub felica_system_code: u16,
+    /// Switch the field off while idling between rounds
+    pub rf_off_between_polls: bool,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            order: vec![CardType::TypeA, CardType::Felica],
+            felica_system_code: 0xFFFF,
+            rf_off_between_polls: false,
+        }
+    }
+}
+
+/// Polls for cards in the background and turns presence changes into [`CardEvent`]s
+#[derive(Debug, Clone)]
+pub struct CardDetector {
+    config: PollingConfig,
+    remove_after: u32,
+}
+
+impl Default for CardDetector {
+    fn default() -> Self {
+        Self {
+            config: PollingConfig::default(),
+            remove_after: 2,
+        }
+    }
+}
+
+impl CardDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_polling_config(mut self, config: PollingConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn get_polling_config(&self) -> &PollingConfig {
+        &self.config
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.config.interval = poll_interval;
+        self
+    }
+
+    pub fn with_type_a(self, enabled: bool) -> Self {
+        self.with_card_type(CardType::TypeA, enabled)
+    }
+
+    pub fn with_felica(self, enabled: bool) -> Self {
+        self.with_card_type(CardType::Felica, enabled)
+    }
+
+    fn with_card_type(mut self, card_type: CardType, enabled: bool) -> Self {
+        let polled = self.config.order.contains(&card_type);
+        if enabled && !polled {
+            self.config.order.push(card_type);
+        } else if !enabled {
+            self.config.order.retain(|&t| t != card_type);
+        }
+        self
+    }
+
+    pub fn with_felica_system_code(mut self, system_code: u16) -> Self {
+        self.config.felica_system_code = system_code;
+        self
+    }
+
+    /// Number of consecutive empty polls before a card counts as removed
+    pub fn with_remove_after(mut self, polls: u32) -> Self {
+        self.remove_after = polls.max(1);
+        self
+    }
+
+    /// Callback based alternative to [`CardDetector::spawn`]
+    pub fn observer(self) -> CardObserver {
+        CardObserver {
+            detector: self,
+            on_tap: None,
+            on_remove: None,
+            on_disconnect: None,
+        }
+    }
+
+    /// Start polling on `port`. Dropping the stream stops the loop and the handle hands the port back.
+    pub fn spawn<P: Pn532Port + Send + 'static>(self, port: P) -> (ReceiverStream<CardEvent>, JoinHandle<P>) {
+        self.spawn_on(port, &Handle::current())
+    }
+
+    /// [`CardDetector::spawn`] on `runtime`, for callers outside of it
+    pub fn spawn_on<P: Pn532Port + Send + 'static>(self, mut port: P, runtime: &Handle) -> (ReceiverStream<CardEvent>, JoinHandle<P>) {
+        let (tx, rx) = mpsc::channel(8);
+        let handle = runtime.spawn(async move {
+            self.run(&mut port, tx).await;
+            port
+        });
+        (ReceiverStream::new(rx), handle)
+    }
+
+    async fn run<P: Pn532Port>(&self, port: &mut P, tx: mpsc::Sender<CardEvent>) {
+        let mut pn532 = Pn532::new(port);
+        // Return from InListPassiveTarget right away when nothing is in the field
+        let _ = pn532.set_max_retries(0xFF, 0x01, 0x01).await;
+
+        let mut presence = self.presence();
+        while !tx.is_closed() {
+            match self.poll_round(&mut pn532).await {
+                Ok(polled) => {
+                    for event in presence.update(polled) {
+                        #[cfg(feature = "metrics")]
+                        if let CardEvent::Tapped(target) = &event {
+                            crate::metrics::record_tap(target);
+                        }
+                        if tx.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(Error::Disconnected(_)) => {
+                    let _ = tx.send(CardEvent::Disconnected).await;
+                    return;
+                }
+                // Anything else is a flaky poll, treat it like an empty field
+                Err(_) => presence.miss(),
+            }
+            tokio::time::sleep(self.config.interval).await;
+        }
+    }
+
+    /// One round over the configured card types, switching the field around it if configured
+    pub(crate) async fn poll_round<P: Pn532Port>(&self, pn532: &mut Pn532<'_, P>) -> HinataResult<Option<PassiveTarget>> {
+        if self.config.rf_off_between_polls {
+            let _ = pn532.set_rf_field(true).await;
+        }
+        let polled = self.poll_once(pn532).await;
+        if self.config.rf_off_between_polls {
+            let _ = pn532.set_rf_field(false).await;
+        }
+        polled
+    }
+
+    pub(crate) fn presence(&self) -> Presence {
+        Presence::new(self.remove_after)
+    }
+
+    /// One round over the configured card types
+    async fn poll_once<P: Pn532Port>(&self, pn532: &mut Pn532<'_, P>) -> HinataResult<Option<PassiveTarget>> {
+        let types: Vec<ScanType> = self.config.order.iter()
+            .map(|card_type| match card_type {
+                CardType::TypeA => ScanType::TypeA,
+                CardType::Felica => ScanType::Felica(self.config.felica_system_code),
+            })
+            .collect();
+        poll_types(pn532, &types).await
+    }
+}
+
+/// Turns poll results into tap and removal events
+pub(crate) struct Presence {
+    present: Option<Vec<u8>>,
+    misses: u32,
+    remove_after: u32,
+}
+
+impl Presence {
+    fn new(remove_after: u32) -> Self {
+        Self { present: None, misses: 0, remove_after }
+    }
+
+    /// Events caused by one poll, a card replacing another is removed before it is tapped
+    pub(crate) fn update(&mut self, polled: Option<PassiveTarget>) -> Vec<CardEvent> {
+        let mut events = Vec::new();
+        match polled {
+            Some(target) => {
+                self.misses = 0;
+                if self.present.as_deref() != Some(target.id_bytes()) {
+                    if self.present.is_some() {
+                        events.push(CardEvent::Removed);
+                    }
+                    self.present = Some(target.id_bytes().to_vec());
+                    events.push(CardEvent::Tapped(target));
+                }
+            }
+            None => {
+                self.miss();
+                if self.present.is_some() && self.misses >= self.remove_after {
+                    self.present = None;
+                    events.push(CardEvent::Removed);
+                }
+            }
+        }
+        events
+    }
+
+    /// A poll that failed, counts towards removal without removing by itself
+    pub(crate) fn miss(&mut self) {
+        self.misses += 1;
+    }
+}
+
+type TapCallback = Box<dyn Fn(&PassiveTarget) + Send>;
+type EventCallback = Box<dyn Fn() + Send>;
+
+/// Runs a [`CardDetector`] and calls back on every event, for callers that would rather not own a stream
+pub struct CardObserver {
+    detector: CardDetector,
+    on_tap: Option<TapCallback>,
+    on_remove: Option<EventCallback>,
+    on_disconnect: Option<EventCallback>,
+}
+
+impl CardObserver {
+    pub fn on_tap(mut self, callback: TapCallback) -> Self {
+        self.on_tap = Some(callback);
+        self
+    }
+
+    pub fn on_remove(mut self, callback: EventCallback) -> Self {
+        self.on_remove = Some(callback);
+        self
+    }
+
+    pub fn on_disconnect(mut self, callback: EventCallback) -> Self {
+        self.on_disconnect = Some(callback);
+        self
+    }
+
+    /// Start polling on `port` on `runtime`, the callbacks run on one of its tasks one at a time
+    pub fn spawn<P: Pn532Port + Send + 'static>(self, port: P, runtime: &Handle) -> CardObserverHandle<P> {
+        let (stream, detector) = self.detector.spawn_on(port, runtime);
+        let mut events = stream.into_inner();
+        let callbacks = runtime.spawn(async move {
+            while let Some(event) = events.recv().await {
+                match event {
+                    CardEvent::Tapped(target) => self.on_tap.iter().for_each(|f| f(&target)),
+                    CardEvent::Removed => self.on_remove.iter().for_each(|f| f()),
+                    CardEvent::Disconnected => self.on_disconnect.iter().for_each(|f| f()),
+                }
+            }
+        });
+        CardObserverHandle { callbacks, detector }
+    }
+}
+
+pub struct CardObserverHandle<P> {
+    callbacks: JoinHandle<()>,
+    detector: JoinHandle<P>,
+}
+
+impl<P> CardObserverHandle<P> {
+    /// Stop polling and hand the port back, `None` if the polling task panicked
+    pub async fn stop(self) -> Option<P> {
+        self.callbacks.abort();
+        self.detector.await.ok()
+    }
+
+    /// Blocking [`CardObserverHandle::stop`] for callers outside the runtime
+    pub fn stop_blocking(self, runtime: &tokio::runtime::Handle) -> Option<P> {
+        runtime.block_on(self.stop())
+    }
+}
+
+/// A [`CardEvent`] together with the reader it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReaderEvent {
+    pub reader: String,
+    pub event: CardEvent,
+}
+
+/// Runs one [`CardDetector`] per reader and merges their events into a single stream
+pub struct MultiReaderDetector {
+    detector: CardDetector,
+    readers: Vec<(String, HinataDevice)>,
+}
+
+impl MultiReaderDetector {
+    pub fn new(detector: CardDetector) -> Self {
+        Self {
+            detector,
+            readers: Vec::new(),
+        }
+    }
+
+    pub fn add_reader(mut self, alias: impl Into<String>, device: HinataDevice) -> Self {
+        self.readers.push((alias.into(), device));
+        self
+    }
+
+    /// Add a reader named after its chip id, which stays the same across USB ports
+    pub async fn add_reader_by_chip_id(self, mut device: HinataDevice) -> HinataResult<Self> {
+        let alias = device.chip_id_hex().await?;
+        Ok(self.add_reader(alias, device))
+    }
+
+    /// Start every reader. A reader that fails only ends its own polling, with a final
+    /// [`CardEvent::Disconnected`]; the handles give the devices back once the stream is dropped.
+    pub fn spawn(self) -> (ReceiverStream<ReaderEvent>, Vec<JoinHandle<HinataDevice>>) {
+        let (tx, rx) = mpsc::channel(8 * self.readers.len().max(1));
+        let mut handles = Vec::with_capacity(self.readers.len());
+
+        for (alias, device) in self.readers {
+            let (stream, handle) = self.detector.clone().spawn(device);
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut events = stream.into_inner();
+                loop {
+                    // Stop with the merged stream even while this reader sees no events, so its detector winds down
+                    let event = tokio::select! {
+                        event = events.recv() => event,
+                        _ = tx.closed() => None,
+                    };
+                    let Some(event) = event else {
+                        break;
+                    };
+                    let event = ReaderEvent { reader: alias.clone(), event };
+                    if tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            handles.push(handle);
+        }
+
+        (ReceiverStream::new(rx), handles)
+    }
+}
+
+#[test]
+fn polling_config_test() {
+    let detector = CardDetector::new().with_type_a(false).with_type_a(true).with_felica(false);
+    assert_eq!(detector.get_polling_config().order, vec![CardType::TypeA]);
+    let detector = detector.with_felica(true).with_felica(true);
+    assert_eq!(detector.get_polling_config().order, vec![CardType::TypeA, CardType::Felica]);
+}
+
+#[test]
+fn presence_test() {
+    use crate::card::Iso14443a;
+
+    let card = |uid: u8| PassiveTarget::Iso14443a(Iso14443a::new(vec![uid; 4], 0x08, 0x0004));
+    let mut presence = Presence::new(2);
+
+    assert_eq!(presence.update(None), []);
+    assert_eq!(presence.update(Some(card(1))), [CardEvent::Tapped(card(1))]);
+    assert_eq!(presence.update(Some(card(1))), []);
+    // One empty poll is not enough, and a poll that sees the card again starts counting over
+    assert_eq!(presence.update(None), []);
+    assert_eq!(presence.update(Some(card(1))), []);
+    assert_eq!(presence.update(None), []);
+    assert_eq!(presence.update(None), [CardEvent::Removed]);
+    assert_eq!(presence.update(None), []);
+
+    assert_eq!(presence.update(Some(card(1))), [CardEvent::Tapped(card(1))]);
+    assert_eq!(presence.update(Some(card(2))), [CardEvent::Removed, CardEvent::Tapped(card(2))]);
+
+    // A failed poll counts like an empty one but only an empty poll removes
+    presence.miss();
+    presence.miss();
+    assert_eq!(presence.update(Some(card(2))), []);
+    presence.miss();
+    assert_eq!(presence.update(None), [CardEvent::Removed]);
+
+    let mut presence = Presence::new(1);
+    assert_eq!(presence.update(Some(card(3))), [CardEvent::Tapped(card(3))]);
+    assert_eq!(presence.update(None), [CardEvent::Removed]);
+}
+
+#[cfg(feature = "simulator")]
+#[tokio::test]
+async fn multi_reader_detector_test() {
+    use std::sync::Arc;
+    use tokio_stream::StreamExt;
+    use crate::backend::simulator::{SimulatorBackend, VirtualHinata};
+    use crate::card::Iso14443a;
+
+    let first = VirtualHinata::new().with_chip_id([1, 2, 3, 4]);
+    let second = VirtualHinata::new().with_chip_id([5, 6, 7, 8]);
+    let backend = Arc::new(SimulatorBackend::new().with_device(first.clone()).with_device(second.clone()));
+    let mut detector = MultiReaderDetector::new(CardDetector::new().with_poll_interval(Duration::from_millis(10)));
+    for builder in crate::find_devices_with_backend(backend, vec![]).await.unwrap() {
+        detector = detector.add_reader_by_chip_id(builder.build(false).unwrap()).await.unwrap();
+    }
+    let (mut events, handles) = detector.spawn();
+    let mut next = async || tokio::time::timeout(Duration::from_secs(5), events.next()).await.unwrap().unwrap();
+
+    let card = PassiveTarget::Iso14443a(Iso14443a::new(vec![0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004));
+    first.place_card(card.clone());
+    assert_eq!(next().await, ReaderEvent { reader: "01020304".into(), event: CardEvent::Tapped(card) });
+
+    // The second reader going away leaves the first one polling
+    second.disconnect();
+    assert_eq!(next().await, ReaderEvent { reader: "05060708".into(), event: CardEvent::Disconnected });
+    first.remove_cards();
+    assert_eq!(next().await, ReaderEvent { reader: "01020304".into(), event: CardEvent::Removed });
+
+    drop(events);
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+#[cfg(feature = "simulator")]
+#[tokio::test]
+async fn card_observer_test() {
+    use std::sync::Arc;
+    use crate::backend::simulator::{SimulatorBackend, VirtualHinata};
+    use crate::card::Iso14443a;
+
+    let reader = VirtualHinata::new();
+    let backend = Arc::new(SimulatorBackend::new().with_device(reader.clone()));
+    let device = crate::find_devices_with_backend(backend, vec![]).await.unwrap()[0].build(false).unwrap();
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (tap, remove) = (tx.clone(), tx.clone());
+    let handle = CardDetector::new().with_poll_interval(Duration::from_millis(10)).observer()
+        .on_tap(Box::new(move |target| tap.send(format!("tap {}", target.display_id())).unwrap()))
+        .on_remove(Box::new(move || remove.send("remove".to_string()).unwrap()))
+        .on_disconnect(Box::new(move || tx.send("disconnect".to_string()).unwrap()))
+        .spawn(device, &Handle::current());
+    let mut next = async || tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+
+    reader.place_card(PassiveTarget::Iso14443a(Iso14443a::new(vec![0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004)));
+    assert_eq!(next().await, "tap DEADBEEF");
+    reader.remove_cards();
+    assert_eq!(next().await, "remove");
+    // Stopping hands back a port that still works
+    let mut device = handle.stop().await.unwrap();
+    device.get_firmware_version().await.unwrap();
+
+    // Started from a thread that is not part of the runtime
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let observer = CardDetector::new().with_poll_interval(Duration::from_millis(10)).observer()
+        .on_disconnect(Box::new(move || tx.send(()).unwrap()));
+    let runtime = Handle::current();
+    let handle = std::thread::spawn(move || observer.spawn(device, &runtime)).join().unwrap();
+    reader.disconnect();
+    tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+    assert!(handle.stop().await.is_some());
+}
@@ -1,11 +1,24 @@
-use crate::error::{Error, HinataResult};
-use crate::message::{InMessage, OutMessage, Subscription, UnSubscribePolicy};
-use crate::pn532::{Pn532, Pn532Command, Pn532Direction, Pn532Packet, Pn532Port};
+use crate::backend::{HidBackend, HidDeviceInfo};
+use crate::error::{Error, ErrorContext, HinataResult};
+use crate::firmware::{poll_until, BootloaderConnector, BootloaderDevice};
+use crate::hooks::{Hooks, RequestKind};
+use crate::message::{ChannelPool, InMessage, ReportStream, Subscription, SubscriptionReceiver, UnSubscribePolicy};
+use crate::card::{CardId, PassiveTarget};
+use crate::request::{Request, DEFAULT_TIMEOUT, PN532_PASSTHROUGH};
+use crate::pn532::{AutoPollType, FelicaPollRequest, Pn532, Pn532Command, Pn532Direction, Pn532Error, Pn532Packet, Pn532Port};
 use crate::types::HidDevicePath;
+use crate::utils::id_format::{device_fingerprint, IdFormat};
+use crate::ChipId;
 use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{Datelike, NaiveDate};
+use hidapi::HidError;
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
 use std::thread::JoinHandle;
-use std::time::Duration;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{oneshot, watch};
 
 #[derive(Debug)]
 pub(crate) struct Info {
@@ -19,10 +32,244 @@ pub(crate) struct Info {
     pub pid: u16,
 }
 
+/// Firmware build parsed from the timestamp banner, e.g. `2025051301` is build 1 of 2025-05-13
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FirmwareVersion {
+    pub date: NaiveDate,
+    pub build: u32,
+}
+
+impl FirmwareVersion {
+    /// Parse the first run of digits in a timestamp response: `YYYYMMDD` followed by the build number.
+    /// Banners without one are [`Error::NotSupport`].
+    pub fn parse(raw: &[u8]) -> HinataResult<Self> {
+        let unknown = || Error::NotSupport(format!("Unknown firmware version format: {:02X?}", &raw[..raw.len().min(16)]));
+        let start = raw.iter().position(u8::is_ascii_digit).ok_or_else(unknown)?;
+        let len = raw[start..].iter().take_while(|b| b.is_ascii_digit()).count();
+        let digits = std::str::from_utf8(&raw[start..start + len]).map_err(|_| unknown())?;
+        if !(9..=17).contains(&digits.len()) {
+            return Err(unknown());
+        }
+
+        let (date, build) = digits.split_at(8);
+        let date = NaiveDate::parse_from_str(date, "%Y%m%d").map_err(|_| unknown())?;
+        Ok(Self { date, build: build.parse()? })
+    }
+
+    /// The `YYYYMMDDBB` number [`HinataDevice::get_firmware_timestamp`] returns, assumes a build below 100
+    pub fn timestamp(&self) -> u32 {
+        let date = self.date.year() as u32 * 10000 + self.date.month() * 100 + self.date.day();
+        date * 100 + self.build
+    }
+}
+
+/// Firmware commands missing from older builds, check with [`HinataDevice::supports`] before using them
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    ChipId,
+    FirmwareCommitHash,
+}
+
+impl Capability {
+    /// Oldest firmware timestamp that has the command
+    pub fn min_timestamp(&self) -> u32 {
+        match self {
+            Capability::ChipId | Capability::FirmwareCommitHash => 2025051301,
+        }
+    }
+
+    pub fn is_supported_by(&self, timestamp: u32) -> bool {
+        timestamp >= self.min_timestamp()
+    }
+
+    /// [`Error::NotSupport`] unless firmware `timestamp` has this capability
+    pub(crate) fn require(&self, timestamp: u32) -> HinataResult<()> {
+        if !self.is_supported_by(timestamp) {
+            return Err(Error::NotSupport(format!("{self:?} needs firmware {} or newer, found {timestamp}", self.min_timestamp())));
+        }
+        Ok(())
+    }
+}
+
+/// What tells readers apart, e.g. as a map key for per-reader state.
+/// The chip id survives moving the reader to another USB port, the instance id is all there is before it was read.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceId {
+    Chip(ChipId),
+    Instance(String),
+}
+
+/// Snapshot of what is known about a device, firmware fields stay `None` until queried.
+/// Equality and hashing go by the instance id only, so a snapshot keeps its identity once the chip id was read.
+/// Compare [`HinataInfo::get_device_id`] to follow a reader across USB ports.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct HinataInfo {
+    pub instance_id: String,
+    pub device_name: String,
+    pub pid: u16,
+    pub path_read: String,
+    pub path_write: String,
+    pub com_instance_id: Option<String>,
+    pub firmware_timestamp: Option<u32>,
+    pub firmware_commit_hash: Option<[u8; 4]>,
+    pub chip_id: Option<[u8; 4]>,
+}
+
+impl HinataInfo {
+    /// The chip id once [`HinataDevice::get_chip_id`] read it, the instance id before
+    pub fn get_device_id(&self) -> DeviceId {
+        match self.chip_id {
+            Some(chip_id) => DeviceId::Chip(chip_id),
+            None => DeviceId::Instance(self.instance_id.clone()),
+        }
+    }
+}
+
+impl PartialEq for HinataInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.instance_id == other.instance_id
+    }
+}
+
+impl Eq for HinataInfo {}
+
+impl Hash for HinataInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.instance_id.hash(state)
+    }
+}
+
+/// Card families [`HinataDevice::scan_card`] polls for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScanType {
+    TypeA,
+    /// FeliCa with the given system code, `0xFFFF` for any
+    Felica(u16),
+}
+
+/// One poll per type in order, the first type that answers wins
+pub(crate) async fn poll_types<P: Pn532Port>(pn532: &mut Pn532<'_, P>, types: &[ScanType]) -> HinataResult<Option<PassiveTarget>> {
+    for scan_type in types {
+        let targets = match scan_type {
+            ScanType::TypeA => pn532.in_list_passive_target(0, 1, &[]).await?,
+            ScanType::Felica(system_code) => {
+                pn532.in_list_passive_target(1, 1, &FelicaPollRequest::new(*system_code).to_bytes()?).await?
+            }
+        };
+        if let Some(target) = targets.into_iter().next() {
+            return Ok(Some(target));
+        }
+    }
+    Ok(None)
+}
+
+const SCAN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Consecutive empty polls before a card counts as gone
+const REMOVE_AFTER: u32 = 2;
+
+/// InAutoPoll period in 150ms units
+const AUTO_POLL_PERIOD: u8 = 2;
+
+/// Round-trip latency distribution of one command over a [`HinataDevice::benchmark`] run
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub min: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    fn from_samples(mut samples: Vec<Duration>) -> Option<Self> {
+        samples.sort();
+        // Nearest rank percentile
+        let percentile = |p: usize| samples[(samples.len() * p).div_ceil(100).max(1) - 1];
+        Some(Self {
+            samples: samples.len(),
+            min: *samples.first()?,
+            p50: percentile(50),
+            p99: percentile(99),
+            max: *samples.last()?,
+        })
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkReport {
+    /// Firmware version query, answered by the MCU without touching the PN532
+    pub firmware: LatencyStats,
+    /// PN532 GetFirmwareVersion, adds the MCU to PN532 hop
+    pub pn532: LatencyStats,
+}
+
+/// One step of [`HinataDevice::self_test`]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SelfTestCheck {
+    FirmwareTimestamp,
+    ChipId,
+    /// PN532 GetFirmwareVersion
+    Pn532Firmware,
+    /// PN532 Diagnose communication line test, echoes a pattern through the MCU
+    Pn532Communication,
+    /// Cycles the LED, only proves the firmware took the commands, the color needs a human or a camera
+    Led,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestStep {
+    pub check: SelfTestCheck,
+    pub passed: bool,
+    /// What was read back on success, the error otherwise
+    pub detail: String,
+    pub elapsed: Duration,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub steps: Vec<SelfTestStep>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|step| step.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestStep> {
+        self.steps.iter().filter(|step| !step.passed)
+    }
+}
+
+/// Pattern echoed by the communication line test
+const SELF_TEST_PATTERN: [u8; 8] = [0x00, 0xFF, 0x55, 0xAA, 0x01, 0x80, 0x7E, 0x81];
+
+/// State of the io threads behind a device, see [`HinataDevice::status_events`]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IoStatus {
+    Running,
+    Disconnected,
+    /// Reads failed for another reason than the device going away, with the platform's message
+    Failed(String),
+    /// An io thread panicked with this message, the device stays unusable
+    Panicked(String),
+}
+
 #[derive(Debug)]
 pub(crate) struct Config {
     pub sega_brightness: u8,
     pub sega_rapid_scan: bool,
+    pub retry_syntax_error: bool,
 }
 
 // --- Device Implementation ---
@@ -34,39 +281,37 @@ pub struct HinataDevice {
     loop_handler: Option<JoinHandle<()>>,
 
     tx: Sender<InMessage>,
+    channels: ChannelPool,
+    /// Detached PN532 requests whose ACK and response are still due
+    detached: Vec<(Pn532Command, SubscriptionReceiver)>,
+    status: watch::Receiver<IoStatus>,
+    hooks: Hooks,
 }
 
 #[async_trait]
 impl Pn532Port for HinataDevice {
     async fn request(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<Vec<u8>> {
-        let (subscription, mut rx) = Subscription::new(UnSubscribePolicy::SpecificNotOn(4, 0));
-        let packet = Pn532Packet::new(Pn532Direction::HostToPn532, pn532_cmd, payload.to_vec());
-        let mut send = vec![1, 0xE2];
-        send.extend_from_slice(&packet.to_bytes());
-
-        let _ = self
-            .tx
-            .send(InMessage::SendPacketAndSubscribe(send, subscription))
-            .await;
-
-        let standard_ack = [0, 0, 0xFF, 0, 0xFF, 0];
-
-        let ack = Self::receive_packet(&mut rx, Duration::from_millis(1000)).await?;
-        if &ack[1..7] != &standard_ack {
-            return Err(Error::Protocol("ack error".to_string()));
-        }
-
-        let res = Self::receive_packet(&mut rx, Duration::from_millis(1000)).await?;
-        let res_packet = Pn532Packet::from_bytes(&res[1..]).map_err(|e| Error::Protocol(e))?;
+        self.request_timeout(pn532_cmd, payload, Duration::from_millis(1000)).await
+    }
 
-        if res_packet.direction != Pn532Direction::Pn532ToHost {
-            return Err(Error::Protocol("Direction mismatch".to_string()));
-        };
-        if res_packet.command != packet.command {
-            return Err(Error::Protocol("Command mismatch".to_string()));
-        };
+    async fn request_timeout(&mut self, pn532_cmd: Pn532Command, payload: &[u8], timeout: Duration) -> HinataResult<Vec<u8>> {
+        let kind = RequestKind::Pn532(pn532_cmd);
+        let start = Instant::now();
+        self.hooks.request_start(kind);
+        let res = self.pn532_request(pn532_cmd, payload, timeout).await;
+        self.hooks.request_end(kind, start.elapsed(), res.as_ref().err());
+        res
+    }
 
-        Ok(res_packet.payload)
+    /// The response is still subscribed to, and swallowed before the next request goes out
+    fn request_detached(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<()> {
+        let (subscription, rx) = Subscription::pooled(UnSubscribePolicy::SpecificNotOn(4, 0), &mut self.channels);
+        let packet = Pn532Packet::new(Pn532Direction::HostToPn532, pn532_cmd, payload);
+        self.tx
+            .try_send(InMessage::SendPacketAndSubscribe(Request::pn532(&packet).into_report(), subscription))
+            .map_err(|e| Error::Disconnected(e.to_string()))?;
+        self.detached.push((pn532_cmd, rx));
+        Ok(())
     }
 }
 
@@ -76,12 +321,36 @@ impl HinataDevice {
         config: Config,
         loop_handler: Option<JoinHandle<()>>,
         tx: Sender<InMessage>,
+        status: watch::Receiver<IoStatus>,
+        hooks: Hooks,
     ) -> Self {
         Self {
             info,
             config,
             loop_handler,
             tx,
+            channels: ChannelPool::default(),
+            detached: Vec::new(),
+            status,
+            hooks,
+        }
+    }
+
+    /// Follow the io threads, e.g. to learn why requests started failing
+    pub fn status_events(&self) -> watch::Receiver<IoStatus> {
+        self.status.clone()
+    }
+
+    pub fn get_io_status(&self) -> IoStatus {
+        self.status.borrow().clone()
+    }
+
+    /// The error for a request the io threads can no longer take
+    fn io_error(&self) -> Error {
+        match self.get_io_status() {
+            IoStatus::Panicked(reason) => Error::IoThreadPanicked(reason),
+            IoStatus::Failed(message) => Error::HidError(HidError::HidApiError { message }),
+            _ => Error::Disconnected("IO thread stopped".into()),
         }
     }
 
@@ -89,17 +358,95 @@ impl HinataDevice {
         self.info.instance_id.to_string()
     }
 
+    async fn pn532_request(&mut self, pn532_cmd: Pn532Command, payload: &[u8], timeout: Duration) -> HinataResult<Vec<u8>> {
+        match self.pn532_exchange(pn532_cmd, payload, timeout).await {
+            Err(Error::Pn532(Pn532Error::SyntaxError)) if self.config.retry_syntax_error => {
+                self.pn532_exchange(pn532_cmd, payload, timeout).await
+            }
+            res => res,
+        }
+    }
+
+    /// Wait out the responses of detached requests, the next exchange would take them for its own
+    async fn drain_detached(&mut self) {
+        for (command, mut rx) in std::mem::take(&mut self.detached) {
+            if Self::receive_pn532_response(&mut rx, command, DEFAULT_TIMEOUT).await.is_err() {
+                let _ = self.tx.send(InMessage::UnSubscribe(PN532_PASSTHROUGH)).await;
+            }
+            self.channels.recycle(rx);
+        }
+    }
+
+    async fn pn532_exchange(&mut self, pn532_cmd: Pn532Command, payload: &[u8], timeout: Duration) -> HinataResult<Vec<u8>> {
+        self.drain_detached().await;
+        let (subscription, mut rx) = Subscription::pooled(UnSubscribePolicy::SpecificNotOn(4, 0), &mut self.channels);
+        let packet = Pn532Packet::new(Pn532Direction::HostToPn532, pn532_cmd, payload);
+        let send = Request::pn532(&packet).into_report();
+
+        if self.tx.send(InMessage::SendPacketAndSubscribe(send, subscription)).await.is_err() {
+            return Err(self.io_error());
+        }
+
+        let start = Instant::now();
+        let res = Self::receive_pn532_response(&mut rx, pn532_cmd, timeout)
+            .await
+            .map_err(|(e, direction, frame)| {
+                let frame = frame.map(Vec::from).unwrap_or_else(|| {
+                    let mut sent = Vec::new();
+                    packet.write_to(&mut sent);
+                    sent
+                });
+                e.with_context(ErrorContext::new(RequestKind::Pn532(pn532_cmd), Some(direction), &frame, start.elapsed()))
+            });
+        if res.is_err() {
+            // The io thread may still wait for a response on our behalf, a late one must not reach the next request
+            let _ = self.tx.send(InMessage::UnSubscribe(PN532_PASSTHROUGH)).await;
+        }
+        self.channels.recycle(rx);
+        res
+    }
+
+    /// Errors come with the frame they are about, `None` when nothing arrived and the sent frame is meant
+    async fn receive_pn532_response(rx: &mut SubscriptionReceiver, command: Pn532Command, timeout: Duration) -> Result<Vec<u8>, (Error, Pn532Direction, Option<Bytes>)> {
+        let standard_ack = [0, 0, 0xFF, 0, 0xFF, 0];
+
+        let ack = Self::receive_packet(rx, Duration::from_millis(1000)).await
+            .map_err(|e| (e, Pn532Direction::HostToPn532, None))?;
+        if Pn532Packet::is_error_frame(&ack[1..]) {
+            return Err((Error::Pn532(Pn532Error::SyntaxError), Pn532Direction::Pn532ToHost, Some(ack)));
+        }
+        if ack.get(1..7) != Some(&standard_ack[..]) {
+            return Err((Error::Protocol("ack error".into()), Pn532Direction::Pn532ToHost, Some(ack)));
+        }
+
+        let res = Self::receive_packet(rx, timeout).await
+            .map_err(|e| (e, Pn532Direction::HostToPn532, None))?;
+        if Pn532Packet::is_error_frame(&res[1..]) {
+            return Err((Error::Pn532(Pn532Error::SyntaxError), Pn532Direction::Pn532ToHost, Some(res)));
+        }
+        let res_packet = match Pn532Packet::from_bytes(&res[1..]) {
+            Ok(packet) => packet,
+            Err(e) => return Err((Error::Protocol(e.into()), Pn532Direction::Pn532ToHost, Some(res.clone()))),
+        };
+
+        if res_packet.direction != Pn532Direction::Pn532ToHost {
+            return Err((Error::Protocol("Direction mismatch".into()), Pn532Direction::Pn532ToHost, Some(res.clone())));
+        };
+        if res_packet.command != command {
+            return Err((Error::Protocol("Command mismatch".into()), Pn532Direction::Pn532ToHost, Some(res.clone())));
+        };
+
+        Ok(res_packet.payload.into_owned())
+    }
+
     async fn receive_packet(
-        rx: &mut Receiver<OutMessage>,
+        rx: &mut SubscriptionReceiver,
         timeout: Duration,
-    ) -> HinataResult<Vec<u8>> {
+    ) -> HinataResult<Bytes> {
         tokio::select! {
             message = rx.recv() => {
                 if let Some(data) = message {
-                    match data {
-                        OutMessage::Response(data) => Ok(data),
-                        OutMessage::DeviceDisconnect => Err(Error::Disconnected("Device disconnected".into()))
-                    }
+                    data.into_result()
                 } else {
                     Err(Error::Disconnected("Subscribe channel disconnected".into()))
                 }
@@ -109,59 +456,213 @@ impl HinataDevice {
         }
     }
 
-    async fn request_without_response(&mut self, cmd: u8, payload: &[u8]) {
-        let mut packet = vec![1, cmd];
-        packet.extend_from_slice(payload);
-        let _ = self.tx.send(InMessage::SendPacket(packet)).await;
+    /// Stream the reports answering firmware command `cmd` until `policy` ends it or the stream is dropped.
+    /// Replaces whatever waits on `cmd`, a request in flight for the same command never gets its response.
+    pub async fn subscribe(&mut self, cmd: u8, policy: UnSubscribePolicy) -> HinataResult<ReportStream> {
+        let (subscription, stream) = Subscription::report_stream(policy);
+        if self.tx.send(InMessage::Subscribe(cmd, subscription)).await.is_err() {
+            return Err(self.io_error());
+        }
+        Ok(stream)
     }
-    async fn request(&mut self, cmd: u8, payload: &[u8]) -> HinataResult<Vec<u8>> {
-        let mut packet = vec![1, cmd];
-        packet.extend_from_slice(payload);
-        let (subscription, mut rx) = Subscription::new(UnSubscribePolicy::Count(1));
-        let _ = self
-            .tx
-            .send(InMessage::SendPacketAndSubscribe(packet, subscription))
-            .await;
-        let res = Self::receive_packet(&mut rx, Duration::from_millis(1000)).await?;
-        Ok(res)
+
+    /// End the subscription on `cmd`, its stream finishes after the reports already buffered
+    pub async fn unsubscribe(&mut self, cmd: u8) {
+        let _ = self.tx.send(InMessage::UnSubscribe(cmd)).await;
+    }
+
+    /// Read feature report `report_id` of up to `len` bytes after the id, the report returned starts with the id
+    pub async fn get_feature_report(&mut self, report_id: u8, len: usize) -> HinataResult<Vec<u8>> {
+        let mut buf = vec![0; len + 1];
+        buf[0] = report_id;
+        let (reply, rx) = oneshot::channel();
+        if self.tx.send(InMessage::GetFeatureReport(buf, reply)).await.is_err() {
+            return Err(self.io_error());
+        }
+        rx.await.map_err(|_| self.io_error())?
+    }
+
+    /// Send a feature report, `data` starts with the report id
+    pub async fn send_feature_report(&mut self, data: &[u8]) -> HinataResult<()> {
+        let (reply, rx) = oneshot::channel();
+        if self.tx.send(InMessage::SendFeatureReport(data.to_vec(), reply)).await.is_err() {
+            return Err(self.io_error());
+        }
+        rx.await.map_err(|_| self.io_error())?
+    }
+
+    /// Write a firmware command the reader does not answer, like the LED ones
+    pub async fn send_request_detached(&mut self, request: Request) {
+        let _ = self.tx.send(InMessage::SendPacket(request.into_report())).await;
+    }
+
+    /// Send a firmware command and wait for the report answering it, starting with the command byte
+    pub async fn send_request(&mut self, request: Request) -> HinataResult<Bytes> {
+        let kind = RequestKind::Firmware(request.get_command());
+        let start = Instant::now();
+        self.hooks.request_start(kind);
+        let res = self.firmware_request(request).await;
+        self.hooks.request_end(kind, start.elapsed(), res.as_ref().err());
+        res
+    }
+
+    async fn firmware_request(&mut self, request: Request) -> HinataResult<Bytes> {
+        let (subscription, mut rx) = Subscription::once();
+        if self.tx.send(InMessage::SendPacketAndSubscribe(request.as_report().to_vec(), subscription)).await.is_err() {
+            return Err(self.io_error());
+        }
+        let start = Instant::now();
+        Self::receive_packet(&mut rx, request.get_timeout())
+            .await
+            .map_err(|e| {
+                let kind = RequestKind::Firmware(request.get_command());
+                e.with_context(ErrorContext::new(kind, None, request.as_report(), start.elapsed()))
+            })
     }
 
     pub fn pn532(&'_ mut self) -> Pn532<'_, Self> {
         Pn532::new(self)
     }
 
+    /// Poll the requested card types until one shows up or `timeout` elapses, returns the target with its Tg
+    pub async fn scan_card(&mut self, timeout: Duration, types: &[ScanType]) -> HinataResult<Option<(u8, PassiveTarget)>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut pn532 = self.pn532();
+        pn532.set_max_retries(0xFF, 0x01, 0x01).await?;
+
+        let found = loop {
+            let found = poll_types(&mut pn532, types).await?.map(|target| (1, target));
+            if found.is_some() || tokio::time::Instant::now() >= deadline {
+                break found;
+            }
+            tokio::time::sleep(SCAN_INTERVAL).await;
+        };
+
+        if found.is_some() {
+            let _ = pn532.in_release(0).await;
+        }
+        Ok(found)
+    }
+
+    /// Wait until the card with `uid` (UID or IDm) is tapped again. A card still resting on the reader
+    /// has to leave the field first, so one long tap never counts twice.
+    pub async fn wait_for_card_with_uid(&mut self, uid: &[u8], timeout: Duration) -> HinataResult<Option<PassiveTarget>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let types = [ScanType::TypeA, ScanType::Felica(0xFFFF)];
+        let mut pn532 = self.pn532();
+        pn532.set_max_retries(0xFF, 0x01, 0x01).await?;
+
+        let mut misses = 0;
+        let found = loop {
+            match poll_types(&mut pn532, &types).await? {
+                Some(target) if target.id_bytes() == uid => {
+                    if misses >= REMOVE_AFTER {
+                        break Some(target);
+                    }
+                    misses = 0;
+                }
+                _ => misses += 1,
+            }
+            if tokio::time::Instant::now() >= deadline {
+                break None;
+            }
+            tokio::time::sleep(SCAN_INTERVAL).await;
+        };
+
+        if found.is_some() {
+            let _ = pn532.in_release(0).await;
+        }
+        Ok(found)
+    }
+
+    /// Wait for a Type A or FeliCa card while the PN532 polls on its own, so the host only hears back once.
+    /// The chip cannot sense a passive card in PowerDown, it goes to sleep between waits when nothing showed up.
+    /// The PN532 counts at most 254 rounds of 600 ms, so a `timeout` beyond about 152 s ends early.
+    pub async fn wait_for_card_low_power(&mut self, timeout: Duration) -> HinataResult<Option<PassiveTarget>> {
+        let types = [AutoPollType::Iso14443a, AutoPollType::Felica212];
+        let round = Duration::from_millis(150) * AUTO_POLL_PERIOD as u32 * types.len() as u32;
+        let poll_nr = timeout.as_millis().div_ceil(round.as_millis()).clamp(1, 0xFE) as u8;
+
+        let mut pn532 = self.pn532();
+        let targets = pn532.in_auto_poll(poll_nr, AUTO_POLL_PERIOD, &types, timeout + Duration::from_millis(1000)).await?;
+        match targets.into_iter().next() {
+            Some(target) => {
+                let _ = pn532.in_release(0).await;
+                Ok(Some(target))
+            }
+            None => {
+                // Wake up on any host interface, the next command brings it back
+                let _ = pn532.power_down(0xB0).await;
+                Ok(None)
+            }
+        }
+    }
+
     pub async fn get_firmware_timestamp(&mut self) -> HinataResult<u32> {
         if self.info.firmware_timestamp > 0 {
             return Ok(self.info.firmware_timestamp);
         }
-        let raw = self.request(1, &[]).await?;
-        let str = String::from_utf8(raw[..10].to_vec())?;
-        let num = str.parse::<u32>()?;
-        self.info.firmware_timestamp = num;
-        Ok(num)
+        let timestamp = self.get_firmware_version().await?.timestamp();
+        self.info.firmware_timestamp = timestamp;
+        Ok(timestamp)
+    }
+
+    pub async fn get_firmware_version(&mut self) -> HinataResult<FirmwareVersion> {
+        let raw = self.send_request(Request::new(1)).await?;
+        let version = FirmwareVersion::parse(&raw)?;
+        self.info.firmware_timestamp = version.timestamp();
+        Ok(version)
     }
 
     pub async fn set_led(&mut self, r: u8, g: u8, b: u8) {
-        self.request_without_response(0x07, &[r, g, b]).await;
+        self.send_request_detached(Request::new(0x07).u8(r).u8(g).u8(b)).await;
     }
 
     pub async fn reset_led(&mut self) {
-        self.request_without_response(0xEA, &[]).await
+        self.send_request_detached(Request::new(0xEA)).await
     }
 
     pub async fn enter_bootloader(&mut self) {
-        self.request_without_response(0xF0, &[]).await
+        self.send_request_detached(Request::new(0xF0)).await
     }
 
-    pub async fn get_chip_id(&mut self) -> HinataResult<[u8; 4]> {
-        let timestamp = self.get_firmware_timestamp().await?;
-        if timestamp < 2025051301 {
-            return Err(Error::NotSupport("Firmware version too old".into()));
+    /// Reboot into the bootloader, wait up to `timeout` for an interface with the connector's
+    /// [`usb_id`](BootloaderConnector::usb_id) to appear on `backend` and open it. Bootloaders that were
+    /// already there are skipped, so another reader waiting in its bootloader is not taken for this one.
+    pub async fn enter_bootloader_and_wait(mut self, backend: &dyn HidBackend, connector: &dyn BootloaderConnector, timeout: Duration) -> HinataResult<BootloaderDevice> {
+        let (instance_id, product_id) = (self.get_instance_id(), self.get_product_id());
+        // How the reader is recognized once it runs its firmware again
+        let device_id = match self.get_chip_id().await {
+            Ok(chip_id) => DeviceId::Chip(chip_id),
+            Err(Error::NotSupport(_)) => DeviceId::Instance(instance_id.clone()),
+            Err(e) => return Err(e),
+        };
+        let (bootloader_vid, bootloader_pid) = connector.usb_id();
+        let bootloaders = || -> HinataResult<Vec<HidDeviceInfo>> {
+            Ok(backend.enumerate(bootloader_vid)?.into_iter().filter(|info| info.product_id == bootloader_pid).collect())
         };
+        let present: Vec<CString> = bootloaders()?.into_iter().map(|info| info.path).collect();
+        self.enter_bootloader().await;
+        drop(self);
+
+        let interface = poll_until(timeout, "Bootloader did not enumerate", || async {
+            Ok(bootloaders()?.into_iter().find(|info| !present.contains(&info.path)))
+        }).await?;
+        let bootloader = connector.open(&interface).await?;
+        Ok(BootloaderDevice::new(instance_id, product_id, device_id, bootloader))
+    }
+
+    /// Whether the firmware has `capability`, reads the firmware timestamp on first use
+    pub async fn supports(&mut self, capability: Capability) -> HinataResult<bool> {
+        Ok(capability.is_supported_by(self.get_firmware_timestamp().await?))
+    }
+
+    pub async fn get_chip_id(&mut self) -> HinataResult<[u8; 4]> {
+        Capability::ChipId.require(self.get_firmware_timestamp().await?)?;
         let chip_id = if let Some(id) = self.info.chip_id {
             id
         } else {
-            let res = self.request(0xE6, &[]).await?;
+            let res = self.send_request(Request::new(0xE6)).await?;
             let array = Self::get_four_bytes(&res[1..])?;
             self.info.chip_id = Some(array);
             array
@@ -169,6 +670,22 @@ impl HinataDevice {
         Ok(chip_id)
     }
 
+    /// Upper-case hex of [`get_chip_id`](Self::get_chip_id), e.g. `1A2B3C4D`
+    pub async fn chip_id_hex(&mut self) -> HinataResult<String> {
+        Ok(IdFormat::new(&self.get_chip_id().await?).hex())
+    }
+
+    /// Upper-case hex of [`get_firmware_commit_hash`](Self::get_firmware_commit_hash)
+    pub async fn commit_hash_hex(&mut self) -> HinataResult<String> {
+        Ok(IdFormat::new(&self.get_firmware_commit_hash().await?).hex())
+    }
+
+    /// See [`device_fingerprint`](crate::utils::id_format::device_fingerprint), stable across USB ports and firmware updates
+    pub async fn device_fingerprint(&mut self) -> HinataResult<String> {
+        let chip_id = self.get_chip_id().await?;
+        Ok(device_fingerprint(self.get_product_id(), &chip_id))
+    }
+
     fn get_four_bytes(data: &[u8]) -> HinataResult<[u8; 4]> {
         let array: [u8; 4] = data
             .get(..4)
@@ -178,14 +695,11 @@ impl HinataDevice {
     }
 
     pub async fn get_firmware_commit_hash(&mut self) -> HinataResult<[u8; 4]> {
-        let timestamp = self.get_firmware_timestamp().await?;
-        if timestamp < 2025051301 {
-            return Err(Error::NotSupport("Firmware version too old".into()));
-        };
+        Capability::FirmwareCommitHash.require(self.get_firmware_timestamp().await?)?;
         let commit_hash = if let Some(hash) = self.info.firmware_commit_hash {
             hash
         } else {
-            let res = self.request(0xE5, &[]).await?;
+            let res = self.send_request(Request::new(0xE5)).await?;
             let array = Self::get_four_bytes(&res[1..])?;
             self.info.firmware_commit_hash = Some(array);
             array
@@ -197,6 +711,27 @@ impl HinataDevice {
         self.info.device_name.clone()
     }
 
+    pub fn get_device_id(&self) -> DeviceId {
+        match self.info.chip_id {
+            Some(chip_id) => DeviceId::Chip(chip_id),
+            None => DeviceId::Instance(self.info.instance_id.clone()),
+        }
+    }
+
+    pub fn get_info(&self) -> HinataInfo {
+        HinataInfo {
+            instance_id: self.info.instance_id.clone(),
+            device_name: self.info.device_name.clone(),
+            pid: self.info.pid,
+            path_read: self.info.path.read.clone(),
+            path_write: self.info.path.write.clone(),
+            com_instance_id: self.info.path.com.clone(),
+            firmware_timestamp: (self.info.firmware_timestamp > 0).then_some(self.info.firmware_timestamp),
+            firmware_commit_hash: self.info.firmware_commit_hash,
+            chip_id: self.info.chip_id,
+        }
+    }
+
     pub fn get_product_id(&self) -> u16 {
         self.info.pid
     }
@@ -209,13 +744,196 @@ impl HinataDevice {
         self.info.path.write.to_string()
     }
 
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
     pub fn get_com_instance_id(&self) -> String {
         self.info.path.com.clone().unwrap_or_default()
     }
 
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
+    #[deprecated(note = "reads the registry on the calling thread, use `get_com_port_async`")]
     pub fn get_com_port(&self) -> HinataResult<String> {
         crate::utils::com::get_com_port_by_com_instance_id(&self.get_com_instance_id())
     }
+
+    #[cfg(all(target_os = "windows", feature = "com-port"))]
+    pub async fn get_com_port_async(&self) -> HinataResult<String> {
+        crate::utils::com::get_com_port_by_com_instance_id_async(&self.get_com_instance_id()).await
+    }
+
+    /// Serial node of the CDC interface, e.g. `/dev/ttyACM0`
+    #[cfg(all(target_os = "linux", feature = "com-port"))]
+    pub fn get_com_port(&self) -> HinataResult<String> {
+        crate::utils::sysfs::get_tty_by_hidraw(&self.info.path.read)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "com-port"))]
+    pub async fn get_com_port_async(&self) -> HinataResult<String> {
+        let path = self.info.path.read.clone();
+        tokio::task::spawn_blocking(move || crate::utils::sysfs::get_tty_by_hidraw(&path))
+            .await
+            .map_err(|e| Error::Other(format!("Blocking COM lookup failed: {e}")))?
+    }
+
+    /// Callout node of the CDC interface, e.g. `/dev/cu.usbmodem1101`
+    #[cfg(all(target_os = "macos", feature = "com-port"))]
+    pub fn get_com_port(&self) -> HinataResult<String> {
+        crate::utils::iokit::get_callout_device_by_hid_path(&self.info.path.read)
+    }
+
+    #[cfg(all(target_os = "macos", feature = "com-port"))]
+    pub async fn get_com_port_async(&self) -> HinataResult<String> {
+        let path = self.info.path.read.clone();
+        tokio::task::spawn_blocking(move || crate::utils::iokit::get_callout_device_by_hid_path(&path))
+            .await
+            .map_err(|e| Error::Other(format!("Blocking COM lookup failed: {e}")))?
+    }
+
+    /// Resolve and open the CDC serial port of this device
+    #[cfg(feature = "serial")]
+    pub async fn open_serial(&self, baud: u32) -> HinataResult<tokio_serial::SerialStream> {
+        let port = self.get_com_port_async().await?;
+        tokio_serial::SerialStream::open(&tokio_serial::new(&port, baud))
+            .map_err(|e| Error::Io(e.into()))
+    }
+
+    /// Measure command round trips `rounds` times each, for comparing hubs, cables, OS HID stacks and backends
+    pub async fn benchmark(&mut self, rounds: usize) -> HinataResult<BenchmarkReport> {
+        if rounds == 0 {
+            return Err(Error::Parse("Benchmark needs at least one round".into()));
+        }
+        let mut firmware = Vec::with_capacity(rounds);
+        let mut pn532 = Vec::with_capacity(rounds);
+        for _ in 0..rounds {
+            let start = std::time::Instant::now();
+            self.send_request(Request::new(1)).await?;
+            firmware.push(start.elapsed());
+
+            let start = std::time::Instant::now();
+            Pn532Port::request(self, Pn532Command::GetFirmwareVersion, &[]).await?;
+            pn532.push(start.elapsed());
+        }
+        Ok(BenchmarkReport {
+            firmware: LatencyStats::from_samples(firmware).ok_or(Error::Other("No samples".into()))?,
+            pn532: LatencyStats::from_samples(pn532).ok_or(Error::Other("No samples".into()))?,
+        })
+    }
+}
+
+impl HinataDevice {
+    /// Run every check in order and report each one, a failing step does not stop the ones after it.
+    /// Meant for manufacturing QA and field troubleshooting.
+    pub async fn self_test(&mut self) -> SelfTestReport {
+        let mut steps = Vec::new();
+
+        let start = Instant::now();
+        let res = self.get_firmware_timestamp().await.map(|timestamp| timestamp.to_string());
+        steps.push(SelfTestStep::new(SelfTestCheck::FirmwareTimestamp, res, start));
+
+        let start = Instant::now();
+        let res = self.chip_id_hex().await;
+        steps.push(SelfTestStep::new(SelfTestCheck::ChipId, res, start));
+
+        let start = Instant::now();
+        let res = Pn532Port::request(self, Pn532Command::GetFirmwareVersion, &[]).await.and_then(|res| match res[..] {
+            [ic, ver, rev, support, ..] => Ok(format!("PN5{ic:02X} v{ver}.{rev}, support {support:02X}")),
+            _ => Err(Error::Protocol("GetFirmwareVersion response too short".into())),
+        });
+        steps.push(SelfTestStep::new(SelfTestCheck::Pn532Firmware, res, start));
+
+        let start = Instant::now();
+        let mut payload = vec![0x00];
+        payload.extend_from_slice(&SELF_TEST_PATTERN);
+        let res = Pn532Port::request(self, Pn532Command::Diagnose, &payload).await.and_then(|res| {
+            if res == payload {
+                Ok(format!("{} bytes echoed", SELF_TEST_PATTERN.len()))
+            } else {
+                Err(Error::Protocol(format!("Echo mismatch: {res:02X?}").into()))
+            }
+        });
+        steps.push(SelfTestStep::new(SelfTestCheck::Pn532Communication, res, start));
+
+        let start = Instant::now();
+        for (r, g, b) in [(0xFF, 0, 0), (0, 0xFF, 0), (0, 0, 0xFF)] {
+            self.set_led(r, g, b).await;
+            tokio::time::sleep(Duration::from_millis(150)).await;
+        }
+        self.reset_led().await;
+        // LED commands are not answered, a request behind them shows they were written
+        let res = self.send_request(Request::new(1)).await.map(|_| "red, green, blue".to_string());
+        steps.push(SelfTestStep::new(SelfTestCheck::Led, res, start));
+
+        SelfTestReport { steps }
+    }
+}
+
+impl SelfTestStep {
+    fn new(check: SelfTestCheck, res: HinataResult<String>, start: Instant) -> Self {
+        let (passed, detail) = match res {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        Self { check, passed, detail, elapsed: start.elapsed() }
+    }
+}
+
+#[test]
+fn latency_stats_test() {
+    let stats = LatencyStats::from_samples((1..=100).rev().map(Duration::from_millis).collect()).unwrap();
+    assert_eq!((stats.min, stats.p50, stats.p99, stats.max), (
+        Duration::from_millis(1),
+        Duration::from_millis(50),
+        Duration::from_millis(99),
+        Duration::from_millis(100),
+    ));
+    assert_eq!(LatencyStats::from_samples(vec![Duration::from_millis(7)]).unwrap().p99, Duration::from_millis(7));
+    assert!(LatencyStats::from_samples(vec![]).is_none());
+}
+
+#[test]
+fn firmware_version_test() {
+    let version = FirmwareVersion::parse(b"2025051301\0\0\0").unwrap();
+    assert_eq!(version.date, NaiveDate::from_ymd_opt(2025, 5, 13).unwrap());
+    assert_eq!(version.build, 1);
+    assert_eq!(version.timestamp(), 2025051301);
+    assert_eq!(FirmwareVersion::parse(b"HINATA v2024120107 lite").unwrap().build, 7);
+
+    assert!(matches!(FirmwareVersion::parse(b"20250"), Err(Error::NotSupport(_))));
+    assert!(matches!(FirmwareVersion::parse(b"2025133101"), Err(Error::NotSupport(_))));
+    assert!(matches!(FirmwareVersion::parse(&[]), Err(Error::NotSupport(_))));
+}
+
+#[test]
+fn capability_test() {
+    assert!(Capability::ChipId.is_supported_by(2025051301));
+    assert!(!Capability::FirmwareCommitHash.is_supported_by(2024120101));
+    assert!(matches!(Capability::ChipId.require(2024120101), Err(Error::NotSupport(_))));
+}
+
+#[test]
+fn device_id_test() {
+    let mut info = HinataInfo {
+        instance_id: "1-2:1.0".into(),
+        device_name: "HINATA".into(),
+        pid: 0x0001,
+        path_read: String::new(),
+        path_write: String::new(),
+        com_instance_id: None,
+        firmware_timestamp: None,
+        firmware_commit_hash: None,
+        chip_id: None,
+    };
+    assert_eq!(info.get_device_id(), DeviceId::Instance("1-2:1.0".into()));
+    let mut readers = std::collections::HashMap::new();
+    readers.insert(info.clone(), "left");
+
+    // Reading the chip id later must not change which entry the reader is
+    let before = info.clone();
+    info.chip_id = Some([1, 2, 3, 4]);
+    assert_eq!(info, before);
+    assert_eq!(readers.get(&info), Some(&"left"));
+
+    // Moved to another port it is another snapshot, the device id still tells it is the same reader
+    let moved = HinataInfo { instance_id: "1-3:1.0".into(), ..info.clone() };
+    assert_ne!(info, moved);
+    assert_eq!(info.get_device_id(), moved.get_device_id());
 }
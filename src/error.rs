@@ -1,8 +1,13 @@
+use std::fmt;
 use std::num::ParseIntError;
 use std::string::FromUtf8Error;
+use std::time::Duration;
 use hidapi::HidError;
+use num_traits::ToPrimitive;
 use thiserror::Error;
-use crate::pn532::Pn532Error;
+use crate::card::felica::FelicaError;
+use crate::hooks::RequestKind;
+use crate::pn532::{Pn532ApplicationError, Pn532Direction, Pn532Error};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -12,11 +17,14 @@ pub enum Error {
     #[error("PN532 Error: {0}")]
     Pn532(#[from] Pn532Error),
 
+    #[error("FeliCa Error: {0}")]
+    Felica(#[from] FelicaError),
+
     #[error("IO Error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("Timeout Error: {0}")]
-    Timeout(String),
+    Timeout(ErrorMessage),
 
     #[error("Not Found Error: {0}")]
     NotFound(String),
@@ -28,7 +36,13 @@ pub enum Error {
     NotSupport(String),
 
     #[error("Protocol Error: {0}")]
-    Protocol(String),
+    Protocol(ErrorMessage),
+
+    #[error("IO Thread Panicked: {0}")]
+    IoThreadPanicked(String),
+
+    #[error("Permission Denied Error: {0}")]
+    PermissionDenied(String),
 
     #[error("Hid Error: {0}")]
     HidError(#[from] HidError),
@@ -39,6 +53,243 @@ pub enum Error {
 
 pub type HinataResult<T> = Result<T, Error>;
 
+/// Longest frame snippet kept in an [`ErrorContext`]
+const FRAME_SNIPPET_LEN: usize = 32;
+
+/// The request a protocol or timeout error happened in
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorContext {
+    pub command: RequestKind,
+    /// Which way `frame` travelled, `None` for firmware commands
+    pub direction: Option<Pn532Direction>,
+    /// The frame that was rejected, or the one that got no answer, cut to 32 bytes
+    pub frame: Vec<u8>,
+    /// Time since the request was sent
+    pub elapsed: Duration,
+}
+
+impl ErrorContext {
+    pub fn new(command: RequestKind, direction: Option<Pn532Direction>, frame: &[u8], elapsed: Duration) -> Self {
+        Self {
+            command,
+            direction,
+            frame: frame[..frame.len().min(FRAME_SNIPPET_LEN)].to_vec(),
+            elapsed,
+        }
+    }
+}
+
+/// Message of [`Error::Protocol`] and [`Error::Timeout`], with the request context once the device attached it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorMessage {
+    message: String,
+    context: Option<Box<ErrorContext>>,
+}
+
+impl ErrorMessage {
+    /// Keeps the context that is already attached, the innermost request knows the most
+    pub fn with_context(mut self, context: ErrorContext) -> Self {
+        self.context.get_or_insert_with(|| Box::new(context));
+        self
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn get_context(&self) -> Option<&ErrorContext> {
+        self.context.as_deref()
+    }
+}
+
+impl From<String> for ErrorMessage {
+    fn from(message: String) -> Self {
+        Self { message, context: None }
+    }
+}
+
+impl From<&str> for ErrorMessage {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(context) = &self.context {
+            write!(f, " ({:?}", context.command)?;
+            if let Some(direction) = context.direction {
+                write!(f, ", {direction:?}")?;
+            }
+            write!(f, ", frame {:02X?} after {:?})", context.frame, context.elapsed)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a HID failure means to the caller, told apart by the platform's error message
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HidErrorKind {
+    /// Missing udev rule, or the OS refused access to the interface
+    PermissionDenied,
+    /// Another process holds the interface exclusively
+    Busy,
+    /// The device was unplugged or never there
+    NoDevice,
+    Other,
+}
+
+impl HidErrorKind {
+    /// Classify a hidapi, errno or Windows/IOKit error message
+    pub fn classify(message: &str) -> Self {
+        let message = message.to_ascii_lowercase();
+        let any = |needles: &[&str]| needles.iter().any(|needle| message.contains(needle));
+        if any(&["permission denied", "access is denied", "not permitted", "os error 13", "e00002e2"]) {
+            Self::PermissionDenied
+        } else if any(&["busy", "being used by another process", "exclusive access", "os error 16", "e00002c5"]) {
+            Self::Busy
+        } else if any(&["no such device", "not connected", "device not configured", "does not exist", "os error 19", "e00002c0"]) {
+            Self::NoDevice
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Category of an [`Error`], the discriminants are stable across releases unlike the messages
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ErrorKind {
+    Parse = 1,
+    Pn532 = 2,
+    Felica = 3,
+    Io = 4,
+    Timeout = 5,
+    NotFound = 6,
+    Disconnected = 7,
+    NotSupported = 8,
+    Protocol = 9,
+    IoThreadPanicked = 10,
+    PermissionDenied = 11,
+    Hid = 12,
+    Other = 13,
+}
+
+impl ErrorKind {
+    /// snake_case name, for logs and metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Pn532 => "pn532",
+            Self::Felica => "felica",
+            Self::Io => "io",
+            Self::Timeout => "timeout",
+            Self::NotFound => "not_found",
+            Self::Disconnected => "disconnected",
+            Self::NotSupported => "not_supported",
+            Self::Protocol => "protocol",
+            Self::IoThreadPanicked => "io_thread_panicked",
+            Self::PermissionDenied => "permission_denied",
+            Self::Hid => "hid",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl Error {
+    /// Attach the request context to protocol and timeout errors, other errors are returned as they are
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            Error::Protocol(message) => Error::Protocol(message.with_context(context)),
+            Error::Timeout(message) => Error::Timeout(message.with_context(context)),
+            other => other,
+        }
+    }
+
+    /// Command, frame and timing of a protocol or timeout error raised by a device request
+    pub fn get_context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::Protocol(message) | Error::Timeout(message) => message.get_context(),
+            _ => None,
+        }
+    }
+
+    /// Why the HID layer failed, `None` for errors that did not come from it
+    pub fn hid_error_kind(&self) -> Option<HidErrorKind> {
+        match self {
+            Error::HidError(e) => Some(HidErrorKind::classify(&e.to_string())),
+            Error::PermissionDenied(_) => Some(HidErrorKind::PermissionDenied),
+            Error::Io(e) => match e.kind() {
+                std::io::ErrorKind::PermissionDenied => Some(HidErrorKind::PermissionDenied),
+                std::io::ErrorKind::ResourceBusy => Some(HidErrorKind::Busy),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotConnected => Some(HidErrorKind::NoDevice),
+                _ => Some(HidErrorKind::classify(&e.to_string())),
+            },
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Parse(_) => ErrorKind::Parse,
+            Error::Pn532(_) => ErrorKind::Pn532,
+            Error::Felica(_) => ErrorKind::Felica,
+            Error::Io(_) => ErrorKind::Io,
+            Error::Timeout(_) => ErrorKind::Timeout,
+            Error::NotFound(_) => ErrorKind::NotFound,
+            Error::Disconnected(_) => ErrorKind::Disconnected,
+            Error::NotSupport(_) => ErrorKind::NotSupported,
+            Error::Protocol(_) => ErrorKind::Protocol,
+            Error::IoThreadPanicked(_) => ErrorKind::IoThreadPanicked,
+            Error::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            Error::HidError(_) => ErrorKind::Hid,
+            Error::Other(_) => ErrorKind::Other,
+        }
+    }
+
+    /// Stable numeric code, the kind in the high byte and a sub code in the low byte:
+    /// the PN532 status byte for [`Error::Pn532`], status flag 2 for [`Error::Felica`], 0 otherwise.
+    /// e.g. `0x0214` for a MIFARE authentication error.
+    pub fn code(&self) -> u16 {
+        let sub = match self {
+            Error::Pn532(e) => e.to_u8().unwrap_or_default(),
+            Error::Felica(e) => e.status2.into(),
+            _ => 0,
+        };
+        (self.kind() as u16) << 8 | sub as u16
+    }
+
+    /// Recovery hint for errors reported by the PN532 itself.
+    /// A request that got no answer in time is [`Pn532ApplicationError::CardLost`] whether the PN532
+    /// ([`Pn532Error::Timeout`]) or the host ([`Error::Timeout`]) gave up waiting: poll again before going on.
+    pub fn application_error(&self) -> Option<Pn532ApplicationError> {
+        match self {
+            Error::Pn532(e) => e.classify(),
+            Error::Timeout(_) => Some(Pn532ApplicationError::CardLost),
+            _ => None,
+        }
+    }
+}
+
+/// Serialized as `{ kind, code, message, context }`, see [`Error::kind`], [`Error::code`] and [`Error::get_context`]
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("kind", &self.kind())?;
+        state.serialize_field("code", &self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("context", &self.get_context())?;
+        state.end()
+    }
+}
+
 impl From<FromUtf8Error> for Error {
     fn from(e: FromUtf8Error) -> Self {
         Error::Parse(e.to_string())
@@ -50,3 +301,101 @@ impl From<ParseIntError> for Error {
         Error::Parse(e.to_string())
     }
 }
+
+#[test]
+fn error_code_test() {
+    use crate::card::felica::FelicaStatus;
+
+    assert_eq!(Error::Pn532(Pn532Error::MifareAuth).code(), 0x0214);
+    assert_eq!(Error::Felica(FelicaError { status1: 0xFF, status2: FelicaStatus::AccessDenied }).code(), 0x03A5);
+    assert_eq!(Error::Timeout("Wait response timeout".into()).code(), 0x0500);
+    assert_eq!(Error::NotSupport("Old firmware".into()).kind().as_str(), "not_supported");
+}
+
+#[test]
+fn application_error_test() {
+    use num_traits::FromPrimitive;
+    use Pn532ApplicationError::*;
+
+    let table = [
+        (Pn532Error::None, None),
+        (Pn532Error::Timeout, Some(CardLost)),
+        (Pn532Error::Crc, Some(Retry)),
+        (Pn532Error::Parity, Some(Retry)),
+        (Pn532Error::CollisionBitCount, Some(Retry)),
+        (Pn532Error::MifareFraming, Some(Retry)),
+        (Pn532Error::CollisionBitCollision, Some(Retry)),
+        (Pn532Error::NoBufs, Some(Fatal)),
+        (Pn532Error::RfNoBufs, Some(Retry)),
+        (Pn532Error::ActiveTooSlow, Some(Retry)),
+        (Pn532Error::RfProto, Some(Retry)),
+        (Pn532Error::TooHot, Some(Fatal)),
+        (Pn532Error::InternalNoBufs, Some(Fatal)),
+        (Pn532Error::Inval, Some(Fatal)),
+        (Pn532Error::DepInvalidCommand, Some(Fatal)),
+        (Pn532Error::DepBadData, Some(Reselect)),
+        (Pn532Error::MifareAuth, Some(Reselect)),
+        (Pn532Error::NoSecure, Some(Fatal)),
+        (Pn532Error::I2cBusy, Some(Retry)),
+        (Pn532Error::UidChecksum, Some(Retry)),
+        (Pn532Error::DepState, Some(Reselect)),
+        (Pn532Error::HciInval, Some(Fatal)),
+        (Pn532Error::Context, Some(Fatal)),
+        (Pn532Error::Released, Some(CardLost)),
+        (Pn532Error::CardSwapped, Some(CardLost)),
+        (Pn532Error::NoCard, Some(CardLost)),
+        (Pn532Error::Mismatch, Some(Fatal)),
+        (Pn532Error::Overcurrent, Some(Fatal)),
+        (Pn532Error::NoNad, Some(Fatal)),
+        (Pn532Error::SyntaxError, Some(Fatal)),
+    ];
+    // Every status the enum knows is in the table
+    assert_eq!((0..=u8::MAX).filter_map(Pn532Error::from_u8).count(), table.len());
+    for (e, class) in table {
+        assert_eq!(e.classify(), class, "{e:?}");
+        assert_eq!(Error::Pn532(e).application_error(), class);
+    }
+    assert_eq!(Error::Timeout("Wait response timeout".into()).application_error(), Some(CardLost));
+    assert_eq!(Error::Protocol("ack error".into()).application_error(), None);
+}
+
+#[test]
+fn error_context_test() {
+    use crate::pn532::Pn532Command;
+
+    let context = ErrorContext::new(RequestKind::Pn532(Pn532Command::InListPassiveTarget), Some(Pn532Direction::Pn532ToHost), &[0xAA; 40], Duration::from_millis(5));
+    let e = Error::Protocol("ack error".into()).with_context(context.clone());
+    assert_eq!(e.get_context().unwrap().frame.len(), 32);
+    assert_eq!(e.get_context().unwrap().command, RequestKind::Pn532(Pn532Command::InListPassiveTarget));
+    assert!(e.to_string().starts_with("Protocol Error: ack error (Pn532(InListPassiveTarget), Pn532ToHost, frame [AA, AA"));
+    assert!(Error::NotFound("Device not found".into()).with_context(context).get_context().is_none());
+}
+
+#[test]
+fn hid_error_kind_test() {
+    assert_eq!(HidErrorKind::classify("hidapi error: Permission denied (os error 13)"), HidErrorKind::PermissionDenied);
+    assert_eq!(HidErrorKind::classify("Access is denied."), HidErrorKind::PermissionDenied);
+    assert_eq!(HidErrorKind::classify("Device or resource busy"), HidErrorKind::Busy);
+    assert_eq!(HidErrorKind::classify("hid_read_timeout: No such device"), HidErrorKind::NoDevice);
+    assert_eq!(HidErrorKind::classify("Invalid argument"), HidErrorKind::Other);
+
+    let busy = Error::Io(std::io::Error::from(std::io::ErrorKind::ResourceBusy));
+    assert_eq!(busy.hid_error_kind(), Some(HidErrorKind::Busy));
+    assert_eq!(Error::Timeout("Wait response timeout".into()).hid_error_kind(), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn error_serialize_test() {
+    use crate::pn532::Pn532Command;
+
+    let context = ErrorContext::new(RequestKind::Pn532(Pn532Command::GetFirmwareVersion), None, &[0xD4, 0x02], Duration::from_millis(1000));
+    let e = Error::Timeout("Wait response timeout".into()).with_context(context);
+    let json = serde_json::to_value(&e).unwrap();
+    assert_eq!(json["kind"], "Timeout");
+    assert_eq!(json["code"], 0x0500);
+    assert_eq!(json["message"], e.to_string());
+    assert_eq!(json["context"]["command"]["Pn532"], "GetFirmwareVersion");
+    assert_eq!(json["context"]["frame"], serde_json::json!([0xD4, 0x02]));
+    assert!(serde_json::to_value(Error::NotFound("Device not found".into())).unwrap()["context"].is_null());
+}
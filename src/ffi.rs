@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use crate::builder::HinataDeviceBuilder;
+use crate::card::aime::ArcadeCard;
+use crate::card::PassiveTarget;
+use crate::device::{HinataDevice, ScanType};
+use crate::error::Error;
+use crate::utils::id_format::IdFormat;
+
+/// Errors crossing the FFI boundary, `code` is [`Error::code`] and the message the [`Display`](std::fmt::Display) of the Rust error
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+pub enum HinataFfiError {
+    #[error("{message}")]
+    NotFound { code: u16, message: String },
+    #[error("{message}")]
+    Timeout { code: u16, message: String },
+    #[error("{message}")]
+    Disconnected { code: u16, message: String },
+    #[error("{message}")]
+    NotSupported { code: u16, message: String },
+    #[error("{message}")]
+    Other { code: u16, message: String },
+}
+
+impl From<Error> for HinataFfiError {
+    fn from(e: Error) -> Self {
+        let (code, message) = (e.code(), e.to_string());
+        match e {
+            Error::NotFound(_) => Self::NotFound { code, message },
+            Error::Timeout(_) => Self::Timeout { code, message },
+            Error::Disconnected(_) | Error::IoThreadPanicked(_) => Self::Disconnected { code, message },
+            Error::NotSupport(_) => Self::NotSupported { code, message },
+            _ => Self::Other { code, message },
+        }
+    }
+}
+
+type FfiResult<T> = Result<T, HinataFfiError>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum FfiCardType {
+    TypeA,
+    Felica,
+}
+
+/// A card found by [`FfiReader::scan_card`]
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct FfiCard {
+    pub card_type: FfiCardType,
+    /// UID for ISO14443-A, IDm for FeliCa
+    pub id: Vec<u8>,
+    /// `id` as upper-case hex
+    pub id_hex: String,
+}
+
+impl From<&PassiveTarget> for FfiCard {
+    fn from(target: &PassiveTarget) -> Self {
+        let (card_type, id) = match target {
+            PassiveTarget::Iso14443a(card) => (FfiCardType::TypeA, card.get_uid().to_vec()),
+            PassiveTarget::Felica(card) => (FfiCardType::Felica, card.get_idm().to_vec()),
+        };
+        let id_hex = IdFormat::new(&id).hex();
+        Self { card_type, id, id_hex }
+    }
+}
+
+/// A reader found by [`discover`], not opened yet
+#[derive(uniffi::Object)]
+pub struct FfiReaderInfo {
+    builder: HinataDeviceBuilder,
+}
+
+#[uniffi::export]
+impl FfiReaderInfo {
+    pub fn get_instance_id(&self) -> String {
+        self.builder.get_instance_id()
+    }
+
+    pub fn get_device_name(&self) -> String {
+        self.builder.get_device_name()
+    }
+
+    pub fn get_product_id(&self) -> u16 {
+        self.builder.get_product_id()
+    }
+
+    pub fn open(&self) -> FfiResult<Arc<FfiReader>> {
+        Ok(Arc::new(FfiReader {
+            instance_id: self.builder.get_instance_id(),
+            device: Mutex::new(self.builder.build(false)?),
+        }))
+    }
+}
+
+/// An opened reader, calls from several threads are serialized
+#[derive(uniffi::Object)]
+pub struct FfiReader {
+    instance_id: String,
+    device: Mutex<HinataDevice>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl FfiReader {
+    pub async fn get_firmware_timestamp(&self) -> FfiResult<u32> {
+        Ok(self.device.lock().await.get_firmware_timestamp().await?)
+    }
+
+    pub async fn get_chip_id(&self) -> FfiResult<Vec<u8>> {
+        Ok(self.device.lock().await.get_chip_id().await?.to_vec())
+    }
+
+    pub async fn set_led(&self, r: u8, g: u8, b: u8) {
+        self.device.lock().await.set_led(r, g, b).await
+    }
+
+    pub async fn reset_led(&self) {
+        self.device.lock().await.reset_led().await
+    }
+
+    /// Wait up to `timeout_ms` for an ISO14443-A or FeliCa card
+    pub async fn scan_card(&self, timeout_ms: u64) -> FfiResult<Option<FfiCard>> {
+        let types = [ScanType::TypeA, ScanType::Felica(0xFFFF)];
+        let found = self.device.lock().await.scan_card(Duration::from_millis(timeout_ms), &types).await?;
+        Ok(found.map(|(_, target)| FfiCard::from(&target)))
+    }
+
+    /// Access code of the Aime card on the reader, `None` if there is no card or it is not an Aime
+    pub async fn read_access_code(&self) -> FfiResult<Option<String>> {
+        let mut device = self.device.lock().await;
+        match device.pn532().identify_arcade_card().await? {
+            Some(ArcadeCard::Aime(card)) => Ok(Some(card.access_code.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_instance_id(&self) -> String {
+        self.instance_id.clone()
+    }
+}
+
+/// Find connected readers, `exclude` lists instance ids to skip
+#[uniffi::export(async_runtime = "tokio")]
+pub async fn discover(exclude: Vec<String>) -> FfiResult<Vec<Arc<FfiReaderInfo>>> {
+    Ok(crate::find_devices(exclude).await?
+        .into_iter()
+        .map(|builder| Arc::new(FfiReaderInfo { builder }))
+        .collect())
+}
+
+#[test]
+fn ffi_error_test() {
+    assert!(matches!(HinataFfiError::from(Error::Timeout("Wait response timeout".into())), HinataFfiError::Timeout { .. }));
+    let e = HinataFfiError::from(Error::Parse("Bad length".into()));
+    assert_eq!(e.to_string(), Error::Parse("Bad length".into()).to_string());
+}
@@ -0,0 +1,527 @@
+#[cfg(feature = "updater")]
+pub mod release;
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use crate::backend::{HidApiBackend, HidBackend, HidDeviceInfo};
+use crate::device::{DeviceId, FirmwareVersion, HinataDevice};
+use crate::error::{Error, HinataResult};
+use crate::utils::id_format::IdFormat;
+use crate::ChipId;
+
+/// How long the reader gets to show up again, as bootloader and with the new firmware
+const DEFAULT_ENUMERATE_TIMEOUT: Duration = Duration::from_secs(10);
+const ENUMERATE_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpdateStage {
+    /// Checking the image and the reader before anything is touched
+    Validate,
+    /// Rebooting into the bootloader and waiting for it to enumerate
+    EnterBootloader,
+    Flash,
+    /// Leaving the bootloader for the new firmware
+    Reboot,
+    /// Waiting for the reader to come back and checking what it runs
+    Verify,
+}
+
+/// `done` and `total` count image bytes during [`UpdateStage::Flash`] and are 0 otherwise
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UpdateProgress {
+    pub stage: UpdateStage,
+    pub done: usize,
+    pub total: usize,
+}
+
+type ProgressCallback = Box<dyn Fn(UpdateProgress) + Send + Sync>;
+
+/// How an update ended once the image was flashed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// The reader came back running the image
+    Verified,
+    /// The reader came back with another timestamp or commit hash than the image
+    Mismatch,
+    /// The reader did not enumerate with its firmware again before the timeout
+    DidNotComeBack,
+}
+
+/// What [`Updater::update`] found after flashing, `expected_*` come from the image, the others from the reader
+#[derive(Debug)]
+pub struct UpdateReport {
+    pub outcome: UpdateOutcome,
+    pub expected_timestamp: Option<u32>,
+    pub expected_commit_hash: Option<[u8; 4]>,
+    pub timestamp: Option<u32>,
+    pub commit_hash: Option<[u8; 4]>,
+    /// The reader opened again, `None` unless it came back
+    pub device: Option<HinataDevice>,
+}
+
+impl UpdateReport {
+    pub fn is_verified(&self) -> bool {
+        self.outcome == UpdateOutcome::Verified
+    }
+
+    /// What to tell the user to do next
+    pub fn guidance(&self) -> &'static str {
+        match self.outcome {
+            UpdateOutcome::Verified => "The update is complete.",
+            UpdateOutcome::Mismatch => "The reader runs different firmware than the image. Flash the image again.",
+            UpdateOutcome::DidNotComeBack => "Unplug the reader and plug it back in. If it stays in its bootloader, flash the image again.",
+        }
+    }
+}
+
+/// A reader in its bootloader, the transfer [`Updater`] hands the image to.
+///
+/// This crate does not implement the bootloader's flashing protocol, so it cannot flash a reader by itself.
+/// The updater only sequences an update around an implementation of this trait.
+#[async_trait]
+pub trait Bootloader: Send {
+    /// Write the whole image, calling `progress` with the bytes written so far
+    async fn flash(&mut self, image: &[u8], progress: &(dyn Fn(usize) + Send + Sync)) -> HinataResult<()>;
+
+    /// Leave the bootloader and start the firmware, also called after a failed [`Bootloader::flash`]
+    async fn reboot(&mut self) -> HinataResult<()>;
+}
+
+/// Opens the bootloader [`HinataDevice::enter_bootloader_and_wait`] found by its USB ids
+#[async_trait]
+pub trait BootloaderConnector: Send + Sync {
+    /// USB vendor and product id the bootloader enumerates with
+    fn usb_id(&self) -> (u16, u16);
+
+    async fn open(&self, interface: &HidDeviceInfo) -> HinataResult<Box<dyn Bootloader>>;
+}
+
+/// A reader rebooted into its bootloader by [`HinataDevice::enter_bootloader_and_wait`]
+pub struct BootloaderDevice {
+    instance_id: String,
+    product_id: u16,
+    device_id: DeviceId,
+    bootloader: Box<dyn Bootloader>,
+}
+
+impl BootloaderDevice {
+    pub(crate) fn new(instance_id: String, product_id: u16, device_id: DeviceId, bootloader: Box<dyn Bootloader>) -> Self {
+        Self { instance_id, product_id, device_id, bootloader }
+    }
+
+    /// Instance id of the HID interface the reader left
+    pub fn get_instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn get_product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    pub fn get_chip_id(&self) -> Option<ChipId> {
+        match self.device_id {
+            DeviceId::Chip(chip_id) => Some(chip_id),
+            DeviceId::Instance(_) => None,
+        }
+    }
+
+    /// What the bootloader was matched by
+    pub fn get_device_id(&self) -> &DeviceId {
+        &self.device_id
+    }
+
+    pub async fn flash(&mut self, image: &[u8], progress: &(dyn Fn(usize) + Send + Sync)) -> HinataResult<()> {
+        self.bootloader.flash(image, progress).await
+    }
+
+    /// Start the firmware, the reader enumerates as a [`HinataDevice`] again
+    pub async fn reboot(mut self) -> HinataResult<()> {
+        self.bootloader.reboot().await
+    }
+}
+
+/// Call `f` until it finds something or `timeout` passed, [`Error::Timeout`] with `message` then
+pub(crate) async fn poll_until<T, F: Future<Output = HinataResult<Option<T>>>>(timeout: Duration, message: &str, mut f: impl FnMut() -> F) -> HinataResult<T> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(found) = f().await? {
+            return Ok(found);
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(Error::Timeout(message.into()));
+        }
+        tokio::time::sleep(ENUMERATE_INTERVAL).await;
+    }
+}
+
+/// A firmware binary as distributed, flashed as is.
+///
+/// The binaries carry no header naming their build or the product they are for, so both are only known when
+/// the caller supplies them, e.g. from the [`release`] feed entry the binary came from. Nothing is read from the
+/// binary itself, and without a product id the image is not checked against the reader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FirmwareImage {
+    data: Vec<u8>,
+    version: Option<FirmwareVersion>,
+    product_id: Option<u16>,
+}
+
+impl FirmwareImage {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data, version: None, product_id: None }
+    }
+
+    /// Build the binary is, verified on the reader after the update
+    pub fn with_version(mut self, version: FirmwareVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Product the binary is for, readers of another product are refused
+    pub fn with_product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    pub fn get_version(&self) -> Option<FirmwareVersion> {
+        self.version
+    }
+
+    pub fn get_product_id(&self) -> Option<u16> {
+        self.product_id
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// e.g. `build 1 of 2025-06-02 for product 0001`, or `unknown build` when nothing was supplied
+impl fmt::Display for FirmwareImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "build {} of {}", version.build, version.date)?,
+            None => write!(f, "unknown build")?,
+        }
+        if let Some(product_id) = self.product_id {
+            write!(f, " for product {product_id:04X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sequences an update around a [`Bootloader`]: validate, enter the bootloader, flash, reboot and verify.
+/// The bytes are written by the [`Bootloader`] the [`BootloaderConnector`] opens, the updater never talks
+/// to the bootloader itself and flashes nothing without one.
+///
+/// Nothing is touched when validation fails. A failed flash reboots the bootloader so a reader whose
+/// flash was not erased yet comes back with its old firmware, otherwise it stays in the bootloader for another try.
+pub struct Updater {
+    image: Vec<u8>,
+    product_id: Option<u16>,
+    chip_id: Option<ChipId>,
+    timestamp: Option<u32>,
+    commit_hash: Option<[u8; 4]>,
+    backend: Arc<dyn HidBackend>,
+    timeout: Duration,
+    progress: Option<ProgressCallback>,
+}
+
+impl Updater {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self {
+            image,
+            product_id: None,
+            chip_id: None,
+            timestamp: None,
+            commit_hash: None,
+            backend: Arc::new(HidApiBackend),
+            timeout: DEFAULT_ENUMERATE_TIMEOUT,
+            progress: None,
+        }
+    }
+
+    /// Update to `image`, refusing readers of another product and verifying the build afterwards when the image names them
+    pub fn from_image(image: FirmwareImage) -> Self {
+        let (product_id, timestamp) = (image.product_id, image.version.map(|version| version.timestamp()));
+        Self { product_id, timestamp, ..Self::new(image.data) }
+    }
+
+    /// Refuse readers with another product id
+    pub fn with_product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    /// Refuse every reader but this one
+    pub fn with_chip_id(mut self, chip_id: ChipId) -> Self {
+        self.chip_id = Some(chip_id);
+        self
+    }
+
+    /// Commit hash the new firmware reports, checked once it booted
+    pub fn with_commit_hash(mut self, commit_hash: [u8; 4]) -> Self {
+        self.commit_hash = Some(commit_hash);
+        self
+    }
+
+    /// Where the bootloader and the updated reader are looked for, hidapi by default
+    pub fn with_backend(mut self, backend: Arc<dyn HidBackend>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// How long to wait for the bootloader and for the reader after the reboot, each
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn on_progress(mut self, f: impl Fn(UpdateProgress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    pub fn get_image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Run the update. Fails while the old firmware may still be in place, reports how it went once the image was flashed.
+    pub async fn update(&self, mut device: HinataDevice, connector: &dyn BootloaderConnector) -> HinataResult<UpdateReport> {
+        self.report(UpdateStage::Validate, 0);
+        let reader = self.validate(&mut device).await?;
+
+        self.report(UpdateStage::EnterBootloader, 0);
+        let mut bootloader = device.enter_bootloader_and_wait(self.backend.as_ref(), connector, self.timeout).await?;
+
+        self.report(UpdateStage::Flash, 0);
+        let res = bootloader.flash(&self.image, &|done| self.report(UpdateStage::Flash, done)).await;
+        self.report(UpdateStage::Reboot, 0);
+        let rebooted = bootloader.reboot().await;
+        res?;
+        rebooted?;
+
+        self.report(UpdateStage::Verify, 0);
+        let mut report = UpdateReport {
+            outcome: UpdateOutcome::DidNotComeBack,
+            expected_timestamp: self.timestamp,
+            expected_commit_hash: self.commit_hash,
+            timestamp: None,
+            commit_hash: None,
+            device: None,
+        };
+        let Ok(mut device) = poll_until(self.timeout, "Reader did not come back after the update", || self.find_reader(&reader)).await else {
+            return Ok(report);
+        };
+        report.timestamp = device.get_firmware_version().await.ok().map(|version| version.timestamp());
+        report.commit_hash = device.get_firmware_commit_hash().await.ok();
+        fn matches<T: PartialEq>(expected: Option<T>, actual: Option<T>) -> bool {
+            expected.is_none() || expected == actual
+        }
+        report.outcome = if matches(report.expected_timestamp, report.timestamp) && matches(report.expected_commit_hash, report.commit_hash) {
+            UpdateOutcome::Verified
+        } else {
+            UpdateOutcome::Mismatch
+        };
+        report.device = Some(device);
+        Ok(report)
+    }
+
+    /// How the reader is recognized after it re-enumerated, by its USB port if the firmware predates chip ids
+    async fn validate(&self, device: &mut HinataDevice) -> HinataResult<DeviceId> {
+        if self.image.is_empty() {
+            return Err(Error::Parse("Firmware image is empty".into()));
+        }
+        if let Some(product_id) = self.product_id && device.get_product_id() != product_id {
+            return Err(Error::NotSupport(format!("Image is for product {product_id:04X}, reader is {:04X}", device.get_product_id())));
+        }
+        let chip_id = match device.get_chip_id().await {
+            Ok(chip_id) => chip_id,
+            Err(Error::NotSupport(_)) => {
+                if let Some(expected) = self.chip_id {
+                    return Err(Error::NotSupport(format!("Image is for chip {}, reader firmware is too old to report its own", IdFormat::new(&expected).hex())));
+                }
+                return Ok(DeviceId::Instance(device.get_instance_id()));
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(expected) = self.chip_id && chip_id != expected {
+            return Err(Error::NotSupport(format!("Image is for chip {}, reader is {}", IdFormat::new(&expected).hex(), IdFormat::new(&chip_id).hex())));
+        }
+        Ok(DeviceId::Chip(chip_id))
+    }
+
+    async fn find_reader(&self, reader: &DeviceId) -> HinataResult<Option<HinataDevice>> {
+        for builder in crate::find_devices_with_backend(self.backend.clone(), vec![]).await? {
+            if let DeviceId::Instance(instance_id) = reader && builder.get_instance_id() != *instance_id {
+                continue;
+            }
+            let Ok(mut device) = builder.build(false) else {
+                continue;
+            };
+            match reader {
+                DeviceId::Chip(chip_id) if device.get_chip_id().await.is_ok_and(|id| id == *chip_id) => return Ok(Some(device)),
+                DeviceId::Chip(_) => {}
+                DeviceId::Instance(_) => return Ok(Some(device)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn report(&self, stage: UpdateStage, done: usize) {
+        if let Some(progress) = &self.progress {
+            let total = if stage == UpdateStage::Flash { self.image.len() } else { 0 };
+            progress(UpdateProgress { stage, done, total });
+        }
+    }
+}
+
+#[test]
+fn firmware_image_test() {
+    let image = FirmwareImage::new(vec![0x00, 0x20, 0x00, 0x20]);
+    assert_eq!(image.to_string(), "unknown build");
+    let updater = Updater::from_image(image);
+    assert_eq!((updater.product_id, updater.timestamp), (None, None));
+
+    let image = FirmwareImage::new(vec![0x00, 0x20, 0x00, 0x20])
+        .with_version(FirmwareVersion::parse(b"2025060201").unwrap())
+        .with_product_id(0x0001);
+    assert_eq!(image.to_string(), "build 1 of 2025-06-02 for product 0001");
+    assert_eq!(image.get_data(), &[0x00, 0x20, 0x00, 0x20]);
+    let updater = Updater::from_image(image);
+    assert_eq!((updater.product_id, updater.timestamp), (Some(0x0001), Some(2025060201)));
+}
+
+#[cfg(feature = "simulator")]
+#[tokio::test]
+async fn updater_test() {
+    use std::ffi::CString;
+    use std::sync::Mutex;
+    use crate::backend::simulator::{SimulatorBackend, VirtualHinata};
+
+    struct SimBootloader(VirtualHinata);
+
+    #[async_trait]
+    impl Bootloader for SimBootloader {
+        async fn flash(&mut self, image: &[u8], progress: &(dyn Fn(usize) + Send + Sync)) -> HinataResult<()> {
+            for (i, chunk) in image.chunks(4).enumerate() {
+                progress(i * 4 + chunk.len());
+            }
+            if image.starts_with(b"bad") {
+                return Err(Error::Protocol("Flash write failed".into()));
+            }
+            self.0.clone().with_commit_hash([image[0], image[1], image[2], image[3]]);
+            Ok(())
+        }
+
+        async fn reboot(&mut self) -> HinataResult<()> {
+            self.0.reconnect();
+            Ok(())
+        }
+    }
+
+    const BOOTLOADER_ID: (u16, u16) = (0xF822, 0xB007);
+
+    /// Remembers which interface it was asked to open
+    struct SimConnector(VirtualHinata, Mutex<Option<CString>>);
+
+    impl SimConnector {
+        fn new(reader: VirtualHinata) -> Self {
+            Self(reader, Mutex::new(None))
+        }
+    }
+
+    #[async_trait]
+    impl BootloaderConnector for SimConnector {
+        fn usb_id(&self) -> (u16, u16) {
+            BOOTLOADER_ID
+        }
+
+        async fn open(&self, interface: &HidDeviceInfo) -> HinataResult<Box<dyn Bootloader>> {
+            *self.1.lock().unwrap() = Some(interface.path.clone());
+            Ok(Box::new(SimBootloader(self.0.clone())))
+        }
+    }
+
+    // A fresh reader per run, devices dropped by the previous one may still be reading from theirs
+    async fn setup() -> (VirtualHinata, Arc<SimulatorBackend>, HinataDevice) {
+        setup_with(VirtualHinata::new().with_chip_id([1, 2, 3, 4])).await
+    }
+
+    async fn setup_with(reader: VirtualHinata) -> (VirtualHinata, Arc<SimulatorBackend>, HinataDevice) {
+        let reader = reader.with_bootloader(BOOTLOADER_ID.0, BOOTLOADER_ID.1);
+        let backend = Arc::new(SimulatorBackend::new().with_device(reader.clone()));
+        let device = crate::find_devices_with_backend(backend.clone(), vec![]).await.unwrap()[0].build(false).unwrap();
+        (reader, backend, device)
+    }
+
+    let (reader, backend, device) = setup().await;
+    let stages = Arc::new(Mutex::new(Vec::new()));
+    let seen = stages.clone();
+    let updater = Updater::new(vec![0xAB, 0xCD, 0xEF, 0x01, 0x02])
+        .with_commit_hash([0xAB, 0xCD, 0xEF, 0x01])
+        .with_backend(backend)
+        .with_timeout(Duration::from_secs(2))
+        .on_progress(move |progress| seen.lock().unwrap().push(progress));
+    let connector = SimConnector::new(reader);
+    let report = updater.update(device, &connector).await.unwrap();
+    assert!(report.is_verified());
+    assert_eq!(connector.1.lock().unwrap().as_deref(), Some(c"sim-0-bootloader"));
+    assert!(!connector.0.is_in_bootloader());
+    assert_eq!(report.commit_hash, Some([0xAB, 0xCD, 0xEF, 0x01]));
+    assert_eq!(report.timestamp, Some(2025051301));
+    drop(report);
+    let stages = stages.lock().unwrap().clone();
+    assert_eq!(stages.first().unwrap().stage, UpdateStage::Validate);
+    assert!(stages.contains(&UpdateProgress { stage: UpdateStage::Flash, done: 5, total: 5 }));
+    assert_eq!(stages.last().unwrap().stage, UpdateStage::Verify);
+
+    // Refused before the reader leaves its firmware
+    let (reader, backend, device) = setup().await;
+    let updater = Updater::new(vec![0; 4]).with_product_id(0x1234).with_backend(backend);
+    assert!(matches!(updater.update(device, &SimConnector::new(reader.clone())).await, Err(Error::NotSupport(_))));
+    assert!(!reader.is_disconnected());
+
+    let (reader, backend, device) = setup().await;
+    let updater = Updater::new(vec![1, 2, 3, 4]).with_commit_hash([9; 4]).with_backend(backend).with_timeout(Duration::from_secs(2));
+    let report = updater.update(device, &SimConnector::new(reader)).await.unwrap();
+    assert_eq!((report.outcome, report.commit_hash), (UpdateOutcome::Mismatch, Some([1, 2, 3, 4])));
+    assert!(report.device.is_some());
+    drop(report);
+
+    let (reader, backend, device) = setup().await;
+    let updater = Updater::new(b"bad image".to_vec()).with_backend(backend);
+    assert!(matches!(updater.update(device, &SimConnector::new(reader.clone())).await, Err(Error::Protocol(_))));
+    assert!(!reader.is_disconnected());
+
+    // Firmware from before chip ids is what most needs the update
+    let (reader, backend, device) = setup_with(VirtualHinata::new().with_firmware_timestamp(2024120101)).await;
+    let updater = Updater::new(vec![1, 2, 3, 4]).with_backend(backend.clone()).with_timeout(Duration::from_secs(2));
+    let connector = SimConnector::new(reader.clone());
+    let report = updater.update(device, &connector).await.unwrap();
+    assert!(report.is_verified());
+    assert_eq!(report.timestamp, Some(2024120101));
+    drop(report);
+
+    // Another reader already waiting in its bootloader is not the one that was just rebooted
+    let waiting = VirtualHinata::new().with_bootloader(BOOTLOADER_ID.0, BOOTLOADER_ID.1);
+    let reader = VirtualHinata::new().with_chip_id([1, 2, 3, 4]).with_bootloader(BOOTLOADER_ID.0, BOOTLOADER_ID.1);
+    let alone = Arc::new(SimulatorBackend::new().with_device(waiting.clone()));
+    crate::find_devices_with_backend(alone, vec![]).await.unwrap()[0].build(false).unwrap().enter_bootloader().await;
+    poll_until(Duration::from_secs(2), "Reader did not enter its bootloader", || async { Ok(waiting.is_in_bootloader().then_some(())) }).await.unwrap();
+    let backend = Arc::new(SimulatorBackend::new().with_device(waiting).with_device(reader.clone()));
+    let device = crate::find_devices_with_backend(backend.clone(), vec![]).await.unwrap()[0].build(false).unwrap();
+    let updater = Updater::new(vec![1, 2, 3, 4]).with_backend(backend).with_timeout(Duration::from_secs(2));
+    let connector = SimConnector::new(reader.clone());
+    assert!(updater.update(device, &connector).await.unwrap().is_verified());
+    assert_eq!(connector.1.lock().unwrap().as_deref(), Some(c"sim-1-bootloader"));
+
+    // A chip id to check against can't be confirmed on such a reader
+    let (reader, backend, device) = setup_with(VirtualHinata::new().with_firmware_timestamp(2024120101)).await;
+    let updater = Updater::new(vec![1, 2, 3, 4]).with_chip_id([1, 2, 3, 4]).with_backend(backend);
+    assert!(matches!(updater.update(device, &SimConnector::new(reader.clone())).await, Err(Error::NotSupport(_))));
+    assert!(!reader.is_disconnected());
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use crate::device::{FirmwareVersion, HinataDevice};
+use crate::error::{Error, HinataResult};
+use crate::firmware::FirmwareImage;
+
+/// One build in a release feed. The feed is a JSON array of these, e.g.
+/// `[{"timestamp": 2025060201, "product_id": 1, "url": "https://…/hinata-2025060201.bin", "sha256": "…"}]`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareRelease {
+    /// `YYYYMMDDBB` as [`HinataDevice::get_firmware_timestamp`] reports it
+    pub timestamp: u32,
+    /// Product the build is for, `None` for every product
+    #[serde(default)]
+    pub product_id: Option<u16>,
+    pub url: String,
+    /// Hex SHA-256 of the file at `url`
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+impl FirmwareRelease {
+    /// `data` downloaded from [`FirmwareRelease::url`], with the build and product this entry names
+    pub fn to_image(&self, data: Vec<u8>) -> HinataResult<FirmwareImage> {
+        let image = FirmwareImage::new(data).with_version(FirmwareVersion::parse(self.timestamp.to_string().as_bytes())?);
+        Ok(match self.product_id {
+            Some(product_id) => image.with_product_id(product_id),
+            None => image,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateAvailability {
+    pub current_timestamp: u32,
+    /// Newest build for the reader's product, even when it is not newer than the current one
+    pub latest: Option<FirmwareRelease>,
+}
+
+impl UpdateAvailability {
+    pub fn from_releases(current_timestamp: u32, product_id: u16, releases: &[FirmwareRelease]) -> Self {
+        let latest = releases.iter()
+            .filter(|release| release.product_id.is_none_or(|id| id == product_id))
+            .max_by_key(|release| release.timestamp)
+            .cloned();
+        Self { current_timestamp, latest }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.latest.as_ref().is_some_and(|release| release.timestamp > self.current_timestamp)
+    }
+}
+
+pub async fn fetch_releases(feed_url: &str) -> HinataResult<Vec<FirmwareRelease>> {
+    let response = reqwest::get(feed_url).await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| Error::Other(format!("Release feed request failed: {e}")))?;
+    response.json().await.map_err(|e| Error::Parse(format!("Invalid release feed: {e}")))
+}
+
+/// Compare the reader's firmware with the newest build for its product in the feed at `feed_url`
+pub async fn check_for_update(device: &mut HinataDevice, feed_url: &str) -> HinataResult<UpdateAvailability> {
+    let current_timestamp = device.get_firmware_timestamp().await?;
+    let releases = fetch_releases(feed_url).await?;
+    Ok(UpdateAvailability::from_releases(current_timestamp, device.get_product_id(), &releases))
+}
+
+#[test]
+fn update_availability_test() {
+    let releases: Vec<FirmwareRelease> = serde_json::from_str(r#"[
+        {"timestamp": 2025051301, "url": "https://example.com/a.bin"},
+        {"timestamp": 2025060201, "product_id": 1, "url": "https://example.com/b.bin", "sha256": "00"},
+        {"timestamp": 2025070101, "product_id": 2, "url": "https://example.com/c.bin"}
+    ]"#).unwrap();
+
+    let availability = UpdateAvailability::from_releases(2025051301, 1, &releases);
+    assert!(availability.is_available());
+    assert_eq!(availability.latest.unwrap().url, "https://example.com/b.bin");
+    assert!(!UpdateAvailability::from_releases(2025060201, 1, &releases).is_available());
+    assert!(!UpdateAvailability::from_releases(2025051301, 3, &releases).is_available());
+
+    assert_eq!(releases[0].to_image(vec![0; 4]).unwrap().to_string(), "build 1 of 2025-05-13");
+    assert_eq!(releases[1].to_image(vec![0; 4]).unwrap().get_product_id(), Some(0x0001));
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::device::IoStatus;
+use crate::error::Error;
+use crate::pn532::Pn532Command;
+
+type ReportHook = Arc<dyn Fn(&[u8]) + Send + Sync>;
+type RequestStartHook = Arc<dyn Fn(RequestKind) + Send + Sync>;
+type RequestEndHook = Arc<dyn Fn(RequestKind, Duration, Option<&Error>) + Send + Sync>;
+type DisconnectHook = Arc<dyn Fn(&IoStatus) + Send + Sync>;
+
+/// What a request was sent for
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RequestKind {
+    /// Command answered by the MCU itself, e.g. `0xE6` for the chip id
+    Firmware(u8),
+    Pn532(Pn532Command),
+}
+
+/// Callbacks for feeding device activity into an application's own metrics.
+///
+/// Report hooks run on the io threads and request hooks on the caller's task, keep them short and non-blocking.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub(crate) report_sent: Option<ReportHook>,
+    pub(crate) report_received: Option<ReportHook>,
+    pub(crate) request_start: Option<RequestStartHook>,
+    pub(crate) request_end: Option<RequestEndHook>,
+    pub(crate) disconnect: Option<DisconnectHook>,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every report written to the device, report id included
+    pub fn on_report_sent(mut self, f: impl Fn(&[u8]) + Send + Sync + 'static) -> Self {
+        self.report_sent = Some(Arc::new(f));
+        self
+    }
+
+    /// Every report read from the device, report id included
+    pub fn on_report_received(mut self, f: impl Fn(&[u8]) + Send + Sync + 'static) -> Self {
+        self.report_received = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_request_start(mut self, f: impl Fn(RequestKind) + Send + Sync + 'static) -> Self {
+        self.request_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Called once the request finished, with its latency and the error it failed with
+    pub fn on_request_end(mut self, f: impl Fn(RequestKind, Duration, Option<&Error>) + Send + Sync + 'static) -> Self {
+        self.request_end = Some(Arc::new(f));
+        self
+    }
+
+    /// The io threads stopped, called once with the final [`IoStatus`]
+    pub fn on_disconnect(mut self, f: impl Fn(&IoStatus) + Send + Sync + 'static) -> Self {
+        self.disconnect = Some(Arc::new(f));
+        self
+    }
+
+    pub(crate) fn report_sent(&self, data: &[u8]) {
+        if let Some(f) = &self.report_sent {
+            f(data)
+        }
+    }
+
+    pub(crate) fn report_received(&self, data: &[u8]) {
+        if let Some(f) = &self.report_received {
+            f(data)
+        }
+    }
+
+    pub(crate) fn request_start(&self, kind: RequestKind) {
+        if let Some(f) = &self.request_start {
+            f(kind)
+        }
+    }
+
+    pub(crate) fn request_end(&self, kind: RequestKind, latency: Duration, error: Option<&Error>) {
+        if let Some(f) = &self.request_end {
+            f(kind, latency, error)
+        }
+    }
+
+    pub(crate) fn disconnect(&self, status: &IoStatus) {
+        if let Some(f) = &self.disconnect {
+            f(status)
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("report_sent", &self.report_sent.is_some())
+            .field("report_received", &self.report_received.is_some())
+            .field("request_start", &self.request_start.is_some())
+            .field("request_end", &self.request_end.is_some())
+            .field("disconnect", &self.disconnect.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::card::aime::ArcadeCard;
+use crate::error::{Error, HinataResult};
+
+/// Where scanned cards go
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InjectTarget {
+    /// segatools card files (`aimePath` / `felicaPath` in segatools.ini), read when the scan key is held.
+    /// Only the file for the scanned card's type is written.
+    Segatools { aime_path: PathBuf, felica_path: PathBuf },
+    /// spice2x SpiceAPI `card.insert` on `unit` (0 or 1), `password` enables its RC4 transport
+    SpiceApi { addr: String, password: Option<String>, unit: u8 },
+}
+
+type IdFormatter = Box<dyn Fn(&ArcadeCard) -> Option<String> + Send + Sync>;
+
+/// Pushes scanned cards into an arcade loader, replacing a separate glue daemon
+pub struct CardInjector {
+    target: InjectTarget,
+    formatter: Option<IdFormatter>,
+}
+
+impl CardInjector {
+    pub fn new(target: InjectTarget) -> Self {
+        Self { target, formatter: None }
+    }
+
+    /// Override the ID written for a card, `None` skips the card
+    pub fn with_formatter(mut self, formatter: IdFormatter) -> Self {
+        self.formatter = Some(formatter);
+        self
+    }
+
+    pub async fn inject(&self, card: &ArcadeCard) -> HinataResult<()> {
+        match &self.target {
+            InjectTarget::Segatools { aime_path, felica_path } => {
+                let path = match card {
+                    ArcadeCard::Aime(_) => aime_path,
+                    ArcadeCard::EAmusement { .. } => felica_path,
+                    ArcadeCard::Unknown { .. } => return Err(Error::NotSupport("segatools has no slot for this card".into())),
+                };
+                let id = self.format(card, || Some(card.get_id()))?;
+                tokio::fs::write(path, id).await?;
+                Ok(())
+            }
+            InjectTarget::SpiceApi { addr, password, unit } => {
+                let id = self.format(card, || match card {
+                    ArcadeCard::EAmusement { .. } => Some(card.get_id()),
+                    _ => None,
+                })?;
+                let request = format!(r#"{{"id":1,"module":"card","function":"insert","params":[{unit},"{id}"]}}"#);
+                check_spice_response(&spice_request(addr, password.as_deref(), &request).await?)
+            }
+        }
+    }
+
+    fn format(&self, card: &ArcadeCard, default: impl FnOnce() -> Option<String>) -> HinataResult<String> {
+        match &self.formatter {
+            Some(formatter) => formatter(card),
+            None => default(),
+        }
+        .ok_or(Error::NotSupport("Card has no ID for this target".into()))
+    }
+}
+
+/// Requests and responses are NUL terminated JSON, RC4 encrypted as one stream per direction when a password is set
+async fn spice_request(addr: &str, password: Option<&str>, request: &str) -> HinataResult<String> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let password = password.filter(|p| !p.is_empty());
+    let mut tx = password.map(|p| Rc4::new(p.as_bytes()));
+    let mut rx = password.map(|p| Rc4::new(p.as_bytes()));
+
+    let mut data = request.as_bytes().to_vec();
+    data.push(0);
+    if let Some(rc4) = tx.as_mut() {
+        rc4.apply(&mut data);
+    }
+    stream.write_all(&data).await?;
+
+    let mut response = Vec::new();
+    loop {
+        let mut byte = [stream.read_u8().await?];
+        if let Some(rc4) = rx.as_mut() {
+            rc4.apply(&mut byte);
+        }
+        if byte[0] == 0 {
+            break;
+        }
+        response.push(byte[0]);
+    }
+    Ok(String::from_utf8(response)?)
+}
+
+/// A SpiceAPI response succeeded when its `errors` array is empty
+fn check_spice_response(response: &str) -> HinataResult<()> {
+    let parsed: serde_json::Value = serde_json::from_str(response)
+        .map_err(|e| Error::Parse(format!("Invalid SpiceAPI response: {e}")))?;
+    if parsed.get("errors").and_then(serde_json::Value::as_array).is_some_and(Vec::is_empty) {
+        Ok(())
+    } else {
+        Err(Error::Protocol(format!("SpiceAPI rejected the card: {response}").into()))
+    }
+}
+
+struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        state.iter_mut().enumerate().for_each(|(i, s)| *s = i as u8);
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+        Self { state, i: 0, j: 0 }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let k = self.state[self.state[self.i as usize].wrapping_add(self.state[self.j as usize]) as usize];
+            *byte ^= k;
+        }
+    }
+}
+
+#[test]
+fn rc4_test() {
+    let mut data = *b"Plaintext";
+    Rc4::new(b"Key").apply(&mut data);
+    assert_eq!(data, [0xBB, 0xF3, 0x16, 0xE8, 0xD9, 0x40, 0xAF, 0x0A, 0xD3]);
+}
+
+#[test]
+fn spice_response_test() {
+    assert!(check_spice_response(r#"{"id":1,"errors":[],"data":[]}"#).is_ok());
+    assert!(check_spice_response(r#"{"id": 1, "errors": [], "data": []}"#).is_ok());
+    assert!(matches!(check_spice_response(r#"{"id":1,"errors":["Unknown module"],"data":[]}"#), Err(Error::Protocol(_))));
+    assert!(matches!(check_spice_response(r#"{"id":1,"data":[],"note":"\"errors\":[]"}"#), Err(Error::Protocol(_))));
+    assert!(matches!(check_spice_response("not json"), Err(Error::Parse(_))));
+}
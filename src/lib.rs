@@ -1,23 +1,57 @@
-mod message;
+pub mod message;
+pub mod aime;
+pub mod apdu;
+pub mod backend;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod builder;
+pub mod detector;
 pub mod device;
 pub mod card;
 pub mod pn532;
+pub mod request;
+pub mod session;
+pub mod ndef;
 pub mod error;
+pub mod firmware;
+pub mod hooks;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "inject")]
+pub mod inject;
+#[cfg(feature = "uniffi")]
+pub mod ffi;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod utils;
 mod types;
 
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
 use tokio::task::spawn_blocking;
 use error::Error;
-use crate::builder::{find_devices_inner, HinataDeviceBuilder};
+use std::sync::Arc;
+use crate::backend::HidBackend;
+use crate::builder::{find_devices_inner, find_devices_with, HinataDeviceBuilder};
 use crate::error::HinataResult;
 
 pub async fn find_devices(exclude: Vec<String>) -> HinataResult<Vec<HinataDeviceBuilder>> {
-    spawn_blocking(|| find_devices_inner(exclude)
-        .map_err(|_| Error::NotFound("Device not found".to_string()))).await
+    spawn_blocking(|| find_devices_inner(exclude)).await
         .map_err(|e| Error::Other(e.to_string()))?
 }
 
+/// [`find_devices`] on another HID backend, the built devices keep using it
+pub async fn find_devices_with_backend(backend: Arc<dyn HidBackend>, exclude: Vec<String>) -> HinataResult<Vec<HinataDeviceBuilder>> {
+    spawn_blocking(|| find_devices_with(backend, exclude)).await
+        .map_err(|e| Error::Other(e.to_string()))?
+}
+
+/// Hardware id from [`device::HinataDevice::get_chip_id`], unlike the instance id it survives moving to another USB port
+pub type ChipId = [u8; 4];
+
 #[cfg(test)]
 mod tests {
     use crate::find_devices;
@@ -1,24 +1,61 @@
-use tokio::sync::mpsc;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use bytes::Bytes;
+use hidapi::HidError;
+use tokio::sync::{mpsc, oneshot};
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_stream::Stream;
+use crate::error::{Error, HinataResult};
 
 pub(crate) enum InMessage {
     SendPacket(Vec<u8>),
     SendPacketAndSubscribe(Vec<u8>, Subscription),
     Subscribe(u8, Subscription),
-    UnSubscribe(u8)
+    UnSubscribe(u8),
+    /// Buffer with the report id first, answered with the report read into it
+    GetFeatureReport(Vec<u8>, oneshot::Sender<HinataResult<Vec<u8>>>),
+    SendFeatureReport(Vec<u8>, oneshot::Sender<HinataResult<()>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum OutMessage {
-    Response(Vec<u8>),
+    /// Report without its report id, sliced out of the reader's buffer
+    Response(Bytes),
     DeviceDisconnect,
+    /// A HID read or write failed with this platform message
+    IoFailed(String),
+    IoThreadPanicked(String),
+}
+
+impl OutMessage {
+    pub(crate) fn into_result(self) -> HinataResult<Bytes> {
+        match self {
+            OutMessage::Response(data) => Ok(data),
+            OutMessage::DeviceDisconnect => Err(Error::Disconnected("Device disconnected".into())),
+            OutMessage::IoFailed(message) => Err(Error::HidError(HidError::HidApiError { message })),
+            OutMessage::IoThreadPanicked(reason) => Err(Error::IoThreadPanicked(reason)),
+        }
+    }
 }
 
-pub(crate) enum UnSubscribePolicy {
+/// Ends a [`UnSubscribePolicy::Predicate`] subscription when it returns `true` for a report
+pub type ReportPredicate = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// When a subscription ends, checked against every report it receives.
+/// Reports are seen without their report id, so index 0 is the command byte.
+pub enum UnSubscribePolicy {
+    /// After this many reports
     Count(usize),
+    /// Until unsubscribed or the device is gone
     Never,
+    /// At the first report with this byte at this index, or too short to have it
     SpecificIsOn(usize, u8),
-    SpecificNotOn(usize, u8)
+    /// At the first report without this byte at this index, or too short to have it
+    SpecificNotOn(usize, u8),
+    /// At the first report the function returns `true` for
+    Predicate(ReportPredicate),
+    /// As soon as one of the policies would end it, e.g. a terminator byte or a report limit
+    Any(Vec<UnSubscribePolicy>),
 }
 
 impl UnSubscribePolicy {
@@ -44,7 +81,9 @@ impl UnSubscribePolicy {
                     }
                 } else {
                     true
-                }
+                },
+                UnSubscribePolicy::Predicate(f) => f(packet),
+                UnSubscribePolicy::Any(policies) => policies.iter().any(|policy| policy.need_dispose(msg, count)),
             }
         } else {
             true
@@ -52,32 +91,191 @@ impl UnSubscribePolicy {
     }
 }
 
+/// Capacity of multi-message subscriptions, enough for an ACK and its response
+const STREAM_CAPACITY: usize = 4;
+/// Idle channels a [`ChannelPool`] keeps around
+const POOL_SIZE: usize = 4;
+/// Reports a [`ReportStream`] buffers before it is ended for falling behind
+const REPORT_STREAM_CAPACITY: usize = 32;
+
+enum SubscriptionSender {
+    Once(Option<oneshot::Sender<OutMessage>>),
+    Stream(Sender<OutMessage>),
+}
+
 pub(crate) struct Subscription {
-    sender: Sender<OutMessage>,
+    sender: SubscriptionSender,
     policy: UnSubscribePolicy,
     count: usize
 }
 
 impl Subscription {
-    pub(crate) fn new(policy: UnSubscribePolicy) -> (Self, Receiver<OutMessage>) {
-        let (sender, receiver) = mpsc::channel::<OutMessage>(32);
+    pub(crate) fn new(policy: UnSubscribePolicy) -> (Self, SubscriptionReceiver) {
+        let (sender, receiver) = mpsc::channel::<OutMessage>(STREAM_CAPACITY);
+        (
+            Self::stream(sender.clone(), policy),
+            SubscriptionReceiver::Stream { sender, receiver }
+        )
+    }
+
+    /// A single response, backed by a oneshot instead of a channel allocation
+    pub(crate) fn once() -> (Self, SubscriptionReceiver) {
+        let (sender, receiver) = oneshot::channel();
         (
             Self {
-                sender,
-                policy,
+                sender: SubscriptionSender::Once(Some(sender)),
+                policy: UnSubscribePolicy::Count(1),
                 count: 0,
             },
-            receiver
+            SubscriptionReceiver::Once(Some(receiver))
         )
     }
+
+    /// Like [`Subscription::new`] on a channel reused from `pool`
+    pub(crate) fn pooled(policy: UnSubscribePolicy, pool: &mut ChannelPool) -> (Self, SubscriptionReceiver) {
+        let Some((sender, receiver)) = pool.0.pop() else {
+            return Self::new(policy);
+        };
+        (
+            Self::stream(sender.clone(), policy),
+            SubscriptionReceiver::Stream { sender, receiver }
+        )
+    }
+
+    /// A subscription handed to the user, the channel closes once the io thread drops it
+    pub(crate) fn report_stream(policy: UnSubscribePolicy) -> (Self, ReportStream) {
+        let (sender, receiver) = mpsc::channel::<OutMessage>(REPORT_STREAM_CAPACITY);
+        (Self::stream(sender, policy), ReportStream(receiver))
+    }
+
+    fn stream(sender: Sender<OutMessage>, policy: UnSubscribePolicy) -> Self {
+        Self {
+            sender: SubscriptionSender::Stream(sender),
+            policy,
+            count: 0,
+        }
+    }
+
+    /// Never blocks, it runs under the subscription lock on the reader thread.
+    /// A receiver whose channel is full stops getting reports, the subscription ends instead.
     pub(crate) fn send(&mut self, msg: OutMessage) -> bool {
         self.count = self.count + 1;
         let mut need_dispose = self.policy.need_dispose(&msg, self.count);
-        if let Err(_) = self.sender.blocking_send(msg) { need_dispose = true }
+        match &mut self.sender {
+            SubscriptionSender::Once(sender) => {
+                if let Some(sender) = sender.take() {
+                    let _ = sender.send(msg);
+                }
+                need_dispose = true
+            }
+            SubscriptionSender::Stream(sender) => {
+                if sender.try_send(msg).is_err() { need_dispose = true }
+            }
+        }
         need_dispose
     }
 
-    pub(crate) fn send_no_check(&self, msg: OutMessage) {
-        let _ = self.sender.blocking_send(msg);
+    pub(crate) fn send_no_check(self, msg: OutMessage) {
+        match self.sender {
+            SubscriptionSender::Once(Some(sender)) => { let _ = sender.send(msg); }
+            SubscriptionSender::Once(None) => {}
+            SubscriptionSender::Stream(sender) => { let _ = sender.try_send(msg); }
+        }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub(crate) enum SubscriptionReceiver {
+    Once(Option<oneshot::Receiver<OutMessage>>),
+    /// Keeps a sender so the channel can go back to its pool
+    Stream { sender: Sender<OutMessage>, receiver: Receiver<OutMessage> },
+}
+
+impl SubscriptionReceiver {
+    pub(crate) async fn recv(&mut self) -> Option<OutMessage> {
+        match self {
+            Self::Once(receiver) => {
+                let message = receiver.as_mut()?.await.ok();
+                *receiver = None;
+                message
+            }
+            // Our own sender keeps the channel open, the io thread always reports a disconnect before letting go
+            Self::Stream { receiver, .. } => receiver.recv().await,
+        }
+    }
+}
+
+/// Reports from [`HinataDevice::subscribe`](crate::device::HinataDevice::subscribe), ends when its policy does.
+/// A disconnect or HID failure comes through as the last item.
+/// A stream left undrained with 32 reports buffered ends too, rather than stalling the device.
+pub struct ReportStream(Receiver<OutMessage>);
+
+impl Stream for ReportStream {
+    type Item = HinataResult<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx).map(|message| message.map(OutMessage::into_result))
+    }
+}
+
+/// Channels of finished multi-message subscriptions, reused by the next request
+#[derive(Debug, Default)]
+pub(crate) struct ChannelPool(Vec<(Sender<OutMessage>, Receiver<OutMessage>)>);
+
+impl ChannelPool {
+    /// Take the channel back once the io thread let go of its end, stale messages are dropped
+    pub(crate) fn recycle(&mut self, receiver: SubscriptionReceiver) {
+        let SubscriptionReceiver::Stream { sender, mut receiver } = receiver else {
+            return;
+        };
+        if sender.strong_count() > 1 || self.0.len() >= POOL_SIZE {
+            return;
+        }
+        while receiver.try_recv().is_ok() {}
+        self.0.push((sender, receiver));
+    }
+}
+
+#[test]
+fn channel_pool_test() {
+    let mut pool = ChannelPool::default();
+    let (mut subscription, receiver) = Subscription::pooled(UnSubscribePolicy::Count(2), &mut pool);
+    assert!(!subscription.send(OutMessage::Response(vec![1].into())));
+
+    // Still held by the io side, must not be handed out again
+    pool.recycle(receiver);
+    assert!(pool.0.is_empty());
+
+    let (subscription, receiver) = Subscription::pooled(UnSubscribePolicy::Count(2), &mut pool);
+    subscription.send_no_check(OutMessage::Response(vec![2].into()));
+    pool.recycle(receiver);
+    assert_eq!(pool.0.len(), 1);
+
+    let (_subscription, receiver) = Subscription::pooled(UnSubscribePolicy::Count(2), &mut pool);
+    let SubscriptionReceiver::Stream { mut receiver, .. } = receiver else { panic!() };
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn unsubscribe_policy_test() {
+    use tokio_stream::StreamExt;
+
+    let policy = UnSubscribePolicy::Any(vec![
+        UnSubscribePolicy::Predicate(Box::new(|report| report.get(3) == Some(&0x02))),
+        UnSubscribePolicy::Count(5),
+    ]);
+    let response = |end| OutMessage::Response(vec![0xE2, 0, 0, end].into());
+    assert!(!policy.need_dispose(&response(0x01), 1));
+    assert!(policy.need_dispose(&response(0x02), 1));
+    assert!(policy.need_dispose(&response(0x01), 5));
+    assert!(policy.need_dispose(&OutMessage::DeviceDisconnect, 1));
+
+    let (mut subscription, mut stream) = Subscription::report_stream(policy);
+    std::thread::spawn(move || {
+        assert!(!subscription.send(response(0x01)));
+        assert!(subscription.send(response(0x02)));
+    }).join().unwrap();
+    assert_eq!(&stream.next().await.unwrap().unwrap()[..], [0xE2, 0, 0, 0x01]);
+    assert_eq!(&stream.next().await.unwrap().unwrap()[..], [0xE2, 0, 0, 0x02]);
+    assert!(stream.next().await.is_none());
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, OnceLock};
+use ::metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+use crate::card::PassiveTarget;
+use crate::error::Error;
+use crate::hooks::{Hooks, RequestKind};
+
+pub const REQUESTS_TOTAL: &str = "hinata_requests_total";
+pub const REQUEST_ERRORS_TOTAL: &str = "hinata_request_errors_total";
+pub const REQUEST_DURATION_SECONDS: &str = "hinata_request_duration_seconds";
+pub const CONNECTS_TOTAL: &str = "hinata_connects_total";
+pub const RECONNECTS_TOTAL: &str = "hinata_reconnects_total";
+pub const DISCONNECTS_TOTAL: &str = "hinata_disconnects_total";
+pub const CARD_TAPS_TOTAL: &str = "hinata_card_taps_total";
+
+/// Register units and help texts with the installed recorder, call once after installing it
+pub fn describe_metrics() {
+    describe_counter!(REQUESTS_TOTAL, "Requests sent to a reader, by command");
+    describe_counter!(REQUEST_ERRORS_TOTAL, "Failed requests, by command and error kind");
+    describe_histogram!(REQUEST_DURATION_SECONDS, Unit::Seconds, "Request round trip time, by command");
+    describe_counter!(CONNECTS_TOTAL, "Devices opened");
+    describe_counter!(RECONNECTS_TOTAL, "Devices opened again after an earlier connection in this process");
+    describe_counter!(DISCONNECTS_TOTAL, "Devices whose io threads stopped, by reason");
+    describe_counter!(CARD_TAPS_TOTAL, "Cards seen by a card detector, by card type");
+}
+
+/// Label for the command a request was sent for
+pub fn command_label(kind: RequestKind) -> String {
+    match kind {
+        RequestKind::Firmware(cmd) => format!("firmware_{cmd:02x}"),
+        RequestKind::Pn532(command) => format!("{command:?}"),
+    }
+}
+
+/// Label for the kind of an error, stable across error messages
+pub fn error_kind(error: &Error) -> &'static str {
+    error.kind().as_str()
+}
+
+/// Count a device being opened, an instance id seen before counts as a reconnect
+pub(crate) fn record_connect(device: &str) {
+    static SEEN: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    counter!(CONNECTS_TOTAL, "device" => device.to_string()).increment(1);
+    let mut seen = SEEN.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if !seen.insert(device.to_string()) {
+        counter!(RECONNECTS_TOTAL, "device" => device.to_string()).increment(1);
+    }
+}
+
+pub(crate) fn record_tap(target: &PassiveTarget) {
+    let card_type = match target {
+        PassiveTarget::Iso14443a(_) => "type_a",
+        PassiveTarget::Felica(_) => "felica",
+    };
+    counter!(CARD_TAPS_TOTAL, "type" => card_type).increment(1);
+}
+
+impl Hooks {
+    /// Record requests and disconnects of `device` on top of whatever the hooks already do
+    pub(crate) fn with_metrics(mut self, device: &str) -> Self {
+        let device: Arc<str> = device.into();
+
+        let request_end = self.request_end.take();
+        let label = device.clone();
+        self.request_end = Some(Arc::new(move |kind, latency, error| {
+            let command = command_label(kind);
+            counter!(REQUESTS_TOTAL, "device" => label.to_string(), "command" => command.clone()).increment(1);
+            histogram!(REQUEST_DURATION_SECONDS, "device" => label.to_string(), "command" => command.clone()).record(latency.as_secs_f64());
+            if let Some(error) = error {
+                counter!(REQUEST_ERRORS_TOTAL, "device" => label.to_string(), "command" => command, "kind" => error_kind(error)).increment(1);
+            }
+            if let Some(f) = &request_end {
+                f(kind, latency, error)
+            }
+        }));
+
+        let disconnect = self.disconnect.take();
+        self.disconnect = Some(Arc::new(move |status| {
+            let reason = match status {
+                crate::device::IoStatus::Panicked(_) => "panicked",
+                crate::device::IoStatus::Failed(_) => "failed",
+                _ => "disconnected",
+            };
+            counter!(DISCONNECTS_TOTAL, "device" => device.to_string(), "reason" => reason).increment(1);
+            if let Some(f) = &disconnect {
+                f(status)
+            }
+        }));
+        self
+    }
+}
+
+#[test]
+fn metrics_label_test() {
+    use crate::pn532::{Pn532Command, Pn532Error};
+
+    assert_eq!(command_label(RequestKind::Firmware(0xE6)), "firmware_e6");
+    assert_eq!(command_label(RequestKind::Pn532(Pn532Command::InListPassiveTarget)), "InListPassiveTarget");
+    assert_eq!(error_kind(&Error::Pn532(Pn532Error::Timeout)), "pn532");
+    assert_eq!(error_kind(&Error::Timeout("Wait response timeout".into())), "timeout");
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use async_trait::async_trait;
+use crate::ChipId;
+use crate::device::{Capability, FirmwareVersion};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Command, Pn532Port};
+
+struct Expectation {
+    command: Pn532Command,
+    /// `None` accepts any payload
+    payload: Option<Vec<u8>>,
+    response: HinataResult<Vec<u8>>,
+}
+
+/// Scripted [`Pn532Port`] for testing card flows without a reader.
+///
+/// Requests must arrive in the order they were expected, anything else fails with [`Error::Protocol`].
+#[derive(Default)]
+pub struct MockPn532Port {
+    script: VecDeque<Expectation>,
+    latency: Duration,
+    requests: Vec<(Pn532Command, Vec<u8>)>,
+}
+
+impl MockPn532Port {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the next `command` with `response`, whatever its payload
+    pub fn expect(self, command: Pn532Command, response: Vec<u8>) -> Self {
+        self.push(command, None, Ok(response))
+    }
+
+    pub fn expect_with_payload(self, command: Pn532Command, payload: Vec<u8>, response: Vec<u8>) -> Self {
+        self.push(command, Some(payload), Ok(response))
+    }
+
+    /// Fail the next `command` with `error`
+    pub fn expect_error(self, command: Pn532Command, error: Error) -> Self {
+        self.push(command, None, Err(error))
+    }
+
+    /// InListPassiveTarget finding one ISO14443-A card
+    pub fn expect_type_a(self, uid: &[u8], sak: u8, atqa: u16) -> Self {
+        let mut response = vec![1, 1];
+        response.extend_from_slice(&atqa.to_be_bytes());
+        response.extend_from_slice(&[sak, uid.len() as u8]);
+        response.extend_from_slice(uid);
+        self.expect(Pn532Command::InListPassiveTarget, response)
+    }
+
+    /// InListPassiveTarget finding one FeliCa card, `system_code` is reported when the poll asked for it
+    pub fn expect_felica(self, idm: [u8; 8], pmm: [u8; 8], system_code: Option<u16>) -> Self {
+        let len = 18 + if system_code.is_some() { 2 } else { 0 };
+        let mut response = vec![1, 1, len, 0x01];
+        response.extend_from_slice(&idm);
+        response.extend_from_slice(&pmm);
+        if let Some(system_code) = system_code {
+            response.extend_from_slice(&system_code.to_be_bytes());
+        }
+        self.expect(Pn532Command::InListPassiveTarget, response)
+    }
+
+    /// InListPassiveTarget with an empty field
+    pub fn expect_no_target(self) -> Self {
+        self.expect(Pn532Command::InListPassiveTarget, vec![0])
+    }
+
+    /// Delay every response, requests whose timeout is shorter fail with [`Error::Timeout`]
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Every request seen so far, detached ones included
+    pub fn get_requests(&self) -> &[(Pn532Command, Vec<u8>)] {
+        &self.requests
+    }
+
+    /// Whether every expected request arrived
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    fn push(mut self, command: Pn532Command, payload: Option<Vec<u8>>, response: HinataResult<Vec<u8>>) -> Self {
+        self.script.push_back(Expectation { command, payload, response });
+        self
+    }
+}
+
+#[async_trait]
+impl Pn532Port for MockPn532Port {
+    async fn request(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<Vec<u8>> {
+        self.request_timeout(pn532_cmd, payload, Duration::from_millis(1000)).await
+    }
+
+    async fn request_timeout(&mut self, pn532_cmd: Pn532Command, payload: &[u8], timeout: Duration) -> HinataResult<Vec<u8>> {
+        self.requests.push((pn532_cmd, payload.to_vec()));
+        if self.latency > timeout {
+            tokio::time::sleep(timeout).await;
+            return Err(Error::Timeout("Wait response timeout".into()));
+        }
+        tokio::time::sleep(self.latency).await;
+
+        let expectation = self.script.pop_front()
+            .ok_or(Error::Protocol(format!("Unexpected {pn532_cmd:?}, the script is done").into()))?;
+        if expectation.command != pn532_cmd {
+            return Err(Error::Protocol(format!("Expected {:?}, got {pn532_cmd:?}", expectation.command).into()));
+        }
+        if expectation.payload.as_ref().is_some_and(|expected| expected != payload) {
+            return Err(Error::Protocol(format!("Unexpected payload for {pn532_cmd:?}: {payload:02X?}").into()));
+        }
+        expectation.response
+    }
+
+    /// Only recorded, detached requests never consume the script
+    fn request_detached(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<()> {
+        self.requests.push((pn532_cmd, payload.to_vec()));
+        Ok(())
+    }
+}
+
+/// Stand-in for [`crate::device::HinataDevice`] with the firmware side kept in memory and PN532 traffic
+/// served by a [`MockPn532Port`]
+pub struct MockHinataDevice {
+    port: MockPn532Port,
+    chip_id: ChipId,
+    firmware_timestamp: u32,
+    led: Option<(u8, u8, u8)>,
+}
+
+impl MockHinataDevice {
+    pub fn new(port: MockPn532Port) -> Self {
+        Self {
+            port,
+            chip_id: [0; 4],
+            firmware_timestamp: 2025051301,
+            led: None,
+        }
+    }
+
+    pub fn with_chip_id(mut self, chip_id: ChipId) -> Self {
+        self.chip_id = chip_id;
+        self
+    }
+
+    pub fn with_firmware_timestamp(mut self, firmware_timestamp: u32) -> Self {
+        self.firmware_timestamp = firmware_timestamp;
+        self
+    }
+
+    pub fn pn532(&'_ mut self) -> Pn532<'_, Self> {
+        Pn532::new(self)
+    }
+
+    pub fn get_port(&self) -> &MockPn532Port {
+        &self.port
+    }
+
+    pub async fn get_firmware_timestamp(&mut self) -> HinataResult<u32> {
+        Ok(self.firmware_timestamp)
+    }
+
+    pub async fn get_firmware_version(&mut self) -> HinataResult<FirmwareVersion> {
+        FirmwareVersion::parse(self.firmware_timestamp.to_string().as_bytes())
+    }
+
+    pub async fn supports(&mut self, capability: Capability) -> HinataResult<bool> {
+        Ok(capability.is_supported_by(self.firmware_timestamp))
+    }
+
+    pub async fn get_chip_id(&mut self) -> HinataResult<ChipId> {
+        Capability::ChipId.require(self.firmware_timestamp)?;
+        Ok(self.chip_id)
+    }
+
+    pub async fn set_led(&mut self, r: u8, g: u8, b: u8) {
+        self.led = Some((r, g, b));
+    }
+
+    pub async fn reset_led(&mut self) {
+        self.led = None;
+    }
+
+    /// Color set through [`MockHinataDevice::set_led`], `None` while the firmware drives the LED
+    pub fn get_led(&self) -> Option<(u8, u8, u8)> {
+        self.led
+    }
+}
+
+#[async_trait]
+impl Pn532Port for MockHinataDevice {
+    async fn request(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<Vec<u8>> {
+        self.port.request(pn532_cmd, payload).await
+    }
+
+    async fn request_timeout(&mut self, pn532_cmd: Pn532Command, payload: &[u8], timeout: Duration) -> HinataResult<Vec<u8>> {
+        self.port.request_timeout(pn532_cmd, payload, timeout).await
+    }
+
+    fn request_detached(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<()> {
+        self.port.request_detached(pn532_cmd, payload)
+    }
+}
+
+#[tokio::test]
+async fn mock_port_test() {
+    use crate::card::{CardId, PassiveTarget};
+    use crate::pn532::Pn532Error;
+
+    let mut device = MockHinataDevice::new(MockPn532Port::new()
+        .expect_no_target()
+        .expect_type_a(&[0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004)
+        .expect_error(Pn532Command::InDataExchange, Error::Pn532(Pn532Error::Timeout)))
+        .with_chip_id([1, 2, 3, 4]);
+    assert_eq!(device.get_chip_id().await.unwrap(), [1, 2, 3, 4]);
+
+    let mut pn532 = device.pn532();
+    assert!(pn532.in_list_passive_target(0, 1, &[]).await.unwrap().is_empty());
+    let targets = pn532.in_list_passive_target(0, 1, &[]).await.unwrap();
+    assert!(matches!(&targets[..], [PassiveTarget::Iso14443a(card)] if card.display_id() == "DEADBEEF"));
+    assert!(matches!(pn532.in_data_exchange(1, 0x30, &[0]).await, Err(Error::Pn532(Pn532Error::Timeout))));
+    assert!(matches!(pn532.in_release(1).await, Err(Error::Protocol(_))));
+
+    assert!(device.get_port().is_done());
+    assert_eq!(device.get_port().get_requests().len(), 4);
+}
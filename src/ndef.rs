@@ -0,0 +1,583 @@
+use crate::apdu::{Apdu, ApduResponse};
+use crate::card::ultralight::{Page, UltralightType, PAGE_SIZE};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{Pn532, Pn532Port};
+
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_CF: u8 = 0x20;
+const FLAG_SR: u8 = 0x10;
+const FLAG_IL: u8 = 0x08;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_LOCK_CONTROL: u8 = 0x01;
+const TLV_MEMORY_CONTROL: u8 = 0x02;
+const TLV_NDEF: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+const CC_MAGIC: u8 = 0xE1;
+
+const NDEF_APPLICATION_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+const CC_FILE_ID: [u8; 2] = [0xE1, 0x03];
+const TLV_NDEF_FILE_CONTROL: u8 = 0x04;
+
+const URI_PREFIXES: [&str; 36] = [
+    "", "http://www.", "https://www.", "http://", "https://", "tel:", "mailto:",
+    "ftp://anonymous:anonymous@", "ftp://ftp.", "ftps://", "sftp://", "smb://", "nfs://", "ftp://",
+    "dav://", "news:", "telnet://", "imap:", "rtsp://", "urn:", "pop:", "sip:", "sips:", "tftp:",
+    "btspp://", "btl2cap://", "btgoep://", "tcpobex://", "irdaobex://", "file://", "urn:epc:id:",
+    "urn:epc:tag:", "urn:epc:pat:", "urn:epc:raw:", "urn:epc:", "urn:nfc:",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Tnf {
+    Empty = 0x00,
+    WellKnown = 0x01,
+    Mime = 0x02,
+    AbsoluteUri = 0x03,
+    External = 0x04,
+    Unknown = 0x05,
+    Unchanged = 0x06,
+    Reserved = 0x07,
+}
+
+impl Tnf {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x07 {
+            0x00 => Tnf::Empty,
+            0x01 => Tnf::WellKnown,
+            0x02 => Tnf::Mime,
+            0x03 => Tnf::AbsoluteUri,
+            0x04 => Tnf::External,
+            0x05 => Tnf::Unknown,
+            0x06 => Tnf::Unchanged,
+            _ => Tnf::Reserved,
+        }
+    }
+}
+
+/// A record as it appears on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub struct NdefRecord {
+    pub tnf: Tnf,
+    pub record_type: Vec<u8>,
+    pub id: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl NdefRecord {
+    pub fn new(tnf: Tnf, record_type: &[u8], payload: Vec<u8>) -> Self {
+        Self {
+            tnf,
+            record_type: record_type.to_vec(),
+            id: Vec::new(),
+            payload,
+        }
+    }
+
+    fn encode(&self, first: bool, last: bool, buffer: &mut Vec<u8>) {
+        let short = self.payload.len() < 256;
+        let mut header = self.tnf as u8;
+        if first {
+            header |= FLAG_MB;
+        }
+        if last {
+            header |= FLAG_ME;
+        }
+        if short {
+            header |= FLAG_SR;
+        }
+        if !self.id.is_empty() {
+            header |= FLAG_IL;
+        }
+
+        buffer.push(header);
+        buffer.push(self.record_type.len() as u8);
+        if short {
+            buffer.push(self.payload.len() as u8);
+        } else {
+            buffer.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        }
+        if !self.id.is_empty() {
+            buffer.push(self.id.len() as u8);
+        }
+        buffer.extend_from_slice(&self.record_type);
+        buffer.extend_from_slice(&self.id);
+        buffer.extend_from_slice(&self.payload);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NdefMessage {
+    pub records: Vec<NdefRecord>,
+}
+
+impl NdefMessage {
+    pub fn new(records: Vec<NdefRecord>) -> Self {
+        Self { records }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        let mut records = Vec::new();
+        let mut pos = 0;
+        let read = |len: usize, pos: &mut usize| -> HinataResult<&[u8]> {
+            let slice = data.get(*pos..*pos + len).ok_or(Error::Parse("NDEF record truncated".into()))?;
+            *pos += len;
+            Ok(slice)
+        };
+
+        // Chunked records are merged into the first chunk
+        let mut chunked: Option<NdefRecord> = None;
+        loop {
+            let header = read(1, &mut pos)?[0];
+            let type_len = read(1, &mut pos)?[0] as usize;
+            let payload_len = if header & FLAG_SR != 0 {
+                read(1, &mut pos)?[0] as usize
+            } else {
+                u32::from_be_bytes(read(4, &mut pos)?.try_into().expect("4 bytes")) as usize
+            };
+            let id_len = if header & FLAG_IL != 0 { read(1, &mut pos)?[0] as usize } else { 0 };
+            let record_type = read(type_len, &mut pos)?.to_vec();
+            let id = read(id_len, &mut pos)?.to_vec();
+            let payload = read(payload_len, &mut pos)?.to_vec();
+
+            match chunked.as_mut() {
+                Some(record) => record.payload.extend_from_slice(&payload),
+                None => chunked = Some(NdefRecord {
+                    tnf: Tnf::from_bits(header),
+                    record_type,
+                    id,
+                    payload,
+                }),
+            }
+            if header & FLAG_CF == 0 {
+                records.extend(chunked.take());
+            }
+            if header & FLAG_ME != 0 {
+                break;
+            }
+        }
+
+        Ok(Self { records })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let count = self.records.len();
+        if count == 0 {
+            // An empty message is a single empty record
+            NdefRecord::new(Tnf::Empty, &[], Vec::new()).encode(true, true, &mut buffer);
+        }
+        for (i, record) in self.records.iter().enumerate() {
+            record.encode(i == 0, i + 1 == count, &mut buffer);
+        }
+        buffer
+    }
+
+    pub fn parsed_records(&self) -> Vec<Record> {
+        self.records.iter().map(Record::from_raw).collect()
+    }
+}
+
+/// Well-known record types decoded into their fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Uri(String),
+    Text { language: String, text: String },
+    Mime { mime_type: String, data: Vec<u8> },
+    SmartPoster { uri: String, titles: Vec<(String, String)> },
+    Other(NdefRecord),
+}
+
+impl Record {
+    pub fn from_raw(record: &NdefRecord) -> Self {
+        Self::try_from_raw(record).unwrap_or_else(|| Record::Other(record.clone()))
+    }
+
+    fn try_from_raw(record: &NdefRecord) -> Option<Self> {
+        match (record.tnf, record.record_type.as_slice()) {
+            (Tnf::WellKnown, b"U") => decode_uri(&record.payload).map(Record::Uri),
+            (Tnf::WellKnown, b"T") => {
+                let status = *record.payload.first()?;
+                if status & 0x80 != 0 {
+                    // UTF-16 text is not supported
+                    return None;
+                }
+                let lang_len = (status & 0x3F) as usize;
+                let language = String::from_utf8(record.payload.get(1..1 + lang_len)?.to_vec()).ok()?;
+                let text = String::from_utf8(record.payload.get(1 + lang_len..)?.to_vec()).ok()?;
+                Some(Record::Text { language, text })
+            }
+            (Tnf::WellKnown, b"Sp") => {
+                let inner = NdefMessage::from_bytes(&record.payload).ok()?;
+                let mut uri = None;
+                let mut titles = Vec::new();
+                for record in inner.parsed_records() {
+                    match record {
+                        Record::Uri(u) => uri = Some(u),
+                        Record::Text { language, text } => titles.push((language, text)),
+                        _ => {}
+                    }
+                }
+                Some(Record::SmartPoster { uri: uri?, titles })
+            }
+            (Tnf::Mime, mime_type) => Some(Record::Mime {
+                mime_type: String::from_utf8(mime_type.to_vec()).ok()?,
+                data: record.payload.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_raw(&self) -> NdefRecord {
+        match self {
+            Record::Uri(uri) => NdefRecord::new(Tnf::WellKnown, b"U", encode_uri(uri)),
+            Record::Text { language, text } => {
+                let mut payload = vec![language.len() as u8 & 0x3F];
+                payload.extend_from_slice(language.as_bytes());
+                payload.extend_from_slice(text.as_bytes());
+                NdefRecord::new(Tnf::WellKnown, b"T", payload)
+            }
+            Record::Mime { mime_type, data } => NdefRecord::new(Tnf::Mime, mime_type.as_bytes(), data.clone()),
+            Record::SmartPoster { uri, titles } => {
+                let mut records = vec![Record::Uri(uri.clone()).to_raw()];
+                records.extend(titles.iter().map(|(language, text)| {
+                    Record::Text { language: language.clone(), text: text.clone() }.to_raw()
+                }));
+                NdefRecord::new(Tnf::WellKnown, b"Sp", NdefMessage::new(records).to_bytes())
+            }
+            Record::Other(record) => record.clone(),
+        }
+    }
+}
+
+fn decode_uri(payload: &[u8]) -> Option<String> {
+    let prefix = URI_PREFIXES.get(*payload.first()? as usize).copied().unwrap_or("");
+    let rest = std::str::from_utf8(&payload[1..]).ok()?;
+    Some(format!("{prefix}{rest}"))
+}
+
+fn encode_uri(uri: &str) -> Vec<u8> {
+    let (code, prefix) = URI_PREFIXES
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, prefix)| uri.starts_with(*prefix))
+        .max_by_key(|(_, prefix)| prefix.len())
+        .unwrap_or((0, &""));
+    let mut payload = vec![code as u8];
+    payload.extend_from_slice(&uri.as_bytes()[prefix.len()..]);
+    payload
+}
+
+/// Type 2 capability container stored in page 3
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CapabilityContainer {
+    pub version: u8,
+    /// Size of the data area in bytes
+    pub data_size: usize,
+    pub read_access: u8,
+    pub write_access: u8,
+}
+
+impl CapabilityContainer {
+    pub fn from_page(page: &[u8]) -> HinataResult<Self> {
+        let page = page.get(..4).ok_or(Error::Parse("Capability container must be 4 bytes".into()))?;
+        if page[0] != CC_MAGIC {
+            return Err(Error::NotSupport("Tag is not NDEF formatted".into()));
+        }
+        Ok(Self {
+            version: page[1],
+            data_size: page[2] as usize * 8,
+            read_access: page[3] >> 4,
+            write_access: page[3] & 0x0F,
+        })
+    }
+
+    pub fn for_tag(tag: UltralightType) -> Self {
+        Self {
+            version: 0x10,
+            data_size: tag.user_memory_size(),
+            read_access: 0,
+            write_access: 0,
+        }
+    }
+
+    pub fn to_page(&self) -> Page {
+        [CC_MAGIC, self.version, (self.data_size / 8) as u8, self.read_access << 4 | self.write_access]
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.write_access == 0
+    }
+}
+
+/// Locate the NDEF message TLV in a Type 2 data area
+pub fn find_ndef_tlv(data: &[u8]) -> HinataResult<Option<&[u8]>> {
+    let mut pos = 0;
+    while let Some(&tag) = data.get(pos) {
+        match tag {
+            TLV_NULL => {
+                pos += 1;
+                continue;
+            }
+            TLV_TERMINATOR => return Ok(None),
+            _ => {}
+        }
+        let (len, header) = match data.get(pos + 1) {
+            Some(0xFF) => {
+                let bytes = data.get(pos + 2..pos + 4).ok_or(Error::Parse("TLV length truncated".into()))?;
+                (u16::from_be_bytes([bytes[0], bytes[1]]) as usize, 4)
+            }
+            Some(&len) => (len as usize, 2),
+            None => return Err(Error::Parse("TLV length truncated".into())),
+        };
+        let value = data.get(pos + header..pos + header + len).ok_or(Error::Parse("TLV value truncated".into()))?;
+        if tag == TLV_NDEF {
+            return Ok(Some(value));
+        }
+        pos += header + len;
+    }
+    Ok(None)
+}
+
+/// Where the NDEF TLV goes in a Type 2 data area, after the NULL, Lock Control and Memory Control TLVs that lead it.
+/// `None` when `data` ends before that.
+fn ndef_tlv_offset(data: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        match *data.get(pos)? {
+            TLV_NULL => pos += 1,
+            TLV_LOCK_CONTROL | TLV_MEMORY_CONTROL => pos += 2 + *data.get(pos + 1)? as usize,
+            _ => return Some(pos),
+        }
+    }
+}
+
+/// Wrap a message in an NDEF TLV followed by the terminator
+pub fn encode_ndef_tlv(message: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![TLV_NDEF];
+    if message.len() < 0xFF {
+        tlv.push(message.len() as u8);
+    } else {
+        tlv.push(0xFF);
+        tlv.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    }
+    tlv.extend_from_slice(message);
+    tlv.push(TLV_TERMINATOR);
+    tlv
+}
+
+/// Type 4 capability container file (E103)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Type4CapabilityContainer {
+    pub mapping_version: u8,
+    /// Maximum R-APDU data size for ReadBinary
+    pub max_le: u16,
+    /// Maximum C-APDU data size for UpdateBinary
+    pub max_lc: u16,
+    pub ndef_file_id: [u8; 2],
+    pub ndef_file_size: u16,
+    pub read_access: u8,
+    pub write_access: u8,
+}
+
+impl Type4CapabilityContainer {
+    pub fn from_bytes(data: &[u8]) -> HinataResult<Self> {
+        let data = data.get(..15).ok_or(Error::Parse("Type 4 capability container too short".into()))?;
+        if data[7] != TLV_NDEF_FILE_CONTROL {
+            return Err(Error::NotSupport("Missing NDEF file control TLV".into()));
+        }
+        Ok(Self {
+            mapping_version: data[2],
+            max_le: u16::from_be_bytes([data[3], data[4]]),
+            max_lc: u16::from_be_bytes([data[5], data[6]]),
+            ndef_file_id: [data[9], data[10]],
+            ndef_file_size: u16::from_be_bytes([data[11], data[12]]),
+            read_access: data[13],
+            write_access: data[14],
+        })
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.write_access == 0x00
+    }
+}
+
+fn check_sw(response: ApduResponse, what: &str) -> HinataResult<Vec<u8>> {
+    if response.is_success() {
+        Ok(response.into_data())
+    } else {
+        Err(Error::Protocol(format!("{what} failed with status {:04X}", response.get_sw()).into()))
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    async fn ndef_type4_select_file(&mut self, tg: u8, file_id: &[u8; 2]) -> HinataResult<()> {
+        let select = Apdu::new(0x00, 0xA4, 0x00, 0x0C).with_data(file_id);
+        check_sw(self.transceive_apdu(tg, &select).await?, "Select file").map(|_| ())
+    }
+
+    async fn ndef_type4_read_binary(&mut self, tg: u8, offset: u16, len: usize) -> HinataResult<Vec<u8>> {
+        let [p1, p2] = offset.to_be_bytes();
+        let read = Apdu::new(0x00, 0xB0, p1, p2).with_le(len);
+        check_sw(self.transceive_apdu(tg, &read).await?, "ReadBinary")
+    }
+
+    async fn ndef_type4_update_binary(&mut self, tg: u8, offset: u16, data: &[u8]) -> HinataResult<()> {
+        let [p1, p2] = offset.to_be_bytes();
+        let update = Apdu::new(0x00, 0xD6, p1, p2).with_data(data);
+        check_sw(self.transceive_apdu(tg, &update).await?, "UpdateBinary").map(|_| ())
+    }
+
+    /// Select the NDEF application and read its capability container
+    pub async fn ndef_type4_select(&mut self, tg: u8) -> HinataResult<Type4CapabilityContainer> {
+        let select_app = Apdu::new(0x00, 0xA4, 0x04, 0x00).with_data(&NDEF_APPLICATION_AID).with_le(256);
+        check_sw(self.transceive_apdu(tg, &select_app).await?, "Select NDEF application")?;
+        self.ndef_type4_select_file(tg, &CC_FILE_ID).await?;
+        let cc = self.ndef_type4_read_binary(tg, 0, 15).await?;
+        Type4CapabilityContainer::from_bytes(&cc)
+    }
+
+    pub async fn ndef_read_type4(&mut self, tg: u8) -> HinataResult<NdefMessage> {
+        let cc = self.ndef_type4_select(tg).await?;
+        self.ndef_type4_select_file(tg, &cc.ndef_file_id).await?;
+
+        let nlen = self.ndef_type4_read_binary(tg, 0, 2).await?;
+        let nlen = u16::from_be_bytes(nlen.get(..2).and_then(|s| s.try_into().ok())
+            .ok_or(Error::Protocol("NLEN must be 2 bytes".into()))?) as usize;
+        if nlen == 0 {
+            return Ok(NdefMessage::default());
+        }
+
+        let chunk = (cc.max_le as usize).max(1);
+        let mut data = Vec::with_capacity(nlen);
+        while data.len() < nlen {
+            let len = chunk.min(nlen - data.len());
+            let part = self.ndef_type4_read_binary(tg, 2 + data.len() as u16, len).await?;
+            if part.is_empty() {
+                return Err(Error::Protocol("ReadBinary returned no data".into()));
+            }
+            data.extend_from_slice(&part);
+        }
+        data.truncate(nlen);
+        NdefMessage::from_bytes(&data)
+    }
+
+    /// Update the NDEF file, NLEN is cleared first so an interrupted write leaves an empty message
+    pub async fn ndef_write_type4(&mut self, tg: u8, message: &NdefMessage) -> HinataResult<()> {
+        let cc = self.ndef_type4_select(tg).await?;
+        if !cc.is_writable() {
+            return Err(Error::NotSupport("NDEF file is read only".into()));
+        }
+        let bytes = message.to_bytes();
+        if bytes.len() + 2 > cc.ndef_file_size as usize {
+            return Err(Error::Protocol(format!("NDEF message needs {} bytes, file holds {}", bytes.len() + 2, cc.ndef_file_size).into()));
+        }
+
+        self.ndef_type4_select_file(tg, &cc.ndef_file_id).await?;
+        self.ndef_type4_update_binary(tg, 0, &[0, 0]).await?;
+        let chunk = (cc.max_lc as usize).max(1);
+        for (i, part) in bytes.chunks(chunk).enumerate() {
+            self.ndef_type4_update_binary(tg, (2 + i * chunk) as u16, part).await?;
+        }
+        self.ndef_type4_update_binary(tg, 0, &(bytes.len() as u16).to_be_bytes()).await
+    }
+
+    pub async fn ndef_read_capability_container(&mut self, tg: u8) -> HinataResult<CapabilityContainer> {
+        let pages = self.ultralight_read_pages(tg, 3).await?;
+        CapabilityContainer::from_page(&pages[..4])
+    }
+
+    pub async fn ndef_read_type2(&mut self, tg: u8) -> HinataResult<NdefMessage> {
+        let cc = self.ndef_read_capability_container(tg).await?;
+        let last_page = 3 + cc.data_size.div_ceil(PAGE_SIZE);
+
+        let mut data = Vec::with_capacity(cc.data_size);
+        let mut pages = self.ultralight_page_range(tg, 4, last_page.min(u8::MAX as usize) as u8);
+        while let Some(page) = pages.next().await {
+            data.extend_from_slice(&page?.1);
+        }
+
+        match find_ndef_tlv(&data)? {
+            Some(message) if !message.is_empty() => NdefMessage::from_bytes(message),
+            _ => Ok(NdefMessage::default()),
+        }
+    }
+
+    pub async fn ndef_write_type2(&mut self, tg: u8, message: &NdefMessage) -> HinataResult<()> {
+        let cc = self.ndef_read_capability_container(tg).await?;
+        if !cc.is_writable() {
+            return Err(Error::NotSupport("Tag is write protected".into()));
+        }
+        // Lock and Memory Control TLVs, as on NTAG215/216, stay in front of the message
+        let mut head = Vec::new();
+        let offset = loop {
+            if let Some(offset) = ndef_tlv_offset(&head) {
+                break offset;
+            }
+            if head.len() >= cc.data_size {
+                return Err(Error::Parse("Control TLVs fill the whole data area".into()));
+            }
+            head.extend_from_slice(&self.ultralight_read_pages(tg, 4 + (head.len() / PAGE_SIZE) as u8).await?);
+        };
+        let tlv = encode_ndef_tlv(&message.to_bytes());
+        if offset + tlv.len() > cc.data_size {
+            return Err(Error::Protocol(format!("NDEF message needs {} bytes, tag holds {}", tlv.len(), cc.data_size - offset).into()));
+        }
+
+        // The page the message starts in keeps the bytes in front of it
+        let start = offset - offset % PAGE_SIZE;
+        let mut data = head[start..offset].to_vec();
+        data.extend_from_slice(&tlv);
+        for (i, chunk) in data.chunks(PAGE_SIZE).enumerate() {
+            let mut page = [0u8; PAGE_SIZE];
+            page[..chunk.len()].copy_from_slice(chunk);
+            self.ultralight_write_page(tg, (4 + start / PAGE_SIZE + i) as u8, &page).await?;
+        }
+        Ok(())
+    }
+
+    /// Write a capability container and an empty NDEF message to a blank tag
+    pub async fn ndef_format_type2(&mut self, tg: u8, tag: UltralightType) -> HinataResult<()> {
+        self.ultralight_write_page(tg, 3, &CapabilityContainer::for_tag(tag).to_page()).await?;
+        self.ultralight_write_page(tg, 4, &[TLV_NDEF, 0x00, TLV_TERMINATOR, 0x00]).await
+    }
+}
+
+#[test]
+fn ndef_tlv_offset_test() {
+    // A Lock Control TLV in front of an empty NDEF message
+    assert_eq!(ndef_tlv_offset(&[0x01, 0x03, 0xA0, 0x0C, 0x34, 0x03, 0x00, 0xFE]), Some(5));
+    assert_eq!(ndef_tlv_offset(&[0x03, 0x00, 0xFE, 0x00]), Some(0));
+    assert_eq!(ndef_tlv_offset(&[0x00, 0x02, 0x03, 0x00, 0x10, 0x44, 0xFE]), Some(6));
+    assert_eq!(ndef_tlv_offset(&[0x01, 0x03, 0xA0, 0x0C]), None);
+}
+
+#[test]
+fn type4_capability_container_test() {
+    let cc = Type4CapabilityContainer::from_bytes(&[
+        0x00, 0x0F, 0x20, 0x00, 0x3B, 0x00, 0x34, 0x04, 0x06, 0xE1, 0x04, 0x00, 0xFF, 0x00, 0x00,
+    ]).unwrap();
+    assert_eq!(cc.max_le, 0x3B);
+    assert_eq!(cc.ndef_file_id, [0xE1, 0x04]);
+    assert!(cc.is_writable());
+}
+
+#[test]
+fn ndef_record_test() {
+    let message = NdefMessage::new(vec![
+        Record::Uri("https://github.com/nerimoe/hinata-rs".into()).to_raw(),
+        Record::Text { language: "en".into(), text: "HINATA".into() }.to_raw(),
+    ]);
+    let bytes = message.to_bytes();
+    assert_eq!(&bytes[..5], &[0x91, 0x01, 0x1D, b'U', 0x04]);
+
+    let parsed = NdefMessage::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed, message);
+    assert_eq!(parsed.parsed_records()[1], Record::Text { language: "en".into(), text: "HINATA".into() });
+
+    let area = [&[0x01, 0x03, 0xA0, 0x0C, 0x44][..], &encode_ndef_tlv(&bytes)].concat();
+    assert_eq!(find_ndef_tlv(&area).unwrap(), Some(&bytes[..]));
+}
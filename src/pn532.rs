@@ -1,19 +1,24 @@
+use std::borrow::Cow;
 use std::io::{Cursor, Read};
+use std::time::Duration;
 use async_trait::async_trait;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
 use thiserror::Error;
-use crate::card::{Felica, Iso14443a, PassiveTarget};
+use crate::apdu::{Apdu, ApduResponse};
+use crate::card::{felica, Felica, Iso14443a, PassiveTarget};
 use crate::error::{Error, HinataResult};
 use byteorder::{BigEndian, ReadBytesExt};
 
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum Pn532Direction {
     HostToPn532 = 0xD4,
     Pn532ToHost = 0xD5,
 }
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum Pn532Command {
@@ -112,9 +117,78 @@ pub enum Pn532Error {
     Overcurrent = 0x2D,
     #[error("NAD missing in DEP frame")]
     NoNad = 0x2E,
+    /// Not a status code, the PN532 answered with a syntax error frame (TFI 0x7F)
+    #[error("Syntax error frame, the PN532 rejected the command")]
+    SyntaxError = 0x7F,
 }
 
-pub enum Pn532ApplicationError {}
+/// How a caller should react to a failed exchange with a target
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pn532ApplicationError {
+    /// Transient RF error, the card is still selected and the command can be sent again
+    Retry,
+    /// The card is still in the field but dropped its state (e.g. halted after a failed auth), select it again first
+    Reselect,
+    /// The card has left the field, poll again before doing anything else
+    CardLost,
+    /// Invalid command, parameter or reader state, retrying will not help
+    Fatal,
+}
+
+impl Pn532Error {
+    pub fn classify(&self) -> Option<Pn532ApplicationError> {
+        use Pn532ApplicationError::*;
+        let class = match self {
+            Pn532Error::None => return None,
+            Pn532Error::Crc
+            | Pn532Error::Parity
+            | Pn532Error::CollisionBitCount
+            | Pn532Error::MifareFraming
+            | Pn532Error::CollisionBitCollision
+            | Pn532Error::RfNoBufs
+            | Pn532Error::ActiveTooSlow
+            | Pn532Error::RfProto
+            | Pn532Error::I2cBusy
+            | Pn532Error::UidChecksum => Retry,
+            Pn532Error::MifareAuth | Pn532Error::DepBadData | Pn532Error::DepState => Reselect,
+            Pn532Error::Timeout | Pn532Error::Released | Pn532Error::CardSwapped | Pn532Error::NoCard => CardLost,
+            Pn532Error::NoBufs
+            | Pn532Error::TooHot
+            | Pn532Error::InternalNoBufs
+            | Pn532Error::Inval
+            | Pn532Error::DepInvalidCommand
+            | Pn532Error::NoSecure
+            | Pn532Error::HciInval
+            | Pn532Error::Context
+            | Pn532Error::Mismatch
+            | Pn532Error::Overcurrent
+            | Pn532Error::NoNad
+            | Pn532Error::SyntaxError => Fatal,
+        };
+        Some(class)
+    }
+
+    pub fn is_card_present(&self) -> bool {
+        self.classify() != Some(Pn532ApplicationError::CardLost)
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.classify(), Some(Pn532ApplicationError::Retry | Pn532ApplicationError::Reselect))
+    }
+
+    pub fn needs_reselect(&self) -> bool {
+        self.classify() == Some(Pn532ApplicationError::Reselect)
+    }
+}
+
+/// Bit 6 of the InDataExchange Tg / status byte: more information follows
+const MI_BIT: u8 = 0x40;
+/// Lower 6 bits of the status byte carry the error code
+const ERROR_CODE_MASK: u8 = 0x3F;
+/// Largest DataOut chunk that keeps an InDataExchange frame inside a single HID report
+const DATA_EXCHANGE_CHUNK: usize = 48;
+/// GET RESPONSE rounds after a `61xx`, enough for the largest extended response at 256 bytes a round
+const MAX_GET_RESPONSE: usize = 256;
 
 #[derive(FromPrimitive, ToPrimitive)]
 #[repr(u8)]
@@ -130,6 +204,21 @@ pub enum MifareCommand {
     /// Specific to Mifare Ultralight cards
     UltralightWrite = 0xA2,
 }
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    A,
+    B,
+}
+
+impl KeyType {
+    pub fn command(&self) -> MifareCommand {
+        match self {
+            KeyType::A => MifareCommand::AuthA,
+            KeyType::B => MifareCommand::AuthB,
+        }
+    }
+}
+
 #[derive(FromPrimitive, ToPrimitive)]
 #[repr(u8)]
 pub enum FelicaCommand {
@@ -138,74 +227,76 @@ pub enum FelicaCommand {
     RequestResponse = 0x04,
     ReadWithoutEncryption = 0x06,
     WriteWithoutEncryption = 0x08,
+    SearchServiceCode = 0x0A,
     RequestSystemCode = 0x0C,
 }
 
+/// A PN532 frame, the payload borrows from the buffer it was parsed from or built with
 #[derive(Debug)]
-pub struct Pn532Packet {
+pub struct Pn532Packet<'a> {
     pub direction: Pn532Direction,
     pub command: Pn532Command,
-    pub payload: Vec<u8>,
+    pub payload: Cow<'a, [u8]>,
 }
 
-impl Pn532Packet {
-    pub fn new(direction: Pn532Direction, command: Pn532Command, payload: Vec<u8>) -> Self {
+impl<'a> Pn532Packet<'a> {
+    pub fn new(direction: Pn532Direction, command: Pn532Command, payload: impl Into<Cow<'a, [u8]>>) -> Self {
         Self {
             direction,
             command,
-            payload,
+            payload: payload.into(),
         }
     }
 
-    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
-        if data.len() < 9 {
-            return Err("Packet too short".into());
-        }
-
-        if data[0] != 0x00 || data[1] != 0x00 || data[2] != 0xFF {
-            return Err("Invalid preamble".into());
-        }
+    /// Whether `data` is the application level error frame the PN532 sends for a command it could not parse
+    pub fn is_error_frame(data: &[u8]) -> bool {
+        matches!(data, [0x00, 0x00, 0xFF, 0x01, 0xFF, 0x7F, 0x81, ..])
+    }
 
-        let payload_len = data[3];
-        let lcs = data[4];
-        if payload_len.wrapping_add(lcs) != 0 {
+    /// Parse one normal information frame, never panics whatever `data` holds
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, String> {
+        let [0x00, 0x00, 0xFF, len, lcs, rest @ ..] = data else {
+            return Err(if data.len() < 5 { "Packet too short" } else { "Invalid preamble" }.into());
+        };
+        if len.wrapping_add(*lcs) != 0 {
             return Err("Invalid length checksum (LCS)".into());
         }
-
-        let direction = Pn532Direction::from_u8(data[5]).ok_or_else(|| "Invalid direction".to_string())?;
-
-        let cmd = Pn532Command::from_u8(match direction {
-            Pn532Direction::HostToPn532 => data[6],
-            Pn532Direction::Pn532ToHost => data[6] - 1
-        }).ok_or_else(|| "Invalid command".to_string())?;
-
-        let dcs_index = 5 + payload_len as usize;
-        if data.len() <= dcs_index {
-            return Err("Packet truncated".into());
-        }
-
-        let mut checksum_sum: u8 = 0;
-        for i in 5..dcs_index {
-            checksum_sum = checksum_sum.wrapping_add(data[i]);
+        // TFI and command are part of LEN, anything shorter is an ACK or error frame
+        if *len < 2 {
+            return Err("Packet too short".into());
         }
 
-        let expected_dcs = data[dcs_index];
-        if checksum_sum.wrapping_add(expected_dcs) != 0 {
-            return Err(format!("Invalid checksum (DCS): sum=0x{:02X}, expected=0x{:02X}", checksum_sum, expected_dcs));
+        let (body, dcs) = match (rest.get(..*len as usize), rest.get(*len as usize)) {
+            (Some(body), Some(dcs)) => (body, *dcs),
+            _ => return Err("Packet truncated".into()),
+        };
+        let checksum_sum = body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if checksum_sum.wrapping_add(dcs) != 0 {
+            return Err(format!("Invalid checksum (DCS): sum=0x{:02X}, expected=0x{:02X}", checksum_sum, dcs));
         }
 
-        let payload = data[7..dcs_index].to_vec();
+        let direction = Pn532Direction::from_u8(body[0]).ok_or_else(|| "Invalid direction".to_string())?;
+        let code = match direction {
+            Pn532Direction::HostToPn532 => Some(body[1]),
+            Pn532Direction::Pn532ToHost => body[1].checked_sub(1),
+        };
+        let command = code.and_then(Pn532Command::from_u8).ok_or_else(|| "Invalid command".to_string())?;
 
         Ok(Pn532Packet {
             direction,
-            command: cmd,
-            payload,
+            command,
+            payload: Cow::Borrowed(&body[2..]),
         })
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
+        let mut buffer = Vec::with_capacity(self.payload.len() + 9);
+        self.write_to(&mut buffer);
+        buffer
+    }
 
+    /// Append the frame to `buffer`, e.g. behind a report header, without an intermediate allocation
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
         let len = (self.payload.len() + 2) as u8;
         let lcs = (!len).wrapping_add(1);
 
@@ -223,21 +314,30 @@ impl Pn532Packet {
         buffer.extend_from_slice(&self.payload);
 
         let mut dcs_sum: u8 = tfi.wrapping_add(cmd);
-        for &byte in &self.payload {
+        for &byte in self.payload.iter() {
             dcs_sum = dcs_sum.wrapping_add(byte);
         }
         let dcs = (!dcs_sum).wrapping_add(1);
 
         buffer.push(dcs);
         buffer.push(0x00); // Postamble
-
-        buffer
     }
 }
 
 #[async_trait]
-pub trait Pn532Port {
+pub trait Pn532Port: Send {
     async fn request(&mut self, pn532_cmd: Pn532Command, payload: &[u8]) -> HinataResult<Vec<u8>>;
+
+    /// Like [`Pn532Port::request`] for commands whose response can take longer than usual.
+    /// Ports without their own timeout wait as long as [`Pn532Port::request`] does.
+    async fn request_timeout(&mut self, pn532_cmd: Pn532Command, payload: &[u8], _timeout: Duration) -> HinataResult<Vec<u8>> {
+        self.request(pn532_cmd, payload).await
+    }
+
+    /// Queue a command without waiting for its response, usable from `Drop`
+    fn request_detached(&mut self, _pn532_cmd: Pn532Command, _payload: &[u8]) -> HinataResult<()> {
+        Err(Error::NotSupport("Detached requests are not supported by this port".into()))
+    }
 }
 
 pub struct Pn532<'a, P: Pn532Port> {
@@ -258,10 +358,43 @@ impl <'a, P: Pn532Port> Pn532<'a, P> {
         parse_in_list_passive_target(&res, brty)
     }
 
+    pub async fn rf_configuration(&mut self, item: u8, data: &[u8]) -> HinataResult<()> {
+        let mut payload = vec![item];
+        payload.extend_from_slice(data);
+        self.port.request(Pn532Command::RfConfiguration, &payload).await?;
+        Ok(())
+    }
+
+    /// Switch the RF field on or off, polling commands need it on
+    pub async fn set_rf_field(&mut self, on: bool) -> HinataResult<()> {
+        self.rf_configuration(0x01, &[on as u8]).await
+    }
+
+    /// Retry counts for ATR_REQ, PSL_REQ and passive activation, `0xFF` retries forever
+    pub async fn set_max_retries(&mut self, atr: u8, psl: u8, passive_activation: u8) -> HinataResult<()> {
+        self.rf_configuration(0x05, &[atr, psl, passive_activation]).await
+    }
+
+    /// Poll for a single FeliCa card at 212 kbps
+    pub async fn felica_poll(&mut self, request: &FelicaPollRequest) -> HinataResult<Option<Felica>> {
+        let targets = self.in_list_passive_target(1, 1, &request.to_bytes()?).await?;
+        Ok(targets.into_iter().find_map(|target| match target {
+            PassiveTarget::Felica(felica) => Some(felica),
+            _ => None,
+        }))
+    }
+
+    /// Poll for a FeliCa card in `system_code`, asking for its system code in the response
+    pub async fn poll_felica(&mut self, system_code: u16) -> HinataResult<Option<Felica>> {
+        let request = FelicaPollRequest::new(system_code).with_request_code(RequestCode::SystemCode);
+        self.felica_poll(&request).await
+    }
+
 
-    fn get_error_code(data: &[u8]) -> HinataResult<()> {
-        let status_byte = data.get(0).ok_or(Error::Protocol("Empty response from InDataExchange".into()))?;
-        let error = Pn532Error::from_u8(*status_byte).ok_or(Error::Protocol(format!("Unknown status code from PN532: {status_byte}")))?;
+    /// Check the status byte at the start of a response, use [`Error::application_error`] on failure to decide how to recover
+    pub fn get_error_code(data: &[u8]) -> HinataResult<()> {
+        let status_byte = data.first().ok_or(Error::Protocol("Empty response from InDataExchange".into()))? & ERROR_CODE_MASK;
+        let error = Pn532Error::from_u8(status_byte).ok_or(Error::Protocol(format!("Unknown status code from PN532: {status_byte}").into()))?;
         if error == Pn532Error::None {
             Ok(())
         } else {
@@ -269,19 +402,97 @@ impl <'a, P: Pn532Port> Pn532<'a, P> {
         }
     }
 
+    /// Exchange data with a target and return only the card data, the status byte is validated and stripped
     pub async fn in_data_exchange(&mut self, tg: u8, cmd: u8, data: &[u8]) -> HinataResult<Vec<u8>> {
-        let mut payload = vec![tg, cmd];
-        payload.extend_from_slice(data);
-        let res = self.port.request(Pn532Command::InDataExchange, &payload).await?;
+        let mut res = self.in_data_exchange_raw(tg, cmd, data).await?;
+        res.remove(0);
+        Ok(res)
+    }
+
+    /// Exchange data with a target and return the raw response, starting with the PN532 status byte
+    pub async fn in_data_exchange_raw(&mut self, tg: u8, cmd: u8, data: &[u8]) -> HinataResult<Vec<u8>> {
+        let mut data_out = vec![cmd];
+        data_out.extend_from_slice(data);
+
+        // Outgoing chaining: every chunk but the last carries the MI bit in Tg
+        let mut chunks = data_out.chunks(DATA_EXCHANGE_CHUNK).peekable();
+        let mut res = Vec::new();
+        while let Some(chunk) = chunks.next() {
+            let tg_byte = if chunks.peek().is_some() { tg | MI_BIT } else { tg };
+            let mut payload = vec![tg_byte];
+            payload.extend_from_slice(chunk);
+            res = self.port.request(Pn532Command::InDataExchange, &payload).await?;
+            Self::get_error_code(&res)?;
+        }
+
+        // Incoming chaining: keep asking while the target signals more information
+        while res[0] & MI_BIT != 0 {
+            let next = self.port.request(Pn532Command::InDataExchange, &[tg]).await?;
+            Self::get_error_code(&next)?;
+            res[0] = next[0];
+            res.extend_from_slice(&next[1..]);
+        }
+
+        Ok(res)
+    }
+
+    pub async fn transceive_apdu(&mut self, tg: u8, capdu: &Apdu) -> HinataResult<ApduResponse> {
+        let mut response = self.transceive_apdu_once(tg, capdu).await?;
+
+        if let Some(le) = response.wrong_length() {
+            let retry = capdu.clone().with_le(le);
+            response = self.transceive_apdu_once(tg, &retry).await?;
+        }
+
+        let mut data = response.get_data().to_vec();
+        let mut rounds = 0;
+        while let Some(remaining) = response.bytes_remaining() {
+            rounds += 1;
+            if rounds > MAX_GET_RESPONSE {
+                return Err(Error::Protocol(format!("Card still had data after {MAX_GET_RESPONSE} GET RESPONSE rounds").into()));
+            }
+            let get_response = Apdu::get_response(remaining as u8);
+            response = self.transceive_apdu_once(tg, &get_response).await?;
+            data.extend_from_slice(response.get_data());
+        }
+
+        Ok(ApduResponse::new(data, response.get_sw1(), response.get_sw2()))
+    }
+
+    async fn transceive_apdu_once(&mut self, tg: u8, capdu: &Apdu) -> HinataResult<ApduResponse> {
+        let bytes = capdu.to_bytes()?;
+        let res = self.in_data_exchange(tg, bytes[0], &bytes[1..]).await?;
+        ApduResponse::from_bytes(&res)
+    }
+
+    /// Send a raw frame to the target, CRC and parity handling follow the CIU register configuration
+    pub async fn in_communicate_thru(&mut self, data: &[u8]) -> HinataResult<Vec<u8>> {
+        let mut res = self.port.request(Pn532Command::InCommunicateThru, data).await?;
         Self::get_error_code(&res)?;
+        res.remove(0);
         Ok(res)
     }
 
-    pub async fn mifare_classic_auth(&mut self, tg: u8, uid: &[u8], block_num: u8, key_num: MifareCommand, key: &[u8]) -> HinataResult<()> {
+    pub async fn read_register(&mut self, address: u16) -> HinataResult<u8> {
+        let res = self.port.request(Pn532Command::ReadRegister, &address.to_be_bytes()).await?;
+        res.first().copied().ok_or(Error::Protocol("Empty response from ReadRegister".into()))
+    }
+
+    pub async fn write_register(&mut self, address: u16, value: u8) -> HinataResult<()> {
+        let mut payload = address.to_be_bytes().to_vec();
+        payload.push(value);
+        self.port.request(Pn532Command::WriteRegister, &payload).await?;
+        Ok(())
+    }
+
+    /// Authenticate a block, 7 and 10 byte UIDs use their last 4 bytes as the spec requires
+    pub async fn mifare_classic_auth(&mut self, tg: u8, uid: &[u8], block_num: u8, key_type: KeyType, key: &[u8; 6]) -> HinataResult<()> {
+        let uid_tail = uid.len().checked_sub(4).map(|start| &uid[start..])
+            .ok_or(Error::Protocol("Mifare UID must be at least 4 bytes for auth".into()))?;
         let mut input = vec![block_num];
-        input.extend_from_slice(key.get(..6).ok_or(Error::Protocol("Mifare key must be 6 bytes".into()))?);
-        input.extend_from_slice(uid.get(..4).ok_or(Error::Protocol("Mifare UID must be at least 4 bytes for auth".into()))?);
-        self.in_data_exchange(tg, key_num as u8, &input).await?;
+        input.extend_from_slice(key);
+        input.extend_from_slice(uid_tail);
+        self.in_data_exchange(tg, key_type.command() as u8, &input).await?;
         Ok(())
     }
 
@@ -296,7 +507,7 @@ impl <'a, P: Pn532Port> Pn532<'a, P> {
         let input = [block_num];
         let res = self.in_data_exchange(tg, MifareCommand::Read as u8, &input).await?;
 
-        let block_data = res.get(1..17).ok_or(Error::Protocol("Invalid data length in Mifare read response".into()))?;
+        let block_data = res.get(..16).ok_or(Error::Protocol("Invalid data length in Mifare read response".into()))?;
         let mut block = [0u8; 16];
         block.copy_from_slice(block_data);
         Ok(block)
@@ -308,33 +519,97 @@ impl <'a, P: Pn532Port> Pn532<'a, P> {
         Self::get_error_code(&res)
     }
 
+    /// Let the PN532 poll by itself, `poll_nr` rounds of `period` x 150ms per type, `0xFF` polls until a target shows up.
+    /// `timeout` should cover the whole polling run.
+    pub async fn in_auto_poll(&mut self, poll_nr: u8, period: u8, types: &[AutoPollType], timeout: Duration) -> HinataResult<Vec<PassiveTarget>> {
+        let mut payload = vec![poll_nr, period.clamp(1, 0x0F)];
+        payload.extend(types.iter().map(|&t| t as u8));
+        let res = self.port.request_timeout(Pn532Command::InAutoPoll, &payload, timeout).await?;
+        parse_in_auto_poll(&res)
+    }
+
+    /// Put the PN532 to sleep until one of the `wakeup_enable` sources fires
+    pub async fn power_down(&mut self, wakeup_enable: u8) -> HinataResult<()> {
+        let res = self.port.request(Pn532Command::PowerDown, &[wakeup_enable]).await?;
+        Self::get_error_code(&res)
+    }
+
+    /// Fire-and-forget InRelease for cleanup paths that cannot await
+    pub(crate) fn in_release_detached(&mut self, tg: u8) -> HinataResult<()> {
+        self.port.request_detached(Pn532Command::InRelease, &[tg])
+    }
+
     pub async fn in_select(&mut self, tg: u8) -> HinataResult<()> {
         let res = self.port.request(Pn532Command::InSelect, &[tg]).await?;
         Self::get_error_code(&res)
     }
 
-    pub async fn felica_read_without_encryption(&mut self, tg: u8, idm: &[u8], services: &[u16], blocks: &[u16]) -> HinataResult<Vec<u8>> {
-        let mut input = vec![FelicaCommand::ReadWithoutEncryption as u8];
-        input.extend_from_slice(idm.get(..8).ok_or(Error::Protocol("Felica IDM must be 8 bytes".to_string()))?);
-        input.push(services.len() as u8);
+    /// Check the target is still in the field without a full exchange.
+    /// Uses the Diagnose presence test, targets that are not ISO14443-4 fall back to InSelect, which drops MIFARE authentication.
+    pub async fn is_card_still_present(&mut self, tg: u8) -> HinataResult<bool> {
+        let res = self.port.request(Pn532Command::Diagnose, &[0x06]).await?;
+        let res = match Self::get_error_code(&res) {
+            Err(Error::Pn532(Pn532Error::Context)) => self.in_select(tg).await,
+            res => res,
+        };
+        match res {
+            Ok(()) => Ok(true),
+            Err(Error::Pn532(e)) if !e.is_card_present() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read blocks from services that need no key, block elements use the 2 byte `0x80 | index, block` form
+    pub async fn felica_read_without_encryption(&mut self, tg: u8, idm: &[u8; 8], services: &[u16], blocks: &[u16]) -> HinataResult<Vec<felica::Block>> {
+        let mut input = vec![services.len() as u8];
         for &service in services {
-            input.extend_from_slice(&service.to_be_bytes());
+            input.extend_from_slice(&service.to_le_bytes());
         }
         input.push(blocks.len() as u8);
         for &block in blocks {
             input.extend_from_slice(&block.to_be_bytes());
         }
 
-        let length = (input.len() + 1) as u8;
-        self.in_data_exchange(tg, length, &input).await
+        let res = self.felica_command(tg, FelicaCommand::ReadWithoutEncryption, idm, &input).await?;
+        let data = felica::check_status(&res)?;
+        let (&count, data) = data.split_first().ok_or(Error::Protocol("FeliCa read response is missing block count".into()))?;
+        if count as usize != blocks.len() || data.len() != blocks.len() * felica::BLOCK_SIZE {
+            return Err(Error::Protocol("Invalid FeliCa read response length".into()));
+        }
+        Ok(data.chunks_exact(felica::BLOCK_SIZE)
+            .map(|c| c.try_into().expect("chunk is a block"))
+            .collect())
+    }
+
+    /// Write blocks to services that need no key, `data` holds one block per block element
+    pub async fn felica_write_without_encryption(&mut self, tg: u8, idm: &[u8; 8], services: &[u16], blocks: &[u16], data: &[felica::Block]) -> HinataResult<()> {
+        if blocks.len() != data.len() {
+            return Err(Error::Protocol("FeliCa write needs one block of data per block element".into()));
+        }
+        let mut input = vec![services.len() as u8];
+        for &service in services {
+            input.extend_from_slice(&service.to_le_bytes());
+        }
+        input.push(blocks.len() as u8);
+        for &block in blocks {
+            input.extend_from_slice(&block.to_be_bytes());
+        }
+        for block in data {
+            input.extend_from_slice(block);
+        }
+
+        let res = self.felica_command(tg, FelicaCommand::WriteWithoutEncryption, idm, &input).await?;
+        felica::check_status(&res)?;
+        Ok(())
     }
 }
 
+/// Every length byte is checked against what is left, so malformed responses fail instead of panicking
 fn parse_in_list_passive_target(data: &[u8], brty: u8) -> HinataResult<Vec<PassiveTarget>> {
     let mut cursor = Cursor::new(data);
 
     let tag_num = cursor.read_u8()?;
-    let mut tags = Vec::with_capacity(tag_num as usize);
+    let mut tags = Vec::with_capacity(tag_num.min(2) as usize);
 
     for _ in 0..tag_num {
         let _tg = cursor.read_u8()?; // 跳过 Tg
@@ -344,29 +619,34 @@ fn parse_in_list_passive_target(data: &[u8], brty: u8) -> HinataResult<Vec<Passi
                 let atqa = cursor.read_u16::<BigEndian>()?;
                 let sak  = cursor.read_u8()?;
                 let len  = cursor.read_u8()? as usize;
+                if !matches!(len, 4 | 7 | 10) {
+                    return Err(Error::Protocol(format!("Invalid UID length {len}").into()));
+                }
 
                 let mut uid = vec![0u8; len];
                 cursor.read_exact(&mut uid)?;
 
+                // ISO14443-4 targets append their ATS, led by its own length byte
+                if sak & 0x20 != 0 && (cursor.position() as usize) < data.len() {
+                    let ats_len = cursor.read_u8()? as usize;
+                    let mut ats = vec![0u8; ats_len.saturating_sub(1)];
+                    cursor.read_exact(&mut ats)?;
+                }
+
                 tags.push(PassiveTarget::Iso14443a(Iso14443a::new(uid, sak, atqa)));
             },
             1 | 2 => { // FeliCa
+                // POL_RES length counts itself, read exactly that much so the next target stays aligned
                 let len = cursor.read_u8()? as usize;
                 if len < 18 { return Err(Error::Other("Len error".into())); }
-
-                let _code = cursor.read_u8()?; // 跳过 code
+                let mut response = vec![0u8; len - 1];
+                cursor.read_exact(&mut response)?;
 
                 let mut idm = [0u8; 8];
-                cursor.read_exact(&mut idm)?;
-
+                idm.copy_from_slice(&response[1..9]);
                 let mut pmm = [0u8; 8];
-                cursor.read_exact(&mut pmm)?;
-
-                let sys_cnt = (len - 18) / 2;
-                let mut sys_codes = Vec::with_capacity(sys_cnt);
-                for _ in 0..sys_cnt {
-                    sys_codes.push(cursor.read_u16::<BigEndian>()?);
-                }
+                pmm.copy_from_slice(&response[9..17]);
+                let sys_codes = response[17..].chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
 
                 tags.push(PassiveTarget::Felica(Felica::new(idm, pmm, sys_codes)));
             }
@@ -375,14 +655,118 @@ fn parse_in_list_passive_target(data: &[u8], brty: u8) -> HinataResult<Vec<Passi
     }
     Ok(tags)
 }
-pub fn gen_felica_poll_initial_data(system_code: u16, request_code: u16) -> Vec<u8> {
-    vec![
-        FelicaCommand::Polling as u8,
-        (system_code >> 8) as u8,
-        (system_code & 0xFF) as u8,
-        (request_code & 0xFF) as u8,
-        0
-    ]
+
+/// Target types for InAutoPoll
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AutoPollType {
+    Iso14443a = 0x10,
+    Felica212 = 0x11,
+    Felica424 = 0x12,
+}
+
+/// Each InAutoPoll entry wraps the same target data InListPassiveTarget returns
+fn parse_in_auto_poll(data: &[u8]) -> HinataResult<Vec<PassiveTarget>> {
+    let mut cursor = Cursor::new(data);
+    let tag_num = cursor.read_u8()?;
+    let mut tags = Vec::with_capacity(tag_num as usize);
+
+    for _ in 0..tag_num {
+        let target_type = cursor.read_u8()?;
+        let len = cursor.read_u8()? as usize;
+        let mut target_data = vec![1u8; len + 1];
+        cursor.read_exact(&mut target_data[1..])?;
+
+        let brty = match target_type {
+            0x10 | 0x20 => 0,
+            0x11 => 1,
+            0x12 => 2,
+            _ => continue,
+        };
+        tags.extend(parse_in_list_passive_target(&target_data, brty)?);
+    }
+    Ok(tags)
+}
+
+/// Entry points for the cargo-fuzz harness in `fuzz/`
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    use super::*;
+
+    pub fn in_list_passive_target(data: &[u8], brty: u8) -> HinataResult<Vec<PassiveTarget>> {
+        parse_in_list_passive_target(data, brty)
+    }
+
+    pub fn in_auto_poll(data: &[u8]) -> HinataResult<Vec<PassiveTarget>> {
+        parse_in_auto_poll(data)
+    }
+}
+
+/// Extra data the card appends to its Polling response
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RequestCode {
+    None = 0x00,
+    SystemCode = 0x01,
+    CommunicationPerformance = 0x02,
+}
+
+/// Initiator data for a FeliCa Polling through InListPassiveTarget
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FelicaPollRequest {
+    /// `0xFFFF` matches any system, `0xFF` in either byte is a wildcard
+    pub system_code: u16,
+    pub request_code: RequestCode,
+    /// Number of response slots, one of 1, 2, 4, 8 or 16
+    pub time_slots: u8,
+}
+
+impl FelicaPollRequest {
+    pub fn new(system_code: u16) -> Self {
+        Self {
+            system_code,
+            request_code: RequestCode::None,
+            time_slots: 1,
+        }
+    }
+
+    pub fn with_request_code(mut self, request_code: RequestCode) -> Self {
+        self.request_code = request_code;
+        self
+    }
+
+    pub fn with_time_slots(mut self, time_slots: u8) -> Self {
+        self.time_slots = time_slots;
+        self
+    }
+
+    pub fn to_bytes(&self) -> HinataResult<Vec<u8>> {
+        if !matches!(self.time_slots, 1 | 2 | 4 | 8 | 16) {
+            return Err(Error::Protocol(format!("Invalid FeliCa time slot count {}", self.time_slots).into()));
+        }
+        let mut buffer = vec![FelicaCommand::Polling as u8];
+        buffer.extend_from_slice(&self.system_code.to_be_bytes());
+        buffer.push(self.request_code as u8);
+        buffer.push(self.time_slots - 1);
+        Ok(buffer)
+    }
+}
+
+#[test]
+fn felica_poll_request_test() {
+    let request = FelicaPollRequest::new(0x88B4)
+        .with_request_code(RequestCode::SystemCode)
+        .with_time_slots(4);
+    assert_eq!(request.to_bytes().unwrap(), vec![0x00, 0x88, 0xB4, 0x01, 0x03]);
+    assert!(request.with_time_slots(3).to_bytes().is_err());
+}
+
+#[test]
+fn in_auto_poll_test() {
+    let data = [0x01, 0x10, 0x09, 0x01, 0x00, 0x04, 0x08, 0x04, 0xDE, 0xAD, 0xBE, 0xEF];
+    let targets = parse_in_auto_poll(&data).unwrap();
+    assert_eq!(targets, vec![PassiveTarget::Iso14443a(Iso14443a::new(vec![0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004))]);
+    assert!(parse_in_auto_poll(&[0x00]).unwrap().is_empty());
 }
 
 #[test]
@@ -394,5 +778,59 @@ fn packet_test() {
     println!("{:?}", packet2);
     println!("{:02X?}", packet.to_bytes());
     println!("{:02X?}", packet2.to_bytes());
+    assert_eq!(packet.to_bytes(), example);
+    assert!(matches!(packet2.payload, Cow::Borrowed(_)));
+
+}
+
+#[test]
+fn malformed_packet_test() {
+    // Response direction with command 0x00 used to underflow, LEN below 2 used to slice backwards
+    assert!(Pn532Packet::from_bytes(&[0x00, 0x00, 0xFF, 0x02, 0xFE, 0xD5, 0x00, 0x2B, 0x00]).is_err());
+    assert!(Pn532Packet::from_bytes(&[0x00, 0x00, 0xFF, 0x01, 0xFF, 0xD5, 0x2B, 0x00, 0x00]).is_err());
+    assert!(Pn532Packet::from_bytes(&[0x00, 0x00, 0xFF, 0x05, 0xFB, 0xD5, 0x4B]).is_err());
+
+    // Two targets, the first an ISO14443-4 card with its ATS
+    let data = [0x02,
+        0x01, 0x03, 0x44, 0x20, 0x07, 1, 2, 3, 4, 5, 6, 7, 0x03, 0x75, 0x77,
+        0x02, 0x00, 0x04, 0x08, 0x04, 0xDE, 0xAD, 0xBE, 0xEF];
+    let targets = parse_in_list_passive_target(&data, 0).unwrap();
+    assert_eq!(targets[1], PassiveTarget::Iso14443a(Iso14443a::new(vec![0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004)));
+    assert!(parse_in_list_passive_target(&[0x02, 0x01, 0x00, 0x04, 0x08, 0xFF], 0).is_err());
+}
+
+#[cfg(feature = "mock")]
+#[tokio::test]
+async fn get_response_limit_test() {
+    use crate::mock::MockPn532Port;
+
+    // A card that never stops announcing more data
+    let mut port = (0..=MAX_GET_RESPONSE).fold(MockPn532Port::new(), |port, _| port.expect(Pn532Command::InDataExchange, vec![0x00, 0xAA, 0x61, 0x01]));
+    let res = Pn532::new(&mut port).transceive_apdu(1, &Apdu::new(0x00, 0xB0, 0x00, 0x00).with_le(1)).await;
+    assert!(matches!(res, Err(Error::Protocol(_))));
+    assert!(port.is_done());
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn packet_parse_never_panics(data in proptest::collection::vec(proptest::num::u8::ANY, 0..300)) {
+        let _ = Pn532Packet::from_bytes(&data);
+    }
 
+    #[test]
+    fn packet_round_trip(code in 0u8..0x95, payload in proptest::collection::vec(proptest::num::u8::ANY, 0..250)) {
+        if let Some(command) = Pn532Command::from_u8(code) {
+            let bytes = Pn532Packet::new(Pn532Direction::Pn532ToHost, command, payload.clone()).to_bytes();
+            let packet = Pn532Packet::from_bytes(&bytes).unwrap();
+            proptest::prop_assert_eq!(packet.command, command);
+            proptest::prop_assert_eq!(&packet.payload[..], &payload[..]);
+        }
+    }
+
+    #[test]
+    fn target_parse_never_panics(brty in 0u8..4, data in proptest::collection::vec(proptest::num::u8::ANY, 0..300)) {
+        let _ = parse_in_list_passive_target(&data, brty);
+        let _ = parse_in_auto_poll(&data);
+    }
 }
@@ -0,0 +1,92 @@
+use std::time::Duration;
+use crate::pn532::Pn532Packet;
+
+/// Report id in front of every report sent to the reader
+pub(crate) const REPORT_ID: u8 = 1;
+/// How long [`HinataDevice::send_request`](crate::device::HinataDevice::send_request) waits unless told otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Firmware command that passes a PN532 frame through
+pub(crate) const PN532_PASSTHROUGH: u8 = 0xE2;
+
+/// A firmware command and its payload, assembled straight into the report that goes out,
+/// e.g. `Request::new(0x07).u8(r).u8(g).u8(b)` to set the LED
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    report: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Request {
+    pub fn new(cmd: u8) -> Self {
+        Self {
+            report: vec![REPORT_ID, cmd],
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// The passthrough command carrying `packet`
+    pub fn pn532(packet: &Pn532Packet) -> Self {
+        let mut request = Self::new(PN532_PASSTHROUGH);
+        request.report.reserve(packet.payload.len() + 9);
+        packet.write_to(&mut request.report);
+        request
+    }
+
+    pub fn u8(mut self, value: u8) -> Self {
+        self.report.push(value);
+        self
+    }
+
+    pub fn u16_be(self, value: u16) -> Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u16_le(self, value: u16) -> Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn bytes(mut self, data: &[u8]) -> Self {
+        self.report.extend_from_slice(data);
+        self
+    }
+
+    /// How long to wait for the response, [`DEFAULT_TIMEOUT`] if not set
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn get_command(&self) -> u8 {
+        self.report[1]
+    }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.report[2..]
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The report as written to the device, report id first
+    pub fn as_report(&self) -> &[u8] {
+        &self.report
+    }
+
+    pub(crate) fn into_report(self) -> Vec<u8> {
+        self.report
+    }
+}
+
+#[test]
+fn request_test() {
+    use crate::pn532::{Pn532Command, Pn532Direction};
+
+    let request = Request::new(0xE6).u16_be(0x1234).u16_le(0x1234).timeout(Duration::from_millis(50));
+    assert_eq!(request.as_report(), [1, 0xE6, 0x12, 0x34, 0x34, 0x12]);
+    assert_eq!((request.get_command(), request.get_payload()), (0xE6, &[0x12, 0x34, 0x34, 0x12][..]));
+    assert_eq!(request.get_timeout(), Duration::from_millis(50));
+
+    let packet = Pn532Packet::new(Pn532Direction::HostToPn532, Pn532Command::GetFirmwareVersion, &[]);
+    assert_eq!(Request::pn532(&packet).into_report(), [&[1, 0xE2][..], &packet.to_bytes()].concat());
+}
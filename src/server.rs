@@ -0,0 +1,606 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use crate::backend::HidBackend;
+use crate::builder::HinataDeviceBuilder;
+use crate::card::PassiveTarget;
+use crate::detector::{CardDetector, CardEvent};
+use crate::device::{HinataDevice, HinataInfo};
+use crate::error::{Error, ErrorContext, ErrorKind, HinataResult};
+use crate::pn532::{Pn532Command, Pn532Port};
+
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+/// A device request failed, `message` is the library error
+pub const DEVICE_ERROR: i32 = -32000;
+
+/// Method name of the notifications sent to subscribed clients
+pub const CARD_EVENT: &str = "card_event";
+
+/// Query parameter carrying the token, browsers can't set headers on a WebSocket
+pub const TOKEN_PARAM: &str = "token";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    /// Absent for notifications, which get no response
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    /// Set for [`DEVICE_ERROR`]s, what to branch on instead of the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<DeviceErrorData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceErrorData {
+    pub kind: ErrorKind,
+    /// [`Error::code`]
+    pub code: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ErrorContext>,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        Self {
+            code: DEVICE_ERROR,
+            message: e.to_string(),
+            data: Some(DeviceErrorData { kind: e.kind(), code: e.code(), context: e.get_context().cloned() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self { jsonrpc: "2.0".into(), id, result, error }
+    }
+}
+
+/// Params of every method that targets one reader, keyed by instance id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReaderParams {
+    pub reader: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedParams {
+    pub reader: String,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pn532Params {
+    pub reader: String,
+    /// PN532 command code, e.g. `0x4A` for InListPassiveTarget
+    pub command: u8,
+    #[serde(default)]
+    pub payload: Vec<u8>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Result of `discover`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReaderSummary {
+    pub instance_id: String,
+    pub device_name: String,
+    pub product_id: u16,
+    /// Whether the server holds the device open
+    pub opened: bool,
+}
+
+/// Params of a `card_event` notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerCardEvent {
+    Tapped { reader: String, target: PassiveTarget },
+    Removed { reader: String },
+    Disconnected { reader: String },
+}
+
+impl ServerCardEvent {
+    fn new(reader: &str, event: CardEvent) -> Self {
+        let reader = reader.to_string();
+        match event {
+            CardEvent::Tapped(target) => Self::Tapped { reader, target },
+            CardEvent::Removed => Self::Removed { reader },
+            CardEvent::Disconnected => Self::Disconnected { reader },
+        }
+    }
+}
+
+/// A device opened by the server, shared by every client
+struct SharedReader {
+    device: Mutex<HinataDevice>,
+    events: broadcast::Sender<ServerCardEvent>,
+    watcher: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Who may connect, checked during the WebSocket handshake
+struct Access {
+    token: String,
+    allowed_origins: Vec<String>,
+}
+
+struct Shared {
+    access: Access,
+    backend: Option<Arc<dyn HidBackend>>,
+    detector: CardDetector,
+    builders: Mutex<HashMap<String, HinataDeviceBuilder>>,
+    readers: Mutex<HashMap<String, Arc<SharedReader>>>,
+}
+
+/// Daemon that owns the readers and serves them to any number of clients over WebSocket JSON-RPC 2.0.
+///
+/// Methods: `discover`, `open`, `close`, `get_info`, `set_led`, `reset_led`, `pn532_request`,
+/// `subscribe` and `unsubscribe`. Subscribed clients get [`CARD_EVENT`] notifications with a [`ServerCardEvent`].
+///
+/// Every client has to present the token, as `?token=` in the URL or an `Authorization: Bearer` header.
+/// Browsers always send an `Origin`, a page is only let in when its origin was allowed with
+/// [`HinataServer::with_allowed_origin`], so any site the user visits can't drive the readers.
+pub struct HinataServer {
+    listener: TcpListener,
+    token: String,
+    allowed_origins: Vec<String>,
+    backend: Option<Arc<dyn HidBackend>>,
+    detector: CardDetector,
+}
+
+impl HinataServer {
+    pub async fn bind(addr: impl ToSocketAddrs) -> HinataResult<Self> {
+        let mut token = [0u8; 16];
+        getrandom::fill(&mut token).map_err(|e| Error::Other(e.to_string()))?;
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            token: token.iter().map(|b| format!("{b:02x}")).collect(),
+            allowed_origins: Vec::new(),
+            backend: None,
+            detector: CardDetector::new(),
+        })
+    }
+
+    /// Replace the random token generated at bind, e.g. with one from the app's configuration
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = token.into();
+        self
+    }
+
+    /// What clients have to present, hand it to the apps allowed to use the readers
+    pub fn get_token(&self) -> &str {
+        &self.token
+    }
+
+    /// Let browser pages from `origin` connect, e.g. `http://localhost:5173`
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Find readers through another HID backend than the default one
+    pub fn with_backend(mut self, backend: Arc<dyn HidBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Polling used for card events while any client is subscribed to a reader
+    pub fn with_detector(mut self, detector: CardDetector) -> Self {
+        self.detector = detector;
+        self
+    }
+
+    pub fn local_addr(&self) -> HinataResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept clients until the listener fails, each client is served on its own task
+    pub async fn serve(self) -> HinataResult<()> {
+        let shared = Arc::new(Shared {
+            access: Access { token: self.token, allowed_origins: self.allowed_origins },
+            backend: self.backend,
+            detector: self.detector,
+            builders: Mutex::new(HashMap::new()),
+            readers: Mutex::new(HashMap::new()),
+        });
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            tokio::spawn(shared.clone().serve_client(stream));
+        }
+    }
+}
+
+impl Shared {
+    async fn serve_client(self: Arc<Self>, stream: TcpStream) {
+        let Ok(ws) = tokio_tungstenite::accept_hdr_async(stream, &self.access).await else {
+            return;
+        };
+        let (mut sink, mut source) = ws.split();
+        let (tx, mut rx) = mpsc::channel::<String>(32);
+        let writer = tokio::spawn(async move {
+            while let Some(text) = rx.recv().await {
+                if sink.send(Message::text(text)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut subscriptions = HashMap::new();
+        while let Some(Ok(message)) = source.next().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if let Some(response) = self.handle(text.as_str(), &tx, &mut subscriptions).await {
+                let Ok(response) = serde_json::to_string(&response) else { continue };
+                if tx.send(response).await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        subscriptions.into_values().for_each(|forward: JoinHandle<()>| forward.abort());
+        writer.abort();
+    }
+
+    async fn handle(
+        self: &Arc<Self>,
+        text: &str,
+        tx: &mpsc::Sender<String>,
+        subscriptions: &mut HashMap<String, JoinHandle<()>>,
+    ) -> Option<RpcResponse> {
+        let request: RpcRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return Some(RpcResponse::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))),
+        };
+        let result = if request.jsonrpc != "2.0" {
+            Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))
+        } else {
+            self.call(&request.method, request.params, tx, subscriptions).await
+        };
+        request.id.map(|id| RpcResponse::new(id, result))
+    }
+
+    async fn call(
+        self: &Arc<Self>,
+        method: &str,
+        params: Value,
+        tx: &mpsc::Sender<String>,
+        subscriptions: &mut HashMap<String, JoinHandle<()>>,
+    ) -> Result<Value, RpcError> {
+        match method {
+            "discover" => to_value(self.discover().await?),
+            "open" => {
+                let params: ReaderParams = parse_params(params)?;
+                self.reader(&params.reader).await?;
+                Ok(Value::Null)
+            }
+            "close" => {
+                let params: ReaderParams = parse_params(params)?;
+                if let Some(reader) = self.readers.lock().await.remove(&params.reader) {
+                    reader.stop_watching();
+                }
+                Ok(Value::Null)
+            }
+            "get_info" => {
+                let params: ReaderParams = parse_params(params)?;
+                let reader = self.reader(&params.reader).await?;
+                let info: HinataInfo = reader.device.lock().await.get_info();
+                to_value(info)
+            }
+            "set_led" => {
+                let params: LedParams = parse_params(params)?;
+                let reader = self.reader(&params.reader).await?;
+                reader.device.lock().await.set_led(params.r, params.g, params.b).await;
+                Ok(Value::Null)
+            }
+            "reset_led" => {
+                let params: ReaderParams = parse_params(params)?;
+                let reader = self.reader(&params.reader).await?;
+                reader.device.lock().await.reset_led().await;
+                Ok(Value::Null)
+            }
+            "pn532_request" => {
+                let params: Pn532Params = parse_params(params)?;
+                let command = Pn532Command::from_u8(params.command)
+                    .ok_or(RpcError::new(INVALID_PARAMS, format!("Unknown PN532 command {:02X}", params.command)))?;
+                let reader = self.reader(&params.reader).await?;
+                let mut device = reader.device.lock().await;
+                let response = match params.timeout_ms {
+                    Some(timeout) => device.request_timeout(command, &params.payload, Duration::from_millis(timeout)).await?,
+                    None => Pn532Port::request(&mut *device, command, &params.payload).await?,
+                };
+                Ok(json!(response))
+            }
+            "subscribe" => {
+                let params: ReaderParams = parse_params(params)?;
+                if let Entry::Vacant(entry) = subscriptions.entry(params.reader) {
+                    let reader = self.reader(entry.key()).await?;
+                    let forward = forward_events(reader.events.subscribe(), tx.clone());
+                    self.start_watching(entry.key(), &reader);
+                    entry.insert(forward);
+                }
+                Ok(Value::Null)
+            }
+            "unsubscribe" => {
+                let params: ReaderParams = parse_params(params)?;
+                if let Some(forward) = subscriptions.remove(&params.reader) {
+                    forward.abort();
+                }
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method {method}"))),
+        }
+    }
+
+    async fn find_builders(&self) -> HinataResult<Vec<HinataDeviceBuilder>> {
+        match &self.backend {
+            Some(backend) => crate::find_devices_with_backend(backend.clone(), vec![]).await,
+            None => crate::find_devices(vec![]).await,
+        }
+    }
+
+    async fn discover(&self) -> HinataResult<Vec<ReaderSummary>> {
+        let found = self.find_builders().await?;
+        let readers = self.readers.lock().await;
+        let summaries = found.iter().map(|builder| ReaderSummary {
+            instance_id: builder.get_instance_id(),
+            device_name: builder.get_device_name(),
+            product_id: builder.get_product_id(),
+            opened: readers.contains_key(&builder.get_instance_id()),
+        }).collect();
+        *self.builders.lock().await = found.into_iter().map(|builder| (builder.get_instance_id(), builder)).collect();
+        Ok(summaries)
+    }
+
+    /// The opened reader with this instance id, opening it on first use
+    async fn reader(&self, instance_id: &str) -> HinataResult<Arc<SharedReader>> {
+        let mut readers = self.readers.lock().await;
+        if let Some(reader) = readers.get(instance_id) {
+            return Ok(reader.clone());
+        }
+
+        let mut builders = self.builders.lock().await;
+        if !builders.contains_key(instance_id) {
+            *builders = self.find_builders().await?.into_iter().map(|builder| (builder.get_instance_id(), builder)).collect();
+        }
+        let builder = builders.get(instance_id).ok_or(Error::NotFound(format!("No reader with instance id {instance_id}")))?;
+        let reader = Arc::new(SharedReader {
+            device: Mutex::new(builder.build(false)?),
+            events: broadcast::channel(16).0,
+            watcher: std::sync::Mutex::new(None),
+        });
+        readers.insert(instance_id.to_string(), reader.clone());
+        Ok(reader)
+    }
+
+    /// Poll `reader` until its last subscriber is gone, taking the device lock only for each poll
+    fn start_watching(&self, instance_id: &str, reader: &Arc<SharedReader>) {
+        let mut watcher = reader.watcher.lock().unwrap_or_else(|e| e.into_inner());
+        if watcher.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            return;
+        }
+        let detector = self.detector.clone();
+        let instance_id = instance_id.to_string();
+        let shared = reader.clone();
+        *watcher = Some(tokio::spawn(async move {
+            let _ = shared.device.lock().await.pn532().set_max_retries(0xFF, 0x01, 0x01).await;
+            let mut presence = detector.presence();
+            while shared.events.receiver_count() > 0 {
+                let polled = {
+                    let mut device = shared.device.lock().await;
+                    detector.poll_round(&mut device.pn532()).await
+                };
+                match polled {
+                    Ok(polled) => {
+                        for event in presence.update(polled) {
+                            let _ = shared.events.send(ServerCardEvent::new(&instance_id, event));
+                        }
+                    }
+                    Err(Error::Disconnected(_)) => {
+                        let _ = shared.events.send(ServerCardEvent::new(&instance_id, CardEvent::Disconnected));
+                        return;
+                    }
+                    Err(_) => presence.miss(),
+                }
+                tokio::time::sleep(detector.get_polling_config().interval).await;
+            }
+        }));
+    }
+}
+
+impl Access {
+    fn check(&self, request: &Request) -> Result<(), (StatusCode, &'static str)> {
+        if let Some(origin) = request.headers().get("origin") {
+            let allowed = origin.to_str().is_ok_and(|origin| self.allowed_origins.iter().any(|allowed| allowed == origin));
+            if !allowed {
+                return Err((StatusCode::FORBIDDEN, "Origin not allowed"));
+            }
+        }
+        let from_query = request.uri().query().into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| pair.strip_prefix(TOKEN_PARAM)?.strip_prefix('='));
+        let from_header = request.headers().get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match from_query.or(from_header) {
+            Some(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => Ok(()),
+            _ => Err((StatusCode::UNAUTHORIZED, "Missing or wrong token")),
+        }
+    }
+}
+
+impl Callback for &Access {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        self.check(request).map(|_| response).map_err(|(status, reason)| {
+            let mut rejection = ErrorResponse::new(Some(reason.into()));
+            *rejection.status_mut() = status;
+            rejection
+        })
+    }
+}
+
+/// Compares without returning early, so the time taken says nothing about how much of the token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl SharedReader {
+    fn stop_watching(&self) {
+        if let Some(handle) = self.watcher.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            handle.abort();
+        }
+    }
+}
+
+fn forward_events(mut events: broadcast::Receiver<ServerCardEvent>, tx: mpsc::Sender<String>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            let notification = json!({ "jsonrpc": "2.0", "method": CARD_EVENT, "params": event });
+            if tx.send(notification.to_string()).await.is_err() {
+                return;
+            }
+        }
+    })
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn to_value(value: impl Serialize) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(DEVICE_ERROR, e.to_string()))
+}
+
+#[cfg(feature = "simulator")]
+#[tokio::test]
+async fn server_test() {
+    use crate::backend::simulator::{SimulatorBackend, VirtualHinata};
+    use crate::card::Iso14443a;
+
+    let reader = VirtualHinata::new();
+    let server = HinataServer::bind("127.0.0.1:0").await.unwrap()
+        .with_backend(Arc::new(SimulatorBackend::new().with_device(reader.clone())))
+        .with_detector(CardDetector::new().with_poll_interval(Duration::from_millis(10)));
+    let addr = server.local_addr().unwrap();
+    let token = server.get_token().to_string();
+    tokio::spawn(server.serve());
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/?{TOKEN_PARAM}={token}")).await.unwrap();
+    let mut call = async |id: u32, method: &str, params: Value| -> RpcResponse {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        ws.send(Message::text(request.to_string())).await.unwrap();
+        loop {
+            let text = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            let message: Value = serde_json::from_str(text.as_str()).unwrap();
+            if message.get("id").is_some() {
+                return serde_json::from_value(message).unwrap();
+            }
+        }
+    };
+
+    let found: Vec<ReaderSummary> = serde_json::from_value(call(1, "discover", Value::Null).await.result.unwrap()).unwrap();
+    assert_eq!(found.len(), 1);
+    let id = found[0].instance_id.clone();
+
+    call(2, "set_led", json!({ "reader": id, "r": 1, "g": 2, "b": 3 })).await;
+    // GetFirmwareVersion through the raw passthrough also orders the LED write before it
+    let version = call(3, "pn532_request", json!({ "reader": id, "command": 0x02 })).await;
+    assert_eq!(version.result.unwrap(), json!([0x32, 0x01, 0x06, 0x07]));
+    assert_eq!(reader.get_led(), Some((1, 2, 3)));
+
+    assert_eq!(call(4, "nope", Value::Null).await.error.unwrap().code, METHOD_NOT_FOUND);
+    assert_eq!(call(5, "set_led", json!({ "reader": id })).await.error.unwrap().code, INVALID_PARAMS);
+
+    assert!(call(6, "subscribe", json!({ "reader": id })).await.error.is_none());
+    reader.place_card(PassiveTarget::Iso14443a(Iso14443a::new(vec![1, 2, 3, 4], 0x08, 0x0004)));
+    let event = loop {
+        let text = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let message: Value = serde_json::from_str(text.as_str()).unwrap();
+        if message["method"] == CARD_EVENT {
+            break serde_json::from_value::<ServerCardEvent>(message["params"].clone()).unwrap();
+        }
+    };
+    assert!(matches!(event, ServerCardEvent::Tapped { reader, .. } if reader == id));
+}
+
+#[tokio::test]
+async fn server_access_test() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Error as WsError;
+
+    let server = HinataServer::bind("127.0.0.1:0").await.unwrap()
+        .with_token("secret")
+        .with_allowed_origin("http://localhost:5173");
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.serve());
+
+    let connect = async |url: String, origin: Option<&str>| {
+        let mut request = url.into_client_request().unwrap();
+        if let Some(origin) = origin {
+            request.headers_mut().insert("origin", origin.parse().unwrap());
+        }
+        match tokio_tungstenite::connect_async(request).await {
+            Ok(_) => StatusCode::SWITCHING_PROTOCOLS,
+            Err(WsError::Http(response)) => response.status(),
+            Err(e) => panic!("{e}"),
+        }
+    };
+
+    let url = format!("ws://{addr}/?{TOKEN_PARAM}=secret");
+    assert_eq!(connect(url.clone(), Some("https://evil.example")).await, StatusCode::FORBIDDEN);
+    assert_eq!(connect(url.clone(), Some("http://localhost:5173")).await, StatusCode::SWITCHING_PROTOCOLS);
+    // Native clients send no origin
+    assert_eq!(connect(url, None).await, StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(connect(format!("ws://{addr}/?{TOKEN_PARAM}=guess"), None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(connect(format!("ws://{addr}/"), Some("http://localhost:5173")).await, StatusCode::UNAUTHORIZED);
+}
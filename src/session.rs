@@ -0,0 +1,131 @@
+use crate::apdu::{Apdu, ApduResponse};
+use crate::card::felica::Block as FelicaBlock;
+use crate::card::{CardId, PassiveTarget};
+use crate::error::{Error, HinataResult};
+use crate::pn532::{FelicaPollRequest, KeyType, Pn532, Pn532Port};
+
+/// A selected target that is released again when the session ends.
+///
+/// Prefer [`CardSession::release`], which reports whether the release worked. Dropping the session
+/// only queues the InRelease, a [`HinataDevice`](crate::device::HinataDevice) waits out its response
+/// before the next command goes out.
+pub struct CardSession<'p, 'a, P: Pn532Port> {
+    pn532: &'p mut Pn532<'a, P>,
+    tg: u8,
+    target: PassiveTarget,
+    released: bool,
+}
+
+impl<'p, 'a, P: Pn532Port> CardSession<'p, 'a, P> {
+    pub fn new(pn532: &'p mut Pn532<'a, P>, tg: u8, target: PassiveTarget) -> Self {
+        Self {
+            pn532,
+            tg,
+            target,
+            released: false,
+        }
+    }
+
+    pub fn get_tg(&self) -> u8 {
+        self.tg
+    }
+
+    pub fn get_target(&self) -> &PassiveTarget {
+        &self.target
+    }
+
+    /// Raw access for operations the session does not wrap
+    pub fn pn532(&mut self) -> &mut Pn532<'a, P> {
+        self.pn532
+    }
+
+    fn felica_idm(&self) -> HinataResult<[u8; 8]> {
+        match &self.target {
+            PassiveTarget::Felica(felica) => Ok(*felica.get_idm()),
+            _ => Err(Error::NotSupport("Target is not a FeliCa card".into())),
+        }
+    }
+
+    pub async fn mifare_classic_auth(&mut self, block: u8, key_type: KeyType, key: &[u8; 6]) -> HinataResult<()> {
+        let uid = self.target.id_bytes().to_vec();
+        self.pn532.mifare_classic_auth(self.tg, &uid, block, key_type, key).await
+    }
+
+    pub async fn mifare_classic_read_block(&mut self, block: u8) -> HinataResult<[u8; 16]> {
+        self.pn532.mifare_classic_read_block(self.tg, block).await
+    }
+
+    pub async fn mifare_classic_write_block(&mut self, block: u8, data: &[u8; 16]) -> HinataResult<()> {
+        self.pn532.mifare_classic_write_block(self.tg, block, data).await
+    }
+
+    pub async fn felica_read_without_encryption(&mut self, services: &[u16], blocks: &[u16]) -> HinataResult<Vec<FelicaBlock>> {
+        let idm = self.felica_idm()?;
+        self.pn532.felica_read_without_encryption(self.tg, &idm, services, blocks).await
+    }
+
+    pub async fn transceive_apdu(&mut self, apdu: &Apdu) -> HinataResult<ApduResponse> {
+        self.pn532.transceive_apdu(self.tg, apdu).await
+    }
+
+    /// FeliCa answers a Request Response, which leaves the card state alone
+    pub async fn is_card_still_present(&mut self) -> HinataResult<bool> {
+        let PassiveTarget::Felica(felica) = &self.target else {
+            return self.pn532.is_card_still_present(self.tg).await;
+        };
+        let idm = *felica.get_idm();
+        match self.pn532.felica_request_response(self.tg, &idm).await {
+            Ok(_) => Ok(true),
+            Err(Error::Pn532(e)) if !e.is_card_present() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn release(mut self) -> HinataResult<()> {
+        self.released = true;
+        self.pn532.in_release(self.tg).await
+    }
+}
+
+impl<P: Pn532Port> Drop for CardSession<'_, '_, P> {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.pn532.in_release_detached(self.tg);
+        }
+    }
+}
+
+impl<'a, P: Pn532Port> Pn532<'a, P> {
+    /// Select one ISO14443-A card
+    pub async fn select_type_a(&mut self) -> HinataResult<Option<CardSession<'_, 'a, P>>> {
+        let target = self.in_list_passive_target(0, 1, &[]).await?.into_iter().next();
+        Ok(target.map(|target| CardSession::new(self, 1, target)))
+    }
+
+    /// Select one FeliCa card
+    pub async fn select_felica(&mut self, request: &FelicaPollRequest) -> HinataResult<Option<CardSession<'_, 'a, P>>> {
+        let felica = self.felica_poll(request).await?;
+        Ok(felica.map(|felica| CardSession::new(self, 1, PassiveTarget::Felica(felica))))
+    }
+}
+
+#[cfg(feature = "simulator")]
+#[tokio::test]
+async fn session_drop_test() {
+    use std::sync::Arc;
+    use crate::backend::simulator::{SimulatorBackend, VirtualHinata};
+    use crate::card::Iso14443a;
+    use crate::pn532::Pn532Command;
+
+    let reader = VirtualHinata::new();
+    reader.place_card(PassiveTarget::Iso14443a(Iso14443a::new(vec![0xDE, 0xAD, 0xBE, 0xEF], 0x08, 0x0004)));
+    let backend = Arc::new(SimulatorBackend::new().with_device(reader));
+    let mut device = crate::find_devices_with_backend(backend, vec![]).await.unwrap()[0].build(false).unwrap();
+
+    for _ in 0..20 {
+        let mut pn532 = device.pn532();
+        drop(pn532.select_type_a().await.unwrap().unwrap());
+        // The InRelease answers must not be taken for this command's
+        assert_eq!(device.request(Pn532Command::GetFirmwareVersion, &[]).await.unwrap(), [0x32, 0x01, 0x06, 0x07]);
+    }
+}
@@ -1,5 +1,17 @@
 pub mod spad0;
+pub mod crc;
+pub mod id_format;
+pub mod hexdump;
+pub mod access_code;
+#[cfg(feature = "crypto")]
+pub mod crypto;
 pub(crate) mod device_parse;
 
-#[cfg(target_os = "windows")]
-pub(crate) mod com;
\ No newline at end of file
+#[cfg(all(target_os = "windows", feature = "com-port"))]
+pub mod com;
+
+#[cfg(all(target_os = "linux", feature = "com-port"))]
+pub mod sysfs;
+
+#[cfg(all(target_os = "macos", feature = "com-port"))]
+pub mod iokit;
@@ -0,0 +1,46 @@
+use crate::card::aime::AccessCode;
+use crate::error::{Error, HinataResult};
+use crate::utils::id_format::IdFormat;
+
+/// Parse a code the way people type it, spaces and dashes between the digits are ignored
+pub fn parse(input: &str) -> HinataResult<AccessCode> {
+    let digits: String = input.chars().filter(|c| !matches!(c, ' ' | '-')).collect();
+    digits.parse()
+}
+
+/// Four digit groups as printed on the card, e.g. `0123 4567 8901 2345 6789`
+pub fn format_grouped(code: &AccessCode, separator: char) -> String {
+    let digits = code.to_string();
+    digits.as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(&separator.to_string())
+}
+
+/// A code a game will accept: 20 digits and not the blank all-zero code
+pub fn is_valid(input: &str) -> bool {
+    parse(input).is_ok_and(|code| !code.is_empty())
+}
+
+/// Pseudo access code for a card without one: the ID read as a big-endian number, zero padded to 20 digits.
+/// SEGA's mapping from a FeliCa IDm to its access code is done server side and not public, this is the stand-in
+/// ARTEMiS' AimeDB hands out for unregistered FeliCa cards (`str(int(idm, 16)).zfill(20)`).
+/// Any ID up to 8 bytes (UID or IDm) fits, so distinct cards never share a code.
+pub fn derive(id: &[u8]) -> HinataResult<AccessCode> {
+    IdFormat::new(id)
+        .decimal(false, 20)
+        .ok_or(Error::Parse("IDs longer than 8 bytes have no pseudo access code".into()))?
+        .parse()
+}
+
+#[test]
+fn access_code_test() {
+    let code = parse("0123 4567-8901 2345 6789").unwrap();
+    assert_eq!(format_grouped(&code, ' '), "0123 4567 8901 2345 6789");
+    assert!(!is_valid("0000 0000 0000 0000 0000"));
+    assert!(!is_valid("0123"));
+
+    assert_eq!(derive(&[0x01, 0x2E, 0x4C, 0xD8, 0xA3, 0x0A, 0x12, 0x34]).unwrap().to_string(), "00085089936298611252");
+    assert!(derive(&[0; 10]).is_err());
+}
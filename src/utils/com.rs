@@ -1,11 +1,22 @@
 use winreg::enums::*;
 use winreg::RegKey;
 use windows::core::{GUID, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, ERROR_SHARING_VIOLATION, HANDLE};
+use windows::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
 use windows::Win32::Devices::DeviceAndDriverInstallation::*;
+use windows::Win32::Devices::SerialCommunication::{ComDBClaimPort, ComDBClose, ComDBOpen, ComDBReleasePort, HCOMDB};
 use windows::Win32::Devices::Properties::{DEVPKEY_Device_ClassGuid, DEVPROPTYPE, DEVPROP_TYPE_GUID};
+use std::ffi::c_void;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tokio_stream::wrappers::ReceiverStream;
 use crate::error::{Error, HinataResult};
+use crate::utils::id_format::IdFormat;
+use crate::{find_devices, ChipId};
 
 const GUID_DEVCLASS_PORTS: GUID = GUID::from_u128(0x4d36e978_e325_11ce_bfc1_08002be10318);
+const GUID_DEVCLASS_PORTS_STR: &str = "{4d36e978-e325-11ce-bfc1-08002be10318}";
 
 pub fn get_com_port_by_hid_instance(instance: &str) -> HinataResult<String> {
     let com_instance = get_com_instance_id_by_hid_instance_id(instance)?;
@@ -112,17 +123,560 @@ unsafe fn find_child_port(parent_node: u32) -> Option<String> {
     }
 }
 
+/// Whether the process runs with an elevated token, writing under `Enum` needs one
+pub fn is_elevated() -> bool {
+    unsafe {
+        let mut token = HANDLE::default();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).is_err() {
+            return false;
+        }
+        let mut elevation = TOKEN_ELEVATION::default();
+        let mut len = 0u32;
+        let queried = GetTokenInformation(
+            token,
+            TokenElevation,
+            Some(&mut elevation as *mut _ as *mut core::ffi::c_void),
+            size_of::<TOKEN_ELEVATION>() as u32,
+            &mut len,
+        ).is_ok();
+        let _ = CloseHandle(token);
+        queried && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Tell a missing key apart from a refused one
+fn registry_error(e: std::io::Error, key_path: &str) -> Error {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => Error::NotFound(format!("Registry key or value missing: {key_path}")),
+        std::io::ErrorKind::PermissionDenied => Error::PermissionDenied(format!("Access to {key_path} denied, administrator rights are required")),
+        _ => Error::Io(e),
+    }
+}
+
 /// 辅助函数：直接从注册表读取 PortName
 pub fn get_com_port_by_com_instance_id(instance_id: &str) -> HinataResult<String> {
     let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
     let key_path = format!("SYSTEM\\CurrentControlSet\\Enum\\{}\\Device Parameters", instance_id);
-    let key = hklm.open_subkey(&key_path)?;
+    let key = hklm.open_subkey(&key_path).map_err(|e| registry_error(e, &key_path))?;
 
-    let port_name: String = key.get_value("PortName")?;
+    let port_name: String = key.get_value("PortName").map_err(|e| registry_error(e, &key_path))?;
 
     Ok(port_name)
 }
 
+/// Instance ids of devices in the Ports class, `present_only` leaves out unplugged devices that still own a port
+fn list_port_instance_ids(present_only: bool) -> HinataResult<Vec<String>> {
+    let filter: Vec<u16> = GUID_DEVCLASS_PORTS_STR
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    let flags = if present_only {
+        CM_GETIDLIST_FILTER_CLASS | CM_GETIDLIST_FILTER_PRESENT
+    } else {
+        CM_GETIDLIST_FILTER_CLASS
+    };
+
+    unsafe {
+        let mut len: u32 = 0;
+        if CM_Get_Device_ID_List_SizeW(&mut len, PCWSTR::from_raw(filter.as_ptr()), flags) != CR_SUCCESS {
+            return Err(Error::NotFound("Could not size the Ports device list".into()));
+        }
+        let mut buffer = vec![0u16; len as usize];
+        if CM_Get_Device_ID_ListW(PCWSTR::from_raw(filter.as_ptr()), &mut buffer, flags) != CR_SUCCESS {
+            return Err(Error::NotFound("Could not list Ports devices".into()));
+        }
+        Ok(buffer
+            .split(|&c| c == 0)
+            .filter(|id| !id.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect())
+    }
+}
+
+/// `VID_xxxx&PID_xxxx` from a USB instance id
+fn parse_vid_pid(instance_id: &str) -> Option<(u16, u16)> {
+    let upper = instance_id.to_uppercase();
+    let vid = upper.split("VID_").nth(1)?.get(..4)?;
+    let pid = upper.split("PID_").nth(1)?.get(..4)?;
+    Some((u16::from_str_radix(vid, 16).ok()?, u16::from_str_radix(pid, 16).ok()?))
+}
+
+/// A serial port together with the device that owns it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComDevice {
+    pub port: String,
+    pub instance_id: String,
+    pub friendly_name: Option<String>,
+    pub is_hinata: bool,
+}
+
+fn get_friendly_name(instance_id: &str) -> Option<String> {
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(format!("SYSTEM\\CurrentControlSet\\Enum\\{}", instance_id))
+        .and_then(|key| key.get_value("FriendlyName"))
+        .ok()
+}
+
+/// Every serial port with a `PortName`, `include_unplugged` adds devices that are gone but still hold their number
+pub fn list_com_ports(include_unplugged: bool) -> HinataResult<Vec<ComDevice>> {
+    let mut ports: Vec<ComDevice> = list_port_instance_ids(!include_unplugged)?
+        .into_iter()
+        .filter_map(|instance_id| {
+            let port = get_com_port_by_com_instance_id(&instance_id).ok()?;
+            Some(ComDevice {
+                port,
+                friendly_name: get_friendly_name(&instance_id),
+                is_hinata: parse_vid_pid(&instance_id).is_some_and(|(vid, _)| vid == crate::builder::HINATA_VID),
+                instance_id,
+            })
+        })
+        .collect();
+    ports.sort_by_key(|device| (com_number(&device.port).unwrap_or(u32::MAX), device.port.clone()));
+    Ok(ports)
+}
+
+/// Every plugged-in serial port of USB devices with `vid`/`pid`, empty when there is none
+pub fn get_com_port_by_vid_pid(vid: u16, pid: u16) -> HinataResult<Vec<ComDevice>> {
+    Ok(list_com_ports(false)?
+        .into_iter()
+        .filter(|device| parse_vid_pid(&device.instance_id) == Some((vid, pid)))
+        .collect())
+}
+
+/// VID and PID of the USB device behind `port`
+pub fn get_vid_pid_by_com_port(port: &str) -> HinataResult<(u16, u16)> {
+    list_port_instance_ids(true)?
+        .iter()
+        .find(|id| get_com_port_by_com_instance_id(id).is_ok_and(|name| name.eq_ignore_ascii_case(port)))
+        .and_then(|id| parse_vid_pid(id))
+        .ok_or(Error::NotFound(format!("No USB device behind {port}")))
+}
+
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> HinataResult<T> + Send + 'static) -> HinataResult<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Other(format!("Blocking COM lookup failed: {e}")))?
+}
+
+/// [`get_com_port_by_hid_instance`] on the blocking pool
+pub async fn get_com_port_by_hid_instance_async(instance: &str) -> HinataResult<String> {
+    let instance = instance.to_string();
+    run_blocking(move || get_com_port_by_hid_instance(&instance)).await
+}
+
+/// [`get_com_instance_id_by_hid_instance_id`] on the blocking pool
+pub async fn get_com_instance_id_by_hid_instance_id_async(instance_id: &str) -> HinataResult<String> {
+    let instance_id = instance_id.to_string();
+    run_blocking(move || get_com_instance_id_by_hid_instance_id(&instance_id)).await
+}
+
+/// [`get_com_port_by_com_instance_id`] on the blocking pool
+pub async fn get_com_port_by_com_instance_id_async(instance_id: &str) -> HinataResult<String> {
+    let instance_id = instance_id.to_string();
+    run_blocking(move || get_com_port_by_com_instance_id(&instance_id)).await
+}
+
+pub async fn get_com_port_by_vid_pid_async(vid: u16, pid: u16) -> HinataResult<Vec<ComDevice>> {
+    run_blocking(move || get_com_port_by_vid_pid(vid, pid)).await
+}
+
+pub async fn get_vid_pid_by_com_port_async(port: &str) -> HinataResult<(u16, u16)> {
+    let port = port.to_string();
+    run_blocking(move || get_vid_pid_by_com_port(&port)).await
+}
+
+/// Disable and re-enable a plugged-in device so a new `PortName` goes live, unplugged devices are left alone
+pub fn restart_device(instance_id: &str) -> HinataResult<()> {
+    let id_wide: Vec<u16> = instance_id.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let mut dev_node: u32 = 0;
+        if CM_Locate_DevNodeW(&mut dev_node, PCWSTR::from_raw(id_wide.as_ptr()), CM_LOCATE_DEVNODE_NORMAL) != CR_SUCCESS {
+            return Ok(());
+        }
+        let ret = CM_Disable_DevNode(dev_node, CM_DISABLE_UI_NOT_OK);
+        if ret != CR_SUCCESS {
+            return Err(Error::Other(format!("Could not disable {instance_id}: {:?}", ret)));
+        }
+        let ret = CM_Enable_DevNode(dev_node, 0);
+        if ret != CR_SUCCESS {
+            return Err(Error::Other(format!("Could not re-enable {instance_id}, it stays disabled: {:?}", ret)));
+        }
+    }
+    Ok(())
+}
+
+/// The COM port database that hands out free port numbers, closed on drop
+struct ComDb(HCOMDB);
+
+impl ComDb {
+    fn open() -> HinataResult<Self> {
+        let mut db = HCOMDB::default();
+        let ret = unsafe { ComDBOpen(&mut db) };
+        if ret != 0 {
+            return Err(Error::Other(format!("Could not open the COM port database: {ret}")));
+        }
+        Ok(Self(db))
+    }
+
+    /// Mark `port` as taken, a port that already is stays taken
+    fn claim(&self, port: u32) -> HinataResult<()> {
+        let ret = unsafe { ComDBClaimPort(self.0, port, false, None) };
+        if ret != 0 && ret != ERROR_SHARING_VIOLATION.0 as i32 {
+            return Err(Error::Other(format!("Could not claim COM{port}: {ret}")));
+        }
+        Ok(())
+    }
+
+    fn release(&self, port: u32) -> HinataResult<()> {
+        let ret = unsafe { ComDBReleasePort(self.0, port) };
+        if ret != 0 {
+            return Err(Error::Other(format!("Could not release COM{port}: {ret}")));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ComDb {
+    fn drop(&mut self) {
+        let _ = unsafe { ComDBClose(self.0) };
+    }
+}
+
+/// `FriendlyName` with its trailing `(COMx)` pointed at `port`, `None` when it names no port
+fn renamed_friendly_name(name: &str, port: &str) -> Option<String> {
+    let (base, last) = name.rsplit_once(" (")?;
+    com_number(last.strip_suffix(')')?)?;
+    Some(format!("{base} ({port})"))
+}
+
+/// Device Manager shows the `FriendlyName`, which only Configuration Manager may write
+fn set_friendly_name(instance_id: &str, name: &str) -> HinataResult<()> {
+    let id_wide: Vec<u16> = instance_id.encode_utf16().chain(std::iter::once(0)).collect();
+    let name_wide: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let mut dev_node: u32 = 0;
+        // Unplugged devices keep their port, their name is updated as well
+        if CM_Locate_DevNodeW(&mut dev_node, PCWSTR::from_raw(id_wide.as_ptr()), CM_LOCATE_DEVNODE_PHANTOM) != CR_SUCCESS {
+            return Err(Error::NotFound(format!("No device node for {instance_id}")));
+        }
+        let ret = CM_Set_DevNode_Registry_PropertyW(
+            dev_node,
+            CM_DRP_FRIENDLYNAME,
+            Some(name_wide.as_ptr() as *const c_void),
+            (name_wide.len() * 2) as u32,
+            0,
+        );
+        if ret != CR_SUCCESS {
+            return Err(Error::Other(format!("Could not rename {instance_id}: {:?}", ret)));
+        }
+    }
+    Ok(())
+}
+
+/// Point the serial device `instance_id` at `port` (e.g. `COM3`), needs administrator rights.
+/// The port database and the name Device Manager shows follow, the old port is freed once no other device holds it.
+/// With `restart` the device node is cycled so the port changes without replugging.
+pub fn set_device_com_port(instance_id: &str, port: &str, restart: bool) -> HinataResult<()> {
+    if !is_elevated() {
+        return Err(Error::PermissionDenied("Changing a COM port needs an elevated (administrator) process".into()));
+    }
+    let old = get_com_port_by_com_instance_id(instance_id).ok();
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let key_path = format!("SYSTEM\\CurrentControlSet\\Enum\\{}\\Device Parameters", instance_id);
+    let key = hklm.open_subkey_with_flags(&key_path, KEY_SET_VALUE).map_err(|e| registry_error(e, &key_path))?;
+    key.set_value("PortName", &port).map_err(|e| registry_error(e, &key_path))?;
+
+    let db = ComDb::open()?;
+    if let Some(number) = com_number(port) {
+        db.claim(number)?;
+    }
+    // During a swap the other device may have been given the old port already
+    if let Some(number) = old.as_deref().filter(|old| !old.eq_ignore_ascii_case(port)).and_then(com_number) {
+        let still_used = list_com_ports(true)?.iter().any(|device| com_number(&device.port) == Some(number));
+        if !still_used {
+            db.release(number)?;
+        }
+    }
+    if let Some(name) = get_friendly_name(instance_id).and_then(|name| renamed_friendly_name(&name, port)) {
+        set_friendly_name(instance_id, &name)?;
+    }
+
+    if restart {
+        restart_device(instance_id)?;
+    }
+    Ok(())
+}
+
+/// Another device that [`force_set_usb_port`] moves out of the way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMove {
+    pub instance_id: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// What [`force_set_usb_port`] would change, without touching the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortPlan {
+    pub instance_id: String,
+    pub current: Option<String>,
+    pub target: String,
+    pub moves: Vec<PortMove>,
+}
+
+impl PortPlan {
+    pub fn is_noop(&self) -> bool {
+        self.current.as_deref().is_some_and(|current| current.eq_ignore_ascii_case(&self.target))
+    }
+}
+
+fn com_number(port: &str) -> Option<u32> {
+    port.to_uppercase().strip_prefix("COM")?.parse().ok()
+}
+
+/// `assignments` holds every known `(instance id, port)`, including unplugged devices
+fn build_port_plan(instance_id: &str, target: &str, assignments: &[(String, String)]) -> PortPlan {
+    let current = assignments.iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(instance_id))
+        .map(|(_, port)| port.clone());
+    let mut plan = PortPlan {
+        instance_id: instance_id.to_string(),
+        current,
+        target: target.to_string(),
+        moves: Vec::new(),
+    };
+    if plan.is_noop() {
+        return plan;
+    }
+
+    let mut used: Vec<u32> = assignments.iter().filter_map(|(_, port)| com_number(port)).collect();
+    used.extend(com_number(target));
+    for (id, port) in assignments {
+        if id.eq_ignore_ascii_case(instance_id) || !port.eq_ignore_ascii_case(target) {
+            continue;
+        }
+        // Hand the displaced device our old port when we have one, otherwise the lowest free number
+        let to = match plan.current.as_ref().filter(|_| plan.moves.is_empty()) {
+            Some(current) => current.clone(),
+            None => {
+                let free = (1..).find(|n| !used.contains(n)).unwrap_or(1);
+                used.push(free);
+                format!("COM{free}")
+            }
+        };
+        plan.moves.push(PortMove { instance_id: id.clone(), from: port.clone(), to });
+    }
+    plan
+}
+
+/// Dry run of [`force_set_usb_port`]
+pub fn plan_force_set_usb_port(instance_id: &str, port: &str) -> HinataResult<PortPlan> {
+    let assignments: Vec<(String, String)> = list_com_ports(true)?
+        .into_iter()
+        .map(|device| (device.instance_id, device.port))
+        .collect();
+    Ok(build_port_plan(instance_id, port, &assignments))
+}
+
+/// Give `instance_id` the port `port`, renumbering any other device that holds it. Returns the plan that was applied.
+/// `restart` cycles every touched device, see [`set_device_com_port`].
+pub fn force_set_usb_port(instance_id: &str, port: &str, restart: bool) -> HinataResult<PortPlan> {
+    // Fail before the first write rather than halfway through the moves
+    if !is_elevated() {
+        return Err(Error::PermissionDenied("Changing a COM port needs an elevated (administrator) process".into()));
+    }
+    let plan = plan_force_set_usb_port(instance_id, port)?;
+    if plan.is_noop() {
+        return Ok(plan);
+    }
+    for PortMove { instance_id, to, .. } in &plan.moves {
+        set_device_com_port(instance_id, to, restart)?;
+    }
+    set_device_com_port(instance_id, port, restart)?;
+    Ok(plan)
+}
+
+/// Give each connected reader in `plan` its COM port, matched by chip id and relocating whatever holds the port.
+/// Returns the applied plans in order; see [`force_set_usb_port`] for `restart`.
+/// Readers without a serial interface are left out, planning a port for one fails.
+pub async fn assign_com_ports(plan: &[(ChipId, &str)], restart: bool) -> HinataResult<Vec<PortPlan>> {
+    for (i, (_, port)) in plan.iter().enumerate() {
+        if plan[..i].iter().any(|(_, other)| other.eq_ignore_ascii_case(port)) {
+            return Err(Error::Parse(format!("{port} is assigned twice")));
+        }
+    }
+
+    let mut connected = Vec::new();
+    for builder in find_devices(vec![]).await? {
+        let mut device = builder.build(false)?;
+        let instance_id = device.get_com_instance_id();
+        if instance_id.is_empty() {
+            continue;
+        }
+        if let Ok(chip_id) = device.get_chip_id().await {
+            connected.push((chip_id, instance_id));
+        }
+    }
+
+    let mut applied = Vec::with_capacity(plan.len());
+    for (chip_id, port) in plan {
+        let instance_id = connected.iter()
+            .find(|(id, _)| id == chip_id)
+            .map(|(_, instance_id)| instance_id.clone())
+            .ok_or(Error::NotFound(format!("No reader with chip id {} and a serial interface", IdFormat::new(chip_id).hex())))?;
+        let port = port.to_string();
+        applied.push(run_blocking(move || force_set_usb_port(&instance_id, &port, restart)).await?);
+    }
+    Ok(applied)
+}
+
+/// Change to a device's COM assignment reported by [`watch_port`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortEvent {
+    Appeared(String),
+    Changed { from: String, to: String },
+    Disappeared,
+}
+
+fn port_transition(old: Option<&str>, new: Option<&str>) -> Option<PortEvent> {
+    match (old, new) {
+        (None, Some(port)) => Some(PortEvent::Appeared(port.to_string())),
+        (Some(from), Some(to)) if from != to => Some(PortEvent::Changed { from: from.to_string(), to: to.to_string() }),
+        (Some(_), None) => Some(PortEvent::Disappeared),
+        _ => None,
+    }
+}
+
+fn is_device_present(instance_id: &str) -> bool {
+    let id_wide: Vec<u16> = instance_id.encode_utf16().chain(std::iter::once(0)).collect();
+    let mut dev_node: u32 = 0;
+    // Without CM_LOCATE_DEVNODE_PHANTOM only devices that are plugged in resolve
+    unsafe { CM_Locate_DevNodeW(&mut dev_node, PCWSTR::from_raw(id_wide.as_ptr()), CM_LOCATE_DEVNODE_NORMAL) == CR_SUCCESS }
+}
+
+fn current_port(instance_id: &str) -> Option<String> {
+    is_device_present(instance_id)
+        .then(|| get_com_port_by_com_instance_id(instance_id).ok())
+        .flatten()
+}
+
+/// Configuration Manager notifications for one device instance, unregistered on drop
+struct DeviceNotification {
+    handle: HCMNOTIFICATION,
+    // Read by the callback until the registration is gone
+    _notify: Arc<Notify>,
+}
+
+// The handle is only passed back to CM_Unregister_Notification
+unsafe impl Send for DeviceNotification {}
+
+unsafe extern "system" fn on_device_change(_: HCMNOTIFICATION, context: *const c_void, _: CM_NOTIFY_ACTION, _: *const CM_NOTIFY_EVENT_DATA, _: u32) -> u32 {
+    unsafe { &*(context as *const Notify) }.notify_one();
+    0
+}
+
+impl DeviceNotification {
+    /// Wake `notify` whenever `instance_id` is enumerated, started or removed
+    fn register(instance_id: &str, notify: Arc<Notify>) -> HinataResult<Self> {
+        let mut filter: CM_NOTIFY_FILTER = unsafe { std::mem::zeroed() };
+        filter.cbSize = size_of::<CM_NOTIFY_FILTER>() as u32;
+        filter.FilterType = CM_NOTIFY_FILTER_TYPE_DEVICEINSTANCE;
+        let id: Vec<u16> = instance_id.encode_utf16().collect();
+        // Leave room for the terminating NUL
+        let slot = unsafe { &mut filter.u.DeviceInstance.InstanceId };
+        if id.len() >= slot.len() {
+            return Err(Error::Parse(format!("Instance id too long to watch: {instance_id}")));
+        }
+        slot[..id.len()].copy_from_slice(&id);
+
+        let mut handle = HCMNOTIFICATION::default();
+        let context = Arc::as_ptr(&notify) as *const c_void;
+        let ret = unsafe { CM_Register_Notification(&filter, Some(context), Some(on_device_change), &mut handle) };
+        if ret != CR_SUCCESS {
+            return Err(Error::Other(format!("Could not watch {instance_id}: {:?}", ret)));
+        }
+        Ok(Self { handle, _notify: notify })
+    }
+}
+
+impl Drop for DeviceNotification {
+    fn drop(&mut self) {
+        // Waits for a running callback, so the Notify outlives every call
+        let _ = unsafe { CM_Unregister_Notification(self.handle) };
+    }
+}
+
+/// Follow the COM port of the serial device `instance_id`. The current port comes first as
+/// [`PortEvent::Appeared`], dropping the stream stops watching.
+///
+/// The port is re-read whenever Windows enumerates, starts or removes the device, so a new `PortName`
+/// shows up once the device restarts, as it does after [`set_device_com_port`] with `restart`.
+pub fn watch_port(instance_id: &str) -> HinataResult<ReceiverStream<PortEvent>> {
+    let instance_id = instance_id.to_string();
+    let notify = Arc::new(Notify::new());
+    let registration = DeviceNotification::register(&instance_id, notify.clone())?;
+    let (tx, rx) = mpsc::channel(8);
+    tokio::spawn(async move {
+        let _registration = registration;
+        let mut port: Option<String> = None;
+        loop {
+            let id = instance_id.clone();
+            let Ok(next) = tokio::task::spawn_blocking(move || current_port(&id)).await else {
+                return;
+            };
+            if let Some(event) = port_transition(port.as_deref(), next.as_deref()) {
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            port = next;
+            // A notification that arrived during the read is kept, the next wait returns at once
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tx.closed() => return,
+            }
+        }
+    });
+    Ok(ReceiverStream::new(rx))
+}
+
+#[test]
+fn port_plan_test() {
+    let assignments = [
+        ("USB\\VID_F822&PID_0147&MI_00\\1".to_string(), "COM5".to_string()),
+        ("USB\\VID_0403&PID_6001\\A1".to_string(), "COM3".to_string()),
+        ("ACPI\\PNP0501\\0".to_string(), "COM1".to_string()),
+    ];
+    let plan = build_port_plan("USB\\VID_F822&PID_0147&MI_00\\1", "COM3", &assignments);
+    assert_eq!(plan.current.as_deref(), Some("COM5"));
+    assert_eq!(plan.moves, vec![PortMove { instance_id: assignments[1].0.clone(), from: "COM3".into(), to: "COM5".into() }]);
+
+    let plan = build_port_plan("USB\\VID_1234&PID_0001\\X", "COM1", &assignments);
+    assert_eq!(plan.moves[0].to, "COM2");
+    assert!(build_port_plan(&assignments[0].0, "com5", &assignments).is_noop());
+}
+
+#[test]
+fn port_transition_test() {
+    assert_eq!(port_transition(None, Some("COM3")), Some(PortEvent::Appeared("COM3".into())));
+    assert_eq!(port_transition(Some("COM3"), Some("COM7")), Some(PortEvent::Changed { from: "COM3".into(), to: "COM7".into() }));
+    assert_eq!(port_transition(Some("COM7"), None), Some(PortEvent::Disappeared));
+    assert_eq!(port_transition(Some("COM7"), Some("COM7")), None);
+}
+
+#[test]
+fn friendly_name_test() {
+    assert_eq!(renamed_friendly_name("USB Serial Device (COM5)", "COM3").as_deref(), Some("USB Serial Device (COM3)"));
+    assert_eq!(renamed_friendly_name("HINATA (Serial) (COM12)", "COM4").as_deref(), Some("HINATA (Serial) (COM4)"));
+    assert_eq!(renamed_friendly_name("USB Serial Device", "COM3"), None);
+}
+
+#[test]
+fn parse_vid_pid_test() {
+    assert_eq!(parse_vid_pid("USB\\VID_F822&PID_0147&MI_00\\7&1A2B3C&0&0000"), Some((0xF822, 0x0147)));
+    assert_eq!(parse_vid_pid("ACPI\\PNP0501\\0"), None);
+}
+
 #[test]
 fn get_port_test() {
     let com_serial = get_com_instance_id_by_hid_instance_id("HID\\VID_F822&PID_0147&MI_02&Col01\\8&38333037&0&0000").unwrap();
@@ -0,0 +1,52 @@
+fn crc16(data: &[u8], init: u16) -> u16 {
+    data.iter().fold(init, |crc, &byte| {
+        let mut ch = byte ^ (crc & 0xFF) as u8;
+        ch ^= ch << 4;
+        (crc >> 8) ^ ((ch as u16) << 8) ^ ((ch as u16) << 3) ^ ((ch as u16) >> 4)
+    })
+}
+
+/// ISO/IEC 14443-3 Type A CRC, returned in transmission order (LSB first)
+pub fn crc_a(data: &[u8]) -> [u8; 2] {
+    crc16(data, 0x6363).to_le_bytes()
+}
+
+/// ISO/IEC 14443-3 Type B CRC, returned in transmission order (LSB first)
+pub fn crc_b(data: &[u8]) -> [u8; 2] {
+    (!crc16(data, 0xFFFF)).to_le_bytes()
+}
+
+pub fn append_crc_a(data: &mut Vec<u8>) {
+    let crc = crc_a(data);
+    data.extend_from_slice(&crc);
+}
+
+pub fn append_crc_b(data: &mut Vec<u8>) {
+    let crc = crc_b(data);
+    data.extend_from_slice(&crc);
+}
+
+/// Check a frame whose last two bytes are its CRC_A
+pub fn verify_crc_a(frame: &[u8]) -> bool {
+    frame.len() >= 2 && crc_a(&frame[..frame.len() - 2]) == frame[frame.len() - 2..]
+}
+
+/// Check a frame whose last two bytes are its CRC_B
+pub fn verify_crc_b(frame: &[u8]) -> bool {
+    frame.len() >= 2 && crc_b(&frame[..frame.len() - 2]) == frame[frame.len() - 2..]
+}
+
+#[test]
+fn crc_test() {
+    assert_eq!(crc_a(&[0x00, 0x00]), [0xA0, 0x1E]);
+    assert_eq!(crc_a(&[0x12, 0x34]), [0x26, 0xCF]);
+    assert_eq!(crc_a(&[0x30, 0x00]), [0x02, 0xA8]);
+    assert_eq!(crc_b(&[0x00, 0x00, 0x00]), [0xCC, 0xC6]);
+    assert_eq!(crc_b(&[0x0F, 0xAA, 0xFF]), [0xFC, 0xD1]);
+
+    let mut frame = vec![0x30, 0x04];
+    append_crc_a(&mut frame);
+    assert!(verify_crc_a(&frame));
+    frame[0] ^= 1;
+    assert!(!verify_crc_a(&frame));
+}
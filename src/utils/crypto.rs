@@ -0,0 +1,151 @@
+use aes::Aes128;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use des::{Des, TdesEde2, TdesEde3};
+use crate::error::{Error, HinataResult};
+
+/// Block ciphers used by the card protocols, CBC and CMAC are built on top
+pub enum CipherKey {
+    Des(Box<Des>),
+    Tdes2(Box<TdesEde2>),
+    Tdes3(Box<TdesEde3>),
+    Aes128(Box<Aes128>),
+}
+
+impl CipherKey {
+    pub fn des(key: &[u8; 8]) -> Self {
+        Self::Des(Box::new(Des::new(GenericArray::from_slice(key))))
+    }
+
+    pub fn tdes2(key: &[u8; 16]) -> Self {
+        Self::Tdes2(Box::new(TdesEde2::new(GenericArray::from_slice(key))))
+    }
+
+    pub fn tdes3(key: &[u8; 24]) -> Self {
+        Self::Tdes3(Box::new(TdesEde3::new(GenericArray::from_slice(key))))
+    }
+
+    pub fn aes128(key: &[u8; 16]) -> Self {
+        Self::Aes128(Box::new(Aes128::new(GenericArray::from_slice(key))))
+    }
+
+    pub fn block_size(&self) -> usize {
+        match self {
+            Self::Aes128(_) => 16,
+            _ => 8,
+        }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8]) {
+        match self {
+            Self::Des(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Tdes2(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Tdes3(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Aes128(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8]) {
+        match self {
+            Self::Des(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Tdes2(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Tdes3(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            Self::Aes128(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    fn check_blocks(&self, iv: &[u8], data: &[u8]) -> HinataResult<()> {
+        if iv.len() != self.block_size() || !data.len().is_multiple_of(self.block_size()) {
+            return Err(Error::Other("Data is not aligned to the cipher block size".into()));
+        }
+        Ok(())
+    }
+
+    /// CBC encrypt in place, `iv` is left holding the last ciphertext block
+    pub fn cbc_encrypt(&self, iv: &mut [u8], data: &mut [u8]) -> HinataResult<()> {
+        self.check_blocks(iv, data)?;
+        for block in data.chunks_mut(self.block_size()) {
+            block.iter_mut().zip(iv.iter()).for_each(|(b, v)| *b ^= v);
+            self.encrypt_block(block);
+            iv.copy_from_slice(block);
+        }
+        Ok(())
+    }
+
+    /// CBC decrypt in place, `iv` is left holding the last ciphertext block
+    pub fn cbc_decrypt(&self, iv: &mut [u8], data: &mut [u8]) -> HinataResult<()> {
+        self.check_blocks(iv, data)?;
+        for block in data.chunks_mut(self.block_size()) {
+            let cipher_block = block.to_vec();
+            self.decrypt_block(block);
+            block.iter_mut().zip(iv.iter()).for_each(|(b, v)| *b ^= v);
+            iv.copy_from_slice(&cipher_block);
+        }
+        Ok(())
+    }
+
+    /// NIST SP 800-38B CMAC chained from `iv`, returns the full block
+    pub fn cmac(&self, iv: &[u8], data: &[u8]) -> Vec<u8> {
+        let size = self.block_size();
+        let rb = if size == 16 { 0x87 } else { 0x1B };
+        let shift = |input: &[u8]| -> Vec<u8> {
+            let mut out = vec![0u8; size];
+            for i in 0..size {
+                out[i] = input[i] << 1 | input.get(i + 1).map_or(0, |b| b >> 7);
+            }
+            if input[0] & 0x80 != 0 {
+                out[size - 1] ^= rb;
+            }
+            out
+        };
+
+        let mut l = vec![0u8; size];
+        self.encrypt_block(&mut l);
+        let k1 = shift(&l);
+        let k2 = shift(&k1);
+
+        let mut padded = data.to_vec();
+        let subkey = if !data.is_empty() && data.len().is_multiple_of(size) {
+            k1
+        } else {
+            padded.push(0x80);
+            padded.resize(padded.len().div_ceil(size) * size, 0);
+            k2
+        };
+        let last = padded.len() - size;
+        padded[last..].iter_mut().zip(subkey.iter()).for_each(|(b, k)| *b ^= k);
+
+        let mut mac = iv.to_vec();
+        for block in padded.chunks(size) {
+            mac.iter_mut().zip(block.iter()).for_each(|(m, b)| *m ^= b);
+            self.encrypt_block(&mut mac);
+        }
+        mac
+    }
+}
+
+pub fn random_bytes<const N: usize>() -> HinataResult<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes).map_err(|e| Error::Other(e.to_string()))?;
+    Ok(bytes)
+}
+
+pub fn rotate_left(data: &[u8]) -> Vec<u8> {
+    let mut rotated = data.to_vec();
+    rotated.rotate_left(1);
+    rotated
+}
+
+#[test]
+fn cmac_test() {
+    // RFC 4493 example 2
+    let key = CipherKey::aes128(&[
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+    ]);
+    let message = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+    ];
+    assert_eq!(key.cmac(&[0u8; 16], &message), vec![
+        0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a, 0x28, 0x7c,
+    ]);
+}
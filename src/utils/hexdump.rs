@@ -0,0 +1,61 @@
+use std::fmt;
+
+/// Bytes per line of the multi-line format
+const LINE_LEN: usize = 16;
+
+/// Formats bytes as space separated upper-case hex, e.g. `01 E6 AA`.
+/// The alternate form `{:#}` prints offset, hex and ASCII columns, 16 bytes per line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HexDump<'a>(&'a [u8]);
+
+impl<'a> HexDump<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+}
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            for (i, b) in self.0.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{b:02X}")?;
+            }
+            return Ok(());
+        }
+
+        for (line, chunk) in self.0.chunks(LINE_LEN).enumerate() {
+            if line > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:04X}: ", line * LINE_LEN)?;
+            for i in 0..LINE_LEN {
+                match chunk.get(i) {
+                    Some(b) => write!(f, "{b:02X} ")?,
+                    None => f.write_str("   ")?,
+                }
+            }
+            f.write_str("|")?;
+            for &b in chunk {
+                let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
+                write!(f, "{c}")?;
+            }
+            f.write_str("|")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn hexdump_test() {
+    assert_eq!(HexDump::new(&[0x01, 0xE6, 0xAA]).to_string(), "01 E6 AA");
+    assert_eq!(HexDump::new(&[]).to_string(), "");
+
+    let data: Vec<u8> = (0x30..0x42).collect();
+    assert_eq!(format!("{:#}", HexDump::new(&data)), concat!(
+        "0000: 30 31 32 33 34 35 36 37 38 39 3A 3B 3C 3D 3E 3F |0123456789:;<=>?|\n",
+        "0010: 40 41                                           |@A|",
+    ));
+}
@@ -0,0 +1,79 @@
+use crate::ChipId;
+
+/// Letter case of hex digits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HexCase {
+    Upper,
+    Lower,
+}
+
+/// Borrowed UID or IDm with the encodings access-control backends commonly expect
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IdFormat<'a>(&'a [u8]);
+
+impl<'a> IdFormat<'a> {
+    pub fn new(id: &'a [u8]) -> Self {
+        Self(id)
+    }
+
+    pub fn get_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// Upper-case hex without separators, e.g. `04A1B2C3`
+    pub fn hex(&self) -> String {
+        self.hex_with(HexCase::Upper, None)
+    }
+
+    pub fn hex_with(&self, case: HexCase, separator: Option<char>) -> String {
+        let mut out = String::with_capacity(self.0.len() * 3);
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 && let Some(sep) = separator {
+                out.push(sep);
+            }
+            match case {
+                HexCase::Upper => out.push_str(&format!("{b:02X}")),
+                HexCase::Lower => out.push_str(&format!("{b:02x}")),
+            }
+        }
+        out
+    }
+
+    pub fn reversed(&self) -> Vec<u8> {
+        self.0.iter().rev().copied().collect()
+    }
+
+    /// Big-endian integer value, `None` for IDs longer than 8 bytes
+    pub fn to_u64(&self) -> Option<u64> {
+        (self.0.len() <= 8).then(|| self.0.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    /// Little-endian integer value, as readers emulating keyboards usually print it
+    pub fn to_u64_le(&self) -> Option<u64> {
+        (self.0.len() <= 8).then(|| self.0.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    /// Decimal "card number", zero padded to `width` digits
+    pub fn decimal(&self, reversed: bool, width: usize) -> Option<String> {
+        let value = if reversed { self.to_u64_le()? } else { self.to_u64()? };
+        Some(format!("{value:0width$}"))
+    }
+}
+
+/// Product id and chip id as one short string naming a physical reader, e.g. `0001-1A2B3C4D`
+pub fn device_fingerprint(product_id: u16, chip_id: &ChipId) -> String {
+    format!("{product_id:04X}-{}", IdFormat::new(chip_id).hex())
+}
+
+#[test]
+fn id_format_test() {
+    let id = IdFormat::new(&[0x04, 0xA1, 0xB2, 0xC3]);
+    assert_eq!(id.hex(), "04A1B2C3");
+    assert_eq!(id.hex_with(HexCase::Lower, Some(':')), "04:a1:b2:c3");
+    assert_eq!(id.reversed(), vec![0xC3, 0xB2, 0xA1, 0x04]);
+    assert_eq!(id.to_u64(), Some(0x04A1B2C3));
+    assert_eq!(id.to_u64_le(), Some(0xC3B2A104));
+    assert_eq!(id.decimal(false, 10).unwrap(), "0077705923");
+    assert_eq!(IdFormat::new(&[0; 10]).to_u64(), None);
+    assert_eq!(device_fingerprint(0x0001, &[0x1A, 0x2B, 0x3C, 0x4D]), "0001-1A2B3C4D");
+}
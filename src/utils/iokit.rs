@@ -0,0 +1,70 @@
+use std::ffi::{c_char, CStr};
+use core_foundation_sys::base::{kCFAllocatorDefault, CFGetTypeID, CFIndex, CFRelease, CFTypeRef};
+use core_foundation_sys::string::{kCFStringEncodingUTF8, CFStringCreateWithCString, CFStringGetCString, CFStringGetTypeID, CFStringRef};
+use io_kit_sys::keys::kIOServicePlane;
+use io_kit_sys::types::io_registry_entry_t;
+use io_kit_sys::{
+    kIOMasterPortDefault, kIORegistryIterateRecursively, IOObjectConformsTo, IOObjectRelease,
+    IORegistryEntryGetParentEntry, IORegistryEntryIDMatching, IORegistryEntrySearchCFProperty,
+    IOServiceGetMatchingService,
+};
+use crate::error::{Error, HinataResult};
+
+/// Older systems still register devices under the legacy USB family
+const USB_DEVICE_CLASSES: [&CStr; 2] = [c"IOUSBHostDevice", c"IOUSBDevice"];
+
+/// Callout node (`/dev/cu.usbmodem…`) on the same USB device as the HID service at `hid_path` (`DevSrvsID:…`)
+pub fn get_callout_device_by_hid_path(hid_path: &str) -> HinataResult<String> {
+    let entry_id: u64 = hid_path
+        .strip_prefix("DevSrvsID:")
+        .and_then(|id| id.parse().ok())
+        .ok_or(Error::Parse(format!("Not an IOService path: {hid_path}")))?;
+
+    unsafe {
+        // Consumes the matching dictionary
+        let hid = IOServiceGetMatchingService(kIOMasterPortDefault, IORegistryEntryIDMatching(entry_id));
+        if hid == 0 {
+            return Err(Error::NotFound(format!("No IOService with id {entry_id}")));
+        }
+        let usb_device = find_usb_device(hid).ok_or(Error::NotFound("No USB device above the HID service".into()))?;
+        let port = search_string_property(usb_device, c"IOCalloutDevice");
+        IOObjectRelease(usb_device);
+        port.ok_or(Error::NotFound("No serial interface on the USB device".into()))
+    }
+}
+
+/// Walk up the service plane, releases every entry it leaves behind
+unsafe fn find_usb_device(mut entry: io_registry_entry_t) -> Option<io_registry_entry_t> {
+    unsafe {
+        loop {
+            if USB_DEVICE_CLASSES.iter().any(|class| IOObjectConformsTo(entry, class.as_ptr() as *mut c_char) != 0) {
+                return Some(entry);
+            }
+            let mut parent: io_registry_entry_t = 0;
+            let ret = IORegistryEntryGetParentEntry(entry, kIOServicePlane, &mut parent);
+            IOObjectRelease(entry);
+            if ret != 0 {
+                return None;
+            }
+            entry = parent;
+        }
+    }
+}
+
+/// First string property named `key` in `entry` or anything below it
+unsafe fn search_string_property(entry: io_registry_entry_t, key: &CStr) -> Option<String> {
+    unsafe {
+        let key = CFStringCreateWithCString(kCFAllocatorDefault, key.as_ptr(), kCFStringEncodingUTF8);
+        let value = IORegistryEntrySearchCFProperty(entry, kIOServicePlane, key, kCFAllocatorDefault, kIORegistryIterateRecursively);
+        CFRelease(key as CFTypeRef);
+        if value.is_null() {
+            return None;
+        }
+
+        let mut buffer = [0 as c_char; 256];
+        let found = CFGetTypeID(value) == CFStringGetTypeID()
+            && CFStringGetCString(value as CFStringRef, buffer.as_mut_ptr(), buffer.len() as CFIndex, kCFStringEncodingUTF8) != 0;
+        CFRelease(value);
+        found.then(|| CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned())
+    }
+}
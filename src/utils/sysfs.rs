@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::error::{Error, HinataResult};
+
+/// Serial node (`/dev/ttyACM0`) on the same USB device as the hidraw node at `hidraw_path`
+pub fn get_tty_by_hidraw(hidraw_path: &str) -> HinataResult<String> {
+    let name = Path::new(hidraw_path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(Error::Parse(format!("Not a hidraw path: {hidraw_path}")))?;
+
+    // hidrawN/device -> .../X-Y/X-Y:1.2/0003:VID:PID.NNNN, two levels up is the USB device
+    let hid = fs::canonicalize(format!("/sys/class/hidraw/{name}/device"))?;
+    let usb_device = hid
+        .parent()
+        .and_then(|interface| interface.parent())
+        .ok_or(Error::NotFound(format!("No USB device above {}", hid.display())))?;
+
+    find_tty(usb_device)
+        .map(|tty| format!("/dev/{tty}"))
+        .ok_or(Error::NotFound(format!("No tty bound to {}", usb_device.display())))
+}
+
+/// Look through the interfaces of a USB device, cdc_acm puts the node under `tty/`, usb-serial right in the interface
+fn find_tty(usb_device: &Path) -> Option<String> {
+    let mut interfaces: Vec<PathBuf> = fs::read_dir(usb_device)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.contains(':')))
+        .collect();
+    interfaces.sort();
+
+    interfaces.iter().find_map(|interface| {
+        let nested = fs::read_dir(interface.join("tty")).into_iter().flatten();
+        let direct = fs::read_dir(interface).into_iter().flatten();
+        nested
+            .chain(direct)
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .find(|name| name.starts_with("ttyACM") || name.starts_with("ttyUSB"))
+    })
+}
+
+#[test]
+fn find_tty_test() {
+    let root = std::env::temp_dir().join(format!("hinata-sysfs-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("1-2:1.0/tty/ttyACM3")).unwrap();
+    fs::create_dir_all(root.join("1-2:1.2/0003:F822:0147.0005")).unwrap();
+    fs::create_dir_all(root.join("power")).unwrap();
+    assert_eq!(find_tty(&root).as_deref(), Some("ttyACM3"));
+    fs::remove_dir_all(&root).unwrap();
+}